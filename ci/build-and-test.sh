@@ -10,13 +10,13 @@ cargo fetch --locked
 end_group
 
 begin_group "Build"
-cargo build --workspace --all-targets --frozen
+cargo build --workspace --all-targets --all-features --frozen
 end_group
 
 begin_group "Test"
-cargo test --workspace --frozen
+cargo test --workspace --all-features --frozen
 end_group
 
 begin_group "Doc"
-cargo doc --workspace --frozen
+cargo doc --workspace --all-features --frozen
 end_group
@@ -8,5 +8,5 @@ cargo fetch --locked
 end_group
 
 begin_group "Run clippy"
-cargo clippy --workspace --all-targets --frozen -- -D warnings
+cargo clippy --workspace --all-targets --all-features --frozen -- -D warnings
 end_group
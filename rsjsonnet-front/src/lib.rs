@@ -44,9 +44,11 @@
 //! assert_eq!(json_result, "3");
 //! ```
 
+mod native;
 mod print;
 mod report;
 mod session;
 mod src_manager;
 
-pub use session::Session;
+pub use native::{NativeFunc, NativeFuncError, NativeParam};
+pub use session::{DataFormat, Session};
@@ -0,0 +1,100 @@
+use rsjsonnet_lang::program::{FromJsonnet, FromJsonnetError, IntoJsonnet, Program, Value};
+
+/// Error returned by [`NativeFunc::call`].
+#[derive(Clone, Debug)]
+pub enum NativeFuncError {
+    /// An argument could not be converted to the type expected by the
+    /// function.
+    InvalidArg {
+        arg_index: usize,
+        error: FromJsonnetError,
+    },
+    /// The function failed with the provided message.
+    Failed(String),
+}
+
+/// A parameter of a native function registered with
+/// [`Session::add_native_func_with_params`](crate::Session::add_native_func_with_params).
+#[derive(Clone)]
+pub enum NativeParam<'a, 'p> {
+    /// A parameter without default value.
+    Required(&'a str),
+    /// A parameter with a default value.
+    DefaultValue(&'a str, Value<'p>),
+    /// A parameter whose default value is the result of evaluating the
+    /// provided Jsonnet code.
+    DefaultCode(&'a str, &'a str),
+    /// A trailing parameter that receives an array with the extra positional
+    /// arguments. It must be the last parameter.
+    Rest(&'a str),
+}
+
+impl<'a> NativeParam<'a, '_> {
+    /// Returns the name of the parameter.
+    pub fn name(&self) -> &'a str {
+        match *self {
+            Self::Required(name)
+            | Self::DefaultValue(name, _)
+            | Self::DefaultCode(name, _)
+            | Self::Rest(name) => name,
+        }
+    }
+}
+
+/// A Rust function that can be registered as a Jsonnet native function with
+/// [`Session::add_native_func`](crate::Session::add_native_func).
+///
+/// `N` is the number of parameters and `Args` is a tuple with their types.
+///
+/// It is implemented for closures that take up to 8 arguments whose types
+/// implement [`FromJsonnet`] and that return `Result<R, String>`, where `R`
+/// implements [`IntoJsonnet`].
+pub trait NativeFunc<'p, Args, const N: usize>: 'static {
+    /// Calls the function with `args`, which has a length of `N`.
+    fn call(
+        &mut self,
+        program: &mut Program<'p>,
+        args: &[Value<'p>],
+    ) -> Result<Value<'p>, NativeFuncError>;
+}
+
+macro_rules! impl_native_func {
+    ($n:literal; $($arg_ty:ident $arg:ident $arg_index:literal),*) => {
+        impl<'p, F, R, $($arg_ty),*> NativeFunc<'p, ($($arg_ty,)*), $n> for F
+        where
+            F: FnMut($($arg_ty),*) -> Result<R, String> + 'static,
+            R: IntoJsonnet<'p>,
+            $($arg_ty: FromJsonnet<'p>,)*
+        {
+            fn call(
+                &mut self,
+                program: &mut Program<'p>,
+                args: &[Value<'p>],
+            ) -> Result<Value<'p>, NativeFuncError> {
+                let [$($arg),*] = args else {
+                    panic!("expected {} arguments, got {}", $n, args.len());
+                };
+                $(
+                    let $arg = $arg_ty::from_jsonnet($arg).map_err(|error| {
+                        NativeFuncError::InvalidArg {
+                            arg_index: $arg_index,
+                            error,
+                        }
+                    })?;
+                )*
+                let result = self($($arg),*).map_err(NativeFuncError::Failed)?;
+                Ok(result.into_jsonnet(program))
+            }
+        }
+    };
+}
+
+impl_native_func!(0;);
+impl_native_func!(1; A0 arg0 0);
+impl_native_func!(2; A0 arg0 0, A1 arg1 1);
+impl_native_func!(3; A0 arg0 0, A1 arg1 1, A2 arg2 2);
+impl_native_func!(4; A0 arg0 0, A1 arg1 1, A2 arg2 2, A3 arg3 3);
+impl_native_func!(5; A0 arg0 0, A1 arg1 1, A2 arg2 2, A3 arg3 3, A4 arg4 4);
+impl_native_func!(6; A0 arg0 0, A1 arg1 1, A2 arg2 2, A3 arg3 3, A4 arg4 4, A5 arg5 5);
+impl_native_func!(7; A0 arg0 0, A1 arg1 1, A2 arg2 2, A3 arg3 3, A4 arg4 4, A5 arg5 5, A6 arg6 6);
+impl_native_func!(8; A0 arg0 0, A1 arg1 1, A2 arg2 2, A3 arg3 3, A4 arg4 4, A5 arg5 5, A6 arg6 6, A7 arg7 7);
@@ -0,0 +1,31 @@
+use rsjsonnet_lang::span::{SpanId, SpanManager};
+
+use super::TextPartKind;
+use super::message::{LabelKind, Message, MessageKind, MessageLabel};
+use crate::src_manager::SrcManager;
+
+/// Renders an error from parsing a data file (such as JSON or TOML), where
+/// `format` is the name of the format.
+#[must_use]
+pub(crate) fn render_parse_error(
+    format: &str,
+    message: &str,
+    span: SpanId,
+    span_mgr: &SpanManager,
+    src_mgr: &SrcManager,
+) -> Vec<(String, TextPartKind)> {
+    let mut out = Vec::new();
+
+    Message {
+        kind: MessageKind::Error,
+        message: format!("failed to parse {format}: {message}"),
+        labels: vec![MessageLabel {
+            span,
+            kind: LabelKind::Error,
+            text: message.into(),
+        }],
+    }
+    .render(span_mgr, src_mgr, &mut out);
+
+    out
+}
@@ -1,5 +1,5 @@
 use rsjsonnet_lang::ast;
-use rsjsonnet_lang::program::{EvalErrorKind, EvalErrorValueType};
+use rsjsonnet_lang::program::{EvalErrorKind, EvalErrorValueType, FromJsonnetError};
 use rsjsonnet_lang::span::SpanManager;
 
 use super::TextPartKind;
@@ -334,6 +334,31 @@ pub(crate) fn render_error_kind(
             }
             .render(span_mgr, src_mgr, &mut out);
         }
+        EvalErrorKind::InvalidNativeFuncArg {
+            ref func_name,
+            ref param_name,
+            ref error,
+        } => {
+            let message = match error {
+                FromJsonnetError::InvalidType {
+                    expected_types,
+                    got_type,
+                } => format!(
+                    "argument `{param_name}` of native function `{func_name}` is expected to be {}, got {}",
+                    types_to_string(expected_types),
+                    type_to_string(*got_type),
+                ),
+                FromJsonnetError::InvalidValue { message } => format!(
+                    "invalid argument `{param_name}` of native function `{func_name}`: {message}",
+                ),
+            };
+            Message {
+                kind: MessageKind::Error,
+                message,
+                labels: vec![],
+            }
+            .render(span_mgr, src_mgr, &mut out);
+        }
         EvalErrorKind::InvalidUnaryOpType { span, op, rhs_type } => {
             Message {
                 kind: MessageKind::Error,
@@ -456,17 +481,7 @@ pub(crate) fn render_error_kind(
             ref expected_types,
             got_type,
         } => {
-            let mut expected_str = String::new();
-            for (i, expected_type) in expected_types.iter().enumerate() {
-                if i != 0 {
-                    if i == expected_types.len() - 1 {
-                        expected_str.push_str(" or ");
-                    } else {
-                        expected_str.push_str(", ");
-                    }
-                }
-                expected_str.push_str(type_to_string(*expected_type));
-            }
+            let expected_str = types_to_string(expected_types);
             let arg_ref = match arg_index {
                 0 => "first argument".into(),
                 1 => "second argument".into(),
@@ -645,6 +660,21 @@ fn type_to_string(type_: EvalErrorValueType) -> &'static str {
     }
 }
 
+fn types_to_string(types: &[EvalErrorValueType]) -> String {
+    let mut s = String::new();
+    for (i, type_) in types.iter().enumerate() {
+        if i != 0 {
+            if i == types.len() - 1 {
+                s.push_str(" or ");
+            } else {
+                s.push_str(", ");
+            }
+        }
+        s.push_str(type_to_string(*type_));
+    }
+    s
+}
+
 fn unary_op_to_string(op: ast::UnaryOp) -> &'static str {
     match op {
         ast::UnaryOp::Minus => "-",
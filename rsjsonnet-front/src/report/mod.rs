@@ -1,4 +1,5 @@
 pub(crate) mod analyze;
+pub(crate) mod data;
 pub(crate) mod eval;
 pub(crate) mod lexer;
 mod message;
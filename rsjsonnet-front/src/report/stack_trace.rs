@@ -27,14 +27,24 @@ pub(crate) fn render(
                 }
                 .render(span_mgr, src_mgr, &mut out);
             }
-            EvalStackTraceItem::Call { span, ref name } => {
+            EvalStackTraceItem::Call {
+                span,
+                ref name,
+                tail_calls,
+            } => {
+                let mut message = if let Some(name) = name {
+                    format!("while evaluating call to `{name}`")
+                } else {
+                    "while evaluating call to function".into()
+                };
+                match tail_calls {
+                    0 => {}
+                    1 => message.push_str(" (followed by 1 elided tail call)"),
+                    n => message.push_str(&format!(" (followed by {n} elided tail calls)")),
+                }
                 Message {
                     kind: MessageKind::Note,
-                    message: if let Some(name) = name {
-                        format!("while evaluating call to `{name}`")
-                    } else {
-                        "while evaluating call to function".into()
-                    },
+                    message,
                     labels: span
                         .map(|span| MessageLabel {
                             span,
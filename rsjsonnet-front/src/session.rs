@@ -4,11 +4,34 @@ use std::path::{Path, PathBuf};
 use rsjsonnet_lang::arena::Arena;
 use rsjsonnet_lang::interner::InternedStr;
 use rsjsonnet_lang::program::{ImportError, LoadError, NativeError, Program, Thunk, Value};
-use rsjsonnet_lang::span::{SourceId, SpanId};
+use rsjsonnet_lang::span::{SourceId, SpanContextId, SpanId};
 
+use crate::native::{NativeFunc, NativeFuncError, NativeParam};
 use crate::src_manager::SrcManager;
 
-type NativeFunc<'p> = Box<dyn FnMut(&mut Program<'p>, &[Value<'p>]) -> Result<Value<'p>, String>>;
+type BoxedNativeFunc<'p> =
+    Box<dyn FnMut(&mut Program<'p>, &[Value<'p>]) -> Result<Value<'p>, NativeFuncError>>;
+
+type BoxedExtVarResolver<'p> = Box<dyn FnMut(&mut Program<'p>, &str) -> Option<Value<'p>>>;
+
+/// Format of data files that can be imported directly, see
+/// [`Session::set_import_format`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl DataFormat {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+        }
+    }
+}
 
 pub struct Session<'p> {
     program: Program<'p>,
@@ -20,7 +43,9 @@ struct SessionInner<'p> {
     source_paths: HashMap<SourceId, PathBuf>,
     source_cache: HashMap<PathBuf, Thunk<'p>>,
     search_paths: Vec<PathBuf>,
-    native_funcs: HashMap<InternedStr<'p>, NativeFunc<'p>>,
+    import_formats: HashMap<String, DataFormat>,
+    native_funcs: HashMap<InternedStr<'p>, BoxedNativeFunc<'p>>,
+    ext_var_resolver: Option<BoxedExtVarResolver<'p>>,
     custom_stack_trace: Vec<String>,
     max_trace: usize,
     #[cfg(feature = "crossterm")]
@@ -42,7 +67,9 @@ impl<'p> Session<'p> {
                 source_paths: HashMap::new(),
                 source_cache: HashMap::new(),
                 search_paths: Vec::new(),
+                import_formats: HashMap::new(),
                 native_funcs: HashMap::new(),
+                ext_var_resolver: None,
                 custom_stack_trace: Vec::new(),
                 max_trace: usize::MAX,
                 #[cfg(feature = "crossterm")]
@@ -79,15 +106,100 @@ impl<'p> Session<'p> {
         self.inner.search_paths.push(path);
     }
 
+    /// Makes `import` parse files with the given extension (without the
+    /// leading dot) as `format` instead of Jsonnet, or as Jsonnet again if
+    /// `format` is `None`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rsjsonnet_front::{DataFormat, Session};
+    ///
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = Session::new(&arena);
+    ///
+    /// session.set_import_format("json", Some(DataFormat::Json));
+    /// session.set_import_format("yaml", Some(DataFormat::Yaml));
+    /// session.set_import_format("yml", Some(DataFormat::Yaml));
+    /// session.set_import_format("toml", Some(DataFormat::Toml));
+    /// ```
+    pub fn set_import_format(&mut self, extension: &str, format: Option<DataFormat>) {
+        if let Some(format) = format {
+            self.inner.import_formats.insert(extension.into(), format);
+        } else {
+            self.inner.import_formats.remove(extension);
+        }
+    }
+
     /// Adds a native function.
     ///
+    /// `func` is usually a closure whose arguments implement
+    /// [`FromJsonnet`](rsjsonnet_lang::program::FromJsonnet) and that returns
+    /// `Result<R, String>`, where `R` implements
+    /// [`IntoJsonnet`](rsjsonnet_lang::program::IntoJsonnet). See
+    /// [`NativeFunc`] for more details.
+    ///
+    /// Arguments that cannot be converted are reported as an evaluation
+    /// error that includes the parameter name. If `func` returns an error,
+    /// it is printed to stderr and the evaluation fails.
+    ///
     /// # Example
     ///
     /// ```
     /// let arena = rsjsonnet_lang::arena::Arena::new();
     /// let mut session = rsjsonnet_front::Session::new(&arena);
     ///
-    /// session.add_native_func("MyFunc", &["arg"], |_, [arg]| {
+    /// session.add_native_func("MyFunc", &["arg", "n"], |arg: String, n: usize| {
+    ///     // Count lowercase vowels in the first `n` characters.
+    ///     let r = arg
+    ///         .chars()
+    ///         .take(n)
+    ///         .filter(|chr| matches!(chr, 'a' | 'e' | 'i' | 'o' | 'u'))
+    ///         .count();
+    ///     Ok(r)
+    /// });
+    ///
+    /// let source = br#"local f = std.native("MyFunc"); f("hello world", 5)"#;
+    /// let thunk = session
+    ///     .load_virt_file("<example>", source.to_vec())
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    ///
+    /// assert_eq!(result.as_number(), Some(2.0));
+    /// ```
+    pub fn add_native_func<const N: usize, Args, F>(
+        &mut self,
+        name: &str,
+        params: &[&str; N],
+        mut func: F,
+    ) where
+        F: NativeFunc<'p, Args, N>,
+    {
+        let name = self.program.intern_str(name);
+        let params: Vec<_> = params.iter().map(|p| self.program.intern_str(p)).collect();
+
+        self.program.register_native_func(name, &params);
+        self.inner.native_funcs.insert(
+            name,
+            Box::new(move |program, args| func.call(program, args)),
+        );
+    }
+
+    /// Adds a native function that receives its arguments as [`Value`]s.
+    ///
+    /// This is the form that [`Session::add_native_func`] took before it
+    /// converted arguments. If `func` returns an error, it is printed to
+    /// stderr and the evaluation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// #[allow(deprecated)]
+    /// session.add_untyped_native_func("MyFunc", &["arg"], |_, [arg]| {
     ///     let Some(arg) = arg.to_string() else {
     ///         return Err("expected a string".into());
     ///     };
@@ -109,7 +221,8 @@ impl<'p> Session<'p> {
     ///
     /// assert_eq!(result.as_number(), Some(3.0));
     /// ```
-    pub fn add_native_func<const N: usize, F>(
+    #[deprecated(note = "use `Session::add_native_func` with typed arguments")]
+    pub fn add_untyped_native_func<const N: usize, F>(
         &mut self,
         name: &str,
         params: &[&str; N],
@@ -123,8 +236,217 @@ impl<'p> Session<'p> {
         self.program.register_native_func(name, &params);
         self.inner.native_funcs.insert(
             name,
-            Box::new(move |program, args| func(program, args.try_into().unwrap())),
+            Box::new(move |program, args| {
+                func(program, args.try_into().unwrap()).map_err(NativeFuncError::Failed)
+            }),
+        );
+    }
+
+    /// Adds a native function whose parameters can have default values and
+    /// that can have a trailing rest parameter.
+    ///
+    /// This is like [`Session::add_native_func`], but each parameter is
+    /// described with a [`NativeParam`]. A [`NativeParam::Rest`] parameter
+    /// must be the last one and `func` receives it as an array, so its type
+    /// is usually a `Vec`.
+    ///
+    /// If the Jsonnet code of a default value fails to load, the error is
+    /// printed to stderr, the function is not added and `None` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rsjsonnet_front::NativeParam;
+    /// use rsjsonnet_lang::program::Value;
+    ///
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// session
+    ///     .add_native_func_with_params(
+    ///         "Join",
+    ///         &[
+    ///             NativeParam::DefaultValue("sep", Value::string(",")),
+    ///             NativeParam::Rest("items"),
+    ///         ],
+    ///         |sep: String, items: Vec<String>| Ok(items.join(&sep)),
+    ///     )
+    ///     .unwrap();
+    ///
+    /// let source = br#"local join = std.native("Join"); [join("-", "a", "b"), join(sep=";")]"#;
+    /// let thunk = session
+    ///     .load_virt_file("<example>", source.to_vec())
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    /// let result = session.manifest_json(&result, false).unwrap();
+    ///
+    /// assert_eq!(result, r#"["a-b", ""]"#);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`NativeParam::Rest`] parameter is not the last one.
+    pub fn add_native_func_with_params<const N: usize, Args, F>(
+        &mut self,
+        name: &str,
+        params: &[NativeParam<'_, 'p>; N],
+        mut func: F,
+    ) -> Option<()>
+    where
+        F: NativeFunc<'p, Args, N>,
+    {
+        let (params, rest_param) = match params.split_last() {
+            Some((NativeParam::Rest(rest_name), params)) => (params, Some(*rest_name)),
+            _ => (&params[..], None),
+        };
+
+        let mut interned_params = Vec::with_capacity(params.len());
+        for param in params.iter() {
+            let default = match param {
+                NativeParam::Required(_) => None,
+                NativeParam::DefaultValue(_, value) => Some(self.program.value_to_thunk(value)),
+                NativeParam::DefaultCode(param_name, code) => Some(self.load_virt_file(
+                    &format!("<default value of {param_name:?} in native function {name:?}>"),
+                    code.as_bytes().to_vec(),
+                )?),
+                NativeParam::Rest(_) => {
+                    panic!("rest parameter must be the last parameter");
+                }
+            };
+            interned_params.push((self.program.intern_str(param.name()), default));
+        }
+
+        let name = self.program.intern_str(name);
+        let rest_param = rest_param.map(|rest_name| self.program.intern_str(rest_name));
+
+        self.program
+            .register_native_func_with_params(name, &interned_params, rest_param);
+        self.inner.native_funcs.insert(
+            name,
+            Box::new(move |program, args| func.call(program, args)),
         );
+        Some(())
+    }
+
+    /// Adds a function to the standard library object (`std`), implemented
+    /// in Rust.
+    ///
+    /// `func` is converted as with [`Session::add_native_func`], and it is
+    /// also registered as a native function with the same name, so it is
+    /// available through `std.native(name)` too.
+    ///
+    /// If the standard library already has a field named `name`, it is
+    /// overridden. See [`Program::add_stdlib_field`] for more details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// session.add_std_func("double", &["x"], |x: f64| Ok(x * 2.0));
+    ///
+    /// let thunk = session
+    ///     .load_virt_file("<example>", b"std.double(21)".to_vec())
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    ///
+    /// assert_eq!(result.as_number(), Some(42.0));
+    /// ```
+    pub fn add_std_func<const N: usize, Args, F>(&mut self, name: &str, params: &[&str; N], func: F)
+    where
+        F: NativeFunc<'p, Args, N>,
+    {
+        self.add_native_func(name, params, func);
+        let name = self.program.intern_str(name);
+        let func = self.program.get_native_func(name).unwrap();
+        let thunk = self.program.value_to_thunk(&func);
+        self.program.add_stdlib_field(name, &thunk);
+    }
+
+    /// Adds a field to the standard library object (`std`), implemented
+    /// in Jsonnet.
+    ///
+    /// `code` is loaded as a virtual file and can access the standard
+    /// library (as it is before adding the field) with `std`.
+    ///
+    /// If the standard library already has a field named `name`, it is
+    /// overridden. See [`Program::add_stdlib_field`] for more details.
+    ///
+    /// In case of failure to load `code`, the error is printed to stderr,
+    /// the field is not added and `None` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// session
+    ///     .add_std_field_code("myorg", "{ greet(name):: 'Hello, ' + name }")
+    ///     .unwrap();
+    ///
+    /// let thunk = session
+    ///     .load_virt_file("<example>", b"std.myorg.greet('world')".to_vec())
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    ///
+    /// assert_eq!(result.to_string().as_deref(), Some("Hello, world"));
+    /// ```
+    pub fn add_std_field_code(&mut self, name: &str, code: &str) -> Option<()> {
+        let thunk = self.load_virt_file(&format!("<std.{name}>"), code.as_bytes().to_vec())?;
+        let name = self.program.intern_str(name);
+        self.program.add_stdlib_field(name, &thunk);
+        Some(())
+    }
+
+    /// Removes a field from the standard library object (`std`).
+    ///
+    /// For example, this can be used to disable `std.extVar` or `std.trace`.
+    /// See [`Program::remove_stdlib_field`] for more details.
+    pub fn remove_std_field(&mut self, name: &str) {
+        let name = self.program.intern_str(name);
+        self.program.remove_stdlib_field(name);
+    }
+
+    /// Sets a function that provides the values of external variables that
+    /// have not been added with
+    /// [`Program::add_ext_var`](rsjsonnet_lang::program::Program::add_ext_var).
+    ///
+    /// `resolver` is called when `std.extVar` is evaluated with an unknown
+    /// variable name. Values it returns are cached, so it is called at most
+    /// once for each variable that exists. If it returns `None`, the
+    /// evaluation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rsjsonnet_lang::program::Value;
+    ///
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// session.set_ext_var_resolver(|_, name| {
+    ///     name.strip_prefix("env_").map(|var| Value::string(&var.to_uppercase()))
+    /// });
+    ///
+    /// let thunk = session
+    ///     .load_virt_file("<example>", br#"std.extVar("env_home")"#.to_vec())
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    ///
+    /// assert_eq!(result.to_string().as_deref(), Some("HOME"));
+    /// assert_eq!(session.program().requested_ext_vars()[0].value(), "env_home");
+    /// ```
+    pub fn set_ext_var_resolver<F>(&mut self, resolver: F)
+    where
+        F: FnMut(&mut Program<'p>, &str) -> Option<Value<'p>> + 'static,
+    {
+        self.inner.ext_var_resolver = Some(Box::new(resolver));
     }
 
     /// Loads a file with the provided `data`.
@@ -136,7 +458,47 @@ impl<'p> Session<'p> {
     /// returned.
     pub fn load_virt_file(&mut self, repr_path: &str, data: Vec<u8>) -> Option<Thunk<'p>> {
         self.inner
-            .load_virt_file(&mut self.program, repr_path, data)
+            .load_virt_file(&mut self.program, repr_path, data, &[])
+    }
+
+    /// Loads a file with the provided `data`, with additional variables in
+    /// its scope.
+    ///
+    /// Each item of `env` is a variable name and its value, which can be
+    /// accessed from the file as a top-level identifier. See
+    /// [`Program::load_source_with_env`] for more details.
+    ///
+    /// In case of failure, the error is printed to stderr and `None` is
+    /// returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rsjsonnet_lang::program::Value;
+    ///
+    /// let arena = rsjsonnet_lang::arena::Arena::new();
+    /// let mut session = rsjsonnet_front::Session::new(&arena);
+    ///
+    /// let program = session.program_mut();
+    /// let cluster = program.value_to_thunk(&Value::string("prod"));
+    /// let env = [(program.intern_str("cluster"), cluster)];
+    ///
+    /// let thunk = session
+    ///     .load_virt_file_with_env("<example>", b"cluster + '-db'".to_vec(), &env)
+    ///     .unwrap();
+    ///
+    /// let result = session.eval_value(&thunk).unwrap();
+    ///
+    /// assert_eq!(result.to_string().as_deref(), Some("prod-db"));
+    /// ```
+    pub fn load_virt_file_with_env(
+        &mut self,
+        repr_path: &str,
+        data: Vec<u8>,
+        env: &[(InternedStr<'p>, Thunk<'p>)],
+    ) -> Option<Thunk<'p>> {
+        self.inner
+            .load_virt_file(&mut self.program, repr_path, data, env)
     }
 
     /// Loads a file from the filesystem.
@@ -183,6 +545,47 @@ impl<'p> Session<'p> {
         }
     }
 
+    /// Evaluates a thunk into an object and returns the thunks of its
+    /// visible fields without evaluating them.
+    ///
+    /// In case of failure, the error is printed to stderr and `None` is
+    /// returned. `Some(None)` is returned if the value is not an object.
+    pub fn eval_object_fields(
+        &mut self,
+        thunk: &Thunk<'p>,
+    ) -> Option<Option<Vec<(InternedStr<'p>, Thunk<'p>)>>> {
+        match self.program.eval_object_fields(thunk, &mut self.inner) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.inner.print_eval_error(&self.program, &e);
+                None
+            }
+        }
+    }
+
+    /// Evaluates a function call into an object and returns the thunks of
+    /// its visible fields without evaluating them.
+    ///
+    /// In case of failure, the error is printed to stderr and `None` is
+    /// returned. `Some(None)` is returned if the value is not an object.
+    pub fn eval_call_object_fields(
+        &mut self,
+        func: &Thunk<'p>,
+        pos_args: &[Thunk<'p>],
+        named_args: &[(InternedStr<'p>, Thunk<'p>)],
+    ) -> Option<Option<Vec<(InternedStr<'p>, Thunk<'p>)>>> {
+        match self
+            .program
+            .eval_call_object_fields(func, pos_args, named_args, &mut self.inner)
+        {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.inner.print_eval_error(&self.program, &e);
+                None
+            }
+        }
+    }
+
     /// Marshals a value as JSON.
     ///
     /// In case of failure, the error is printed to stderr and `None` is
@@ -223,6 +626,7 @@ impl<'p> SessionInner<'p> {
         program: &mut Program<'p>,
         repr_path: &str,
         data: Vec<u8>,
+        env: &[(InternedStr<'p>, Thunk<'p>)],
     ) -> Option<Thunk<'p>> {
         let (span_ctx, source_id) = program.span_manager_mut().insert_source_context(data.len());
 
@@ -230,7 +634,7 @@ impl<'p> SessionInner<'p> {
             .insert_file(source_id, repr_path.into(), data.into_boxed_slice());
         let data = self.src_mgr.get_file_data(source_id);
 
-        match program.load_source(span_ctx, data, true, repr_path) {
+        match program.load_source_with_env(span_ctx, data, true, repr_path, env) {
             Ok(thunk) => Some(thunk),
             Err(ref e) => {
                 self.print_load_error(program, e);
@@ -240,6 +644,75 @@ impl<'p> SessionInner<'p> {
     }
 
     fn load_real_file(&mut self, program: &mut Program<'p>, path: &Path) -> Option<Thunk<'p>> {
+        self.load_real_file_with(program, path, |this, program, span_ctx, data, repr_path| {
+            match program.load_source(span_ctx, data, true, repr_path) {
+                Ok(thunk) => Some(thunk),
+                Err(ref e) => {
+                    this.print_load_error(program, e);
+                    None
+                }
+            }
+        })
+    }
+
+    fn load_real_data_file(
+        &mut self,
+        program: &mut Program<'p>,
+        path: &Path,
+        format: DataFormat,
+    ) -> Option<Thunk<'p>> {
+        self.load_real_file_with(program, path, |this, program, span_ctx, data, _| {
+            let Ok(data) = std::str::from_utf8(data) else {
+                this.print_error(format_args!("file {path:?} is not valid UTF-8"));
+                return None;
+            };
+
+            let result = match format {
+                DataFormat::Json => program
+                    .parse_json(data)
+                    .map_err(|e| Some((e.offset, e.message))),
+                DataFormat::Yaml => program.parse_yaml(data).map_err(|e| {
+                    this.print_error(format_args!("failed to parse YAML {path:?}: {e}"));
+                    None
+                }),
+                DataFormat::Toml => program
+                    .parse_toml(data)
+                    .map_err(|e| Some((e.offset, e.message))),
+            };
+            match result {
+                Ok(value) => Some(program.value_to_thunk(&value)),
+                Err(None) => None,
+                Err(Some((offset, message))) => {
+                    let end = data[offset..]
+                        .chars()
+                        .next()
+                        .map_or(offset, |chr| offset + chr.len_utf8());
+                    let span = program
+                        .span_manager_mut()
+                        .intern_span(span_ctx, offset, end);
+                    let msg = crate::report::data::render_parse_error(
+                        format.name(),
+                        &message,
+                        span,
+                        program.span_manager(),
+                        &this.src_mgr,
+                    );
+                    this.print_rich_message(&msg);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Reads the file at `path` and loads it with `load`, which receives
+    /// its contents and the path to show in messages. Files are cached by
+    /// their canonical path, so each one is only loaded once.
+    fn load_real_file_with(
+        &mut self,
+        program: &mut Program<'p>,
+        path: &Path,
+        load: impl FnOnce(&Self, &mut Program<'p>, SpanContextId, &[u8], &str) -> Option<Thunk<'p>>,
+    ) -> Option<Thunk<'p>> {
         let norm_path = match path.canonicalize() {
             Ok(p) => p,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -271,16 +744,9 @@ impl<'p> SessionInner<'p> {
         self.source_paths.insert(source_id, path.to_path_buf());
         let data = self.src_mgr.get_file_data(source_id);
 
-        match program.load_source(span_ctx, data, true, &repr_path) {
-            Ok(thunk) => {
-                self.source_cache.insert(norm_path, thunk.clone());
-                Some(thunk)
-            }
-            Err(ref e) => {
-                self.print_load_error(program, e);
-                None
-            }
-        }
+        let thunk = load(self, program, span_ctx, data, &repr_path)?;
+        self.source_cache.insert(norm_path, thunk.clone());
+        Some(thunk)
     }
 
     fn find_import(&self, program: &Program<'p>, from: SpanId, path: &str) -> Option<PathBuf> {
@@ -343,6 +809,10 @@ impl<'p> SessionInner<'p> {
     }
 
     fn print_eval_error(&self, program: &Program<'p>, error: &rsjsonnet_lang::program::EvalError) {
+        // Keep stderr locked while printing reports made of several
+        // messages, so reports printed from different threads are not
+        // interleaved.
+        let _stderr = std::io::stderr().lock();
         self.print_rich_message(&crate::report::eval::render_error_kind(
             &error.kind,
             program.span_manager(),
@@ -410,7 +880,17 @@ impl<'p> rsjsonnet_lang::program::Callbacks<'p> for SessionInner<'p> {
             self.print_error(format_args!("import {path:?} not found in search path"));
             return Err(ImportError);
         };
-        match self.load_real_file(program, &full_path) {
+        let format = full_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.import_formats.get(ext))
+            .copied();
+        let thunk = if let Some(format) = format {
+            self.load_real_data_file(program, &full_path, format)
+        } else {
+            self.load_real_file(program, &full_path)
+        };
+        match thunk {
             None => Err(ImportError),
             Some(thunk) => Ok(thunk),
         }
@@ -456,12 +936,19 @@ impl<'p> rsjsonnet_lang::program::Callbacks<'p> for SessionInner<'p> {
         Ok(data)
     }
 
+    fn ext_var(&mut self, program: &mut Program<'p>, name: InternedStr<'p>) -> Option<Thunk<'p>> {
+        let resolver = self.ext_var_resolver.as_mut()?;
+        let value = resolver(program, name.value())?;
+        Some(program.value_to_thunk(&value))
+    }
+
     fn trace(
         &mut self,
         program: &mut Program<'p>,
         message: &str,
         stack: &[rsjsonnet_lang::program::EvalStackTraceItem],
     ) {
+        let _stderr = std::io::stderr().lock();
         self.print_rich_message(&[
             ("TRACE".into(), crate::print::TextPartKind::NoteLabel),
             (": ".into(), crate::print::TextPartKind::MainMessage),
@@ -481,9 +968,12 @@ impl<'p> rsjsonnet_lang::program::Callbacks<'p> for SessionInner<'p> {
         let native_func = &mut self.native_funcs.get_mut(&name).unwrap();
         match native_func(program, args) {
             Ok(v) => Ok(v),
-            Err(e) => {
+            Err(NativeFuncError::InvalidArg { arg_index, error }) => {
+                Err(NativeError::InvalidArg { arg_index, error })
+            }
+            Err(NativeFuncError::Failed(e)) => {
                 self.print_error(format_args!("native function {name:?} failed: {e}"));
-                Err(NativeError)
+                Err(NativeError::Failed)
             }
         }
     }
@@ -1,3 +1,26 @@
+//! Garbage-collected heap used for Jsonnet values.
+//!
+//! Objects are reference counted boxes owned by [`GcContext`]. A [`GcView`]
+//! is a strong reference that keeps an object alive and gives access to
+//! it, while a [`Gc`] is a weak reference used for edges between objects.
+//! Since there are no strong references between objects, dropping the
+//! objects that are not reachable also breaks any cycle among them.
+//!
+//! Roots are found without a root set: an object is a root if it has a
+//! [`GcView`], or if it has more [`Gc`]s than the references counted while
+//! tracing the heap (the rest are held outside the heap).
+//!
+//! Objects that survive a collection become old. [`GcContext::minor_gc`]
+//! only collects young objects, treating any reference from an old object
+//! as a root, so short-lived objects are reclaimed without tracing the
+//! whole heap. [`GcContext::gc`] collects the whole heap.
+//!
+//! Handles are `Rc` and `Weak` pointers, so each [`Gc::view`] costs a
+//! reference count update. Handles that index into a slab owned by the
+//! context would avoid that, but without `unsafe` objects could then only
+//! be accessed through the context, which the evaluator does not thread
+//! through to the places where it reads objects.
+
 use std::cell::{Cell, RefCell};
 use std::rc::{Rc, Weak};
 
@@ -68,6 +91,13 @@ impl<T: GcTrace> Gc<T> {
                 .expect("attempted to access destroyed object"),
         }
     }
+
+    /// Like [`Gc::view`], but returns `None` if the object has been
+    /// destroyed.
+    #[inline]
+    pub(super) fn try_view(&self) -> Option<GcView<T>> {
+        self.inner.upgrade().map(|inner| GcView { inner })
+    }
 }
 
 pub(crate) struct GcView<T: GcTrace> {
@@ -83,6 +113,14 @@ impl<T: GcTrace> Clone for GcView<T> {
     }
 }
 
+impl<T: GcTrace> GcView<T> {
+    /// Returns the epoch of the context when the object was allocated.
+    #[inline]
+    pub(crate) fn epoch(&self) -> u32 {
+        self.inner.epoch
+    }
+}
+
 impl<T: GcTrace> std::ops::Deref for GcView<T> {
     type Target = T;
 
@@ -95,11 +133,15 @@ impl<T: GcTrace> std::ops::Deref for GcView<T> {
 struct GcBox<T: ?Sized> {
     visits: Cell<usize>,
     mark: Cell<bool>,
+    /// Whether the object has survived a collection.
+    old: Cell<bool>,
+    /// See [`GcContext::new_epoch`].
+    epoch: u32,
     value: T,
 }
 
 trait GcTraceDyn {
-    fn trace_count(&self);
+    fn trace_count(&self, ctx: &mut GcCountCtx);
 
     fn trace_mark<'a>(&self, ctx: &mut GcMarkCtx<'a>)
     where
@@ -107,8 +149,8 @@ trait GcTraceDyn {
 }
 
 impl<T: GcTrace> GcTraceDyn for T {
-    fn trace_count(&self) {
-        self.trace(&mut GcCountCtx);
+    fn trace_count(&self, ctx: &mut GcCountCtx) {
+        self.trace(ctx);
     }
 
     fn trace_mark<'a>(&self, ctx: &mut GcMarkCtx<'a>)
@@ -124,14 +166,22 @@ pub(crate) struct GcContext<'a> {
 }
 
 struct GcContextInner<'a> {
+    /// Old objects come first, followed by the objects allocated after the
+    /// last collection.
     objs: Vec<Rc<GcBox<dyn GcTraceDyn + 'a>>>,
+    num_old: usize,
+    epoch: u32,
 }
 
 impl<'a> GcContext<'a> {
     #[inline]
     pub(crate) fn new() -> Self {
         Self {
-            inner: RefCell::new(GcContextInner { objs: Vec::new() }),
+            inner: RefCell::new(GcContextInner {
+                objs: Vec::new(),
+                num_old: 0,
+                epoch: 0,
+            }),
         }
     }
 
@@ -141,6 +191,8 @@ impl<'a> GcContext<'a> {
         let obj = Rc::new(GcBox {
             visits: Cell::new(0),
             mark: Cell::new(false),
+            old: Cell::new(false),
+            epoch: inner.epoch,
             value,
         });
         let weak = Rc::downgrade(&obj);
@@ -154,20 +206,53 @@ impl<'a> GcContext<'a> {
         let obj = Rc::new(GcBox {
             visits: Cell::new(0),
             mark: Cell::new(false),
+            old: Cell::new(false),
+            epoch: inner.epoch,
             value,
         });
         inner.objs.push(obj.clone());
         GcView { inner: obj }
     }
 
+    /// Starts a new epoch and returns it.
+    ///
+    /// Objects are tagged with the epoch in which they are allocated, which
+    /// allows to tell apart objects allocated before and after a given
+    /// point.
+    pub(crate) fn new_epoch(&self) -> u32 {
+        let mut inner = self.inner.borrow_mut();
+        inner.epoch = inner.epoch.checked_add(1).expect("too many epochs");
+        inner.epoch
+    }
+
+    /// Calls `f`, allocating objects in `epoch` instead of the current one.
+    pub(crate) fn with_epoch<R>(&self, epoch: u32, f: impl FnOnce() -> R) -> R {
+        let prev_epoch = std::mem::replace(&mut self.inner.borrow_mut().epoch, epoch);
+        let r = f();
+        self.inner.borrow_mut().epoch = prev_epoch;
+        r
+    }
+
     #[inline]
     pub(crate) fn num_objects(&self) -> usize {
         self.inner.borrow().objs.len()
     }
 
+    /// Returns the number of objects allocated after the last collection.
+    #[inline]
+    pub(crate) fn num_young_objects(&self) -> usize {
+        let inner = self.inner.borrow();
+        inner.objs.len() - inner.num_old
+    }
+
+    /// Collects the whole heap.
     pub(crate) fn gc(&self) {
         let mut inner = self.inner.borrow_mut();
-        let mut mark_ctx = GcMarkCtx { queue: Vec::new() };
+        let mut count_ctx = GcCountCtx { minor: false };
+        let mut mark_ctx = GcMarkCtx {
+            queue: Vec::new(),
+            minor: false,
+        };
 
         // Count (to identify roots)
         let mut known_with_view = 0;
@@ -196,7 +281,7 @@ impl<'a> GcContext<'a> {
                 inner.objs.swap_remove(i);
             } else if !obj.mark.get() {
                 // There is at least one `Gc`, count
-                obj.value.trace_count();
+                obj.value.trace_count(&mut count_ctx);
                 i += 1;
             } else {
                 // There is at least one `Gc`, but it is already marked
@@ -223,34 +308,101 @@ impl<'a> GcContext<'a> {
             if obj.mark.get() {
                 obj.visits.set(0);
                 obj.mark.set(false);
+                obj.old.set(true);
+                i += 1;
+            } else {
+                inner.objs.swap_remove(i);
+            }
+        }
+        inner.num_old = inner.objs.len();
+    }
+
+    /// Collects only the objects allocated after the last collection.
+    ///
+    /// Old objects are assumed to be alive, so references from them keep
+    /// young objects alive. Since young objects are only traced to count
+    /// references among themselves, old objects do not need to be traced.
+    pub(crate) fn minor_gc(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let mut count_ctx = GcCountCtx { minor: true };
+        let mut mark_ctx = GcMarkCtx {
+            queue: Vec::new(),
+            minor: true,
+        };
+
+        // Count
+        let mut i = inner.num_old;
+        while i < inner.objs.len() {
+            let obj = &inner.objs[i];
+            if Rc::strong_count(obj) == 1 && Rc::weak_count(obj) == 0 {
+                // There is not any `Gc` or `GcView`, destroy directly.
+                inner.objs.swap_remove(i);
+            } else {
+                obj.value.trace_count(&mut count_ctx);
+                i += 1;
+            }
+        }
+
+        // Mark from objects referenced by a `GcView`, an old object or from
+        // outside the heap.
+        for obj in inner.objs[inner.num_old..].iter() {
+            if !obj.mark.get()
+                && (Rc::strong_count(obj) > 1 || Rc::weak_count(obj) > obj.visits.get())
+            {
+                obj.mark.set(true);
+                obj.value.trace_mark(&mut mark_ctx);
+                while let Some(sub_obj) = mark_ctx.queue.pop() {
+                    debug_assert!(sub_obj.mark.get());
+                    sub_obj.value.trace_mark(&mut mark_ctx);
+                }
+            }
+        }
+
+        // Sweep and promote
+        let mut i = inner.num_old;
+        while i < inner.objs.len() {
+            let obj = &inner.objs[i];
+            if obj.mark.get() {
+                obj.visits.set(0);
+                obj.mark.set(false);
+                obj.old.set(true);
                 i += 1;
             } else {
                 inner.objs.swap_remove(i);
             }
         }
+        inner.num_old = inner.objs.len();
     }
 }
 
-struct GcCountCtx;
+struct GcCountCtx {
+    /// Whether only young objects are being collected.
+    minor: bool,
+}
 
 impl<'a> GcTraceCtx<'a> for GcCountCtx {
     #[inline]
     fn visit_obj<T: GcTrace + 'a>(&mut self, obj: &Gc<T>) {
         if let Some(inner) = obj.inner.upgrade() {
-            inner.visits.set(inner.visits.get() + 1);
+            if !self.minor || !inner.old.get() {
+                inner.visits.set(inner.visits.get() + 1);
+            }
         }
     }
 }
 
 struct GcMarkCtx<'a> {
     queue: Vec<Rc<GcBox<dyn GcTraceDyn + 'a>>>,
+    /// Whether only young objects are being collected.
+    minor: bool,
 }
 
 impl<'a> GcTraceCtx<'a> for GcMarkCtx<'a> {
     #[inline]
     fn visit_obj<T: GcTrace + 'a>(&mut self, obj: &Gc<T>) {
         if let Some(inner) = obj.inner.upgrade() {
-            if !inner.mark.get() {
+            if !inner.mark.get() && (!self.minor || !inner.old.get()) {
                 inner.mark.set(true);
                 self.queue.push(inner);
             }
@@ -81,6 +81,7 @@ enum TestAction {
     CheckObj { id: u32 },
     CheckSubObj { id: u32, index: usize, sub_id: u32 },
     Gc { dead: usize, maybe: usize },
+    MinorGc { dead: usize, maybe: usize },
 }
 
 enum TestObjRef<'a> {
@@ -154,6 +155,11 @@ fn test(actions: &[TestAction]) {
                 num_dead -= dead;
                 num_extra_maybe -= maybe;
             }
+            TestAction::MinorGc { dead, maybe } => {
+                ctx.minor_gc();
+                num_dead -= dead;
+                num_extra_maybe -= maybe;
+            }
         }
 
         let total = objs.len() + num_dead + num_extra_maybe;
@@ -555,6 +561,113 @@ fn test_cyclic_two() {
     ]);
 }
 
+#[test]
+fn test_minor() {
+    // Young cycle
+    test(&[
+        TestAction::Create { id: 1, view: false },
+        TestAction::Create { id: 2, view: false },
+        TestAction::Create { id: 3, view: false },
+        TestAction::PushSub {
+            dst_id: 1,
+            src_id: 2,
+        },
+        TestAction::PushSub {
+            dst_id: 2,
+            src_id: 1,
+        },
+        TestAction::Remove { id: 1, dead: false },
+        TestAction::Remove { id: 2, dead: false },
+        TestAction::Remove { id: 3, dead: true },
+        TestAction::MinorGc { dead: 1, maybe: 2 },
+    ]);
+
+    // Young object referenced by an old object
+    test(&[
+        TestAction::Create { id: 1, view: false },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::Create { id: 2, view: false },
+        TestAction::PushSub {
+            dst_id: 1,
+            src_id: 2,
+        },
+        TestAction::Remove { id: 2, dead: false },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::CheckSubObj {
+            id: 1,
+            index: 0,
+            sub_id: 2,
+        },
+        TestAction::Remove { id: 1, dead: true },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::Gc { dead: 1, maybe: 1 },
+    ]);
+
+    // Young object referenced by an old object with view
+    test(&[
+        TestAction::Create { id: 1, view: true },
+        TestAction::Gc { dead: 0, maybe: 0 },
+        TestAction::Create { id: 2, view: false },
+        TestAction::Create { id: 3, view: false },
+        TestAction::PushSub {
+            dst_id: 1,
+            src_id: 2,
+        },
+        TestAction::PushSub {
+            dst_id: 2,
+            src_id: 3,
+        },
+        TestAction::Remove { id: 2, dead: false },
+        TestAction::Remove { id: 3, dead: false },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::CheckSubObj {
+            id: 1,
+            index: 0,
+            sub_id: 2,
+        },
+    ]);
+
+    // Young object that references an old object
+    test(&[
+        TestAction::Create { id: 1, view: false },
+        TestAction::Gc { dead: 0, maybe: 0 },
+        TestAction::Create { id: 2, view: true },
+        TestAction::PushSub {
+            dst_id: 2,
+            src_id: 1,
+        },
+        TestAction::Remove { id: 1, dead: false },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::CheckSubObj {
+            id: 2,
+            index: 0,
+            sub_id: 1,
+        },
+        TestAction::Remove { id: 2, dead: true },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::Gc { dead: 1, maybe: 1 },
+    ]);
+
+    // Old cycle is only collected by a full collection
+    test(&[
+        TestAction::Create { id: 1, view: false },
+        TestAction::Create { id: 2, view: false },
+        TestAction::PushSub {
+            dst_id: 1,
+            src_id: 2,
+        },
+        TestAction::PushSub {
+            dst_id: 2,
+            src_id: 1,
+        },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::Remove { id: 1, dead: false },
+        TestAction::Remove { id: 2, dead: false },
+        TestAction::MinorGc { dead: 0, maybe: 0 },
+        TestAction::Gc { dead: 0, maybe: 2 },
+    ]);
+}
+
 #[test]
 fn test_doubly_linked_big() {
     const NUM_SETS: usize = 4;
@@ -3,27 +3,45 @@ use std::collections::hash_map::Entry as HashMapEntry;
 use super::{AnalyzeError, Program, ir};
 use crate::interner::InternedStr;
 use crate::span::SpanId;
-use crate::{FHashMap, FHashSet, ast};
+use crate::{FHashMap, ast};
 
 pub(super) struct Analyzer<'a, 'p> {
     program: &'a Program<'p>,
+    optimize: bool,
+    std_name: InternedStr<'p>,
+    /// Number of references to each variable name analyzed so far, used
+    /// to find unused `local` bindings when optimizing.
+    var_uses: FHashMap<InternedStr<'p>, usize>,
 }
 
 impl<'a, 'p> Analyzer<'a, 'p> {
     pub(super) fn new(program: &'a Program<'p>) -> Self {
-        Self { program }
+        Self {
+            program,
+            optimize: program.optimize,
+            std_name: program.intern_str("std"),
+            var_uses: FHashMap::default(),
+        }
     }
 
     pub(super) fn analyze(
         mut self,
         ast: &ast::Expr<'p, '_>,
-        env: FHashSet<InternedStr<'p>>,
+        env: &[InternedStr<'p>],
+        std_is_stdlib: bool,
     ) -> Result<&'p ir::Expr<'p>, AnalyzeError> {
-        let env = Env {
+        let mut top_env = Env {
             is_obj: false,
-            vars: env,
+            vars: FHashMap::default(),
+            depth: 0,
+            scope_len: 0,
+            std_is_stdlib: true,
         };
-        self.analyze_expr(ast, &env, false)
+        for &name in env.iter() {
+            self.bind_var(&mut top_env, name);
+        }
+        top_env.std_is_stdlib = std_is_stdlib;
+        self.analyze_expr(ast, &top_env, false)
     }
 
     fn analyze_expr<'ast>(
@@ -50,7 +68,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
         loop {
             match state {
                 State::Analyzed(expr_ir) => match stack.pop() {
-                    None => return Ok(expr_ir),
+                    None => return Ok(self.program.compile_expr(expr_ir)),
                     Some(StackItem::ArrayItem(span, mut items_ir, rem_items_ast)) => {
                         items_ir.push(expr_ir);
 
@@ -70,19 +88,24 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                     }
                     Some(StackItem::BinaryRhs(span, op, lhs)) => {
                         let rhs = expr_ir;
-                        state = State::Analyzed(self.program.arena.alloc(ir::Expr::Binary {
-                            op,
-                            lhs,
-                            rhs,
-                            span,
+                        let folded = self
+                            .optimize
+                            .then(|| self.program.fold_binary_op(op, lhs, rhs, span))
+                            .flatten();
+                        state = State::Analyzed(folded.unwrap_or_else(|| {
+                            self.program
+                                .arena
+                                .alloc(ir::Expr::Binary { op, lhs, rhs, span })
                         }));
                     }
                     Some(StackItem::UnaryRhs(span, op)) => {
                         let rhs = expr_ir;
-                        state = State::Analyzed(self.program.arena.alloc(ir::Expr::Unary {
-                            op,
-                            rhs,
-                            span,
+                        let folded = self
+                            .optimize
+                            .then(|| self.program.fold_unary_op(op, rhs, span))
+                            .flatten();
+                        state = State::Analyzed(folded.unwrap_or_else(|| {
+                            self.program.arena.alloc(ir::Expr::Unary { op, rhs, span })
                         }));
                     }
                 },
@@ -166,11 +189,23 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                     }
                     ast::ExprKind::Field(obj_ast, field_name_ast) => {
                         let object = self.analyze_expr(obj_ast, env, false)?;
-                        state = State::Analyzed(self.program.arena.alloc(ir::Expr::Field {
-                            object,
-                            field_name: field_name_ast.value,
-                            expr_span: expr_ast.span,
-                        }));
+                        let field_name = field_name_ast.value;
+                        if self.optimize
+                            && env.std_is_stdlib
+                            && matches!(*object, ir::Expr::Var { name, .. } if name == self.std_name)
+                            && self.program.is_unmodified_std_builtin(field_name)
+                        {
+                            state = State::Analyzed(self.program.arena.alloc(ir::Expr::StdField {
+                                field_name,
+                                expr_span: expr_ast.span,
+                            }));
+                        } else {
+                            state = State::Analyzed(self.program.arena.alloc(ir::Expr::Field {
+                                object,
+                                field_name,
+                                expr_span: expr_ast.span,
+                            }));
+                        }
                     }
                     ast::ExprKind::Index(obj_ast, index_ast) => {
                         let object = self.analyze_expr(obj_ast, env, false)?;
@@ -251,6 +286,19 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                             }
                         }
 
+                        if self.optimize && named_args.is_empty() {
+                            if let (ir::Expr::StdField { field_name, .. }, &[arg]) =
+                                (*callee, positional_args.as_slice())
+                            {
+                                if let Some(folded) =
+                                    self.program.fold_std_call(field_name, arg, expr_ast.span)
+                                {
+                                    state = State::Analyzed(folded);
+                                    continue;
+                                }
+                            }
+                        }
+
                         state = State::Analyzed(self.program.arena.alloc(ir::Expr::Call {
                             callee,
                             positional_args: self.program.arena.alloc_slice(&positional_args),
@@ -260,12 +308,18 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                         }));
                     }
                     ast::ExprKind::Ident(ref name) => {
-                        if env.vars.contains(&name.value) {
-                            state = State::Analyzed(
-                                self.program
-                                    .arena
-                                    .alloc(ir::Expr::Var(name.value, expr_ast.span)),
-                            );
+                        if let Some(&(depth, index)) = env.vars.get(&name.value) {
+                            if self.optimize {
+                                *self.var_uses.entry(name.value).or_default() += 1;
+                            }
+                            state = State::Analyzed(self.program.arena.alloc(ir::Expr::Var {
+                                name: name.value,
+                                slot: ir::VarSlot {
+                                    depth: env.depth - depth,
+                                    index,
+                                },
+                                span: expr_ast.span,
+                            }));
                         } else {
                             return Err(AnalyzeError::UnknownVariable {
                                 span: name.span,
@@ -274,7 +328,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                         }
                     }
                     ast::ExprKind::Local(binds_ast, inner_ast) => {
-                        let mut inner_env = env.clone();
+                        let mut inner_env = env.new_scope();
 
                         let mut locals_spans = FHashMap::<InternedStr<'p>, SpanId>::default();
                         for bind_ast in binds_ast.iter() {
@@ -289,11 +343,20 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                                 }
                                 HashMapEntry::Vacant(entry) => {
                                     entry.insert(bind_ast.name.span);
-                                    inner_env.vars.insert(name);
+                                    self.bind_var(&mut inner_env, name);
                                 }
                             }
                         }
 
+                        let uses_before: Vec<_> = if self.optimize {
+                            binds_ast
+                                .iter()
+                                .map(|bind_ast| self.var_uses(bind_ast.name.value))
+                                .collect()
+                        } else {
+                            Vec::new()
+                        };
+
                         let mut bindings = Vec::new();
                         for bind_ast in binds_ast.iter() {
                             let name = bind_ast.name.value;
@@ -302,11 +365,22 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                             } else {
                                 self.analyze_expr(&bind_ast.value, &inner_env, false)?
                             };
-                            bindings.push((name, value));
+                            bindings.push((name, Some(value)));
                         }
 
                         let inner = self.analyze_expr(inner_ast, &inner_env, can_be_tailstrict)?;
 
+                        if self.optimize {
+                            // Bindings are evaluated lazily, so a binding that
+                            // is not referenced can be dropped.
+                            for ((name, value), uses_before) in bindings.iter_mut().zip(uses_before)
+                            {
+                                if uses_before == self.var_uses(*name) {
+                                    *value = None;
+                                }
+                            }
+                        }
+
                         state = State::Analyzed(self.program.arena.alloc(ir::Expr::Local {
                             bindings: self.program.arena.alloc_slice(&bindings),
                             inner,
@@ -319,11 +393,17 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                             .map(|e| self.analyze_expr(e, env, can_be_tailstrict))
                             .transpose()?;
 
-                        state = State::Analyzed(self.program.arena.alloc(ir::Expr::If {
-                            cond,
-                            cond_span: cond_ast.span,
-                            then_body,
-                            else_body,
+                        let folded = self
+                            .optimize
+                            .then(|| self.program.fold_if(cond, then_body, else_body))
+                            .flatten();
+                        state = State::Analyzed(folded.unwrap_or_else(|| {
+                            self.program.arena.alloc(ir::Expr::If {
+                                cond,
+                                cond_span: cond_ast.span,
+                                then_body,
+                                else_body,
+                            })
                         }));
                     }
                     ast::ExprKind::Binary(lhs_ast, op, rhs_ast) => {
@@ -443,7 +523,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
     ) -> Result<&'p ir::Expr<'p>, AnalyzeError> {
         match *obj_inside_ast {
             ast::ObjInside::Members(members_ast) => {
-                let mut inner_env = env.clone();
+                let mut inner_env = env.new_scope();
                 inner_env.is_obj = true;
 
                 let mut locals_spans = FHashMap::<InternedStr<'p>, SpanId>::default();
@@ -460,7 +540,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                             }
                             HashMapEntry::Vacant(entry) => {
                                 entry.insert(local_ast.bind.name.span);
-                                inner_env.vars.insert(name);
+                                self.bind_var(&mut inner_env, name);
                             }
                         }
                     }
@@ -542,11 +622,19 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                     }
                 }
 
+                let fields_order = if self.optimize {
+                    ir::fixed_fields_order(&fields)
+                        .map(|order| self.program.arena.alloc_slice(&order))
+                } else {
+                    None
+                };
+
                 Ok(self.program.arena.alloc(ir::Expr::Object {
                     is_top: !env.is_obj,
                     locals: self.program.arena.alloc_slice(&locals),
                     asserts: self.program.arena.alloc_slice(&asserts),
                     fields: self.program.arena.alloc_slice(&fields),
+                    fields_order,
                 }))
             }
             ast::ObjInside::Comp {
@@ -558,7 +646,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                 comp_spec: comp_spec_ast,
             } => {
                 let (comp_spec, env) = self.analyze_comp_spec(comp_spec_ast, env)?;
-                let mut inner_env = env.clone();
+                let mut inner_env = env.new_scope();
                 inner_env.is_obj = true;
 
                 let mut locals_spans = FHashMap::default();
@@ -574,7 +662,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                         }
                         HashMapEntry::Vacant(entry) => {
                             entry.insert(local_ast.bind.name.span);
-                            inner_env.vars.insert(name);
+                            self.bind_var(&mut inner_env, name);
                         }
                     }
                 }
@@ -612,7 +700,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
         body_ast: &ast::Expr<'p, '_>,
         env: &Env<'p>,
     ) -> Result<&'p ir::Expr<'p>, AnalyzeError> {
-        let mut inner_env = env.clone();
+        let mut inner_env = env.new_scope();
 
         let mut params_spans = FHashMap::default();
         for param_ast in params_ast.iter() {
@@ -627,7 +715,7 @@ impl<'a, 'p> Analyzer<'a, 'p> {
                 }
                 HashMapEntry::Vacant(entry) => {
                     entry.insert(param_ast.name.span);
-                    inner_env.vars.insert(name);
+                    self.bind_var(&mut inner_env, name);
                 }
             }
         }
@@ -670,20 +758,34 @@ impl<'a, 'p> Analyzer<'a, 'p> {
         })
     }
 
+    fn bind_var(&self, env: &mut Env<'p>, name: InternedStr<'p>) {
+        if name == self.std_name {
+            env.std_is_stdlib = false;
+        }
+        env.vars.insert(name, (env.depth, env.scope_len));
+        env.scope_len += 1;
+    }
+
+    fn var_uses(&self, name: InternedStr<'p>) -> usize {
+        self.var_uses.get(&name).copied().unwrap_or(0)
+    }
+
     fn analyze_comp_spec(
         &mut self,
         comp_spec_ast: &[ast::CompSpecPart<'p, '_>],
         env: &Env<'p>,
     ) -> Result<(&'p [ir::CompSpecPart<'p>], Env<'p>), AnalyzeError> {
         let mut parts = Vec::new();
-        let mut current_env = env.clone();
+        // The value of the first `for` is evaluated in the outer environment,
+        // the rest of the comprehension in a single new environment.
+        let mut current_env = env.new_scope();
         for part_ast in comp_spec_ast.iter() {
             match part_ast {
                 ast::CompSpecPart::For(for_spec_ast) => {
-                    let inner = self.analyze_expr(&for_spec_ast.inner, &current_env, false)?;
-                    current_env.vars.insert(for_spec_ast.var.value);
+                    let value_env = if parts.is_empty() { env } else { &current_env };
+                    let inner = self.analyze_expr(&for_spec_ast.inner, value_env, false)?;
+                    self.bind_var(&mut current_env, for_spec_ast.var.value);
                     parts.push(ir::CompSpecPart::For {
-                        var: for_spec_ast.var.value,
                         value: inner,
                         value_span: for_spec_ast.inner.span,
                     });
@@ -705,5 +807,25 @@ impl<'a, 'p> Analyzer<'a, 'p> {
 #[derive(Clone)]
 pub(super) struct Env<'p> {
     pub(super) is_obj: bool,
-    pub(super) vars: FHashSet<InternedStr<'p>>,
+    /// Variables in scope, with the depth of the environment that holds
+    /// them and their index in it.
+    pub(super) vars: FHashMap<InternedStr<'p>, (u32, u32)>,
+    /// Number of environments above the top-level one.
+    pub(super) depth: u32,
+    /// Number of variables in the innermost environment.
+    pub(super) scope_len: u32,
+    /// Whether `std` refers to the standard library object.
+    pub(super) std_is_stdlib: bool,
+}
+
+impl<'p> Env<'p> {
+    fn new_scope(&self) -> Self {
+        Self {
+            is_obj: self.is_obj,
+            vars: self.vars.clone(),
+            depth: self.depth + 1,
+            scope_len: 0,
+            std_is_stdlib: self.std_is_stdlib,
+        }
+    }
 }
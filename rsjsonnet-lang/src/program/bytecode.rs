@@ -0,0 +1,251 @@
+//! Compilation of expressions to bytecode.
+//!
+//! A tree of operators, conditionals, field accesses, indexing and calls is
+//! flattened into a sequence of [`ir::Op`]s, which the evaluator runs in a
+//! loop instead of pushing a state for each node. Literals, variables and
+//! `std` fields become instructions too. Any other expression, such as an
+//! object, is kept as an [`ir::Op::Expr`] and evaluated as a tree. Call
+//! arguments are not part of the code, since they are evaluated lazily.
+//!
+//! The code pushes the same stack trace items as the tree would, and an
+//! expression at the end of the code is still in tail position, so errors,
+//! stack traces and frame reuse of `tailstrict` calls do not change.
+
+use super::{Program, ir};
+use crate::ast;
+use crate::span::SpanId;
+
+impl<'p> Program<'p> {
+    /// Returns `expr` compiled to bytecode if it is a `Binary`, `Unary`,
+    /// `If`, `Field`, `Index` or `Call`, or `expr` itself otherwise.
+    ///
+    /// Operands that are already compiled are copied into the new code,
+    /// except for the branches of an `if` in tail position, which are
+    /// evaluated on their own to keep chains of `else if` linear in size.
+    pub(super) fn compile_expr(&self, expr: &'p ir::Expr<'p>) -> &'p ir::Expr<'p> {
+        if !matches!(
+            *expr,
+            ir::Expr::Binary { .. }
+                | ir::Expr::Unary { .. }
+                | ir::Expr::If { .. }
+                | ir::Expr::Field { .. }
+                | ir::Expr::Index { .. }
+                | ir::Expr::Call { .. },
+        ) {
+            return expr;
+        }
+
+        let code = compile(expr);
+        self.arena.alloc(ir::Expr::Compiled {
+            code: self.arena.alloc_slice(&code),
+            expr,
+        })
+    }
+}
+
+enum Item<'p> {
+    Expr { expr: &'p ir::Expr<'p>, tail: bool },
+    Op(ir::Op<'p>),
+    ShortCircuit(bool),
+    JumpIfFalse(SpanId),
+    Else,
+    PatchJump,
+}
+
+fn compile<'p>(root: &'p ir::Expr<'p>) -> Vec<ir::Op<'p>> {
+    let mut code = Vec::new();
+    // Indices of jumps whose target is not known yet.
+    let mut pending_jumps = Vec::new();
+    let mut stack = vec![Item::Expr {
+        expr: root,
+        tail: true,
+    }];
+
+    while let Some(item) = stack.pop() {
+        match item {
+            Item::Expr { expr, tail } => match *expr {
+                ir::Expr::Null => code.push(ir::Op::Null),
+                ir::Expr::Bool(value) => code.push(ir::Op::Bool(value)),
+                ir::Expr::Number(value, span) => code.push(ir::Op::Number(value, span)),
+                ir::Expr::String(s) => code.push(ir::Op::String(s)),
+                ir::Expr::Var { name, slot, span } => {
+                    code.push(ir::Op::Var { name, slot, span });
+                }
+                ir::Expr::StdField {
+                    field_name,
+                    expr_span,
+                } => {
+                    code.push(ir::Op::StdField {
+                        field_name,
+                        span: expr_span,
+                    });
+                }
+                ir::Expr::Field {
+                    object,
+                    field_name,
+                    expr_span,
+                } => {
+                    stack.push(Item::Op(ir::Op::Field {
+                        field_name,
+                        span: expr_span,
+                    }));
+                    stack.push(Item::Expr {
+                        expr: object,
+                        tail: false,
+                    });
+                }
+                ir::Expr::Index {
+                    object,
+                    index,
+                    expr_span,
+                } => {
+                    stack.push(Item::Op(ir::Op::Index { span: expr_span }));
+                    stack.push(Item::Expr {
+                        expr: index,
+                        tail: false,
+                    });
+                    stack.push(Item::Expr {
+                        expr: object,
+                        tail: false,
+                    });
+                }
+                ir::Expr::Call { callee, .. } => {
+                    stack.push(Item::Op(ir::Op::Call(expr)));
+                    stack.push(Item::Expr {
+                        expr: callee,
+                        tail: false,
+                    });
+                }
+                ir::Expr::Binary { op, lhs, rhs, span } => {
+                    let lhs = Item::Expr {
+                        expr: lhs,
+                        tail: false,
+                    };
+                    let rhs = Item::Expr {
+                        expr: rhs,
+                        tail: false,
+                    };
+                    // Items are pushed in reverse order.
+                    match op {
+                        ast::BinaryOp::Lt
+                        | ast::BinaryOp::Le
+                        | ast::BinaryOp::Gt
+                        | ast::BinaryOp::Ge => {
+                            stack.push(Item::Op(ir::Op::PopTrace));
+                            stack.push(Item::Op(ir::Op::Compare(op)));
+                            stack.push(rhs);
+                            stack.push(lhs);
+                            stack.push(Item::Op(ir::Op::PushTrace(span)));
+                        }
+                        ast::BinaryOp::Eq | ast::BinaryOp::Ne => {
+                            stack.push(Item::Op(ir::Op::PopTrace));
+                            stack.push(Item::Op(ir::Op::Equals {
+                                negate: op == ast::BinaryOp::Ne,
+                            }));
+                            stack.push(rhs);
+                            stack.push(lhs);
+                            stack.push(Item::Op(ir::Op::PushTrace(span)));
+                        }
+                        ast::BinaryOp::LogicAnd | ast::BinaryOp::LogicOr => {
+                            stack.push(Item::PatchJump);
+                            stack.push(Item::Op(ir::Op::Binary { op, span }));
+                            stack.push(rhs);
+                            stack.push(Item::ShortCircuit(op == ast::BinaryOp::LogicOr));
+                            stack.push(lhs);
+                        }
+                        _ => {
+                            stack.push(Item::Op(ir::Op::Binary { op, span }));
+                            stack.push(rhs);
+                            stack.push(lhs);
+                        }
+                    }
+                }
+                ir::Expr::Unary { op, rhs, span } => {
+                    stack.push(Item::Op(ir::Op::Unary { op, span }));
+                    stack.push(Item::Expr {
+                        expr: rhs,
+                        tail: false,
+                    });
+                }
+                ir::Expr::If {
+                    cond,
+                    cond_span,
+                    then_body,
+                    else_body,
+                } => {
+                    stack.push(Item::PatchJump);
+                    stack.push(Item::Expr {
+                        expr: else_body.unwrap_or(&ir::Expr::Null),
+                        tail,
+                    });
+                    stack.push(Item::Else);
+                    stack.push(Item::Expr {
+                        expr: then_body,
+                        tail,
+                    });
+                    stack.push(Item::JumpIfFalse(cond_span));
+                    stack.push(Item::Expr {
+                        expr: cond,
+                        tail: false,
+                    });
+                }
+                ir::Expr::Compiled { code: sub_code, .. } if !tail => {
+                    let offset = code_len(&code);
+                    code.extend(sub_code.iter().map(|&op| match op {
+                        ir::Op::ShortCircuit { value, target } => ir::Op::ShortCircuit {
+                            value,
+                            target: target + offset,
+                        },
+                        ir::Op::JumpIfFalse { cond_span, target } => ir::Op::JumpIfFalse {
+                            cond_span,
+                            target: target + offset,
+                        },
+                        ir::Op::Jump(target) => ir::Op::Jump(target + offset),
+                        op => op,
+                    }));
+                }
+                _ => code.push(ir::Op::Expr(expr)),
+            },
+            Item::Op(op) => code.push(op),
+            Item::ShortCircuit(value) => {
+                pending_jumps.push(code.len());
+                code.push(ir::Op::ShortCircuit { value, target: 0 });
+            }
+            Item::JumpIfFalse(cond_span) => {
+                pending_jumps.push(code.len());
+                code.push(ir::Op::JumpIfFalse {
+                    cond_span,
+                    target: 0,
+                });
+            }
+            Item::Else => {
+                let if_false = pending_jumps.pop().unwrap();
+                pending_jumps.push(code.len());
+                code.push(ir::Op::Jump(0));
+                patch_jump(&mut code, if_false);
+            }
+            Item::PatchJump => {
+                let jump = pending_jumps.pop().unwrap();
+                patch_jump(&mut code, jump);
+            }
+        }
+    }
+
+    assert!(pending_jumps.is_empty());
+    code
+}
+
+fn code_len(code: &[ir::Op<'_>]) -> u32 {
+    u32::try_from(code.len()).expect("bytecode too long")
+}
+
+/// Makes the jump at `index` target the end of `code`.
+fn patch_jump(code: &mut [ir::Op<'_>], index: usize) {
+    let end = code_len(code);
+    match code[index] {
+        ir::Op::ShortCircuit { ref mut target, .. }
+        | ir::Op::JumpIfFalse { ref mut target, .. }
+        | ir::Op::Jump(ref mut target) => *target = end,
+        _ => unreachable!(),
+    }
+}
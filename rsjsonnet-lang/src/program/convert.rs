@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::BuildHasher;
+
+use super::{EvalErrorValueType, Program, Value, ValueData};
+
+/// Error returned by [`FromJsonnet::from_jsonnet`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FromJsonnetError {
+    /// The value does not have any of the expected types.
+    InvalidType {
+        expected_types: Vec<EvalErrorValueType>,
+        got_type: EvalErrorValueType,
+    },
+    /// The value has an expected type, but it cannot be converted.
+    InvalidValue { message: String },
+}
+
+impl FromJsonnetError {
+    fn invalid_type(expected_types: &[EvalErrorValueType], value: &Value<'_>) -> Self {
+        Self::InvalidType {
+            expected_types: expected_types.to_vec(),
+            got_type: EvalErrorValueType::from_value(&value.inner),
+        }
+    }
+
+    /// Wraps an error that happened while converting an inner value
+    /// (e.g., an array item) into [`FromJsonnetError::InvalidValue`].
+    fn nested(self, what: fmt::Arguments<'_>) -> Self {
+        Self::InvalidValue {
+            message: format!("{what}: {self}"),
+        }
+    }
+}
+
+impl fmt::Display for FromJsonnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidType {
+                expected_types,
+                got_type,
+            } => {
+                f.write_str("expected ")?;
+                for (i, expected_type) in expected_types.iter().enumerate() {
+                    if i != 0 {
+                        if i == expected_types.len() - 1 {
+                            f.write_str(" or ")?;
+                        } else {
+                            f.write_str(", ")?;
+                        }
+                    }
+                    f.write_str(expected_type.to_str())?;
+                }
+                write!(f, ", got {}", got_type.to_str())
+            }
+            Self::InvalidValue { message } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for FromJsonnetError {}
+
+/// Conversion from an evaluated Jsonnet [`Value`].
+///
+/// Used, for example, to convert the arguments of native functions.
+pub trait FromJsonnet<'p>: Sized {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError>;
+}
+
+/// Conversion into a Jsonnet [`Value`].
+///
+/// Used, for example, to convert the return value of native functions.
+pub trait IntoJsonnet<'p> {
+    fn into_jsonnet(self, program: &mut Program<'p>) -> Value<'p>;
+}
+
+impl<'p> FromJsonnet<'p> for Value<'p> {
+    #[inline]
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        Ok(value.clone())
+    }
+}
+
+impl<'p> IntoJsonnet<'p> for Value<'p> {
+    #[inline]
+    fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+        self
+    }
+}
+
+impl<'p> FromJsonnet<'p> for bool {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        value
+            .as_bool()
+            .ok_or_else(|| FromJsonnetError::invalid_type(&[EvalErrorValueType::Bool], value))
+    }
+}
+
+impl<'p> IntoJsonnet<'p> for bool {
+    #[inline]
+    fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+        Value::bool(self)
+    }
+}
+
+impl<'p> FromJsonnet<'p> for f64 {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        value
+            .as_number()
+            .ok_or_else(|| FromJsonnetError::invalid_type(&[EvalErrorValueType::Number], value))
+    }
+}
+
+impl<'p> IntoJsonnet<'p> for f64 {
+    #[inline]
+    fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+        Value::number(self)
+    }
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl<'p> FromJsonnet<'p> for $t {
+                fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+                    let number = f64::from_jsonnet(value)?;
+                    // The upper bound is exclusive because, for 64-bit
+                    // types, `MAX as f64` is rounded up.
+                    if number.trunc() == number
+                        && number >= <$t>::MIN as f64
+                        && number < <$t>::MAX as f64 + 1.0
+                    {
+                        Ok(number as $t)
+                    } else {
+                        Err(FromJsonnetError::InvalidValue {
+                            message: format!(
+                                "expected an integer between {} and {}, got {}",
+                                <$t>::MIN,
+                                <$t>::MAX,
+                                number,
+                            ),
+                        })
+                    }
+                }
+            }
+
+            impl<'p> IntoJsonnet<'p> for $t {
+                #[inline]
+                fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+                    Value::number(self as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<'p> FromJsonnet<'p> for String {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        value
+            .to_string()
+            .ok_or_else(|| FromJsonnetError::invalid_type(&[EvalErrorValueType::String], value))
+    }
+}
+
+impl<'p> IntoJsonnet<'p> for String {
+    #[inline]
+    fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+        Value::from_value(ValueData::String(self.into()))
+    }
+}
+
+impl<'p> IntoJsonnet<'p> for &str {
+    #[inline]
+    fn into_jsonnet(self, _program: &mut Program<'p>) -> Value<'p> {
+        Value::string(self)
+    }
+}
+
+impl<'p, T: FromJsonnet<'p>> FromJsonnet<'p> for Option<T> {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            match T::from_jsonnet(value) {
+                Ok(value) => Ok(Some(value)),
+                Err(FromJsonnetError::InvalidType {
+                    mut expected_types,
+                    got_type,
+                }) => {
+                    expected_types.push(EvalErrorValueType::Null);
+                    Err(FromJsonnetError::InvalidType {
+                        expected_types,
+                        got_type,
+                    })
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+impl<'p, T: IntoJsonnet<'p>> IntoJsonnet<'p> for Option<T> {
+    fn into_jsonnet(self, program: &mut Program<'p>) -> Value<'p> {
+        match self {
+            Some(value) => value.into_jsonnet(program),
+            None => Value::null(),
+        }
+    }
+}
+
+impl<'p, T: FromJsonnet<'p>> FromJsonnet<'p> for Vec<T> {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        let Some(items) = value.to_array() else {
+            return Err(FromJsonnetError::invalid_type(
+                &[EvalErrorValueType::Array],
+                value,
+            ));
+        };
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                T::from_jsonnet(item).map_err(|e| e.nested(format_args!("array item {i}")))
+            })
+            .collect()
+    }
+}
+
+impl<'p, T: IntoJsonnet<'p>> IntoJsonnet<'p> for Vec<T> {
+    fn into_jsonnet(self, program: &mut Program<'p>) -> Value<'p> {
+        let items: Vec<_> = self
+            .into_iter()
+            .map(|item| item.into_jsonnet(program))
+            .collect();
+        program.make_array(&items)
+    }
+}
+
+fn object_from_jsonnet<'p, T: FromJsonnet<'p>>(
+    value: &Value<'p>,
+) -> Result<impl Iterator<Item = Result<(String, T), FromJsonnetError>>, FromJsonnetError> {
+    let Some(fields) = value.to_object() else {
+        return Err(FromJsonnetError::invalid_type(
+            &[EvalErrorValueType::Object],
+            value,
+        ));
+    };
+    Ok(fields.into_iter().map(|(name, value)| {
+        let value = T::from_jsonnet(&value)
+            .map_err(|e| e.nested(format_args!("field {:?}", name.value())))?;
+        Ok((name.value().into(), value))
+    }))
+}
+
+fn object_into_jsonnet<'p, T: IntoJsonnet<'p>>(
+    program: &mut Program<'p>,
+    fields: impl IntoIterator<Item = (String, T)>,
+) -> Value<'p> {
+    let fields: Vec<_> = fields
+        .into_iter()
+        .map(|(name, value)| (program.intern_str(&name), value.into_jsonnet(program)))
+        .collect();
+    program.make_object(&fields)
+}
+
+impl<'p, T: FromJsonnet<'p>, S: BuildHasher + Default> FromJsonnet<'p> for HashMap<String, T, S> {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        object_from_jsonnet(value)?.collect()
+    }
+}
+
+impl<'p, T: IntoJsonnet<'p>, S> IntoJsonnet<'p> for HashMap<String, T, S> {
+    fn into_jsonnet(self, program: &mut Program<'p>) -> Value<'p> {
+        object_into_jsonnet(program, self)
+    }
+}
+
+impl<'p, T: FromJsonnet<'p>> FromJsonnet<'p> for BTreeMap<String, T> {
+    fn from_jsonnet(value: &Value<'p>) -> Result<Self, FromJsonnetError> {
+        object_from_jsonnet(value)?.collect()
+    }
+}
+
+impl<'p, T: IntoJsonnet<'p>> IntoJsonnet<'p> for BTreeMap<String, T> {
+    fn into_jsonnet(self, program: &mut Program<'p>) -> Value<'p> {
+        object_into_jsonnet(program, self)
+    }
+}
@@ -1,7 +1,9 @@
+use std::borrow::Cow;
 use std::cell::{Cell, OnceCell, RefCell};
 use std::collections::BTreeMap;
-use std::rc::Rc;
 
+use super::fork::{ForkNode, ForkStates, ProgramId};
+use super::string_data::StringData;
 use super::{Program, ir};
 use crate::gc::{Gc, GcTrace, GcTraceCtx, GcView};
 use crate::interner::{InternedStr, SortedInternedStr};
@@ -51,7 +53,7 @@ impl<'p> Program<'p> {
         if items.is_empty() {
             Gc::from(&self.empty_array)
         } else {
-            self.gc_alloc(items)
+            self.gc_alloc(ArrayData::new_flat(items))
         }
     }
 
@@ -67,7 +69,7 @@ impl<'p> Program<'p> {
         if items.is_empty() {
             Gc::from(&self.empty_array)
         } else {
-            self.gc_alloc(items)
+            self.gc_alloc(ArrayData::new_flat(items))
         }
     }
 
@@ -81,11 +83,17 @@ impl<'p> Program<'p> {
             Gc::from(rhs)
         } else if rhs.is_empty() {
             Gc::from(lhs)
-        } else {
+        } else if lhs.len() + rhs.len() < MIN_LAZY_ARRAY_LEN {
             let mut items = Vec::with_capacity(lhs.len() + rhs.len());
             items.extend(lhs.iter().cloned());
             items.extend(rhs.iter().cloned());
-            self.gc_alloc(items.into_boxed_slice())
+            self.gc_alloc(ArrayData::new_flat(items.into_boxed_slice()))
+        } else {
+            self.gc_alloc(ArrayData {
+                len: lhs.len() + rhs.len(),
+                flat: OnceCell::new(),
+                parts: RefCell::new(Some(ArrayParts::Concat(Gc::from(lhs), Gc::from(rhs)))),
+            })
         }
     }
 
@@ -97,17 +105,41 @@ impl<'p> Program<'p> {
         end: usize,
         step: usize,
     ) -> Gc<ArrayData<'p>> {
-        let items: Box<[_]> = array
-            .iter()
-            .skip(start)
-            .take(end - start)
-            .step_by(step)
-            .cloned()
-            .collect();
-        if items.is_empty() {
+        let end = end.min(array.len());
+        let len = end.saturating_sub(start).div_ceil(step);
+        if len == 0 {
             Gc::from(&self.empty_array)
+        } else if len == array.len() {
+            Gc::from(array)
+        } else if len < MIN_LAZY_ARRAY_LEN {
+            let items: Box<[_]> = array[start..]
+                .iter()
+                .step_by(step)
+                .take(len)
+                .cloned()
+                .collect();
+            self.gc_alloc(ArrayData::new_flat(items))
         } else {
-            self.gc_alloc(items)
+            let mut base = Gc::from(array);
+            let (mut start, mut step) = (start, step);
+            if array.flat.get().is_none() {
+                // Slice the base of a slice directly, so slices do not nest.
+                if let Some(ArrayParts::Slice {
+                    base: ref base_base,
+                    start: base_start,
+                    step: base_step,
+                }) = *array.parts.borrow()
+                {
+                    base = base_base.clone();
+                    start = base_start + start * base_step;
+                    step *= base_step;
+                }
+            }
+            self.gc_alloc(ArrayData {
+                len,
+                flat: OnceCell::new(),
+                parts: RefCell::new(Some(ArrayParts::Slice { base, start, step })),
+            })
         }
     }
 
@@ -121,10 +153,11 @@ impl<'p> Program<'p> {
         let env = self.gc_alloc_view(ThunkEnv::new());
         let mut env_data = ThunkEnvData::new(Some(base_env.clone()));
         for &(local_name, local_value) in layer.locals.iter() {
-            env_data.set_var(
-                local_name,
-                self.new_pending_expr_thunk(local_value, Gc::from(&env), Some(local_name)),
-            );
+            env_data.push_var(self.new_pending_expr_thunk(
+                local_value,
+                Gc::from(&env),
+                Some(local_name),
+            ));
         }
         let top_obj = if layer.is_top {
             Gc::from(object)
@@ -148,7 +181,13 @@ impl<'p> Program<'p> {
         let layer = object.get_layer(layer_i);
         layer
             .env
-            .get_or_init(|| self.init_object_env(object, layer_i, layer.base_env.as_ref().unwrap()))
+            .get_or_init(|| {
+                // Shared with `object`, which might be shared with other
+                // programs.
+                self.gc_ctx.with_epoch(object.epoch(), || {
+                    self.init_object_env(object, layer_i, layer.base_env.as_ref().unwrap())
+                })
+            })
             .clone()
     }
 
@@ -160,18 +199,20 @@ impl<'p> Program<'p> {
     ) -> Option<GcView<ThunkData<'p>>> {
         let (layer_i, field) = object.find_field(layer_i, name)?;
         let thunk = field.thunk.get_or_init(|| {
-            let (expr, plus) = field.expr.as_ref().unwrap();
-            let env = if let Some(base_env) = field.base_env.as_ref() {
-                self.init_object_env(object, layer_i, base_env)
-            } else {
-                self.get_object_layer_env(object, layer_i)
-            };
-            let thunk = if *plus {
-                ThunkData::new_pending_field_plus(expr, name, env)
-            } else {
-                ThunkData::new_pending_expr(expr, env)
-            };
-            self.gc_alloc(thunk)
+            self.gc_ctx.with_epoch(object.epoch(), || {
+                let (expr, plus) = field.expr.as_ref().unwrap();
+                let env = if let Some(base_env) = field.base_env.as_ref() {
+                    self.init_object_env(object, layer_i, base_env)
+                } else {
+                    self.get_object_layer_env(object, layer_i)
+                };
+                let thunk = if *plus {
+                    ThunkData::new_pending_field_plus(expr, name, env)
+                } else {
+                    ThunkData::new_pending_expr(expr, env)
+                };
+                self.gc_alloc(thunk)
+            })
         });
         Some(thunk.view())
     }
@@ -203,6 +244,31 @@ impl<'p> Program<'p> {
             super_layers,
             fields_order: OnceCell::new(),
             asserts_checked: Cell::new(false),
+            fork_asserts_checked: ForkStates::new(),
+        })
+    }
+
+    /// Like [`Program::extend_object`], but fields of `lhs` whose thunk has
+    /// already been created keep it, so they are still evaluated with
+    /// `self` bound to `lhs` and are not evaluated again for each new
+    /// object.
+    pub(super) fn extend_object_sharing_thunks(
+        &mut self,
+        lhs: &ObjectData<'p>,
+        rhs: ObjectData<'p>,
+    ) -> Gc<ObjectData<'p>> {
+        debug_assert!(rhs.super_layers.is_empty());
+
+        let mut super_layers = Vec::with_capacity(lhs.super_layers.len() + 1);
+        super_layers.push(share_thunks_clone_layer(&lhs.self_layer));
+        super_layers.extend(lhs.super_layers.iter().map(share_thunks_clone_layer));
+
+        self.gc_alloc(ObjectData {
+            self_layer: rhs.self_layer,
+            super_layers,
+            fields_order: OnceCell::new(),
+            asserts_checked: Cell::new(lhs.asserts_checked.get() && rhs.asserts_checked.get()),
+            fork_asserts_checked: ForkStates::new(),
         })
     }
 
@@ -233,6 +299,7 @@ impl<'p> Program<'p> {
             super_layers,
             fields_order: OnceCell::new(),
             asserts_checked: Cell::new(false),
+            fork_asserts_checked: ForkStates::new(),
         })
     }
 }
@@ -269,8 +336,39 @@ fn extend_object_clone_layer<'p>(layer: &ObjectLayer<'p>) -> ObjectLayer<'p> {
     }
 }
 
+fn share_thunks_clone_layer<'p>(layer: &ObjectLayer<'p>) -> ObjectLayer<'p> {
+    let cloned_fields = layer
+        .fields
+        .iter()
+        .map(|(name, field)| {
+            let field = match field {
+                ObjectField::Normal(field) => ObjectField::Normal(ObjectFieldData {
+                    base_env: field.base_env.clone(),
+                    visibility: field.visibility,
+                    expr: field.expr,
+                    thunk: field.thunk.clone(),
+                }),
+                ObjectField::Removed(depth) => ObjectField::Removed(*depth),
+            };
+            (*name, field)
+        })
+        .collect();
+
+    ObjectLayer {
+        is_top: layer.is_top,
+        locals: layer.locals,
+        base_env: layer.base_env.clone(),
+        env: layer.env.clone(),
+        fields: cloned_fields,
+        asserts: layer.asserts,
+    }
+}
+
 pub(super) struct ThunkData<'p> {
     state: RefCell<ThunkState<'p>>,
+    /// Used instead of `state` when the thunk is shared by several
+    /// programs. See [`Program::fork`].
+    fork_states: ForkStates<ThunkState<'p>>,
 }
 
 impl GcTrace for ThunkData<'_> {
@@ -279,22 +377,27 @@ impl GcTrace for ThunkData<'_> {
         Self: 'a,
     {
         self.state.borrow().trace(ctx);
+        self.fork_states.trace(ctx);
     }
 }
 
 impl<'p> ThunkData<'p> {
     #[inline]
-    pub(super) fn new_done(value: ValueData<'p>) -> Self {
+    fn new(state: ThunkState<'p>) -> Self {
         Self {
-            state: RefCell::new(ThunkState::Done(value)),
+            state: RefCell::new(state),
+            fork_states: ForkStates::new(),
         }
     }
 
+    #[inline]
+    pub(super) fn new_done(value: ValueData<'p>) -> Self {
+        Self::new(ThunkState::Done(value))
+    }
+
     #[inline]
     pub(super) fn new_pending_expr(expr: &'p ir::Expr<'p>, env: Gc<ThunkEnv<'p>>) -> Self {
-        Self {
-            state: RefCell::new(ThunkState::Pending(PendingThunk::Expr { expr, env })),
-        }
+        Self::new(ThunkState::Pending(PendingThunk::Expr { expr, env }))
     }
 
     #[inline]
@@ -303,50 +406,116 @@ impl<'p> ThunkData<'p> {
         field: InternedStr<'p>,
         env: Gc<ThunkEnv<'p>>,
     ) -> Self {
-        Self {
-            state: RefCell::new(ThunkState::Pending(PendingThunk::FieldPlus {
-                expr,
-                field,
-                env,
-            })),
-        }
+        Self::new(ThunkState::Pending(PendingThunk::FieldPlus {
+            expr,
+            field,
+            env,
+        }))
     }
 
     pub(super) fn new_pending_call(func: Gc<FuncData<'p>>, args: Box<[Gc<Self>]>) -> Self {
-        Self {
-            state: RefCell::new(ThunkState::Pending(PendingThunk::Call { func, args })),
-        }
-    }
-
-    #[inline]
-    pub(super) fn state(&self) -> std::cell::Ref<'_, ThunkState<'p>> {
-        self.state.borrow()
+        Self::new(ThunkState::Pending(PendingThunk::Call { func, args }))
     }
 
+    /// Returns the state of the thunk if it has not been evaluated yet,
+    /// and marks it as in progress.
+    ///
+    /// `fork_node` is the fork node of the program when the thunk is shared
+    /// by several programs, in which case only the state of that program is
+    /// changed.
     #[inline]
-    pub(crate) fn switch_state(&self) -> ThunkState<'p> {
+    pub(super) fn switch_state(&self, fork_node: Option<&ForkNode<'p>>) -> ThunkState<'p> {
         let mut state = self.state.borrow_mut();
         match *state {
             ThunkState::Done(ref value) => ThunkState::Done(value.clone()),
-            ThunkState::Pending(_) => std::mem::replace(&mut *state, ThunkState::InProgress),
+            ThunkState::Pending(ref pending) => {
+                if let Some(fork_node) = fork_node {
+                    match self.get_fork_state(fork_node, |state| match *state {
+                        ThunkState::Done(ref value) => ThunkState::Done(value.clone()),
+                        _ => ThunkState::InProgress,
+                    }) {
+                        Some(fork_state) => fork_state,
+                        None => {
+                            let pending = pending.clone();
+                            self.fork_states.set(fork_node.id, ThunkState::InProgress);
+                            ThunkState::Pending(pending)
+                        }
+                    }
+                } else {
+                    std::mem::replace(&mut *state, ThunkState::InProgress)
+                }
+            }
             ThunkState::InProgress => ThunkState::InProgress,
         }
     }
 
+    /// Sets the value of the thunk. Returns whether `fork_id` did not have
+    /// a state yet.
     #[inline]
-    pub(super) fn set_done(&self, value: ValueData<'p>) {
-        let mut state = self.state.borrow_mut();
-        assert!(matches!(*state, ThunkState::InProgress));
-        *state = ThunkState::Done(value);
+    pub(super) fn set_done(&self, fork_id: Option<ProgramId>, value: ValueData<'p>) -> bool {
+        if let Some(fork_id) = fork_id {
+            self.fork_states.set(fork_id, ThunkState::Done(value))
+        } else {
+            let mut state = self.state.borrow_mut();
+            assert!(matches!(*state, ThunkState::InProgress));
+            *state = ThunkState::Done(value);
+            false
+        }
+    }
+
+    /// Calls `f` with the state of the thunk stored by `fork_node`, or with
+    /// the value evaluated by one of the programs it was forked from.
+    fn get_fork_state<R>(
+        &self,
+        fork_node: &ForkNode<'p>,
+        f: impl Fn(&ThunkState<'p>) -> R,
+    ) -> Option<R> {
+        self.fork_states.get(fork_node.id, &f).or_else(|| {
+            fork_node.ancestors().find_map(|ancestor| {
+                self.fork_states
+                    .get(ancestor.id, |state| {
+                        matches!(state, ThunkState::Done(_)).then(|| f(state))
+                    })
+                    .flatten()
+            })
+        })
     }
 
+    /// Returns the value of the thunk if it has been evaluated, by
+    /// `program` if it is shared.
     #[inline]
-    pub(super) fn get_value(&self) -> Option<ValueData<'p>> {
+    pub(super) fn get_value(&self, program: &ForkNode<'p>) -> Option<ValueData<'p>> {
         match *self.state.borrow() {
             ThunkState::Done(ref value) => Some(value.clone()),
-            _ => None,
+            ThunkState::Pending(_) => self
+                .get_fork_state(program, |state| match *state {
+                    ThunkState::Done(ref value) => Some(value.clone()),
+                    _ => None,
+                })
+                .flatten(),
+            ThunkState::InProgress => None,
+        }
+    }
+
+    /// Returns whether the thunk has not been evaluated by `program` or has
+    /// been evaluated into a value that might need deep evaluation.
+    #[inline]
+    pub(super) fn might_need_deep(&self, program: &ForkNode<'p>) -> bool {
+        match *self.state.borrow() {
+            ThunkState::Done(ref value) => value.might_need_deep(),
+            ThunkState::Pending(_) => self
+                .get_fork_state(program, |state| match *state {
+                    ThunkState::Done(ref value) => value.might_need_deep(),
+                    _ => true,
+                })
+                .unwrap_or(true),
+            ThunkState::InProgress => true,
         }
     }
+
+    pub(super) fn remove_fork_state(&self, program: ProgramId) {
+        self.fork_states.remove(program);
+    }
 }
 
 pub(super) enum ThunkState<'p> {
@@ -368,6 +537,7 @@ impl GcTrace for ThunkState<'_> {
     }
 }
 
+#[derive(Clone)]
 pub(super) enum PendingThunk<'p> {
     Expr {
         expr: &'p ir::Expr<'p>,
@@ -405,7 +575,7 @@ pub(super) enum ValueData<'p> {
     Null,
     Bool(bool),
     Number(f64),
-    String(Rc<str>),
+    String(StringData),
     Array(Gc<ArrayData<'p>>),
     Object(Gc<ObjectData<'p>>),
     Function(Gc<FuncData<'p>>),
@@ -446,13 +616,182 @@ impl ValueData<'_> {
     }
 }
 
-pub(super) type ArrayData<'p> = Box<[Gc<ThunkData<'p>>]>;
+/// Concatenations and slices that produce arrays shorter than this are
+/// copied eagerly instead of building a lazy node.
+const MIN_LAZY_ARRAY_LEN: usize = 32;
+
+/// Items of an array value.
+///
+/// Concatenating or slicing long arrays does not copy their items. Instead,
+/// a node that refers to the operands is built and flattened the first time
+/// the items are accessed, so accumulating an array with `acc + [x]` or
+/// recursing over `arr[1:]` does not take quadratic time.
+pub(super) struct ArrayData<'p> {
+    len: usize,
+    flat: OnceCell<Box<[Gc<ThunkData<'p>>]>>,
+    parts: RefCell<Option<ArrayParts<'p>>>,
+}
+
+enum ArrayParts<'p> {
+    Concat(Gc<ArrayData<'p>>, Gc<ArrayData<'p>>),
+    Slice {
+        base: Gc<ArrayData<'p>>,
+        start: usize,
+        step: usize,
+    },
+}
+
+impl GcTrace for ArrayData<'_> {
+    fn trace<'a>(&self, ctx: &mut impl GcTraceCtx<'a>)
+    where
+        Self: 'a,
+    {
+        self.flat.trace(ctx);
+        match *self.parts.borrow() {
+            Some(ArrayParts::Concat(ref lhs, ref rhs)) => {
+                lhs.trace(ctx);
+                rhs.trace(ctx);
+            }
+            Some(ArrayParts::Slice { ref base, .. }) => base.trace(ctx),
+            None => {}
+        }
+    }
+}
+
+impl<'p> ArrayData<'p> {
+    #[inline]
+    pub(super) fn new_flat(items: Box<[Gc<ThunkData<'p>>]>) -> Self {
+        Self {
+            len: items.len(),
+            flat: OnceCell::from(items),
+            parts: RefCell::new(None),
+        }
+    }
+
+    /// Returns the number of items, without flattening.
+    #[inline]
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(super) fn get(&self, index: usize) -> Option<Gc<ThunkData<'p>>> {
+        if index >= self.len {
+            return None;
+        }
+        if self.flat.get().is_none() {
+            if let Some(ArrayParts::Slice {
+                ref base,
+                start,
+                step,
+            }) = *self.parts.borrow()
+            {
+                // Index the base directly, so recursing over `arr[1:]` does
+                // not flatten every slice.
+                return Some(base.view().as_slice()[start + index * step].clone());
+            }
+        }
+        Some(self.as_slice()[index].clone())
+    }
+
+    #[inline]
+    pub(super) fn as_slice(&self) -> &[Gc<ThunkData<'p>>] {
+        self.flat.get_or_init(|| self.flatten())
+    }
+
+    #[cold]
+    fn flatten(&self) -> Box<[Gc<ThunkData<'p>>]> {
+        let mut items = Vec::with_capacity(self.len);
+
+        // Each pending item is a request to take `len` items from `array`,
+        // starting at `start` and advancing by `step`. The nodes are
+        // traversed iteratively, since they can be very deep.
+        let parts = self.parts.take().unwrap();
+        let mut pending = Vec::new();
+        match parts {
+            ArrayParts::Concat(lhs, rhs) => {
+                let lhs = lhs.view();
+                let rhs = rhs.view();
+                let (lhs_len, rhs_len) = (lhs.len, rhs.len);
+                pending.push((rhs, 0, 1, rhs_len));
+                pending.push((lhs, 0, 1, lhs_len));
+            }
+            ArrayParts::Slice { base, start, step } => {
+                pending.push((base.view(), start, step, self.len));
+            }
+        }
+
+        while let Some((array, start, step, len)) = pending.pop() {
+            if len == 0 {
+                continue;
+            }
+            if let Some(flat) = array.flat.get() {
+                items.extend(flat[start..].iter().step_by(step).take(len).cloned());
+                continue;
+            }
+            match *array.parts.borrow() {
+                Some(ArrayParts::Concat(ref lhs, ref rhs)) => {
+                    let lhs = lhs.view();
+                    let lhs_len = if start < lhs.len {
+                        (lhs.len - start).div_ceil(step).min(len)
+                    } else {
+                        0
+                    };
+                    if lhs_len < len {
+                        let rhs_start = start + lhs_len * step - lhs.len;
+                        pending.push((rhs.view(), rhs_start, step, len - lhs_len));
+                    }
+                    pending.push((lhs, start, step, lhs_len));
+                }
+                Some(ArrayParts::Slice {
+                    ref base,
+                    start: base_start,
+                    step: base_step,
+                }) => {
+                    pending.push((
+                        base.view(),
+                        base_start + start * base_step,
+                        step * base_step,
+                        len,
+                    ));
+                }
+                None => unreachable!(),
+            }
+        }
+
+        debug_assert_eq!(items.len(), self.len);
+        items.into_boxed_slice()
+    }
+}
+
+impl<'p> FromIterator<Gc<ThunkData<'p>>> for ArrayData<'p> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = Gc<ThunkData<'p>>>>(iter: I) -> Self {
+        Self::new_flat(iter.into_iter().collect())
+    }
+}
+
+impl<'p> std::ops::Deref for ArrayData<'p> {
+    type Target = [Gc<ThunkData<'p>>];
+
+    #[inline]
+    fn deref(&self) -> &[Gc<ThunkData<'p>>] {
+        self.as_slice()
+    }
+}
 
 pub(super) struct ObjectData<'p> {
     pub(super) self_layer: ObjectLayer<'p>,
     pub(super) super_layers: Vec<ObjectLayer<'p>>,
-    pub(super) fields_order: OnceCell<Box<[(InternedStr<'p>, ast::Visibility)]>>,
+    pub(super) fields_order: OnceCell<Cow<'p, [(InternedStr<'p>, ast::Visibility)]>>,
     pub(super) asserts_checked: Cell<bool>,
+    /// Used instead of `asserts_checked` when the object is shared by
+    /// several programs. See [`Program::fork`].
+    pub(super) fork_asserts_checked: ForkStates<()>,
 }
 
 impl GcTrace for ObjectData<'_> {
@@ -480,6 +819,7 @@ impl<'p> ObjectData<'p> {
             super_layers: Vec::new(),
             fields_order: OnceCell::new(),
             asserts_checked: Cell::new(true),
+            fork_asserts_checked: ForkStates::new(),
         }
     }
 
@@ -740,6 +1080,12 @@ impl<'p> SimpleObjectBuilder<'p> {
         }
     }
 
+    /// Inserts a field that hides the field with the same name in the
+    /// `depth` layers below this object.
+    pub(super) fn insert_removed_field(&mut self, name: InternedStr<'p>, depth: usize) {
+        self.fields.insert(name, ObjectField::Removed(depth));
+    }
+
     pub(super) fn build(self) -> ObjectData<'p> {
         ObjectData {
             self_layer: ObjectLayer {
@@ -753,6 +1099,7 @@ impl<'p> SimpleObjectBuilder<'p> {
             super_layers: Vec::new(),
             fields_order: OnceCell::new(),
             asserts_checked: Cell::new(true),
+            fork_asserts_checked: ForkStates::new(),
         }
     }
 }
@@ -768,6 +1115,7 @@ impl GcTrace for FuncData<'_> {
     where
         Self: 'a,
     {
+        self.params.default_thunks.trace(ctx);
         self.kind.trace(ctx);
     }
 }
@@ -786,11 +1134,30 @@ impl<'p> FuncData<'p> {
             params: FuncParams {
                 order: params_order,
                 by_name: params_by_name,
+                default_thunks: Box::new([]),
+                has_rest: false,
             },
             kind,
         }
     }
 
+    pub(super) fn new_native_func(
+        name: InternedStr<'p>,
+        params_order: &'p [(InternedStr<'p>, Option<&'p ir::Expr<'p>>)],
+        default_thunks: Box<[Option<Gc<ThunkData<'p>>>]>,
+        has_rest: bool,
+    ) -> Self {
+        let mut this = Self::new(params_order, FuncKind::Native { name });
+        if has_rest {
+            // The rest parameter can only be bound with positional arguments.
+            let rest_name = params_order.last().unwrap().0;
+            this.params.by_name.remove(&rest_name);
+        }
+        this.params.default_thunks = default_thunks;
+        this.params.has_rest = has_rest;
+        this
+    }
+
     pub(super) fn new_identity_func(
         func_name: Option<InternedStr<'p>>,
         param: &'p [(InternedStr<'p>, Option<&'p ir::Expr<'p>>); 1],
@@ -802,6 +1169,12 @@ impl<'p> FuncData<'p> {
 pub(super) struct FuncParams<'p> {
     pub(super) order: &'p [(InternedStr<'p>, Option<&'p ir::Expr<'p>>)],
     pub(super) by_name: FHashMap<InternedStr<'p>, usize>,
+    /// Already evaluated default values, for parameters without a default
+    /// expression. Only used by native functions.
+    pub(super) default_thunks: Box<[Option<Gc<ThunkData<'p>>>]>,
+    /// Whether the last parameter collects the extra positional arguments
+    /// into an array. Only used by native functions.
+    pub(super) has_rest: bool,
 }
 
 pub(super) enum FuncKind<'p> {
@@ -900,6 +1273,12 @@ pub(super) enum BuiltInFunc {
     SplitLimit,
     SplitLimitR,
     StrReplace,
+    RegexMatch,
+    RegexFullMatch,
+    RegexFindAll,
+    RegexReplace,
+    RegexSplit,
+    RegexQuoteMeta,
     Trim,
     EqualsIgnoreCase,
     AsciiUpper,
@@ -917,6 +1296,9 @@ pub(super) enum BuiltInFunc {
     ParseHex,
     ParseJson,
     ParseYaml,
+    ParseToml,
+    ParseCsv,
+    ParseXml,
     EncodeUtf8,
     DecodeUtf8,
     // Manifestation
@@ -928,6 +1310,7 @@ pub(super) enum BuiltInFunc {
     ManifestYamlStream,
     ManifestXmlJsonml,
     ManifestTomlEx,
+    ManifestCsv,
     // Arrays
     MakeArray,
     Member,
@@ -969,11 +1352,29 @@ pub(super) enum BuiltInFunc {
     Base64,
     Base64DecodeBytes,
     Base64Decode,
+    Base64UrlEncode,
+    Base64UrlDecodeBytes,
+    Base64UrlDecode,
+    Base32Encode,
+    Base32DecodeBytes,
+    Base32Decode,
+    HexEncode,
+    HexDecodeBytes,
+    HexDecode,
+    UrlEncode,
+    UrlDecodeBytes,
+    UrlDecode,
     Md5,
     Sha1,
     Sha256,
     Sha512,
     Sha3,
+    Blake2b,
+    Blake3,
+    Crc32,
+    Xxh64,
+    Xxh3,
+    Hmac,
     // JSON Merge Patch
     MergePatch,
     // Other
@@ -1024,20 +1425,22 @@ impl<'p> ThunkEnv<'p> {
         self.data.get().expect("env data not set")
     }
 
-    pub(super) fn get_var(&self, name: InternedStr<'p>) -> Gc<ThunkData<'p>> {
+    pub(super) fn get_var(&self, slot: ir::VarSlot) -> Gc<ThunkData<'p>> {
         let data = self.data();
-        if let Some(var) = data.vars.get(&name) {
-            var.clone()
+        if slot.depth == 0 {
+            data.vars[slot.index as usize].clone()
         } else {
-            let mut env = data.parent.as_ref().map(Gc::view);
-            while let Some(parent) = env {
-                let parent = parent.data();
-                if let Some(var) = parent.vars.get(&name) {
-                    return var.clone();
-                }
-                env = parent.parent.as_ref().map(Gc::view);
+            let mut env = data.parent.as_ref().expect("variable not found").view();
+            for _ in 1..slot.depth {
+                let parent = env
+                    .data()
+                    .parent
+                    .as_ref()
+                    .expect("variable not found")
+                    .view();
+                env = parent;
             }
-            panic!("variable not found");
+            env.data().vars[slot.index as usize].clone()
         }
     }
 
@@ -1056,7 +1459,9 @@ impl<'p> ThunkEnv<'p> {
 
 pub(super) struct ThunkEnvData<'p> {
     parent: Option<Gc<ThunkEnv<'p>>>,
-    vars: FHashMap<InternedStr<'p>, Gc<ThunkData<'p>>>,
+    /// Variables in the order they are bound, indexed by
+    /// [`ir::VarSlot::index`].
+    vars: Vec<Gc<ThunkData<'p>>>,
     object: Option<ThunkEnvObject<'p>>,
 }
 
@@ -1067,7 +1472,7 @@ impl GcTrace for ThunkEnvData<'_> {
         Self: 'a,
     {
         self.parent.trace(ctx);
-        for var in self.vars.values() {
+        for var in self.vars.iter() {
             var.trace(ctx);
         }
         self.object.trace(ctx);
@@ -1098,14 +1503,14 @@ impl<'p> ThunkEnvData<'p> {
         let object = parent.as_ref().and_then(|p| p.view().data().object.clone());
         Self {
             parent,
-            vars: FHashMap::default(),
+            vars: Vec::new(),
             object,
         }
     }
 
     #[inline]
-    pub(super) fn set_var(&mut self, name: InternedStr<'p>, thunk: Gc<ThunkData<'p>>) {
-        self.vars.insert(name, thunk);
+    pub(super) fn push_var(&mut self, thunk: Gc<ThunkData<'p>>) {
+        self.vars.push(thunk);
     }
 
     #[inline]
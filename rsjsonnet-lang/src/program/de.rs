@@ -0,0 +1,429 @@
+use std::fmt;
+
+use serde::de::{self, IntoDeserializer as _};
+
+use super::{Program, Value, ValueData};
+
+/// Error returned by [`Program::deserialize`].
+#[derive(Clone, Debug)]
+pub struct DeserializeError {
+    message: String,
+    // Innermost item first.
+    path: Vec<PathItem>,
+}
+
+#[derive(Clone, Debug)]
+enum PathItem {
+    Field(String),
+    Index(usize),
+}
+
+impl DeserializeError {
+    /// Returns the error message, without the location.
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the location of the value that caused the error, such as
+    /// `.spec.containers[0].image`.
+    ///
+    /// Empty if the error was caused by the root value.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for item in self.path.iter().rev() {
+            match item {
+                PathItem::Field(name) => {
+                    path.push('.');
+                    path.push_str(name);
+                }
+                PathItem::Index(index) => {
+                    path.push('[');
+                    path.push_str(&index.to_string());
+                    path.push(']');
+                }
+            }
+        }
+        path
+    }
+
+    fn with_path_item(mut self, item: PathItem) -> Self {
+        self.path.push(item);
+        self
+    }
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{} at `{}`", self.message, self.path())
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            message: msg.to_string(),
+            path: Vec::new(),
+        }
+    }
+}
+
+impl<'p> Program<'p> {
+    /// Deserializes an evaluated value into a Rust type.
+    ///
+    /// `value` must have been returned by [`Program::eval_value`] or
+    /// [`Program::eval_call`], so all its array items and visible object
+    /// fields are already evaluated. Hidden object fields are ignored.
+    ///
+    /// Numbers are provided as integers when they do not have a fractional
+    /// part and fit in the requested integer type.
+    pub fn deserialize<T: de::DeserializeOwned>(
+        &self,
+        value: &Value<'p>,
+    ) -> Result<T, DeserializeError> {
+        T::deserialize(ValueDeserializer {
+            value: value.clone(),
+        })
+    }
+}
+
+struct ValueDeserializer<'p> {
+    value: Value<'p>,
+}
+
+impl ValueDeserializer<'_> {
+    fn invalid_type<E: de::Expected>(&self, exp: &E) -> DeserializeError {
+        let unexpected = match self.value.inner {
+            ValueData::Null => de::Unexpected::Unit,
+            ValueData::Bool(value) => de::Unexpected::Bool(value),
+            ValueData::Number(value) => de::Unexpected::Float(value),
+            ValueData::String(ref s) => de::Unexpected::Str(s),
+            ValueData::Array(_) => de::Unexpected::Seq,
+            ValueData::Object(_) => de::Unexpected::Map,
+            ValueData::Function(_) => de::Unexpected::Other("function"),
+        };
+        de::Error::invalid_type(unexpected, exp)
+    }
+
+    fn deserialize_integer<'de, V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        match self.value.inner {
+            // `i64::MAX as f64` is rounded to 2^63, which does not fit in
+            // an `i64`, so that bound must be exclusive.
+            ValueData::Number(value)
+                if value.trunc() == value
+                    && value >= i64::MIN as f64
+                    && value < i64::MAX as f64 =>
+            {
+                visitor.visit_i64(value as i64)
+            }
+            ValueData::Number(value)
+                if value.trunc() == value && value >= 0.0 && value < u64::MAX as f64 =>
+            {
+                visitor.visit_u64(value as u64)
+            }
+            _ => self.deserialize_any_inner(visitor),
+        }
+    }
+
+    fn deserialize_any_inner<'de, V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        match self.value.inner {
+            ValueData::Null => visitor.visit_unit(),
+            ValueData::Bool(value) => visitor.visit_bool(value),
+            ValueData::Number(value) => visitor.visit_f64(value),
+            ValueData::String(ref s) => visitor.visit_str(s),
+            ValueData::Array(ref array) => {
+                let items = self.value.extract_array(&array.view());
+                let mut seq = SeqDeserializer {
+                    items: items.into_iter(),
+                    index: 0,
+                };
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            ValueData::Object(ref object) => {
+                let fields = self.value.extract_object(&object.view());
+                let mut map = MapDeserializer {
+                    fields: fields.into_iter(),
+                    pending_value: None,
+                };
+                visitor.visit_map(&mut map)
+            }
+            ValueData::Function(_) => Err(self.invalid_type(&visitor)),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value.inner {
+            ValueData::Number(value)
+                if value.trunc() == value && value.abs() < (1u64 << 53) as f64 =>
+            {
+                visitor.visit_i64(value as i64)
+            }
+            _ => self.deserialize_any_inner(visitor),
+        }
+    }
+
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_i128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_u128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_integer(visitor)
+    }
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_any_inner(visitor)
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_any_inner(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.value.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value.inner {
+            ValueData::String(ref s) => visitor.visit_enum(String::from(&**s).into_deserializer()),
+            ValueData::Object(ref object) => {
+                let mut fields = self.value.extract_object(&object.view());
+                if fields.len() != 1 {
+                    return Err(de::Error::invalid_value(
+                        de::Unexpected::Map,
+                        &"an object with a single field",
+                    ));
+                }
+                let (variant, value) = fields.pop().unwrap();
+                visitor.visit_enum(EnumDeserializer {
+                    variant: variant.value().into(),
+                    value,
+                })
+            }
+            _ => Err(self.invalid_type(&"a string or an object with a single field")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier
+    }
+}
+
+struct SeqDeserializer<'p> {
+    items: std::vec::IntoIter<Value<'p>>,
+    index: usize,
+}
+
+impl SeqDeserializer<'_> {
+    fn end(self) -> Result<(), DeserializeError> {
+        let remaining = self.items.len();
+        if remaining == 0 {
+            Ok(())
+        } else {
+            Err(de::Error::invalid_length(
+                self.index + remaining,
+                &"fewer elements in array",
+            ))
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        let Some(value) = self.items.next() else {
+            return Ok(None);
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(ValueDeserializer { value })
+            .map(Some)
+            .map_err(|e| e.with_path_item(PathItem::Index(index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapDeserializer<'p> {
+    fields: std::vec::IntoIter<(crate::interner::InternedStr<'p>, Value<'p>)>,
+    pending_value: Option<(String, Value<'p>)>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((name, value)) = self.fields.next() else {
+            return Ok(None);
+        };
+        let name = String::from(name.value());
+        let key = seed.deserialize(de::value::StrDeserializer::<DeserializeError>::new(&name))?;
+        self.pending_value = Some((name, value));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (name, value) = self
+            .pending_value
+            .take()
+            .expect("`next_value_seed` called before `next_key_seed`");
+        seed.deserialize(ValueDeserializer { value })
+            .map_err(|e| e.with_path_item(PathItem::Field(name)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+struct EnumDeserializer<'p> {
+    variant: String,
+    value: Value<'p>,
+}
+
+impl<'de, 'p> de::EnumAccess<'de> for EnumDeserializer<'p> {
+    type Error = DeserializeError;
+    type Variant = VariantDeserializer<'p>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(de::value::StrDeserializer::<DeserializeError>::new(
+            &self.variant,
+        ))?;
+        Ok((
+            variant,
+            VariantDeserializer {
+                name: self.variant,
+                value: self.value,
+            },
+        ))
+    }
+}
+
+struct VariantDeserializer<'p> {
+    name: String,
+    value: Value<'p>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        de::Deserialize::deserialize(ValueDeserializer { value: self.value })
+            .map_err(|e: DeserializeError| e.with_path_item(PathItem::Field(self.name)))
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        seed.deserialize(ValueDeserializer { value: self.value })
+            .map_err(|e| e.with_path_item(PathItem::Field(self.name)))
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_seq(ValueDeserializer { value: self.value }, visitor)
+            .map_err(|e| e.with_path_item(PathItem::Field(self.name)))
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_map(ValueDeserializer { value: self.value }, visitor)
+            .map_err(|e| e.with_path_item(PathItem::Field(self.name)))
+    }
+}
@@ -1,4 +1,4 @@
-use super::{EvalStackTraceItem, ValueData};
+use super::{EvalStackTraceItem, FromJsonnetError, ValueData};
 use crate::ast;
 use crate::lexer::LexError;
 use crate::parser::ParseError;
@@ -135,6 +135,11 @@ pub enum EvalErrorKind {
         param_name: String,
     },
     NativeCallFailed,
+    InvalidNativeFuncArg {
+        func_name: String,
+        param_name: String,
+        error: FromJsonnetError,
+    },
     InvalidUnaryOpType {
         span: SpanId,
         op: ast::UnaryOp,
@@ -268,3 +273,91 @@ impl From<AnalyzeError> for LoadError {
         Self::Analyze(err)
     }
 }
+
+/// Error returned by [`Program::parse_json`](super::Program::parse_json).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseJsonError {
+    /// Line (starting from 1) where the error happened.
+    pub line: usize,
+    /// Column (starting from 1) where the error happened.
+    pub column: usize,
+    /// Offset (in bytes) where the error happened.
+    pub offset: usize,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseJsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message,
+        )
+    }
+}
+
+impl std::error::Error for ParseJsonError {}
+
+/// Error returned by [`Program::parse_yaml`](super::Program::parse_yaml).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseYamlError {
+    /// Description of the error.
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseYamlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseYamlError {}
+
+/// Error returned by [`Program::parse_toml`](super::Program::parse_toml).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTomlError {
+    /// Line (starting from 1) where the error happened.
+    pub line: usize,
+    /// Column (starting from 1) where the error happened.
+    pub column: usize,
+    /// Offset (in bytes) where the error happened.
+    pub offset: usize,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseTomlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message,
+        )
+    }
+}
+
+impl std::error::Error for ParseTomlError {}
+
+/// Error returned when manifesting a value into a writer.
+#[derive(Debug)]
+pub enum ManifestError {
+    /// The value could not be manifested.
+    Eval(EvalError),
+    /// The writer returned an error.
+    Io(std::io::Error),
+}
+
+impl From<EvalError> for ManifestError {
+    #[inline]
+    fn from(err: EvalError) -> Self {
+        Self::Eval(err)
+    }
+}
+
+impl From<std::io::Error> for ManifestError {
+    #[inline]
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,249 @@
+use super::super::{ThunkEnv, ValueData, ir};
+use super::{EvalErrorKind, EvalErrorValueType, EvalResult, Evaluator, State, TraceItem};
+use crate::ast;
+use crate::gc::GcView;
+
+impl<'p> Evaluator<'_, 'p> {
+    /// Runs `code` from `pc` until it finishes or an instruction needs more
+    /// than the values on top of the stack, such as a variable that has not
+    /// been evaluated yet.
+    ///
+    /// In that case, the states that do the work are pushed on top of a
+    /// [`State::Bytecode`] that resumes after the instruction. If nothing is
+    /// left to run, no state is left behind, so the work is in tail position
+    /// as it would be in the tree.
+    pub(super) fn run_code(
+        &mut self,
+        code: &'p [ir::Op<'p>],
+        mut pc: usize,
+        env: GcView<ThunkEnv<'p>>,
+    ) -> EvalResult<()> {
+        while let Some(&op) = code.get(pc) {
+            pc += 1;
+            match op {
+                ir::Op::Null => {
+                    self.value_stack.push(ValueData::Null);
+                }
+                ir::Op::Bool(value) => {
+                    self.value_stack.push(ValueData::Bool(value));
+                }
+                ir::Op::Number(value, span) => {
+                    self.check_number_value(value, Some(span))?;
+                    self.value_stack.push(ValueData::Number(value));
+                }
+                ir::Op::String(s) => {
+                    self.value_stack.push(ValueData::String(s.into()));
+                }
+                ir::Op::Var { name, slot, span } => {
+                    let thunk = env.get_var(slot).view();
+                    if let Some(value) = thunk.get_value(&self.program.fork_node) {
+                        self.value_stack.push(value);
+                    } else {
+                        self.suspend_code(code, pc, &env);
+                        self.push_trace_item(TraceItem::Variable { span, name });
+                        self.state_stack.push(State::DoThunk(thunk));
+                        return Ok(());
+                    }
+                }
+                ir::Op::StdField { field_name, span } => {
+                    let thunk = self.program.stdlib_builtins[&field_name].clone();
+                    if let Some(value) = thunk.get_value(&self.program.fork_node) {
+                        self.value_stack.push(value);
+                    } else {
+                        self.suspend_code(code, pc, &env);
+                        self.push_trace_item(TraceItem::ObjectField {
+                            span: Some(span),
+                            name: field_name,
+                        });
+                        self.state_stack.push(State::DoThunk(thunk));
+                        return Ok(());
+                    }
+                }
+                ir::Op::Field { field_name, span } => {
+                    if self.run_inline(code, pc, &env, |this| this.do_field(span, field_name))? {
+                        return Ok(());
+                    }
+                }
+                ir::Op::Index { span } => {
+                    if self.run_inline(code, pc, &env, |this| this.do_index(span))? {
+                        return Ok(());
+                    }
+                }
+                ir::Op::Call(call_expr) => {
+                    self.suspend_code(code, pc, &env);
+                    self.state_stack.push(State::CallWithExpr {
+                        call_expr,
+                        call_env: env,
+                    });
+                    return Ok(());
+                }
+                ir::Op::Expr(expr) => {
+                    self.suspend_code(code, pc, &env);
+                    self.state_stack.push(State::Expr { expr, env });
+                    return Ok(());
+                }
+                ir::Op::PushTrace(span) => {
+                    self.push_trace_item(TraceItem::Expr { span });
+                    if self.stack_trace_len > self.program.max_stack {
+                        return Err(self.report_error(EvalErrorKind::StackOverflow));
+                    }
+                }
+                ir::Op::PopTrace => {
+                    let Some(State::TraceItem(_)) = self.state_stack.pop() else {
+                        unreachable!();
+                    };
+                    self.dec_trace_len();
+                }
+                ir::Op::Compare(op) => {
+                    let [ref lhs, ref rhs] = self.value_stack[(self.value_stack.len() - 2)..]
+                    else {
+                        unreachable!();
+                    };
+                    let cmp_ord = match (lhs, rhs) {
+                        // NaNs are not allowed.
+                        (ValueData::Number(lhs), ValueData::Number(rhs)) => {
+                            Some(lhs.partial_cmp(rhs).unwrap())
+                        }
+                        (ValueData::String(lhs), ValueData::String(rhs)) => Some(lhs.cmp(rhs)),
+                        _ => None,
+                    };
+                    if let Some(cmp_ord) = cmp_ord {
+                        let value = match op {
+                            ast::BinaryOp::Lt => cmp_ord.is_lt(),
+                            ast::BinaryOp::Le => cmp_ord.is_le(),
+                            ast::BinaryOp::Gt => cmp_ord.is_gt(),
+                            ast::BinaryOp::Ge => cmp_ord.is_ge(),
+                            _ => unreachable!(),
+                        };
+                        self.value_stack.truncate(self.value_stack.len() - 2);
+                        self.value_stack.push(ValueData::Bool(value));
+                    } else {
+                        self.suspend_code(code, pc, &env);
+                        self.state_stack.push(match op {
+                            ast::BinaryOp::Lt => State::CmpOrdToBoolValueIsLt,
+                            ast::BinaryOp::Le => State::CmpOrdToBoolValueIsLe,
+                            ast::BinaryOp::Gt => State::CmpOrdToBoolValueIsGt,
+                            ast::BinaryOp::Ge => State::CmpOrdToBoolValueIsGe,
+                            _ => unreachable!(),
+                        });
+                        self.state_stack.push(State::CompareValue);
+                        return Ok(());
+                    }
+                }
+                ir::Op::Equals { negate } => {
+                    let [ref lhs, ref rhs] = self.value_stack[(self.value_stack.len() - 2)..]
+                    else {
+                        unreachable!();
+                    };
+                    let equal = match (lhs, rhs) {
+                        (ValueData::Null, ValueData::Null) => Some(true),
+                        (ValueData::Bool(lhs), ValueData::Bool(rhs)) => Some(lhs == rhs),
+                        (ValueData::Number(lhs), ValueData::Number(rhs)) => Some(lhs == rhs),
+                        (ValueData::String(lhs), ValueData::String(rhs)) => Some(lhs == rhs),
+                        _ => None,
+                    };
+                    if let Some(equal) = equal {
+                        self.value_stack.truncate(self.value_stack.len() - 2);
+                        self.value_stack.push(ValueData::Bool(equal != negate));
+                    } else {
+                        self.suspend_code(code, pc, &env);
+                        self.state_stack.push(State::BoolToValue);
+                        if negate {
+                            self.state_stack.push(State::InvertBool);
+                        }
+                        self.state_stack.push(State::EqualsValue);
+                        return Ok(());
+                    }
+                }
+                ir::Op::Binary { op, span } => {
+                    let [ref lhs, ref rhs] = self.value_stack[(self.value_stack.len() - 2)..]
+                    else {
+                        unreachable!();
+                    };
+                    if matches!((lhs, rhs), (ValueData::Number(_), ValueData::Number(_))) {
+                        // Operators on numbers do not push states.
+                        self.do_binary_op(Some(span), op)?;
+                    } else if self
+                        .run_inline(code, pc, &env, |this| this.do_binary_op(Some(span), op))?
+                    {
+                        return Ok(());
+                    }
+                }
+                ir::Op::Unary { op, span } => {
+                    self.do_unary_op(span, op)?;
+                }
+                ir::Op::ShortCircuit { value, target } => {
+                    if matches!(self.value_stack.last().unwrap(), &ValueData::Bool(b) if b == value)
+                    {
+                        pc = target as usize;
+                    }
+                }
+                ir::Op::JumpIfFalse { cond_span, target } => {
+                    match self.value_stack.pop().unwrap() {
+                        ValueData::Bool(true) => {}
+                        ValueData::Bool(false) => pc = target as usize,
+                        cond_value => {
+                            return Err(self.report_error(EvalErrorKind::CondIsNotBool {
+                                span: cond_span,
+                                got_type: EvalErrorValueType::from_value(&cond_value),
+                            }));
+                        }
+                    }
+                }
+                ir::Op::Jump(target) => {
+                    pc = target as usize;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f`, which may push states to finish its work. If it does, they
+    /// run before `code` is resumed at `pc` and `true` is returned, so the
+    /// caller must stop running the code.
+    fn run_inline(
+        &mut self,
+        code: &'p [ir::Op<'p>],
+        pc: usize,
+        env: &GcView<ThunkEnv<'p>>,
+        f: impl FnOnce(&mut Self) -> EvalResult<()>,
+    ) -> EvalResult<bool> {
+        let suspended = self.suspend_code(code, pc, env);
+        let stack_len = self.state_stack.len();
+        f(self)?;
+        if self.state_stack.len() != stack_len {
+            return Ok(true);
+        }
+        if suspended {
+            let Some(State::Bytecode { .. }) = self.state_stack.pop() else {
+                unreachable!();
+            };
+        }
+        Ok(false)
+    }
+
+    /// Pushes a [`State::Bytecode`] that resumes `code` at `pc`, unless
+    /// only jumps to the end are left. Returns whether it was pushed.
+    fn suspend_code(
+        &mut self,
+        code: &'p [ir::Op<'p>],
+        pc: usize,
+        env: &GcView<ThunkEnv<'p>>,
+    ) -> bool {
+        let mut next = pc;
+        loop {
+            match code.get(next) {
+                None => return false,
+                Some(&ir::Op::Jump(target)) => next = target as usize,
+                Some(_) => break,
+            }
+        }
+        self.state_stack.push(State::Bytecode {
+            code,
+            pc,
+            env: env.clone(),
+        });
+        true
+    }
+}
@@ -1,6 +1,9 @@
 use super::super::{
-    BuiltInFunc, FuncData, FuncKind, FuncParams, ThunkData, ThunkEnv, ThunkEnvData, ir,
+    ArrayData, BuiltInFunc, FuncData, FuncKind, FuncParams, ThunkData, ThunkEnv, ThunkEnvData,
+    ValueData, ir,
 };
+use super::encoding::ByteEncoding;
+use super::hash::HashAlgo;
 use super::{EvalErrorKind, EvalResult, Evaluator, State, TraceItem};
 use crate::gc::{Gc, GcView};
 use crate::interner::InternedStr;
@@ -81,10 +84,18 @@ impl<'p> Evaluator<'_, 'p> {
         func_env: Option<Gc<ThunkEnv<'p>>>,
         call_span: Option<SpanId>,
     ) -> EvalResult<Box<[Gc<ThunkData<'p>>]>> {
-        if positional_args.len() > params.order.len() {
+        // The rest parameter (if any) is bound separately at the end.
+        let num_params = params.order.len() - usize::from(params.has_rest);
+        let (positional_args, rest_args) = if params.has_rest {
+            positional_args.split_at(positional_args.len().min(num_params))
+        } else {
+            (positional_args, &[][..])
+        };
+
+        if positional_args.len() > num_params {
             return Err(self.report_error(EvalErrorKind::TooManyCallArgs {
                 span: call_span,
-                num_params: params.order.len(),
+                num_params,
             }));
         }
 
@@ -92,13 +103,14 @@ impl<'p> Evaluator<'_, 'p> {
         let mut args_thunks = Vec::with_capacity(params.order.len());
         args_thunks.extend(positional_args.iter().map(|arg| pos_arg_thunk(self, arg)));
 
-        if args_thunks.len() == params.order.len() && named_args.is_empty() {
+        if args_thunks.len() == num_params && named_args.is_empty() {
             // Fast path when all arguments are positional
+            self.bind_rest_arg(params, &mut args_thunks, rest_args, &pos_arg_thunk);
             return Ok(args_thunks.into_boxed_slice());
         }
 
         // Handle named arguments into a temporary vector
-        let mut named_args_tmp = vec![None; params.order.len() - args_thunks.len()];
+        let mut named_args_tmp = vec![None; num_params - args_thunks.len()];
 
         for named_arg in named_args.iter() {
             let param_name = named_arg_name(named_arg);
@@ -139,7 +151,8 @@ impl<'p> Evaluator<'_, 'p> {
         if named_args_tmp.len() == 0 {
             // Fast path when all parameters are bound
             // without needing defaults.
-            assert_eq!(args_thunks.len(), params.order.len());
+            assert_eq!(args_thunks.len(), num_params);
+            self.bind_rest_arg(params, &mut args_thunks, rest_args, &pos_arg_thunk);
             return Ok(args_thunks.into_boxed_slice());
         }
 
@@ -150,24 +163,30 @@ impl<'p> Evaluator<'_, 'p> {
             if let Some(arg) = arg_tmp {
                 args_thunks.push(arg);
             } else {
-                let (param_name, default_arg) = params.order[args_thunks.len()];
-                let Some(default_arg) = default_arg else {
+                let param_i = args_thunks.len();
+                let (param_name, default_arg) = params.order[param_i];
+                if let Some(default_arg) = default_arg {
+                    args_thunks.push(self.program.new_pending_expr_thunk(
+                        default_arg,
+                        Gc::from(&args_env),
+                        None,
+                    ));
+                } else if let Some(Some(default_thunk)) = params.default_thunks.get(param_i) {
+                    args_thunks.push(default_thunk.clone());
+                } else {
                     return Err(self.report_error(EvalErrorKind::CallParamNotBound {
                         span: call_span,
                         param_name: param_name.value().into(),
                     }));
-                };
-                args_thunks.push(self.program.new_pending_expr_thunk(
-                    default_arg,
-                    Gc::from(&args_env),
-                    None,
-                ));
+                }
             }
         }
 
+        self.bind_rest_arg(params, &mut args_thunks, rest_args, &pos_arg_thunk);
+
         let mut args_env_data = ThunkEnvData::new(func_env);
-        for (&(arg_name, _), arg_thunk) in params.order.iter().zip(args_thunks.iter()) {
-            args_env_data.set_var(arg_name, arg_thunk.clone());
+        for arg_thunk in args_thunks.iter() {
+            args_env_data.push_var(arg_thunk.clone());
         }
 
         args_env.set_data(args_env_data);
@@ -176,6 +195,34 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(args_thunks.into_boxed_slice())
     }
 
+    /// Collects the extra positional arguments into an array that is bound to
+    /// the rest parameter, if the function has one.
+    #[inline]
+    fn bind_rest_arg<PosArg>(
+        &self,
+        params: &FuncParams<'p>,
+        args_thunks: &mut Vec<Gc<ThunkData<'p>>>,
+        rest_args: &[PosArg],
+        pos_arg_thunk: &impl Fn(&Self, &PosArg) -> Gc<ThunkData<'p>>,
+    ) {
+        if !params.has_rest {
+            return;
+        }
+        let array = if rest_args.is_empty() {
+            Gc::from(&self.program.empty_array)
+        } else {
+            let items: ArrayData<'p> = rest_args
+                .iter()
+                .map(|arg| pos_arg_thunk(self, arg))
+                .collect();
+            self.program.gc_alloc(items)
+        };
+        args_thunks.push(
+            self.program
+                .gc_alloc(ThunkData::new_done(ValueData::Array(array))),
+        );
+    }
+
     #[inline]
     pub(super) fn check_thunk_args_and_execute_call(
         &mut self,
@@ -190,6 +237,8 @@ impl<'p> Evaluator<'_, 'p> {
         self.push_trace_item(TraceItem::Call {
             span: call_span,
             name: func_name,
+            reusable: matches!(func.kind, FuncKind::Normal { .. }),
+            tail_calls: 0,
         });
         self.execute_call(func, args_thunks);
         Ok(())
@@ -210,7 +259,7 @@ impl<'p> Evaluator<'_, 'p> {
                 self.execute_built_in_call(kind, &args);
             }
             FuncKind::Native { name, .. } => {
-                self.execute_native_call(name, &args);
+                self.execute_native_call(name, func.params.order, &args);
             }
         }
     }
@@ -224,8 +273,9 @@ impl<'p> Evaluator<'_, 'p> {
     ) {
         let inner_env = self.program.gc_alloc_view(ThunkEnv::new());
         let mut inner_env_data = ThunkEnvData::new(Some(env));
-        for (&(arg_name, _), arg_thunk) in params.order.iter().zip(Vec::from(args)) {
-            inner_env_data.set_var(arg_name, arg_thunk);
+        debug_assert_eq!(args.len(), params.order.len());
+        for arg_thunk in Vec::from(args) {
+            inner_env_data.push_var(arg_thunk);
         }
         inner_env.set_data(inner_env_data);
 
@@ -606,6 +656,48 @@ impl<'p> Evaluator<'_, 'p> {
                 self.state_stack.push(State::DoThunk(arg1.view()));
                 self.state_stack.push(State::DoThunk(arg0.view()));
             }
+            BuiltInFunc::RegexMatch => {
+                let [arg0, arg1] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_match));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::RegexFullMatch => {
+                let [arg0, arg1] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_full_match));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::RegexFindAll => {
+                let [arg0, arg1] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_find_all));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::RegexReplace => {
+                let [arg0, arg1, arg2] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_replace));
+                self.state_stack.push(State::DoThunk(arg2.view()));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::RegexSplit => {
+                let [arg0, arg1] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_split));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::RegexQuoteMeta => {
+                let [arg] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_regex_quote_meta));
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
             BuiltInFunc::Trim => {
                 let [arg] = check_num_args(args);
                 self.state_stack.push(State::FnFallible(Self::do_std_trim));
@@ -707,6 +799,29 @@ impl<'p> Evaluator<'_, 'p> {
                     .push(State::FnFallible(Self::do_std_parse_yaml));
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
+            BuiltInFunc::ParseToml => {
+                let [arg] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_parse_toml));
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::ParseCsv => {
+                let [arg0, arg1, arg2, arg3] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_parse_csv));
+                self.state_stack.push(State::DoThunk(arg3.view()));
+                self.state_stack.push(State::DoThunk(arg2.view()));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
+            BuiltInFunc::ParseXml => {
+                let [arg0, arg1, arg2] = check_num_args(args);
+                self.state_stack
+                    .push(State::FnFallible(Self::do_std_parse_xml));
+                self.state_stack.push(State::DoThunk(arg2.view()));
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
             BuiltInFunc::EncodeUtf8 => {
                 self.state_stack
                     .push(State::FnFallible(Self::do_std_encode_utf8));
@@ -767,6 +882,15 @@ impl<'p> Evaluator<'_, 'p> {
                 self.state_stack.push(State::DoThunk(arg1.view()));
                 self.state_stack.push(State::DoThunk(arg0.view()));
             }
+            BuiltInFunc::ManifestCsv => {
+                let [arg0, arg1, arg2, arg3] = check_num_args(args);
+                self.state_stack.push(State::StdManifestCsv);
+                self.state_stack.push(State::DoThunk(arg3.view()));
+                self.state_stack.push(State::DoThunk(arg2.view()));
+                self.state_stack.push(State::DeepValue);
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
             BuiltInFunc::MakeArray => {
                 let [arg0, arg1] = check_num_args(args);
                 self.state_stack
@@ -989,46 +1113,224 @@ impl<'p> Evaluator<'_, 'p> {
             }
             BuiltInFunc::Base64 => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::StdBase64);
+                self.state_stack.push(State::StdEncodeBytes {
+                    encoding: ByteEncoding::Base64,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Base64DecodeBytes => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::StdBase64DecodeBytes);
+                self.state_stack.push(State::StdDecodeBytes {
+                    encoding: ByteEncoding::Base64,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Base64Decode => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::StdBase64Decode);
+                self.state_stack.push(State::StdDecodeString {
+                    encoding: ByteEncoding::Base64,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base64UrlEncode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdEncodeBytes {
+                    encoding: ByteEncoding::Base64Url,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base64UrlDecodeBytes => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeBytes {
+                    encoding: ByteEncoding::Base64Url,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base64UrlDecode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeString {
+                    encoding: ByteEncoding::Base64Url,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base32Encode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdEncodeBytes {
+                    encoding: ByteEncoding::Base32,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base32DecodeBytes => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeBytes {
+                    encoding: ByteEncoding::Base32,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Base32Decode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeString {
+                    encoding: ByteEncoding::Base32,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::HexEncode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdEncodeBytes {
+                    encoding: ByteEncoding::Hex,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::HexDecodeBytes => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeBytes {
+                    encoding: ByteEncoding::Hex,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::HexDecode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeString {
+                    encoding: ByteEncoding::Hex,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::UrlEncode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdEncodeBytes {
+                    encoding: ByteEncoding::Url,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::UrlDecodeBytes => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeBytes {
+                    encoding: ByteEncoding::Url,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::UrlDecode => {
+                let [arg] = check_num_args(args);
+                self.state_stack.push(State::StdDecodeString {
+                    encoding: ByteEncoding::Url,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Md5 => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::FnFallible(Self::do_std_md5));
+                let algo = HashAlgo::Md5;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Sha1 => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::FnFallible(Self::do_std_sha1));
+                let algo = HashAlgo::Sha1;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Sha256 => {
                 let [arg] = check_num_args(args);
-                self.state_stack
-                    .push(State::FnFallible(Self::do_std_sha256));
+                let algo = HashAlgo::Sha256;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Sha512 => {
                 let [arg] = check_num_args(args);
-                self.state_stack
-                    .push(State::FnFallible(Self::do_std_sha512));
+                let algo = HashAlgo::Sha512;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
             BuiltInFunc::Sha3 => {
                 let [arg] = check_num_args(args);
-                self.state_stack.push(State::FnFallible(Self::do_std_sha3));
+                let algo = HashAlgo::Sha3;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Blake2b => {
+                let [arg] = check_num_args(args);
+                let algo = HashAlgo::Blake2b;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Blake3 => {
+                let [arg] = check_num_args(args);
+                let algo = HashAlgo::Blake3;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Crc32 => {
+                let [arg] = check_num_args(args);
+                let algo = HashAlgo::Crc32;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
                 self.state_stack.push(State::DoThunk(arg.view()));
             }
+            BuiltInFunc::Xxh64 => {
+                let [arg] = check_num_args(args);
+                let algo = HashAlgo::Xxh64;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Xxh3 => {
+                let [arg] = check_num_args(args);
+                let algo = HashAlgo::Xxh3;
+                self.state_stack.push(State::StdHash { algo });
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: algo.func_name(),
+                    arg_index: 0,
+                });
+                self.state_stack.push(State::DoThunk(arg.view()));
+            }
+            BuiltInFunc::Hmac => {
+                let [arg0, arg1, arg2] = check_num_args(args);
+                self.state_stack.push(State::StdHmac);
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: "hmac",
+                    arg_index: 2,
+                });
+                self.state_stack.push(State::DoThunk(arg2.view()));
+                self.state_stack.push(State::StdCollectBytes {
+                    func_name: "hmac",
+                    arg_index: 1,
+                });
+                self.state_stack.push(State::DoThunk(arg1.view()));
+                self.state_stack.push(State::DoThunk(arg0.view()));
+            }
             BuiltInFunc::MergePatch => {
                 let [arg0, arg1] = check_num_args(args);
                 self.state_stack.push(State::StdMergePatchValue);
@@ -1056,9 +1358,15 @@ impl<'p> Evaluator<'_, 'p> {
         }
     }
 
-    fn execute_native_call(&mut self, name: InternedStr<'p>, args: &[Gc<ThunkData<'p>>]) {
+    fn execute_native_call(
+        &mut self,
+        name: InternedStr<'p>,
+        params: &'p [(InternedStr<'p>, Option<&'p ir::Expr<'p>>)],
+        args: &[Gc<ThunkData<'p>>],
+    ) {
         self.state_stack.push(State::ExecNativeCall {
             name,
+            params,
             args: args.iter().map(Gc::view).collect(),
         });
 
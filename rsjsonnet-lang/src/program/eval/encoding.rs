@@ -0,0 +1,323 @@
+use std::fmt::Write as _;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Text encodings of byte strings provided by the standard library.
+///
+/// Strings are encoded one byte per codepoint (like `std.base64`), except
+/// with `Url`, which encodes strings as UTF-8.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(super) enum ByteEncoding {
+    /// RFC 4648 base64, with padding.
+    Base64,
+    /// RFC 4648 base64 with the URL and filename safe alphabet, without
+    /// padding.
+    Base64Url,
+    /// RFC 4648 base32, with padding.
+    Base32,
+    /// Lowercase hexadecimal.
+    Hex,
+    /// Percent-encoding of URL query components.
+    Url,
+}
+
+impl ByteEncoding {
+    /// Name used in error messages.
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Self::Base64 => "base64",
+            Self::Base64Url => "base64url",
+            Self::Base32 => "base32",
+            Self::Hex => "hex",
+            Self::Url => "URL",
+        }
+    }
+
+    pub(super) fn encode_func_name(self) -> &'static str {
+        match self {
+            Self::Base64 => "base64",
+            Self::Base64Url => "base64UrlEncode",
+            Self::Base32 => "base32Encode",
+            Self::Hex => "hexEncode",
+            Self::Url => "urlEncode",
+        }
+    }
+
+    pub(super) fn decode_func_name(self) -> &'static str {
+        match self {
+            Self::Base64 => "base64Decode",
+            Self::Base64Url => "base64UrlDecode",
+            Self::Base32 => "base32Decode",
+            Self::Hex => "hexDecode",
+            Self::Url => "urlDecode",
+        }
+    }
+
+    pub(super) fn decode_bytes_func_name(self) -> &'static str {
+        match self {
+            Self::Base64 => "base64DecodeBytes",
+            Self::Base64Url => "base64UrlDecodeBytes",
+            Self::Base32 => "base32DecodeBytes",
+            Self::Hex => "hexDecodeBytes",
+            Self::Url => "urlDecodeBytes",
+        }
+    }
+
+    pub(super) fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Base64 => encode_base64(bytes, BASE64_ALPHABET, true),
+            Self::Base64Url => encode_base64(bytes, BASE64_URL_ALPHABET, false),
+            Self::Base32 => encode_base32(bytes),
+            Self::Hex => {
+                let mut encoded = String::with_capacity(bytes.len() * 2);
+                for &byte in bytes {
+                    write!(encoded, "{byte:02x}").unwrap();
+                }
+                encoded
+            }
+            Self::Url => encode_url(bytes),
+        }
+    }
+
+    pub(super) fn decode(self, encoded: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Base64 => decode_base64(encoded),
+            Self::Base64Url => decode_base64_url(encoded),
+            Self::Base32 => decode_base32(encoded),
+            Self::Hex => decode_hex(encoded),
+            Self::Url => decode_url(encoded),
+        }
+    }
+}
+
+fn encode_base64(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0];
+        let b1 = chunk.get(1).copied().unwrap_or(0);
+        let b2 = chunk.get(2).copied().unwrap_or(0);
+
+        encoded.push(char::from(alphabet[usize::from(b0 >> 2)]));
+        encoded.push(char::from(
+            alphabet[usize::from(((b0 & 0b11) << 4) | (b1 >> 4))],
+        ));
+        if chunk.len() > 1 {
+            encoded.push(char::from(
+                alphabet[usize::from(((b1 & 0b1111) << 2) | (b2 >> 6))],
+            ));
+        } else if pad {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(char::from(alphabet[usize::from(b2 & 0b111111)]));
+        } else if pad {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let encoded: Vec<_> = encoded.chars().collect();
+    let mut chunks = encoded.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return Err("length of base64 string is not a multiple of 4".into());
+    }
+
+    let mut decoded = Vec::new();
+    if let Some(last_chunk) = chunks.next_back() {
+        fn chr_to_index(chr: char) -> Result<u8, String> {
+            match chr {
+                'A'..='Z' => Ok(chr as u8 - b'A'),
+                'a'..='z' => Ok(chr as u8 - b'a' + 26),
+                '0'..='9' => Ok(chr as u8 - b'0' + 52),
+                '+' => Ok(62),
+                '/' => Ok(63),
+                _ => Err(format!("invalid base64 character: {chr:?}")),
+            }
+        }
+
+        for chunk in chunks {
+            let i0 = chr_to_index(chunk[0])?;
+            let i1 = chr_to_index(chunk[1])?;
+            let i2 = chr_to_index(chunk[2])?;
+            let i3 = chr_to_index(chunk[3])?;
+            decoded.push((i0 << 2) | (i1 >> 4));
+            decoded.push((i1 << 4) | (i2 >> 2));
+            decoded.push((i2 << 6) | i3);
+        }
+
+        let i0 = chr_to_index(last_chunk[0])?;
+        let i1 = chr_to_index(last_chunk[1])?;
+        decoded.push((i0 << 2) | (i1 >> 4));
+
+        match (last_chunk[2], last_chunk[3]) {
+            ('=', '=') => {}
+            (e2, '=') => {
+                let i2 = chr_to_index(e2)?;
+                decoded.push((i1 << 4) | (i2 >> 2));
+            }
+            (e2, e3) => {
+                let i2 = chr_to_index(e2)?;
+                let i3 = chr_to_index(e3)?;
+                decoded.push((i1 << 4) | (i2 >> 2));
+                decoded.push((i2 << 6) | i3);
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes base64url. Padding is not required, but accepted.
+fn decode_base64_url(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut encoded = encoded;
+    if encoded.len() % 4 == 0 {
+        encoded = encoded.strip_suffix('=').unwrap_or(encoded);
+        encoded = encoded.strip_suffix('=').unwrap_or(encoded);
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut num_bits = 0;
+    let mut num_chars = 0usize;
+    for chr in encoded.chars() {
+        let index = match chr {
+            'A'..='Z' => chr as u8 - b'A',
+            'a'..='z' => chr as u8 - b'a' + 26,
+            '0'..='9' => chr as u8 - b'0' + 52,
+            '-' => 62,
+            '_' => 63,
+            _ => return Err(format!("invalid base64url character: {chr:?}")),
+        };
+        acc = (acc << 6) | u32::from(index);
+        num_bits += 6;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            decoded.push((acc >> num_bits) as u8);
+            acc &= (1 << num_bits) - 1;
+        }
+        num_chars += 1;
+    }
+    if num_chars % 4 == 1 {
+        return Err("invalid length of base64url string".into());
+    }
+
+    Ok(decoded)
+}
+
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut acc = 0u32;
+    let mut num_bits = 0;
+    for &byte in bytes {
+        acc = (acc << 8) | u32::from(byte);
+        num_bits += 8;
+        while num_bits >= 5 {
+            num_bits -= 5;
+            encoded.push(char::from(
+                BASE32_ALPHABET[((acc >> num_bits) & 0b11111) as usize],
+            ));
+        }
+        acc &= (1 << num_bits) - 1;
+    }
+    if num_bits != 0 {
+        encoded.push(char::from(
+            BASE32_ALPHABET[((acc << (5 - num_bits)) & 0b11111) as usize],
+        ));
+    }
+    while encoded.len() % 8 != 0 {
+        encoded.push('=');
+    }
+    encoded
+}
+
+fn decode_base32(encoded: &str) -> Result<Vec<u8>, String> {
+    if encoded.len() % 8 != 0 {
+        return Err("length of base32 string is not a multiple of 8".into());
+    }
+    let data = encoded.trim_end_matches('=');
+    if !matches!(encoded.len() - data.len(), 0 | 1 | 3 | 4 | 6) {
+        return Err("invalid base32 padding".into());
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() * 5 / 8);
+    let mut acc = 0u32;
+    let mut num_bits = 0;
+    for chr in data.chars() {
+        let index = match chr {
+            'A'..='Z' => chr as u8 - b'A',
+            'a'..='z' => chr as u8 - b'a',
+            '2'..='7' => chr as u8 - b'2' + 26,
+            _ => return Err(format!("invalid base32 character: {chr:?}")),
+        };
+        acc = (acc << 5) | u32::from(index);
+        num_bits += 5;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            decoded.push((acc >> num_bits) as u8);
+            acc &= (1 << num_bits) - 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn decode_hex(encoded: &str) -> Result<Vec<u8>, String> {
+    fn digit(chr: char) -> Result<u8, String> {
+        chr.to_digit(16)
+            .map(|d| d as u8)
+            .ok_or_else(|| format!("invalid hex character: {chr:?}"))
+    }
+
+    let mut chars = encoded.chars();
+    let mut decoded = Vec::with_capacity(encoded.len() / 2);
+    while let Some(hi) = chars.next() {
+        let Some(lo) = chars.next() else {
+            return Err("length of hex string is not even".into());
+        };
+        decoded.push((digit(hi)? << 4) | digit(lo)?);
+    }
+    Ok(decoded)
+}
+
+/// Percent-encodes everything except the RFC 3986 unreserved characters.
+fn encode_url(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            write!(encoded, "%{byte:02X}").unwrap();
+        }
+    }
+    encoded
+}
+
+/// Decodes percent-encoding. `+` is decoded as a space, as in
+/// `application/x-www-form-urlencoded` query strings.
+fn decode_url(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut rem = encoded.as_bytes();
+    while let Some((&byte, rest)) = rem.split_first() {
+        rem = rest;
+        match byte {
+            b'%' => {
+                let digits = rem
+                    .get(..2)
+                    .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+                    .ok_or("invalid percent-encoded sequence")?;
+                let digits = std::str::from_utf8(digits).unwrap();
+                decoded.push(u8::from_str_radix(digits, 16).unwrap());
+                rem = &rem[2..];
+            }
+            b'+' => decoded.push(b' '),
+            _ => decoded.push(byte),
+        }
+    }
+    Ok(decoded)
+}
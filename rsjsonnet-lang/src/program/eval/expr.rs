@@ -1,14 +1,16 @@
+use std::borrow::Cow;
 use std::cell::{Cell, OnceCell};
 
+use super::super::fork::ForkStates;
 use super::super::{
-    ArrayData, FuncData, FuncKind, ImportError, ObjectData, ObjectLayer, ThunkEnv, ThunkEnvData,
-    ValueData, ir,
+    ArrayData, FuncData, FuncKind, ImportError, ObjectData, ObjectLayer, StringData, ThunkEnv,
+    ThunkEnvData, ValueData, ir,
 };
 use super::{EvalErrorKind, EvalErrorValueType, EvalResult, Evaluator, State, TraceItem};
 use crate::gc::{Gc, GcView};
 use crate::interner::InternedStr;
 use crate::span::SpanId;
-use crate::{FHashMap, ast};
+use crate::{FHashMap, ast, float};
 
 impl<'p> Evaluator<'_, 'p> {
     pub(super) fn do_expr(
@@ -35,6 +37,7 @@ impl<'p> Evaluator<'_, 'p> {
                 locals: ir_locals,
                 asserts: ir_asserts,
                 fields: ir_fields,
+                fields_order,
             } => {
                 self.object_stack.push(ObjectData {
                     self_layer: ObjectLayer {
@@ -42,12 +45,18 @@ impl<'p> Evaluator<'_, 'p> {
                         locals: ir_locals,
                         base_env: Some(Gc::from(&env)),
                         env: OnceCell::new(),
-                        fields: FHashMap::default(),
+                        fields: FHashMap::with_capacity_and_hasher(
+                            ir_fields.len(),
+                            Default::default(),
+                        ),
                         asserts: ir_asserts,
                     },
                     super_layers: Vec::new(),
-                    fields_order: OnceCell::new(),
+                    fields_order: fields_order
+                        .map(|order| OnceCell::from(Cow::Borrowed(order)))
+                        .unwrap_or_default(),
                     asserts_checked: Cell::new(false),
+                    fork_asserts_checked: ForkStates::new(),
                 });
 
                 self.state_stack.push(State::ObjectToValue);
@@ -166,6 +175,16 @@ impl<'p> Evaluator<'_, 'p> {
                 }
                 self.state_stack.push(State::Expr { expr: array, env });
             }
+            ir::Expr::StdField {
+                field_name,
+                expr_span,
+            } => {
+                let thunk = self.program.stdlib_builtins[&field_name].clone();
+                self.want_thunk_direct(thunk, || TraceItem::ObjectField {
+                    span: Some(expr_span),
+                    name: field_name,
+                });
+            }
             ir::Expr::SuperField {
                 super_span,
                 field_name,
@@ -195,8 +214,12 @@ impl<'p> Evaluator<'_, 'p> {
                     env: env.clone(),
                 });
             }
-            ir::Expr::Var(var_name, span) => {
-                let thunk = env.get_var(var_name).view();
+            ir::Expr::Var {
+                name: var_name,
+                slot,
+                span,
+            } => {
+                let thunk = env.get_var(slot).view();
                 self.want_thunk_direct(thunk, || TraceItem::Variable {
                     span,
                     name: var_name,
@@ -214,12 +237,16 @@ impl<'p> Evaluator<'_, 'p> {
                 let new_env = self.program.gc_alloc_view(ThunkEnv::new());
                 let mut new_env_data = ThunkEnvData::new(Some(Gc::from(&env)));
                 for &(var_name, value_expr) in bindings.iter() {
-                    let var_thunk = self.program.new_pending_expr_thunk(
-                        value_expr,
-                        Gc::from(&new_env),
-                        Some(var_name),
-                    );
-                    new_env_data.set_var(var_name, var_thunk);
+                    let var_thunk = if let Some(value_expr) = value_expr {
+                        self.program.new_pending_expr_thunk(
+                            value_expr,
+                            Gc::from(&new_env),
+                            Some(var_name),
+                        )
+                    } else {
+                        Gc::from(&self.program.unused_var)
+                    };
+                    new_env_data.push_var(var_thunk);
                 }
                 new_env.set_data(new_env_data);
                 self.state_stack.push(State::Expr {
@@ -467,6 +494,9 @@ impl<'p> Evaluator<'_, 'p> {
                     message: msg.into(),
                 }));
             }
+            ir::Expr::Compiled { code, .. } => {
+                self.run_code(code, 0, env)?;
+            }
         }
 
         Ok(())
@@ -475,13 +505,8 @@ impl<'p> Evaluator<'_, 'p> {
     fn want_comp_spec(&mut self, comp_spec: &[ir::CompSpecPart<'p>], env: GcView<ThunkEnv<'p>>) {
         for comp_spec_part in comp_spec[1..].iter().rev() {
             match *comp_spec_part {
-                ir::CompSpecPart::For {
-                    var,
-                    value,
-                    value_span,
-                } => {
+                ir::CompSpecPart::For { value, value_span } => {
                     self.state_stack.push(State::ForSpec {
-                        var_name: var,
                         value,
                         value_span,
                         env: env.clone(),
@@ -497,13 +522,8 @@ impl<'p> Evaluator<'_, 'p> {
             }
         }
         match comp_spec[0] {
-            ir::CompSpecPart::For {
-                var,
-                value,
-                value_span,
-            } => {
+            ir::CompSpecPart::For { value, value_span } => {
                 self.state_stack.push(State::InitCompSpec {
-                    var_name: var,
                     value,
                     value_span,
                     env,
@@ -515,6 +535,99 @@ impl<'p> Evaluator<'_, 'p> {
         }
     }
 
+    /// Gets the field `field_name` of the object on top of the stack.
+    pub(super) fn do_field(&mut self, span: SpanId, field_name: InternedStr<'p>) -> EvalResult<()> {
+        let object = self.value_stack.pop().unwrap();
+        if let ValueData::Object(ref object) = object {
+            self.want_field(&object.view(), field_name, span)?;
+        } else {
+            return Err(self.report_error(EvalErrorKind::FieldOfNonObject { span }));
+        }
+        Ok(())
+    }
+
+    /// Indexes the string, array or object below the top of the stack with
+    /// the value on top of it.
+    pub(super) fn do_index(&mut self, span: SpanId) -> EvalResult<()> {
+        let index_value = self.value_stack.pop().unwrap();
+        let object_value = self.value_stack.pop().unwrap();
+        match object_value {
+            ValueData::String(ref s) => {
+                let ValueData::Number(index) = index_value else {
+                    return Err(self.report_error(EvalErrorKind::StringIndexIsNotNumber {
+                        span,
+                        got_type: EvalErrorValueType::from_value(&index_value),
+                    }));
+                };
+                let Some(index_usize) = float::try_to_usize_exact(index) else {
+                    return Err(self.report_error(EvalErrorKind::NumericIndexIsNotValid {
+                        span,
+                        index: index.to_string(),
+                    }));
+                };
+                if let Some(chr) = s.chars().nth(index_usize) {
+                    self.value_stack.push(ValueData::from_char(chr));
+                } else {
+                    return Err(self.report_error(EvalErrorKind::NumericIndexOutOfRange {
+                        span,
+                        index: index_usize,
+                        length: s.chars().count(),
+                    }));
+                }
+            }
+            ValueData::Array(ref array) => {
+                let array = array.view();
+                let ValueData::Number(index) = index_value else {
+                    return Err(self.report_error(EvalErrorKind::ArrayIndexIsNotNumber {
+                        span,
+                        got_type: EvalErrorValueType::from_value(&index_value),
+                    }));
+                };
+                let Some(index_usize) = float::try_to_usize_exact(index) else {
+                    return Err(self.report_error(EvalErrorKind::NumericIndexIsNotValid {
+                        span,
+                        index: index.to_string(),
+                    }));
+                };
+                if let Some(item) = array.get(index_usize) {
+                    self.want_thunk_direct(item.view(), || TraceItem::ArrayItem {
+                        span: Some(span),
+                        index: index_usize,
+                    });
+                } else {
+                    return Err(self.report_error(EvalErrorKind::NumericIndexOutOfRange {
+                        span,
+                        index: index_usize,
+                        length: array.len(),
+                    }));
+                }
+            }
+            ValueData::Object(ref object) => {
+                let ValueData::String(ref field_name) = index_value else {
+                    return Err(self.report_error(EvalErrorKind::ObjectIndexIsNotString {
+                        span,
+                        got_type: EvalErrorValueType::from_value(&index_value),
+                    }));
+                };
+                if let Some(field_name) = self.program.str_interner.get_interned(field_name) {
+                    self.want_field(&object.view(), field_name, span)?;
+                } else {
+                    return Err(self.report_error(EvalErrorKind::UnknownObjectField {
+                        span,
+                        field_name: (**field_name).into(),
+                    }));
+                }
+            }
+            _ => {
+                return Err(self.report_error(EvalErrorKind::InvalidIndexedType {
+                    span,
+                    got_type: EvalErrorValueType::from_value(&object_value),
+                }));
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn want_field(
         &mut self,
         object: &GcView<ObjectData<'p>>,
@@ -522,7 +635,7 @@ impl<'p> Evaluator<'_, 'p> {
         expr_span: SpanId,
     ) -> EvalResult<()> {
         if let Some(field_thunk) = self.program.find_object_field_thunk(object, 0, field_name) {
-            if object.asserts_checked.get() {
+            if self.program.object_asserts_checked(object) {
                 self.want_thunk_direct(field_thunk, || TraceItem::ObjectField {
                     span: Some(expr_span),
                     name: field_name,
@@ -695,6 +808,33 @@ impl<'p> Evaluator<'_, 'p> {
         Ok((start, end, step))
     }
 
+    pub(super) fn do_unary_op(&mut self, span: SpanId, op: ast::UnaryOp) -> EvalResult<()> {
+        let rhs = self.value_stack.pop().unwrap();
+        match (op, rhs) {
+            (ast::UnaryOp::Minus, ValueData::Number(rhs)) => {
+                self.value_stack.push(ValueData::Number(-rhs));
+            }
+            (ast::UnaryOp::Plus, ValueData::Number(rhs)) => {
+                self.value_stack.push(ValueData::Number(rhs));
+            }
+            (ast::UnaryOp::BitwiseNot, ValueData::Number(rhs)) => {
+                let int = self.safe_f64_to_i64(rhs, Some(span))?;
+                self.value_stack.push(ValueData::Number(!int as f64));
+            }
+            (ast::UnaryOp::LogicNot, ValueData::Bool(rhs)) => {
+                self.value_stack.push(ValueData::Bool(!rhs));
+            }
+            (_, rhs) => {
+                return Err(self.report_error(EvalErrorKind::InvalidUnaryOpType {
+                    span,
+                    op,
+                    rhs_type: EvalErrorValueType::from_value(&rhs),
+                }));
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn do_binary_op(
         &mut self,
         span: Option<SpanId>,
@@ -801,16 +941,8 @@ impl<'p> Evaluator<'_, 'p> {
             }
             // String
             (ast::BinaryOp::Add, ValueData::String(lhs), ValueData::String(rhs)) => {
-                if lhs.is_empty() {
-                    self.value_stack.push(ValueData::String(rhs));
-                } else if rhs.is_empty() {
-                    self.value_stack.push(ValueData::String(lhs));
-                } else {
-                    let mut r = String::with_capacity(lhs.len() + rhs.len());
-                    r.push_str(&lhs);
-                    r.push_str(&rhs);
-                    self.value_stack.push(ValueData::String(r.into()));
-                }
+                let r = StringData::concat(&lhs, &rhs);
+                self.value_stack.push(ValueData::String(r));
             }
             // Array
             (ast::BinaryOp::Add, ValueData::Array(lhs), ValueData::Array(rhs)) => {
@@ -1,5 +1,6 @@
 use std::rc::Rc;
 
+use super::super::string_data::StringBuilder;
 use super::super::{ArrayData, ObjectData, ThunkData, ValueData};
 use super::manifest::ManifestJsonFormat;
 use super::{EvalError, EvalErrorKind, EvalErrorValueType, EvalResult, Evaluator, State};
@@ -97,7 +98,9 @@ impl<'p> Evaluator<'_, 'p> {
             }
             val => {
                 let val = self.program.gc_alloc(ThunkData::new_done(val));
-                let array: GcView<Box<[_]>> = self.program.gc_alloc_view(Box::new([val]));
+                let array = self
+                    .program
+                    .gc_alloc_view(ArrayData::new_flat(Box::new([val])));
 
                 self.want_format_array(fmt_parts, array);
             }
@@ -298,7 +301,7 @@ impl<'p> Evaluator<'_, 'p> {
         parts: Vec<FormatPart>,
         array: GcView<ArrayData<'p>>,
     ) {
-        self.string_stack.push(String::new());
+        self.string_builder_stack.push(StringBuilder::new());
         self.state_stack.push(State::StdFormatCodesArray1 {
             parts: Rc::new(parts),
             array,
@@ -312,7 +315,7 @@ impl<'p> Evaluator<'_, 'p> {
         parts: Vec<FormatPart>,
         object: GcView<ObjectData<'p>>,
     ) {
-        self.string_stack.push(String::new());
+        self.string_builder_stack.push(StringBuilder::new());
         self.state_stack.push(State::StdFormatCodesObject1 {
             parts: Rc::new(parts),
             object,
@@ -337,13 +340,13 @@ impl<'p> Evaluator<'_, 'p> {
                     ),
                 }));
             } else {
-                let s = self.string_stack.pop().unwrap();
-                self.value_stack.push(ValueData::String(s.into()));
+                let s = self.string_builder_stack.pop().unwrap();
+                self.value_stack.push(ValueData::String(s.finish()));
                 return Ok(());
             }
         }
 
-        let result = self.string_stack.last_mut().unwrap();
+        let result = self.string_builder_stack.last_mut().unwrap();
         match parts[part_i] {
             FormatPart::Literal(ref s) => {
                 result.push_str(s);
@@ -505,7 +508,7 @@ impl<'p> Evaluator<'_, 'p> {
         });
 
         if code.ctype == ConvType::Percent {
-            self.string_stack.push("%".into());
+            self.value_stack.push(ValueData::String("%".into()));
         } else {
             self.state_stack.push(State::StdFormatCode {
                 parts,
@@ -531,21 +534,23 @@ impl<'p> Evaluator<'_, 'p> {
             unreachable!();
         };
 
-        let s = self.string_stack.pop().unwrap();
-        let result = self.string_stack.last_mut().unwrap();
+        let ValueData::String(s) = self.value_stack.pop().unwrap() else {
+            unreachable!();
+        };
+        let result = self.string_builder_stack.last_mut().unwrap();
 
         let fw = fw as usize;
         if s.len() < fw {
-            let pad_len = fw - s.chars().count();
+            let pad = " ".repeat(fw - s.chars().count());
             if code.cflags.left {
                 result.push_str(&s);
-                result.extend(std::iter::repeat_n(' ', pad_len));
+                result.push_str(&pad);
             } else {
-                result.extend(std::iter::repeat_n(' ', pad_len));
+                result.push_str(&pad);
                 result.push_str(&s);
             }
         } else {
-            result.push_str(&s);
+            result.push_string(&s);
         }
 
         self.state_stack.push(State::StdFormatCodesArray1 {
@@ -564,12 +569,12 @@ impl<'p> Evaluator<'_, 'p> {
         part_i: usize,
     ) -> EvalResult<()> {
         if part_i >= parts.len() {
-            let s = self.string_stack.pop().unwrap();
-            self.value_stack.push(ValueData::String(s.into()));
+            let s = self.string_builder_stack.pop().unwrap();
+            self.value_stack.push(ValueData::String(s.finish()));
             return Ok(());
         }
 
-        let result = self.string_stack.last_mut().unwrap();
+        let result = self.string_builder_stack.last_mut().unwrap();
         match parts[part_i] {
             FormatPart::Literal(ref s) => {
                 result.push_str(s);
@@ -648,7 +653,7 @@ impl<'p> Evaluator<'_, 'p> {
                 });
 
                 if code.ctype == ConvType::Percent {
-                    self.string_stack.push("%".into());
+                    self.value_stack.push(ValueData::String("%".into()));
                 } else {
                     self.state_stack.push(State::StdFormatCode {
                         parts,
@@ -676,21 +681,23 @@ impl<'p> Evaluator<'_, 'p> {
             unreachable!();
         };
 
-        let s = self.string_stack.pop().unwrap();
-        let result = self.string_stack.last_mut().unwrap();
+        let ValueData::String(s) = self.value_stack.pop().unwrap() else {
+            unreachable!();
+        };
+        let result = self.string_builder_stack.last_mut().unwrap();
 
         let fw = fw as usize;
         if s.len() < fw {
-            let pad_len = fw - s.chars().count();
+            let pad = " ".repeat(fw - s.chars().count());
             if code.cflags.left {
                 result.push_str(&s);
-                result.extend(std::iter::repeat_n(' ', pad_len));
+                result.push_str(&pad);
             } else {
-                result.extend(std::iter::repeat_n(' ', pad_len));
+                result.push_str(&pad);
                 result.push_str(&s);
             }
         } else {
-            result.push_str(&s);
+            result.push_string(&s);
         }
 
         self.state_stack.push(State::StdFormatCodesObject1 {
@@ -755,7 +762,7 @@ impl<'p> Evaluator<'_, 'p> {
                     code.cflags.blank,
                 );
 
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::Octal => {
                 let ValueData::Number(value) = value else {
@@ -782,7 +789,7 @@ impl<'p> Evaluator<'_, 'p> {
                     8,
                     zero_prefix,
                 );
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::HexLower | ConvType::HexUpper => {
                 let ValueData::Number(value) = value else {
@@ -804,7 +811,7 @@ impl<'p> Evaluator<'_, 'p> {
                     code.cflags.alt,
                     code.ctype == ConvType::HexUpper,
                 );
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::ExpLower | ConvType::ExpUpper => {
                 let ValueData::Number(value) = value else {
@@ -827,7 +834,7 @@ impl<'p> Evaluator<'_, 'p> {
                     false,
                     code.ctype == ConvType::ExpUpper,
                 );
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::FloatLower | ConvType::FloatUpper => {
                 let ValueData::Number(value) = value else {
@@ -849,7 +856,7 @@ impl<'p> Evaluator<'_, 'p> {
                     code.cflags.alt,
                     false,
                 );
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::FloatGLower | ConvType::FloatGUpper => {
                 let ValueData::Number(value) = value else {
@@ -898,7 +905,7 @@ impl<'p> Evaluator<'_, 'p> {
                         !code.cflags.alt,
                     )
                 };
-                self.string_stack.push(s);
+                self.value_stack.push(ValueData::String(s.into()));
             }
             ConvType::Char => match value {
                 ValueData::String(s) => {
@@ -911,7 +918,7 @@ impl<'p> Evaluator<'_, 'p> {
                             ),
                         }));
                     }
-                    self.string_stack.push((*s).into());
+                    self.value_stack.push(ValueData::String(s));
                 }
                 ValueData::Number(n) => {
                     let Some(chr) = float::try_to_u32(n).and_then(char::from_u32) else {
@@ -922,7 +929,7 @@ impl<'p> Evaluator<'_, 'p> {
                     };
                     let mut utf8_buf = [0; 4];
                     let utf8 = chr.encode_utf8(&mut utf8_buf);
-                    self.string_stack.push(utf8.into());
+                    self.value_stack.push(ValueData::String((*utf8).into()));
                 }
                 _ => {
                     return Err(self.report_error(EvalErrorKind::Other {
@@ -936,8 +943,9 @@ impl<'p> Evaluator<'_, 'p> {
             },
             ConvType::String => {
                 if let ValueData::String(s) = value {
-                    self.string_stack.push((*s).into());
+                    self.value_stack.push(ValueData::String(s));
                 } else {
+                    self.state_stack.push(State::StringToValue);
                     self.state_stack.push(State::ManifestJson {
                         format: ManifestJsonFormat::default_to_string(),
                         depth: 0,
@@ -0,0 +1,134 @@
+use std::fmt::Write as _;
+
+/// Hash functions provided by the standard library.
+#[derive(Copy, Clone)]
+pub(super) enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    /// SHA3-512
+    Sha3,
+    /// BLAKE2b-512
+    Blake2b,
+    /// BLAKE3 with a 256-bit output.
+    Blake3,
+    /// CRC-32 (ISO-HDLC), as used by zlib and gzip.
+    Crc32,
+    /// XXH64 with a zero seed.
+    Xxh64,
+    /// 64-bit XXH3 with a zero seed.
+    Xxh3,
+}
+
+impl HashAlgo {
+    /// Name of the standard library function, also used to select the
+    /// algorithm in `std.hmac`.
+    pub(super) fn func_name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Sha3 => "sha3",
+            Self::Blake2b => "blake2b",
+            Self::Blake3 => "blake3",
+            Self::Crc32 => "crc32",
+            Self::Xxh64 => "xxh64",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    /// Returns the hash of `data` as a lowercase hexadecimal string.
+    pub(super) fn hash(self, data: &[u8]) -> String {
+        use sha1::Digest as _;
+
+        match self {
+            Self::Md5 => to_hex_string(&md5::Md5::digest(data)),
+            Self::Sha1 => to_hex_string(&sha1::Sha1::digest(data)),
+            Self::Sha256 => to_hex_string(&sha2::Sha256::digest(data)),
+            Self::Sha512 => to_hex_string(&sha2::Sha512::digest(data)),
+            Self::Sha3 => to_hex_string(&sha3::Sha3_512::digest(data)),
+            Self::Blake2b => to_hex_string(&blake2::Blake2b512::digest(data)),
+            Self::Blake3 => to_hex_string(blake3::hash(data).as_bytes()),
+            Self::Crc32 => format!("{:08x}", crc32(data)),
+            Self::Xxh64 => format!("{:016x}", xxhash_rust::xxh64::xxh64(data, 0)),
+            Self::Xxh3 => format!("{:016x}", xxhash_rust::xxh3::xxh3_64(data)),
+        }
+    }
+
+    /// Returns the HMAC of `msg` as a lowercase hexadecimal string, or
+    /// `None` if the algorithm is not a cryptographic hash.
+    pub(super) fn hmac(self, key: &[u8], msg: &[u8]) -> Option<String> {
+        fn compute<M: hmac::KeyInit + hmac::Mac>(key: &[u8], msg: &[u8]) -> String {
+            let mut mac = M::new_from_slice(key).unwrap();
+            mac.update(msg);
+            to_hex_string(&mac.finalize().into_bytes())
+        }
+
+        match self {
+            Self::Md5 => Some(compute::<hmac::Hmac<md5::Md5>>(key, msg)),
+            Self::Sha1 => Some(compute::<hmac::Hmac<sha1::Sha1>>(key, msg)),
+            Self::Sha256 => Some(compute::<hmac::Hmac<sha2::Sha256>>(key, msg)),
+            Self::Sha512 => Some(compute::<hmac::Hmac<sha2::Sha512>>(key, msg)),
+            Self::Sha3 => Some(compute::<hmac::Hmac<sha3::Sha3_512>>(key, msg)),
+            // BLAKE2b does not implement the eager buffering used by `Hmac`.
+            Self::Blake2b => Some(compute::<hmac::SimpleHmac<blake2::Blake2b512>>(key, msg)),
+            // BLAKE3 has its own keyed mode instead of HMAC.
+            Self::Blake3 | Self::Crc32 | Self::Xxh64 | Self::Xxh3 => None,
+        }
+    }
+
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Md5,
+            Self::Sha1,
+            Self::Sha256,
+            Self::Sha512,
+            Self::Sha3,
+            Self::Blake2b,
+            Self::Blake3,
+            Self::Crc32,
+            Self::Xxh64,
+            Self::Xxh3,
+        ]
+        .into_iter()
+        .find(|algo| algo.func_name() == name)
+    }
+}
+
+fn to_hex_string(hash: &[u8]) -> String {
+    let mut hash_string = String::with_capacity(hash.len() * 2);
+    for &byte in hash {
+        write!(hash_string, "{byte:02x}").unwrap();
+    }
+    hash_string
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[usize::from((crc as u8) ^ byte)] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -2,11 +2,12 @@ use std::fmt::Write as _;
 use std::rc::Rc;
 
 use super::super::{ArrayData, ObjectData, ValueData};
-use super::{EvalErrorKind, EvalResult, Evaluator, State, TraceItem};
+use super::parse_csv::CsvFormat;
+use super::{EvalErrorKind, EvalErrorValueType, EvalResult, Evaluator, State, TraceItem};
 use crate::gc::GcView;
 use crate::interner::InternedStr;
 
-pub(super) struct ManifestJsonFormat {
+pub(in super::super) struct ManifestJsonFormat {
     indent: Box<str>,
     newline: Box<str>,
     key_val_sep: Box<str>,
@@ -39,18 +40,184 @@ impl ManifestJsonFormat {
     }
 
     pub(super) fn for_std_manifest_ex(indent: &str, newline: &str, key_val_sep: &str) -> Rc<Self> {
+        Self::custom(indent, newline, key_val_sep, ",")
+    }
+
+    pub(super) fn custom(
+        indent: &str,
+        newline: &str,
+        key_val_sep: &str,
+        item_sep: &str,
+    ) -> Rc<Self> {
         Rc::new(Self {
             indent: indent.into(),
             newline: newline.into(),
             key_val_sep: key_val_sep.into(),
-            item_sep: ",".into(),
+            item_sep: item_sep.into(),
             empty_array: None,
             empty_object: None,
         })
     }
 }
 
+/// Format of a value manifested with `EvalInput::Manifest`.
+pub(in super::super) enum ManifestKind {
+    Json(Rc<ManifestJsonFormat>),
+    YamlDoc {
+        indent_array_in_object: bool,
+        quote_keys: bool,
+    },
+    YamlStream {
+        indent_array_in_object: bool,
+        c_document_end: bool,
+        quote_keys: bool,
+    },
+    Toml {
+        indent: Rc<str>,
+    },
+    Ini,
+}
+
+impl ManifestKind {
+    pub(in super::super) fn json(multiline: bool) -> Self {
+        Self::Json(if multiline {
+            ManifestJsonFormat::default_manifest()
+        } else {
+            ManifestJsonFormat::default_to_string()
+        })
+    }
+
+    pub(in super::super) fn json_ex(
+        indent: &str,
+        newline: &str,
+        key_val_sep: &str,
+        item_sep: &str,
+    ) -> Self {
+        Self::Json(ManifestJsonFormat::custom(
+            indent,
+            newline,
+            key_val_sep,
+            item_sep,
+        ))
+    }
+}
+
 impl<'p> Evaluator<'_, 'p> {
+    pub(super) fn do_manifest(&mut self, kind: ManifestKind) -> EvalResult<()> {
+        match kind {
+            ManifestKind::Json(format) => {
+                self.state_stack
+                    .push(State::ManifestJson { format, depth: 0 });
+            }
+            ManifestKind::YamlDoc {
+                indent_array_in_object,
+                quote_keys,
+            } => {
+                self.state_stack.push(State::ManifestYamlDoc {
+                    indent_array_in_object,
+                    quote_keys,
+                    depth: 0,
+                    parent_is_array: false,
+                    parent_is_object: false,
+                });
+            }
+            ManifestKind::YamlStream {
+                indent_array_in_object,
+                c_document_end,
+                quote_keys,
+            } => {
+                let ValueData::Array(array) = self.value_stack.pop().unwrap() else {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: "yaml stream must be an array".into(),
+                    }));
+                };
+                self.prepare_manifest_yaml_stream(
+                    array.view(),
+                    indent_array_in_object,
+                    c_document_end,
+                    quote_keys,
+                );
+            }
+            ManifestKind::Toml { indent } => {
+                let ValueData::Object(object) = self.value_stack.pop().unwrap() else {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: "toml root must be an object".into(),
+                    }));
+                };
+                self.prepare_manifest_toml_table(object.view(), false, Rc::new([]), indent);
+            }
+            ManifestKind::Ini => {
+                let ValueData::Object(object) = self.value_stack.pop().unwrap() else {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: "ini root must be an object".into(),
+                    }));
+                };
+                self.prepare_manifest_ini(object.view())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn prepare_manifest_ini(&mut self, ini: GcView<ObjectData<'p>>) -> EvalResult<()> {
+        let main_name = self.program.intern_str("main");
+        let sections_name = self.program.intern_str("sections");
+
+        let Some(sections_thunk) = self.program.find_object_field_thunk(&ini, 0, sections_name)
+        else {
+            return Err(self.report_error(EvalErrorKind::Other {
+                span: None,
+                message: "missing \"sections\" field".into(),
+            }));
+        };
+
+        let main_thunk = self.program.find_object_field_thunk(&ini, 0, main_name);
+
+        self.state_stack.push(State::StdManifestIniSections);
+        self.state_stack.push(State::DoThunk(sections_thunk));
+        if let Some(main_thunk) = main_thunk {
+            self.state_stack.push(State::ManifestIniSection);
+            self.state_stack.push(State::DoThunk(main_thunk));
+        }
+        self.check_object_asserts(&ini);
+
+        Ok(())
+    }
+
+    pub(super) fn prepare_manifest_yaml_stream(
+        &mut self,
+        array: GcView<ArrayData<'p>>,
+        indent_array_in_object: bool,
+        c_document_end: bool,
+        quote_keys: bool,
+    ) {
+        if c_document_end {
+            self.state_stack
+                .push(State::AppendToString("\n...\n".into()));
+        } else {
+            self.state_stack.push(State::AppendToString('\n'.into()));
+        }
+
+        for (i, item) in array.iter().rev().enumerate() {
+            if i != 0 {
+                self.state_stack
+                    .push(State::AppendToString("\n---\n".into()));
+            }
+            self.state_stack.push(State::ManifestYamlDoc {
+                indent_array_in_object,
+                quote_keys,
+                depth: 0,
+                parent_is_array: false,
+                parent_is_object: false,
+            });
+            self.state_stack.push(State::DoThunk(item.view()));
+        }
+
+        self.state_stack.push(State::AppendToString("---\n".into()));
+    }
+
     pub(super) fn do_manifest_ini_section(&mut self) -> EvalResult<()> {
         let ValueData::Object(object) = self.value_stack.pop().unwrap() else {
             return Err(self.report_error(EvalErrorKind::Other {
@@ -547,13 +714,22 @@ impl<'p> Evaluator<'_, 'p> {
             .map(|name| {
                 // Check if the field is an object or an array of objects
                 let (_, field) = object.find_field(0, name).unwrap();
-                let field_value = field.thunk.get().unwrap().view().get_value().unwrap();
+                let field_value = field
+                    .thunk
+                    .get()
+                    .unwrap()
+                    .view()
+                    .get_value(&self.program.fork_node)
+                    .unwrap();
                 let is_sub_table = match field_value {
                     ValueData::Array(array) => {
                         let array = array.view();
                         !array.is_empty()
                             && array.iter().all(|item| {
-                                matches!(item.view().get_value().unwrap(), ValueData::Object(_))
+                                matches!(
+                                    item.view().get_value(&self.program.fork_node).unwrap(),
+                                    ValueData::Object(_)
+                                )
                             })
                     }
                     ValueData::Object(_) => true,
@@ -577,7 +753,13 @@ impl<'p> Evaluator<'_, 'p> {
             has_sub_tables = true;
 
             let (_, field) = object.find_field(0, field_name).unwrap();
-            let field_value = field.thunk.get().unwrap().view().get_value().unwrap();
+            let field_value = field
+                .thunk
+                .get()
+                .unwrap()
+                .view()
+                .get_value(&self.program.fork_node)
+                .unwrap();
 
             let sub_path: Rc<[_]> = path
                 .iter()
@@ -609,7 +791,9 @@ impl<'p> Evaluator<'_, 'p> {
                             self.state_stack.push(State::AppendToString('\n'.into()));
                         }
 
-                        let ValueData::Object(sub_object) = item.view().get_value().unwrap() else {
+                        let ValueData::Object(sub_object) =
+                            item.view().get_value(&self.program.fork_node).unwrap()
+                        else {
                             unreachable!();
                         };
                         let sub_object = sub_object.view();
@@ -801,6 +985,188 @@ impl<'p> Evaluator<'_, 'p> {
 
         Ok(())
     }
+
+    pub(super) fn do_manifest_csv_first_row(
+        &mut self,
+        format: Rc<CsvFormat>,
+        columns: Option<Rc<[InternedStr<'p>]>>,
+        rows: GcView<ArrayData<'p>>,
+    ) -> EvalResult<()> {
+        let first_row = self.value_stack.pop().unwrap();
+
+        let columns = match (columns, first_row) {
+            (None, ValueData::Object(object)) => {
+                let columns: Rc<[_]> = object.view().get_visible_fields_order().collect();
+                let header = self.string_stack.last_mut().unwrap();
+                if let Err(e) = write_csv_record(&format, &columns, |name| name.value(), header) {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: e.into(),
+                    }));
+                }
+                Some(columns)
+            }
+            (columns, _) => columns,
+        };
+
+        for (i, row) in rows.iter().enumerate().rev() {
+            self.push_trace_item(TraceItem::ManifestArrayItem { index: i });
+            self.state_stack.push(State::ManifestCsvRow {
+                format: format.clone(),
+                columns: columns.clone(),
+            });
+            self.state_stack.push(State::DoThunk(row.view()));
+            self.delay_trace_item();
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn do_manifest_csv_row(
+        &mut self,
+        format: Rc<CsvFormat>,
+        columns: Option<Rc<[InternedStr<'p>]>>,
+    ) -> EvalResult<()> {
+        let row = self.value_stack.pop().unwrap();
+        self.state_stack.push(State::AppendToString("\n".into()));
+        match row {
+            ValueData::Array(array) => {
+                let array = array.view();
+                if let Some(ref columns) = columns {
+                    if array.len() != columns.len() {
+                        return Err(self.report_error(EvalErrorKind::Other {
+                            span: None,
+                            message: format!(
+                                "expected {} fields, got {}",
+                                columns.len(),
+                                array.len(),
+                            ),
+                        }));
+                    }
+                }
+
+                for (i, item) in array.iter().enumerate().rev() {
+                    self.push_trace_item(TraceItem::ManifestArrayItem { index: i });
+                    self.state_stack.push(State::ManifestCsvField {
+                        format: format.clone(),
+                        only_field: array.len() == 1,
+                    });
+                    self.state_stack.push(State::DoThunk(item.view()));
+                    self.delay_trace_item();
+                    if i != 0 {
+                        self.state_stack
+                            .push(State::AppendToString(format.delimiter.into()));
+                    }
+                }
+            }
+            ValueData::Object(object) => {
+                let object = object.view();
+                let Some(columns) = columns else {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: "cannot manifest object row without column names".into(),
+                    }));
+                };
+                if let Some(field_name) = object
+                    .get_visible_fields_order()
+                    .find(|field_name| !columns.contains(field_name))
+                {
+                    return Err(self.report_error(EvalErrorKind::Other {
+                        span: None,
+                        message: format!("field {:?} is not a column", field_name.value()),
+                    }));
+                }
+
+                for (i, &column) in columns.iter().enumerate().rev() {
+                    if object.has_visible_field(column) {
+                        let field_thunk = self
+                            .program
+                            .find_object_field_thunk(&object, 0, column)
+                            .unwrap();
+                        self.push_trace_item(TraceItem::ManifestObjectField { name: column });
+                        self.state_stack.push(State::ManifestCsvField {
+                            format: format.clone(),
+                            only_field: columns.len() == 1,
+                        });
+                        self.state_stack.push(State::DoThunk(field_thunk));
+                        self.delay_trace_item();
+                    } else if columns.len() == 1 {
+                        // A single empty field must be quoted, so it is not
+                        // confused with an empty line.
+                        let mut tmp = String::new();
+                        if let Err(e) = format.write_field("", true, &mut tmp) {
+                            return Err(self.report_error(EvalErrorKind::Other {
+                                span: None,
+                                message: e.into(),
+                            }));
+                        }
+                        self.state_stack.push(State::AppendToString(tmp));
+                    }
+                    if i != 0 {
+                        self.state_stack
+                            .push(State::AppendToString(format.delimiter.into()));
+                    }
+                }
+                self.check_object_asserts(&object);
+            }
+            _ => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: format!(
+                        "CSV row must be an array or an object, got {}",
+                        EvalErrorValueType::from_value(&row).to_str(),
+                    ),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn do_manifest_csv_field(
+        &mut self,
+        format: Rc<CsvFormat>,
+        only_field: bool,
+    ) -> EvalResult<()> {
+        let value = self.value_stack.pop().unwrap();
+        let result = self.string_stack.last_mut().unwrap();
+        let r = match value {
+            ValueData::Null => format.write_field("", only_field, result),
+            ValueData::Bool(b) => {
+                format.write_field(if b { "true" } else { "false" }, only_field, result)
+            }
+            ValueData::Number(n) => format.write_field(&n.to_string(), only_field, result),
+            ValueData::String(s) => format.write_field(&s, only_field, result),
+            ValueData::Array(_) => Err("cannot manifest array in CSV field"),
+            ValueData::Object(_) => Err("cannot manifest object in CSV field"),
+            ValueData::Function(_) => {
+                return Err(self.report_error(EvalErrorKind::ManifestFunction));
+            }
+        };
+        r.map_err(|e| {
+            self.report_error(EvalErrorKind::Other {
+                span: None,
+                message: e.into(),
+            })
+        })
+    }
+}
+
+/// Appends a CSV record with `fields` to `out`, including the line break.
+pub(super) fn write_csv_record<T>(
+    format: &CsvFormat,
+    fields: &[T],
+    get_str: impl Fn(&T) -> &str,
+    out: &mut String,
+) -> Result<(), &'static str> {
+    for (i, field) in fields.iter().enumerate() {
+        if i != 0 {
+            out.push(format.delimiter);
+        }
+        format.write_field(get_str(field), fields.len() == 1, out)?;
+    }
+    out.push('\n');
+    Ok(())
 }
 
 fn is_safe_yaml_plain(s: &str) -> bool {
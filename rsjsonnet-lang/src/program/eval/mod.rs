@@ -6,26 +6,36 @@ use super::data::{
     ArrayData, FuncData, FuncKind, ObjectData, ObjectField, ObjectFieldData, ObjectLayer,
     PendingThunk, ThunkData, ThunkEnv, ThunkEnvData, ThunkState,
 };
+use super::fork::ForkStates;
+use super::string_data::StringBuilder;
 use super::{
-    Callbacks, EvalError, EvalErrorKind, EvalErrorValueType, EvalStackTraceItem, Program,
-    ValueData, ir,
+    Callbacks, EvalError, EvalErrorKind, EvalErrorValueType, EvalStackTraceItem, ManifestError,
+    NativeError, Program, ValueData, ir,
 };
 use crate::gc::{Gc, GcView};
 use crate::interner::InternedStr;
 use crate::span::SpanId;
 use crate::{FHashMap, ast, float};
 
+mod bytecode;
 mod call;
+mod encoding;
 mod expr;
 mod format;
+mod hash;
 mod manifest;
-mod parse_json;
-mod parse_yaml;
+pub(super) mod parse_csv;
+pub(super) mod parse_json;
+pub(super) mod parse_toml;
+pub(super) mod parse_xml;
+pub(super) mod parse_yaml;
 mod state;
 mod stdlib;
 
 use manifest::ManifestJsonFormat;
+pub(super) use manifest::ManifestKind;
 use state::State;
+pub(super) use stdlib::CachedRegex;
 
 // `EvalError` is boxed to reduce the size of `Result`s returned by internal
 // functions.
@@ -39,23 +49,36 @@ pub(super) struct Evaluator<'a, 'p> {
     value_stack: Vec<ValueData<'p>>,
     bool_stack: Vec<bool>,
     string_stack: Vec<String>,
+    string_builder_stack: Vec<StringBuilder>,
     array_stack: Vec<Vec<Gc<ThunkData<'p>>>>,
     object_stack: Vec<ObjectData<'p>>,
     comp_spec_stack: Vec<CompSpec<'p>>,
     cmp_ord_stack: Vec<std::cmp::Ordering>,
     byte_array_stack: Vec<Vec<u8>>,
+    output: Option<EvalOutputWriter<'a>>,
 }
 
+/// Writer that receives the output string as it is built.
+struct EvalOutputWriter<'a> {
+    writer: &'a mut dyn std::io::Write,
+    error: Option<std::io::Error>,
+}
+
+/// Size of the output string above which it is flushed to the output writer.
+const OUTPUT_FLUSH_THRESHOLD: usize = 64 * 1024;
+
 pub(super) enum EvalInput<'p> {
     Value(GcView<ThunkData<'p>>),
     Call(GcView<ThunkData<'p>>, TopLevelArgs<'p>),
-    ManifestJson(GcView<ThunkData<'p>>, bool),
+    Manifest(GcView<ThunkData<'p>>, ManifestKind),
+    ObjectFields(GcView<ThunkData<'p>>, Option<TopLevelArgs<'p>>),
 }
 
 #[must_use]
 pub(super) enum EvalOutput<'p> {
     Value(ValueData<'p>),
     String(String),
+    ObjectFields(Option<Vec<(InternedStr<'p>, GcView<ThunkData<'p>>)>>),
 }
 
 pub(super) struct TopLevelArgs<'p> {
@@ -71,6 +94,11 @@ enum TraceItem<'p> {
     Call {
         span: Option<SpanId>,
         name: Option<InternedStr<'p>>,
+        /// Whether this is the frame of a Jsonnet function, which can be
+        /// reused by `tailstrict` calls made in tail position of its body.
+        reusable: bool,
+        /// Number of tail calls made after this call.
+        tail_calls: usize,
     },
     Variable {
         span: SpanId,
@@ -102,7 +130,9 @@ enum TraceItem<'p> {
 }
 
 struct CompSpec<'p> {
-    vars: Vec<FHashMap<InternedStr<'p>, GcView<ThunkData<'p>>>>,
+    /// Values of the variables of each iteration, in the order they are
+    /// bound.
+    vars: Vec<Vec<GcView<ThunkData<'p>>>>,
 }
 
 impl<'p, 'a> Evaluator<'a, 'p> {
@@ -111,7 +141,44 @@ impl<'p, 'a> Evaluator<'a, 'p> {
         callbacks: Option<&'a mut dyn Callbacks<'p>>,
         input: EvalInput<'p>,
     ) -> EvalResult<EvalOutput<'p>> {
-        let mut this = Self {
+        Self::new(program, callbacks, None).eval_input(input)
+    }
+
+    /// Like [`Evaluator::eval`], but the output string is written to
+    /// `writer` while it is being built, so it is never fully stored in
+    /// memory.
+    ///
+    /// `input` must produce a string output.
+    pub(super) fn eval_to_writer(
+        program: &'a mut Program<'p>,
+        callbacks: Option<&'a mut dyn Callbacks<'p>>,
+        input: EvalInput<'p>,
+        writer: &'a mut dyn std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let output = EvalOutputWriter {
+            writer,
+            error: None,
+        };
+        let mut this = Self::new(program, callbacks, Some(output));
+        let result = this.eval_input(input);
+
+        let output = this.output.take().unwrap();
+        if let Some(e) = output.error {
+            return Err(ManifestError::Io(e));
+        }
+        let EvalOutput::String(s) = result.map_err(|e| ManifestError::Eval(*e))? else {
+            unreachable!();
+        };
+        output.writer.write_all(s.as_bytes())?;
+        Ok(())
+    }
+
+    fn new(
+        program: &'a mut Program<'p>,
+        callbacks: Option<&'a mut dyn Callbacks<'p>>,
+        output: Option<EvalOutputWriter<'a>>,
+    ) -> Self {
+        Self {
             program,
             callbacks,
             stack_trace_len: 0,
@@ -119,72 +186,137 @@ impl<'p, 'a> Evaluator<'a, 'p> {
             value_stack: Vec::new(),
             bool_stack: Vec::new(),
             string_stack: Vec::new(),
+            string_builder_stack: Vec::new(),
             array_stack: Vec::new(),
             object_stack: Vec::new(),
             comp_spec_stack: Vec::new(),
             cmp_ord_stack: Vec::new(),
             byte_array_stack: Vec::new(),
-        };
+            output,
+        }
+    }
 
+    fn eval_input(&mut self, input: EvalInput<'p>) -> EvalResult<EvalOutput<'p>> {
         enum OutputKind {
             Value,
             String,
+            ObjectFields,
         }
 
         let output_kind;
         match input {
             EvalInput::Value(thunk) => {
                 output_kind = OutputKind::Value;
-                this.state_stack.push(State::DeepValue);
-                this.state_stack.push(State::DoThunk(thunk));
+                self.state_stack.push(State::DeepValue);
+                self.state_stack.push(State::DoThunk(thunk));
             }
             EvalInput::Call(func, args) => {
                 output_kind = OutputKind::Value;
-                this.state_stack.push(State::DeepValue);
-                this.state_stack.push(State::TopLevelCall {
+                self.state_stack.push(State::DeepValue);
+                self.state_stack.push(State::TopLevelCall {
                     pos_args: args.positional,
                     named_args: args.named,
                 });
-                this.state_stack.push(State::DoThunk(func));
+                self.state_stack.push(State::DoThunk(func));
             }
-            EvalInput::ManifestJson(thunk, multiline) => {
+            EvalInput::Manifest(thunk, kind) => {
                 output_kind = OutputKind::String;
-                this.state_stack.push(State::ManifestJson {
-                    format: if multiline {
-                        ManifestJsonFormat::default_manifest()
-                    } else {
-                        ManifestJsonFormat::default_to_string()
-                    },
-                    depth: 0,
-                });
-                this.state_stack.push(State::DoThunk(thunk));
-                this.string_stack.push(String::new());
+                self.state_stack.push(State::Manifest(kind));
+                self.state_stack.push(State::DoThunk(thunk));
+                self.string_stack.push(String::new());
+            }
+            EvalInput::ObjectFields(thunk, args) => {
+                output_kind = OutputKind::ObjectFields;
+                self.state_stack.push(State::FnInfallible(|this| {
+                    if let ValueData::Object(object) = this.value_stack.last().unwrap() {
+                        let object = object.view();
+                        this.check_object_asserts(&object);
+                    }
+                }));
+                if let Some(args) = args {
+                    self.state_stack.push(State::TopLevelCall {
+                        pos_args: args.positional,
+                        named_args: args.named,
+                    });
+                }
+                self.state_stack.push(State::DoThunk(thunk));
             }
         }
 
-        this.run()?;
+        self.run()?;
 
         let output = match output_kind {
-            OutputKind::Value => EvalOutput::Value(this.value_stack.pop().unwrap()),
-            OutputKind::String => EvalOutput::String(this.string_stack.pop().unwrap()),
+            OutputKind::Value => EvalOutput::Value(self.value_stack.pop().unwrap()),
+            OutputKind::String => EvalOutput::String(self.string_stack.pop().unwrap()),
+            OutputKind::ObjectFields => {
+                let fields = match self.value_stack.pop().unwrap() {
+                    ValueData::Object(object) => {
+                        let object = object.view();
+                        let fields = object
+                            .get_fields_order()
+                            .iter()
+                            .filter(|&&(_, visibility)| visibility != ast::Visibility::Hidden)
+                            .map(|&(name, _)| {
+                                let thunk = self
+                                    .program
+                                    .find_object_field_thunk(&object, 0, name)
+                                    .unwrap();
+                                (name, thunk)
+                            })
+                            .collect();
+                        Some(fields)
+                    }
+                    _ => None,
+                };
+                EvalOutput::ObjectFields(fields)
+            }
         };
 
-        assert_eq!(this.stack_trace_len, 0);
-        assert!(this.state_stack.is_empty());
-        assert!(this.value_stack.is_empty());
-        assert!(this.bool_stack.is_empty());
-        assert!(this.string_stack.is_empty());
-        assert!(this.array_stack.is_empty());
-        assert!(this.object_stack.is_empty());
-        assert!(this.comp_spec_stack.is_empty());
-        assert!(this.cmp_ord_stack.is_empty());
-        assert!(this.byte_array_stack.is_empty());
+        assert_eq!(self.stack_trace_len, 0);
+        assert!(self.state_stack.is_empty());
+        assert!(self.value_stack.is_empty());
+        assert!(self.bool_stack.is_empty());
+        assert!(self.string_stack.is_empty());
+        assert!(self.string_builder_stack.is_empty());
+        assert!(self.array_stack.is_empty());
+        assert!(self.object_stack.is_empty());
+        assert!(self.comp_spec_stack.is_empty());
+        assert!(self.cmp_ord_stack.is_empty());
+        assert!(self.byte_array_stack.is_empty());
 
         Ok(output)
     }
 
+    /// Writes the output string to the output writer if it is large enough.
+    ///
+    /// The output string is at the bottom of the string stack, so it can only
+    /// be flushed when there are no other strings being built.
+    fn flush_output(&mut self) -> EvalResult<()> {
+        let [output_str] = self.string_stack.as_mut_slice() else {
+            return Ok(());
+        };
+        if output_str.len() < OUTPUT_FLUSH_THRESHOLD {
+            return Ok(());
+        }
+        let output = self.output.as_mut().unwrap();
+        if let Err(e) = output.writer.write_all(output_str.as_bytes()) {
+            output.error = Some(e);
+            // The actual error is taken from `self.output` by
+            // `Evaluator::eval_to_writer`.
+            return Err(self.report_error(EvalErrorKind::Other {
+                span: None,
+                message: "failed to write output".into(),
+            }));
+        }
+        output_str.clear();
+        Ok(())
+    }
+
     fn run(&mut self) -> EvalResult<()> {
         while let Some(state) = self.state_stack.pop() {
+            if self.output.is_some() {
+                self.flush_output()?;
+            }
             match state {
                 State::FnInfallible(f) => f(self),
                 State::FnFallible(f) => f(self)?,
@@ -197,7 +329,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                 State::DiscardValue => {
                     self.value_stack.pop().unwrap();
                 }
-                State::DoThunk(thunk) => match thunk.switch_state() {
+                State::DoThunk(thunk) => match self.program.switch_thunk_state(&thunk) {
                     ThunkState::Done(value) => {
                         self.value_stack.push(value);
                     }
@@ -245,24 +377,15 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                 },
                 State::GotThunk(thunk) => {
                     let value = self.value_stack.last().unwrap();
-                    thunk.set_done(value.clone());
+                    self.program.set_thunk_done(&thunk, value.clone());
                 }
                 State::DeepValue => {
-                    #[inline]
-                    fn might_need_deep(thunk: &ThunkData<'_>) -> bool {
-                        if let ThunkState::Done(ref value) = *thunk.state() {
-                            value.might_need_deep()
-                        } else {
-                            true
-                        }
-                    }
-
                     let value = self.value_stack.last().unwrap();
                     if let ValueData::Array(array) = value {
                         let array = array.view();
                         for (i, item) in array.iter().enumerate().rev() {
                             let item = item.view();
-                            if might_need_deep(&item) {
+                            if item.might_need_deep(&self.program.fork_node) {
                                 self.push_trace_item(TraceItem::ArrayItem {
                                     span: None,
                                     index: i,
@@ -283,7 +406,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                                     .program
                                     .find_object_field_thunk(&object, 0, field_name)
                                     .unwrap();
-                                if might_need_deep(&thunk) {
+                                if thunk.might_need_deep(&self.program.fork_node) {
                                     self.push_trace_item(TraceItem::ObjectField {
                                         span: None,
                                         name: field_name,
@@ -381,9 +504,8 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                 }
                 State::ArrayToValue => {
                     let array = self.array_stack.pop().unwrap();
-                    self.value_stack.push(ValueData::Array(
-                        self.program.gc_alloc(array.into_boxed_slice()),
-                    ));
+                    self.value_stack
+                        .push(ValueData::Array(self.program.make_thunk_array(array)));
                 }
                 State::ObjectToValue => {
                     let object = self.object_stack.pop().unwrap();
@@ -452,6 +574,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
 
                     self.do_slice(indexable, start, end, step, false, Some(span))?;
                 }
+                State::Manifest(kind) => self.do_manifest(kind)?,
                 State::ManifestIniSection => self.do_manifest_ini_section()?,
                 State::ManifestIniSectionItem { name } => {
                     self.do_manifest_ini_section_item(name)?
@@ -486,6 +609,17 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     depth,
                     single_line,
                 } => self.do_manifest_toml_value(indent, depth, single_line)?,
+                State::ManifestCsvFirstRow {
+                    format,
+                    columns,
+                    rows,
+                } => self.do_manifest_csv_first_row(format, columns, rows)?,
+                State::ManifestCsvRow { format, columns } => {
+                    self.do_manifest_csv_row(format, columns)?
+                }
+                State::ManifestCsvField { format, only_field } => {
+                    self.do_manifest_csv_field(format, only_field)?
+                }
                 State::Expr { expr, env } => self.do_expr(expr, env)?,
                 State::Error { span } => {
                     let msg = self.string_stack.pop().unwrap();
@@ -558,21 +692,14 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     }
                 }
                 State::InitCompSpec {
-                    var_name,
                     value,
                     value_span,
                     env,
                 } => {
-                    self.state_stack.push(State::GotInitCompSpec {
-                        var_name,
-                        value_span,
-                    });
+                    self.state_stack.push(State::GotInitCompSpec { value_span });
                     self.state_stack.push(State::Expr { expr: value, env });
                 }
-                State::GotInitCompSpec {
-                    var_name,
-                    value_span,
-                } => {
+                State::GotInitCompSpec { value_span } => {
                     let value = self.value_stack.pop().unwrap();
                     let ValueData::Array(array) = value else {
                         return Err(self.report_error(EvalErrorKind::ForSpecValueIsNotArray {
@@ -584,28 +711,22 @@ impl<'p, 'a> Evaluator<'a, 'p> {
 
                     let mut comp_spec = CompSpec { vars: Vec::new() };
                     for item in array.iter() {
-                        let mut vars = FHashMap::default();
-                        vars.insert(var_name, item.view());
-                        comp_spec.vars.push(vars);
+                        comp_spec.vars.push(vec![item.view()]);
                     }
 
                     self.comp_spec_stack.push(comp_spec);
                 }
                 State::ForSpec {
-                    var_name,
                     value,
                     value_span,
                     env,
                 } => {
                     let comp_spec = self.comp_spec_stack.last().unwrap();
-                    self.state_stack.push(State::GotForSpec {
-                        var_name,
-                        value_span,
-                    });
+                    self.state_stack.push(State::GotForSpec { value_span });
                     for vars in comp_spec.vars.iter().rev() {
                         let mut inner_env_data = ThunkEnvData::new(Some(Gc::from(&env)));
-                        for (var_name, var_value) in vars.iter() {
-                            inner_env_data.set_var(*var_name, Gc::from(var_value));
+                        for var_value in vars.iter() {
+                            inner_env_data.push_var(Gc::from(var_value));
                         }
                         let inner_env = self.program.gc_alloc_view(ThunkEnv::from(inner_env_data));
                         self.state_stack.push(State::Expr {
@@ -614,10 +735,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         });
                     }
                 }
-                State::GotForSpec {
-                    var_name,
-                    value_span,
-                } => {
+                State::GotForSpec { value_span } => {
                     let comp_spec = self.comp_spec_stack.last_mut().unwrap();
                     let old_vars = std::mem::take(&mut comp_spec.vars);
 
@@ -636,7 +754,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         let array = array.view();
                         for item in array.iter() {
                             let mut new_vars = old_vars.clone();
-                            new_vars.insert(var_name, item.view());
+                            new_vars.push(item.view());
                             comp_spec.vars.push(new_vars);
                         }
                     }
@@ -650,8 +768,8 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     self.state_stack.push(State::GotIfSpec { cond_span });
                     for vars in comp_spec.vars.iter().rev() {
                         let mut inner_env_data = ThunkEnvData::new(Some(Gc::from(&env)));
-                        for (var_name, var_value) in vars.iter() {
-                            inner_env_data.set_var(*var_name, Gc::from(var_value));
+                        for var_value in vars.iter() {
+                            inner_env_data.push_var(Gc::from(var_value));
                         }
                         let inner_env = self.program.gc_alloc_view(ThunkEnv::from(inner_env_data));
                         self.state_stack.push(State::Expr {
@@ -690,15 +808,14 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         let mut array = Vec::with_capacity(comp_spec.vars.len());
                         for vars in comp_spec.vars.iter() {
                             let mut item_env_data = ThunkEnvData::new(Some(Gc::from(&env)));
-                            for (var_name, var_value) in vars.iter() {
-                                item_env_data.set_var(*var_name, Gc::from(var_value));
+                            for var_value in vars.iter() {
+                                item_env_data.push_var(Gc::from(var_value));
                             }
                             let item_env = self.program.gc_alloc(ThunkEnv::from(item_env_data));
                             array.push(self.program.new_pending_expr_thunk(item, item_env, None));
                         }
-                        self.value_stack.push(ValueData::Array(
-                            self.program.gc_alloc(array.into_boxed_slice()),
-                        ));
+                        self.value_stack
+                            .push(ValueData::Array(self.program.make_thunk_array(array)));
                     }
                 }
                 State::ObjectComp { expr, env } => {
@@ -729,14 +846,15 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         super_layers: Vec::new(),
                         fields_order: OnceCell::new(),
                         asserts_checked: Cell::new(true),
+                        fork_asserts_checked: ForkStates::new(),
                     });
 
                     self.state_stack.push(State::FinishObjectComp);
 
                     for vars in comp_spec.vars.iter().rev() {
                         let mut item_env_data = ThunkEnvData::new(Some(Gc::from(&env)));
-                        for (var_name, var_value) in vars.iter() {
-                            item_env_data.set_var(*var_name, Gc::from(var_value));
+                        for var_value in vars.iter() {
+                            item_env_data.push_var(Gc::from(var_value));
                         }
                         let outer_env = self.program.gc_alloc_view(ThunkEnv::from(item_env_data));
 
@@ -760,106 +878,10 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     self.value_stack.push(ValueData::Object(object));
                 }
                 State::Field { span, field_name } => {
-                    let object = self.value_stack.pop().unwrap();
-                    if let ValueData::Object(ref object) = object {
-                        self.want_field(&object.view(), field_name, span)?;
-                    } else {
-                        return Err(self.report_error(EvalErrorKind::FieldOfNonObject { span }));
-                    }
+                    self.do_field(span, field_name)?;
                 }
                 State::Index { span } => {
-                    let index_value = self.value_stack.pop().unwrap();
-                    let object_value = self.value_stack.pop().unwrap();
-                    match object_value {
-                        ValueData::String(ref s) => {
-                            let ValueData::Number(index) = index_value else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::StringIndexIsNotNumber {
-                                        span,
-                                        got_type: EvalErrorValueType::from_value(&index_value),
-                                    },
-                                ));
-                            };
-                            let Some(index_usize) = float::try_to_usize_exact(index) else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::NumericIndexIsNotValid {
-                                        span,
-                                        index: index.to_string(),
-                                    },
-                                ));
-                            };
-                            if let Some(chr) = s.chars().nth(index_usize) {
-                                self.value_stack.push(ValueData::from_char(chr));
-                            } else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::NumericIndexOutOfRange {
-                                        span,
-                                        index: index_usize,
-                                        length: s.chars().count(),
-                                    },
-                                ));
-                            }
-                        }
-                        ValueData::Array(ref array) => {
-                            let array = array.view();
-                            let ValueData::Number(index) = index_value else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::ArrayIndexIsNotNumber {
-                                        span,
-                                        got_type: EvalErrorValueType::from_value(&index_value),
-                                    },
-                                ));
-                            };
-                            let Some(index_usize) = float::try_to_usize_exact(index) else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::NumericIndexIsNotValid {
-                                        span,
-                                        index: index.to_string(),
-                                    },
-                                ));
-                            };
-                            if let Some(item) = array.get(index_usize) {
-                                self.want_thunk_direct(item.view(), || TraceItem::ArrayItem {
-                                    span: Some(span),
-                                    index: index_usize,
-                                });
-                            } else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::NumericIndexOutOfRange {
-                                        span,
-                                        index: index_usize,
-                                        length: array.len(),
-                                    },
-                                ));
-                            }
-                        }
-                        ValueData::Object(ref object) => {
-                            let ValueData::String(ref field_name) = index_value else {
-                                return Err(self.report_error(
-                                    EvalErrorKind::ObjectIndexIsNotString {
-                                        span,
-                                        got_type: EvalErrorValueType::from_value(&index_value),
-                                    },
-                                ));
-                            };
-                            if let Some(field_name) =
-                                self.program.str_interner.get_interned(field_name)
-                            {
-                                self.want_field(&object.view(), field_name, span)?;
-                            } else {
-                                return Err(self.report_error(EvalErrorKind::UnknownObjectField {
-                                    span,
-                                    field_name: (**field_name).into(),
-                                }));
-                            }
-                        }
-                        _ => {
-                            return Err(self.report_error(EvalErrorKind::InvalidIndexedType {
-                                span,
-                                got_type: EvalErrorValueType::from_value(&object_value),
-                            }));
-                        }
-                    }
+                    self.do_index(span)?;
                 }
                 State::SuperIndex {
                     span,
@@ -883,29 +905,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     }
                 }
                 State::UnaryOp { span, op } => {
-                    let rhs = self.value_stack.pop().unwrap();
-                    match (op, rhs) {
-                        (ast::UnaryOp::Minus, ValueData::Number(rhs)) => {
-                            self.value_stack.push(ValueData::Number(-rhs));
-                        }
-                        (ast::UnaryOp::Plus, ValueData::Number(rhs)) => {
-                            self.value_stack.push(ValueData::Number(rhs));
-                        }
-                        (ast::UnaryOp::BitwiseNot, ValueData::Number(rhs)) => {
-                            let int = self.safe_f64_to_i64(rhs, Some(span))?;
-                            self.value_stack.push(ValueData::Number(!int as f64));
-                        }
-                        (ast::UnaryOp::LogicNot, ValueData::Bool(rhs)) => {
-                            self.value_stack.push(ValueData::Bool(!rhs));
-                        }
-                        (_, rhs) => {
-                            return Err(self.report_error(EvalErrorKind::InvalidUnaryOpType {
-                                span,
-                                op,
-                                rhs_type: EvalErrorValueType::from_value(&rhs),
-                            }));
-                        }
-                    }
+                    self.do_unary_op(span, op)?;
                 }
                 State::BinaryOp { span, op } => {
                     self.do_binary_op(span, op)?;
@@ -1217,6 +1217,8 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         FuncKind::Normal { .. } if tailstrict => {
                             let params_order = func.params.order;
                             self.state_stack.push(State::ExecTailstrictCall {
+                                call_span,
+                                func_name,
                                 func,
                                 args: args_thunks.clone(),
                             });
@@ -1235,8 +1237,9 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                             self.push_trace_item(TraceItem::Call {
                                 span: Some(call_span),
                                 name: func_name,
+                                reusable: matches!(func.kind, FuncKind::Normal { .. }),
+                                tail_calls: 0,
                             });
-
                             self.execute_call(&func, args_thunks);
                         }
                     }
@@ -1260,25 +1263,43 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         None,
                     )?;
                 }
-                State::ExecTailstrictCall { func, args } => {
+                State::ExecTailstrictCall {
+                    call_span,
+                    func_name,
+                    func,
+                    args,
+                } => {
+                    self.push_tail_call_trace_item(call_span, func_name)?;
                     self.execute_call(&func, args);
                 }
-                State::ExecNativeCall { name, args } => {
+                State::ExecNativeCall { name, params, args } => {
                     let callbacks = self
                         .callbacks
                         .as_deref_mut()
                         .expect("unexpected call to native function without callbacks");
                     let args: Vec<_> = args
                         .iter()
-                        .map(|arg| super::Value::from_thunk(arg))
+                        .map(|arg| super::Value::from_thunk(arg, &self.program.fork_node))
                         .collect();
                     match callbacks.native_call(self.program, name, &args) {
                         Ok(result_value) => {
                             self.value_stack.push(result_value.inner);
                         }
-                        Err(_) => {
+                        Err(NativeError::Failed) => {
                             return Err(self.report_error(EvalErrorKind::NativeCallFailed));
                         }
+                        Err(NativeError::InvalidArg { arg_index, error }) => {
+                            // `arg_index` comes from the callbacks, so it is
+                            // not trusted to be in range.
+                            let Some(&(param_name, _)) = params.get(arg_index) else {
+                                return Err(self.report_error(EvalErrorKind::NativeCallFailed));
+                            };
+                            return Err(self.report_error(EvalErrorKind::InvalidNativeFuncArg {
+                                func_name: name.value().into(),
+                                param_name: param_name.value().into(),
+                                error,
+                            }));
+                        }
                     }
                 }
                 State::If {
@@ -1309,6 +1330,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                         }));
                     }
                 }
+                State::Bytecode { code, pc, env } => self.run_code(code, pc, env)?,
                 State::StdPruneValue => self.do_std_prune_value(),
                 State::StdPruneArrayItem => self.do_std_prune_array_item(),
                 State::StdPruneObjectField { name } => self.do_std_prune_object_field(name),
@@ -1363,6 +1385,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                 State::StdManifestXmlJsonmlItem1 => self.do_std_manifest_xml_jsonml_item_1()?,
                 State::StdManifestXmlJsonmlItemN => self.do_std_manifest_xml_jsonml_item_n()?,
                 State::StdManifestTomlEx => self.do_std_manifest_toml_ex()?,
+                State::StdManifestCsv => self.do_std_manifest_csv()?,
                 State::StdMember { value } => self.do_std_member(value)?,
                 State::StdMemberString { string } => self.do_std_member_string(string)?,
                 State::StdMemberArray { array, index } => self.do_std_member_array(array, index),
@@ -1529,10 +1552,20 @@ impl<'p, 'a> Evaluator<'a, 'p> {
                     end,
                     mid,
                 } => self.do_std_set_member_check(keyf, arr, start, end, mid)?,
-                State::StdBase64 => self.do_std_base64()?,
-                State::StdBase64Array { input, bytes } => self.do_std_base64_array(input, bytes)?,
-                State::StdBase64DecodeBytes => self.do_std_base64_decode_bytes()?,
-                State::StdBase64Decode => self.do_std_base64_decode()?,
+                State::StdEncodeBytes { encoding } => self.do_std_encode_bytes(encoding)?,
+                State::StdEncodeBytesArray {
+                    encoding,
+                    input,
+                    bytes,
+                } => self.do_std_encode_bytes_array(encoding, input, bytes)?,
+                State::StdDecodeBytes { encoding } => self.do_std_decode_bytes(encoding)?,
+                State::StdDecodeString { encoding } => self.do_std_decode_string(encoding)?,
+                State::StdCollectBytes {
+                    func_name,
+                    arg_index,
+                } => self.do_std_collect_bytes(func_name, arg_index)?,
+                State::StdHash { algo } => self.do_std_hash(algo),
+                State::StdHmac => self.do_std_hmac()?,
                 State::StdMergePatchValue => self.do_std_merge_patch_value(),
                 State::StdMergePatchField { name } => self.do_std_merge_patch_field(name),
             }
@@ -1553,6 +1586,40 @@ impl<'p, 'a> Evaluator<'a, 'p> {
         self.inc_trace_len();
     }
 
+    /// Pushes the trace item of a `tailstrict` call to a Jsonnet function.
+    ///
+    /// If the top of the state stack is the frame of another Jsonnet
+    /// function, nothing is left to evaluate in its body, so the call is in
+    /// tail position. In that case, the frame is reused and only counts the
+    /// tail call, so tail recursion does not grow the stack. The number of
+    /// tail calls in a frame is limited by `max_tail_calls`, so runaway
+    /// recursion still fails.
+    fn push_tail_call_trace_item(
+        &mut self,
+        span: SpanId,
+        name: Option<InternedStr<'p>>,
+    ) -> EvalResult<()> {
+        if let Some(State::TraceItem(TraceItem::Call {
+            reusable: true,
+            tail_calls,
+            ..
+        })) = self.state_stack.last_mut()
+        {
+            if *tail_calls >= self.program.max_tail_calls {
+                return Err(self.report_error(EvalErrorKind::StackOverflow));
+            }
+            *tail_calls += 1;
+        } else {
+            self.push_trace_item(TraceItem::Call {
+                span: Some(span),
+                name,
+                reusable: true,
+                tail_calls: 0,
+            });
+        }
+        Ok(())
+    }
+
     #[inline]
     fn delay_trace_item(&mut self) {
         self.state_stack.push(State::DelayedTraceItem);
@@ -1575,7 +1642,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
         thunk: GcView<ThunkData<'p>>,
         trace_item: impl FnOnce() -> TraceItem<'p>,
     ) {
-        if let Some(value) = thunk.get_value() {
+        if let Some(value) = thunk.get_value(&self.program.fork_node) {
             self.value_stack.push(value);
         } else {
             self.push_trace_item(trace_item());
@@ -1624,8 +1691,8 @@ impl<'p, 'a> Evaluator<'a, 'p> {
     }
 
     fn check_object_asserts(&mut self, object: &GcView<ObjectData<'p>>) {
-        if !object.asserts_checked.get() {
-            object.asserts_checked.set(true);
+        if !self.program.object_asserts_checked(object) {
+            self.program.set_object_asserts_checked(object);
             let layer_iter = object
                 .super_layers
                 .iter()
@@ -1741,7 +1808,7 @@ impl<'p, 'a> Evaluator<'a, 'p> {
         arg_index: usize,
     ) -> EvalResult<Rc<str>> {
         match value {
-            ValueData::String(s) => Ok(s),
+            ValueData::String(s) => Ok(s.to_rc()),
             value => Err(self.report_error(EvalErrorKind::InvalidStdFuncArgType {
                 func_name: func_name.into(),
                 arg_index,
@@ -1820,9 +1887,15 @@ impl<'p, 'a> Evaluator<'a, 'p> {
             fn conv_trace_item(item: &TraceItem<'_>) -> EvalStackTraceItem {
                 match *item {
                     TraceItem::Expr { span } => EvalStackTraceItem::Expr { span },
-                    TraceItem::Call { span, ref name } => EvalStackTraceItem::Call {
+                    TraceItem::Call {
+                        span,
+                        ref name,
+                        tail_calls,
+                        ..
+                    } => EvalStackTraceItem::Call {
                         span,
                         name: name.as_ref().map(|s| s.value().into()),
+                        tail_calls,
                     },
                     TraceItem::Variable { span, ref name } => EvalStackTraceItem::Variable {
                         span,
@@ -0,0 +1,326 @@
+use super::super::data::{ObjectData, SimpleObjectBuilder, ThunkData, ValueData};
+use super::Program;
+use crate::ast;
+use crate::gc::Gc;
+
+/// Delimiter and quote character of a CSV document.
+pub(super) struct CsvFormat {
+    pub(super) delimiter: char,
+    /// `None` disables quoting.
+    pub(super) quote: Option<char>,
+}
+
+impl CsvFormat {
+    /// Builds a format from the `delimiter` and `quote` arguments of
+    /// `std.parseCsv` and `std.manifestCsv`.
+    pub(super) fn from_args(delimiter: &str, quote: &str) -> Result<Self, &'static str> {
+        let mut delimiter_chars = delimiter.chars();
+        let (Some(delimiter), None) = (delimiter_chars.next(), delimiter_chars.next()) else {
+            return Err("delimiter must be a single character");
+        };
+        let mut quote_chars = quote.chars();
+        let quote = match (quote_chars.next(), quote_chars.next()) {
+            (None, _) => None,
+            (Some(quote), None) => Some(quote),
+            (Some(_), Some(_)) => return Err("quote must be empty or a single character"),
+        };
+        if matches!(delimiter, '\r' | '\n') || matches!(quote, Some('\r' | '\n')) {
+            return Err("delimiter and quote cannot be line breaks");
+        }
+        if quote == Some(delimiter) {
+            return Err("delimiter and quote must be different");
+        }
+        Ok(Self { delimiter, quote })
+    }
+
+    /// Appends `field` to `out`, quoting it if needed.
+    ///
+    /// `only_field` must be true when the record has a single field, so an
+    /// empty field is not written as an empty line.
+    pub(super) fn write_field(
+        &self,
+        field: &str,
+        only_field: bool,
+        out: &mut String,
+    ) -> Result<(), &'static str> {
+        let needs_quotes = field.chars().any(|chr| {
+            chr == self.delimiter || chr == '\n' || chr == '\r' || Some(chr) == self.quote
+        }) || (only_field && field.is_empty());
+        if !needs_quotes {
+            out.push_str(field);
+            return Ok(());
+        }
+
+        let Some(quote) = self.quote else {
+            return Err("field contains a delimiter or line break and quoting is disabled");
+        };
+        out.push(quote);
+        for chr in field.chars() {
+            if chr == quote {
+                out.push(quote);
+            }
+            out.push(chr);
+        }
+        out.push(quote);
+        Ok(())
+    }
+}
+
+pub(super) struct ParseError {
+    line: usize,
+    column: usize,
+    kind: ParseErrorKind,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line + 1,
+            self.column + 1,
+            self.kind,
+        )
+    }
+}
+
+enum ParseErrorKind {
+    UnfinishedQuotedField,
+    QuoteInUnquotedField,
+    ExpectedDelimiter,
+    RepeatedColumnName(String),
+    WrongFieldCount { expected: usize, got: usize },
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnfinishedQuotedField => write!(f, "unfinished quoted field"),
+            Self::QuoteInUnquotedField => write!(f, "quote in unquoted field"),
+            Self::ExpectedDelimiter => write!(f, "expected delimiter or end of line"),
+            Self::RepeatedColumnName(name) => write!(f, "repeated column name {name:?}"),
+            Self::WrongFieldCount { expected, got } => {
+                write!(f, "expected {expected} fields, got {got}")
+            }
+        }
+    }
+}
+
+/// Parses a CSV document as described in RFC 4180, with a configurable
+/// delimiter and quote. Line breaks can be LF or CRLF and empty lines are
+/// ignored.
+///
+/// When `header` is true, the first record contains the column names and
+/// the result is an array of objects. Otherwise, the result is an array of
+/// arrays.
+pub(super) fn parse_csv<'p>(
+    program: &mut Program<'p>,
+    s: &str,
+    header: bool,
+    format: &CsvFormat,
+) -> Result<ValueData<'p>, ParseError> {
+    let records = parse_records(s, format)?;
+
+    let items: Vec<_> = if header {
+        let mut records = records.into_iter();
+        let Some((header_line, header_fields)) = records.next() else {
+            return Ok(ValueData::Array(Gc::from(&program.empty_array)));
+        };
+
+        let mut column_names = Vec::with_capacity(header_fields.len());
+        for (i, name) in header_fields.iter().enumerate() {
+            let name = program.intern_str(name);
+            if column_names.contains(&name) {
+                return Err(ParseError {
+                    line: header_line,
+                    column: 0,
+                    kind: ParseErrorKind::RepeatedColumnName(header_fields[i].clone()),
+                });
+            }
+            column_names.push(name);
+        }
+
+        let mut items = Vec::with_capacity(records.len());
+        for (line, fields) in records {
+            if fields.len() != column_names.len() {
+                return Err(ParseError {
+                    line,
+                    column: 0,
+                    kind: ParseErrorKind::WrongFieldCount {
+                        expected: column_names.len(),
+                        got: fields.len(),
+                    },
+                });
+            }
+            let object = if fields.is_empty() {
+                ObjectData::new_empty()
+            } else {
+                let mut obj_builder = SimpleObjectBuilder::new();
+                for (&name, field) in column_names.iter().zip(fields) {
+                    obj_builder.insert_field(
+                        name,
+                        ast::Visibility::Default,
+                        program.gc_alloc(ThunkData::new_done(ValueData::String(field.into()))),
+                    );
+                }
+                obj_builder.build()
+            };
+            let object = ValueData::Object(program.gc_alloc(object));
+            items.push(program.gc_alloc(ThunkData::new_done(object)));
+        }
+        items
+    } else {
+        records
+            .into_iter()
+            .map(|(_, fields)| {
+                let fields: Vec<_> = fields
+                    .into_iter()
+                    .map(|field| {
+                        program.gc_alloc(ThunkData::new_done(ValueData::String(field.into())))
+                    })
+                    .collect();
+                let array = ValueData::Array(program.make_thunk_array(fields));
+                program.gc_alloc(ThunkData::new_done(array))
+            })
+            .collect()
+    };
+
+    if items.is_empty() {
+        Ok(ValueData::Array(Gc::from(&program.empty_array)))
+    } else {
+        Ok(ValueData::Array(program.make_thunk_array(items)))
+    }
+}
+
+/// Splits `s` into records, returning each one with the line where it
+/// starts.
+fn parse_records(s: &str, format: &CsvFormat) -> Result<Vec<(usize, Vec<String>)>, ParseError> {
+    let mut lexer = Lexer {
+        line: 0,
+        column: 0,
+        rem: s,
+    };
+
+    let mut records = Vec::new();
+    loop {
+        if lexer.rem.is_empty() {
+            break;
+        }
+        if lexer.eat_newline() {
+            continue;
+        }
+
+        let record_line = lexer.line;
+        let mut fields = Vec::new();
+        loop {
+            fields.push(lexer.lex_field(format)?);
+            if !lexer.eat_char(format.delimiter) {
+                break;
+            }
+        }
+        if !lexer.eat_newline() && !lexer.rem.is_empty() {
+            return Err(lexer.get_error(ParseErrorKind::ExpectedDelimiter));
+        }
+        records.push((record_line, fields));
+    }
+
+    Ok(records)
+}
+
+struct Lexer<'a> {
+    line: usize,
+    column: usize,
+    rem: &'a str,
+}
+
+impl Lexer<'_> {
+    #[must_use]
+    #[inline]
+    fn get_error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    #[inline]
+    fn peek_char(&self) -> Option<char> {
+        self.rem.chars().next()
+    }
+
+    fn eat_any_char(&mut self) -> Option<char> {
+        let mut chars = self.rem.chars();
+        let chr = chars.next()?;
+        self.rem = chars.as_str();
+        if chr == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(chr)
+    }
+
+    fn eat_char(&mut self, chr: char) -> bool {
+        if self.peek_char() == Some(chr) {
+            self.eat_any_char();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_newline(&mut self) -> bool {
+        if self.rem.starts_with("\r\n") {
+            self.rem = &self.rem[2..];
+            self.line += 1;
+            self.column = 0;
+            true
+        } else {
+            self.eat_char('\n')
+        }
+    }
+
+    #[inline]
+    fn at_field_end(&self, format: &CsvFormat) -> bool {
+        match self.peek_char() {
+            None | Some('\n') => true,
+            Some(chr) => chr == format.delimiter || self.rem.starts_with("\r\n"),
+        }
+    }
+
+    fn lex_field(&mut self, format: &CsvFormat) -> Result<String, ParseError> {
+        let mut field = String::new();
+        if let Some(quote) = format
+            .quote
+            .filter(|&quote| self.peek_char() == Some(quote))
+        {
+            let start_error = self.get_error(ParseErrorKind::UnfinishedQuotedField);
+            self.eat_any_char();
+            loop {
+                match self.eat_any_char() {
+                    None => return Err(start_error),
+                    Some(chr) if chr == quote => {
+                        if !self.eat_char(quote) {
+                            break;
+                        }
+                        field.push(quote);
+                    }
+                    Some(chr) => field.push(chr),
+                }
+            }
+            if !self.at_field_end(format) {
+                return Err(self.get_error(ParseErrorKind::ExpectedDelimiter));
+            }
+        } else {
+            while !self.at_field_end(format) {
+                if format.quote == self.peek_char() {
+                    return Err(self.get_error(ParseErrorKind::QuoteInUnquotedField));
+                }
+                field.push(self.eat_any_char().unwrap());
+            }
+        }
+        Ok(field)
+    }
+}
@@ -4,12 +4,24 @@ use crate::ast;
 use crate::gc::Gc;
 use crate::interner::InternedStr;
 
-pub(super) struct ParseError<'p> {
+pub(crate) struct ParseError<'p> {
     line: usize,
     column: usize,
+    offset: usize,
     kind: ParseErrorKind<'p>,
 }
 
+impl From<ParseError<'_>> for super::super::ParseJsonError {
+    fn from(e: ParseError<'_>) -> Self {
+        Self {
+            line: e.line + 1,
+            column: e.column + 1,
+            offset: e.offset,
+            message: e.kind.to_string(),
+        }
+    }
+}
+
 impl std::fmt::Display for ParseError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -56,7 +68,7 @@ impl std::fmt::Display for ParseErrorKind<'_> {
     }
 }
 
-pub(super) fn parse_json<'p>(
+pub(crate) fn parse_json<'p>(
     program: &mut Program<'p>,
     s: &str,
 ) -> Result<ValueData<'p>, ParseError<'p>> {
@@ -68,6 +80,7 @@ pub(super) fn parse_json<'p>(
     let mut lexer = Lexer {
         line: 0,
         column: 0,
+        input_len: s.len(),
         rem: s,
     };
 
@@ -129,7 +142,7 @@ pub(super) fn parse_json<'p>(
                         items.push(program.gc_alloc(ThunkData::new_done(value)));
                         if lexer.eat_char(']') {
                             lexer.skip_spaces();
-                            value = ValueData::Array(program.gc_alloc(items.into_boxed_slice()));
+                            value = ValueData::Array(program.make_thunk_array(items));
                         } else if lexer.eat_char(',') {
                             lexer.skip_spaces();
                             stack.push(StackItem::Array(items));
@@ -185,6 +198,7 @@ pub(super) fn parse_json<'p>(
 struct Lexer<'a> {
     line: usize,
     column: usize,
+    input_len: usize,
     rem: &'a str,
 }
 
@@ -194,6 +208,7 @@ impl Lexer<'_> {
         ParseError {
             line: self.line,
             column: self.column,
+            offset: self.input_len - self.rem.len(),
             kind,
         }
     }
@@ -292,6 +307,7 @@ impl Lexer<'_> {
 
         let mut state = State::Start;
         let start_column = self.column;
+        let start_offset = self.input_len - self.rem.len();
         let init_rem = self.rem;
         loop {
             match state {
@@ -315,6 +331,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_column,
+                            offset: start_offset,
                             kind: ParseErrorKind::InvalidNumber,
                         });
                     }
@@ -324,6 +341,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_column,
+                            offset: start_offset,
                             kind: ParseErrorKind::InvalidNumber,
                         });
                     } else if self.eat_char('.') {
@@ -352,6 +370,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_column,
+                            offset: start_offset,
                             kind: ParseErrorKind::InvalidNumber,
                         });
                     }
@@ -374,6 +393,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_column,
+                            offset: start_offset,
                             kind: ParseErrorKind::InvalidNumber,
                         });
                     }
@@ -385,6 +405,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_column,
+                            offset: start_offset,
                             kind: ParseErrorKind::InvalidNumber,
                         });
                     }
@@ -411,6 +432,7 @@ impl Lexer<'_> {
                 Err(ParseError {
                     line: self.line,
                     column: start_column,
+                    offset: start_offset,
                     kind: ParseErrorKind::NumberOverflow,
                 })
             }
@@ -423,15 +445,18 @@ impl Lexer<'_> {
         }
 
         let start_col = self.column;
+        let start_offset = self.input_len - self.rem.len();
         let mut string = String::new();
         loop {
             if self.eat_char('"') {
                 break;
             }
             let chr_col = self.column;
+            let chr_offset = self.input_len - self.rem.len();
             let chr = self.eat_any_char().ok_or(ParseError {
                 line: self.line,
                 column: start_col,
+                offset: start_offset,
                 kind: ParseErrorKind::UnfinishedString,
             })?;
             match chr {
@@ -440,6 +465,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: start_col,
+                            offset: start_offset,
                             kind: ParseErrorKind::UnfinishedString,
                         });
                     }
@@ -492,6 +518,7 @@ impl Lexer<'_> {
                             return Err(ParseError {
                                 line: self.line,
                                 column: chr_col,
+                                offset: chr_offset,
                                 kind: ParseErrorKind::InvalidStringEscape,
                             });
                         };
@@ -501,6 +528,7 @@ impl Lexer<'_> {
                                 return Err(ParseError {
                                     line: self.line,
                                     column: chr_col,
+                                    offset: chr_offset,
                                     kind: ParseErrorKind::InvalidStringEscape,
                                 });
                             };
@@ -510,6 +538,7 @@ impl Lexer<'_> {
                                 return Err(ParseError {
                                     line: self.line,
                                     column: chr_col,
+                                    offset: chr_offset,
                                     kind: ParseErrorKind::InvalidStringEscape,
                                 });
                             }
@@ -519,6 +548,7 @@ impl Lexer<'_> {
                             return Err(ParseError {
                                 line: self.line,
                                 column: chr_col,
+                                offset: chr_offset,
                                 kind: ParseErrorKind::InvalidStringEscape,
                             });
                         }
@@ -527,6 +557,7 @@ impl Lexer<'_> {
                         return Err(ParseError {
                             line: self.line,
                             column: chr_col,
+                            offset: chr_offset,
                             kind: ParseErrorKind::InvalidStringEscape,
                         });
                     }
@@ -538,6 +569,7 @@ impl Lexer<'_> {
                     return Err(ParseError {
                         line: self.line,
                         column: chr_col,
+                        offset: chr_offset,
                         kind: ParseErrorKind::InvalidChrInString,
                     });
                 }
@@ -0,0 +1,913 @@
+use super::super::data::{ObjectData, SimpleObjectBuilder, ThunkData, ValueData};
+use super::Program;
+use crate::gc::Gc;
+use crate::{FHashMap, ast};
+
+/// Maximum nesting of tables and arrays, which avoids overflowing the stack
+/// while parsing and building the value.
+const MAX_DEPTH: usize = 256;
+
+pub(crate) struct ParseError {
+    line: usize,
+    column: usize,
+    offset: usize,
+    kind: ParseErrorKind,
+}
+
+impl From<ParseError> for super::super::ParseTomlError {
+    fn from(e: ParseError) -> Self {
+        Self {
+            line: e.line + 1,
+            column: e.column + 1,
+            offset: e.offset,
+            message: e.kind.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line + 1,
+            self.column + 1,
+            self.kind,
+        )
+    }
+}
+
+enum ParseErrorKind {
+    Expected(&'static str),
+    InvalidChr,
+    InvalidStringEscape,
+    InvalidNumber,
+    NumberOverflow,
+    IntegerOutOfRange,
+    NonFiniteNumber,
+    InvalidDatetime,
+    TooDeep,
+    RepeatedKey(String),
+    CannotExtend(String),
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Expected(what) => write!(f, "expected {what}"),
+            Self::InvalidChr => write!(f, "invalid character"),
+            Self::InvalidStringEscape => write!(f, "invalid string escape"),
+            Self::InvalidNumber => write!(f, "invalid number"),
+            Self::NumberOverflow => write!(f, "number overflow"),
+            Self::IntegerOutOfRange => {
+                write!(f, "integer out of range (must be between -2^53 and 2^53)")
+            }
+            Self::NonFiniteNumber => write!(f, "infinite and NaN numbers are not supported"),
+            Self::InvalidDatetime => write!(f, "invalid date or time"),
+            Self::TooDeep => write!(f, "too many nested tables or arrays"),
+            Self::RepeatedKey(key) => write!(f, "repeated key {key:?}"),
+            Self::CannotExtend(key) => write!(f, "cannot extend {key:?}"),
+        }
+    }
+}
+
+enum Node {
+    Value(ValueNode),
+    Table(Table),
+    /// An array defined with `[[...]]` headers.
+    TableArray(Vec<Table>),
+}
+
+enum ValueNode {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Array(Vec<ValueNode>),
+    Table(Table),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TableKind {
+    /// Created as the parent of a `[...]` or `[[...]]` header.
+    Implicit,
+    /// Defined with a `[...]` or `[[...]]` header.
+    Header,
+    /// Created by a dotted key.
+    Dotted,
+    /// Defined inline with `{...}`, it cannot be extended.
+    Inline,
+}
+
+struct Table {
+    kind: TableKind,
+    items: FHashMap<String, Node>,
+}
+
+impl Table {
+    fn new(kind: TableKind) -> Self {
+        Self {
+            kind,
+            items: FHashMap::default(),
+        }
+    }
+}
+
+pub(crate) fn parse_toml<'p>(
+    program: &mut Program<'p>,
+    s: &str,
+) -> Result<ValueData<'p>, ParseError> {
+    let mut parser = Parser {
+        input: s,
+        pos: s.strip_prefix('\u{FEFF}').map_or(0, |_| 3),
+    };
+    let mut root = Table::new(TableKind::Header);
+    let mut current_path = Vec::new();
+
+    loop {
+        parser.skip_spaces();
+        match parser.peek() {
+            None => break,
+            Some('#' | '\n' | '\r') => {}
+            Some('[') => {
+                parser.pos += 1;
+                let is_array = parser.eat_char('[');
+                parser.skip_spaces();
+                let key = parser.parse_key()?;
+                parser.skip_spaces();
+                let closing = if is_array { "]]" } else { "]" };
+                if !parser.eat_str(closing) {
+                    return Err(parser.error(ParseErrorKind::Expected(if is_array {
+                        "`]]`"
+                    } else {
+                        "`]`"
+                    })));
+                }
+                if key.len() > MAX_DEPTH {
+                    return Err(parser.error_at(key[0].1, ParseErrorKind::TooDeep));
+                }
+                define_header_table(&parser, &mut root, &key, is_array)?;
+                current_path = key.into_iter().map(|(name, _)| name).collect();
+            }
+            Some(_) => {
+                let table = get_table_mut(&mut root, &current_path);
+                parser.parse_key_value(table, current_path.len())?;
+            }
+        }
+        parser.skip_spaces();
+        parser.skip_comment()?;
+        if !parser.eat_newline() && parser.peek().is_some() {
+            return Err(parser.error(ParseErrorKind::Expected("end of line")));
+        }
+    }
+
+    Ok(table_to_value(program, root))
+}
+
+fn define_header_table(
+    parser: &Parser<'_>,
+    root: &mut Table,
+    key: &[(String, usize)],
+    is_array: bool,
+) -> Result<(), ParseError> {
+    let (last_key, parent_key) = key.split_last().unwrap();
+    let mut table = root;
+    for (name, offset) in parent_key {
+        let node = table
+            .items
+            .entry(name.clone())
+            .or_insert_with(|| Node::Table(Table::new(TableKind::Implicit)));
+        table = match node {
+            Node::Table(sub_table) if sub_table.kind != TableKind::Inline => sub_table,
+            Node::TableArray(tables) => tables.last_mut().unwrap(),
+            _ => {
+                return Err(parser.error_at(*offset, ParseErrorKind::CannotExtend(name.clone())));
+            }
+        };
+    }
+
+    let (name, offset) = last_key;
+    if is_array {
+        match table.items.get_mut(name) {
+            None => {
+                table.items.insert(
+                    name.clone(),
+                    Node::TableArray(vec![Table::new(TableKind::Header)]),
+                );
+            }
+            Some(Node::TableArray(tables)) => {
+                tables.push(Table::new(TableKind::Header));
+            }
+            Some(_) => {
+                return Err(parser.error_at(*offset, ParseErrorKind::CannotExtend(name.clone())));
+            }
+        }
+    } else {
+        match table.items.get_mut(name) {
+            None => {
+                table
+                    .items
+                    .insert(name.clone(), Node::Table(Table::new(TableKind::Header)));
+            }
+            Some(Node::Table(sub_table)) if sub_table.kind == TableKind::Implicit => {
+                sub_table.kind = TableKind::Header;
+            }
+            Some(_) => {
+                return Err(parser.error_at(*offset, ParseErrorKind::RepeatedKey(name.clone())));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_table_mut<'t>(root: &'t mut Table, path: &[String]) -> &'t mut Table {
+    let mut table = root;
+    for name in path {
+        table = match table.items.get_mut(name) {
+            Some(Node::Table(sub_table)) => sub_table,
+            Some(Node::TableArray(tables)) => tables.last_mut().unwrap(),
+            _ => unreachable!(),
+        };
+    }
+    table
+}
+
+fn table_to_value<'p>(program: &mut Program<'p>, table: Table) -> ValueData<'p> {
+    if table.items.is_empty() {
+        return ValueData::Object(program.gc_alloc(ObjectData::new_empty()));
+    }
+
+    let mut obj_builder = SimpleObjectBuilder::new();
+    for (name, node) in table.items {
+        let value = match node {
+            Node::Value(value) => value_node_to_value(program, value),
+            Node::Table(sub_table) => table_to_value(program, sub_table),
+            Node::TableArray(tables) => {
+                let items: Vec<_> = tables
+                    .into_iter()
+                    .map(|sub_table| {
+                        let value = table_to_value(program, sub_table);
+                        program.gc_alloc(ThunkData::new_done(value))
+                    })
+                    .collect();
+                ValueData::Array(program.make_thunk_array(items))
+            }
+        };
+        let inserted = obj_builder.try_insert_field(
+            program.intern_str(&name),
+            ast::Visibility::Default,
+            program.gc_alloc(ThunkData::new_done(value)),
+        );
+        assert!(inserted);
+    }
+    ValueData::Object(program.gc_alloc(obj_builder.build()))
+}
+
+fn value_node_to_value<'p>(program: &mut Program<'p>, value: ValueNode) -> ValueData<'p> {
+    match value {
+        ValueNode::String(s) => ValueData::String(s.into()),
+        ValueNode::Number(n) => ValueData::Number(n),
+        ValueNode::Bool(b) => ValueData::Bool(b),
+        ValueNode::Array(items) => {
+            if items.is_empty() {
+                ValueData::Array(Gc::from(&program.empty_array))
+            } else {
+                let items: Vec<_> = items
+                    .into_iter()
+                    .map(|item| {
+                        let value = value_node_to_value(program, item);
+                        program.gc_alloc(ThunkData::new_done(value))
+                    })
+                    .collect();
+                ValueData::Array(program.make_thunk_array(items))
+            }
+        }
+        ValueNode::Table(table) => table_to_value(program, table),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: ParseErrorKind) -> ParseError {
+        let before = &self.input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            line: before.matches('\n').count(),
+            column: before[line_start..].chars().count(),
+            offset,
+            kind,
+        }
+    }
+
+    #[inline]
+    fn rem(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rem().chars().next()
+    }
+
+    #[inline]
+    fn eat_char(&mut self, chr: char) -> bool {
+        if self.peek() == Some(chr) {
+            self.pos += chr.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn eat_str(&mut self, s: &str) -> bool {
+        if self.rem().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_newline(&mut self) -> bool {
+        self.eat_char('\n') || self.eat_str("\r\n")
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.eat_char(' ') || self.eat_char('\t') {}
+    }
+
+    fn skip_comment(&mut self) -> Result<(), ParseError> {
+        if self.eat_char('#') {
+            while let Some(chr) = self.peek() {
+                if chr == '\n' || self.rem().starts_with("\r\n") {
+                    break;
+                }
+                if is_invalid_chr(chr) {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                self.pos += chr.len_utf8();
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips spaces, newlines and comments inside arrays.
+    fn skip_spaces_and_newlines(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_spaces();
+            self.skip_comment()?;
+            if !self.eat_newline() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses a (possibly dotted) key, returning each part with its offset.
+    fn parse_key(&mut self) -> Result<Vec<(String, usize)>, ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            let start = self.pos;
+            let part = if self.peek() == Some('"') {
+                if self.rem().starts_with("\"\"\"") {
+                    return Err(self.error(ParseErrorKind::Expected("key")));
+                }
+                self.parse_basic_string()?
+            } else if self.peek() == Some('\'') {
+                if self.rem().starts_with("'''") {
+                    return Err(self.error(ParseErrorKind::Expected("key")));
+                }
+                self.parse_literal_string()?
+            } else {
+                let len = self
+                    .rem()
+                    .find(|chr: char| !is_bare_key_chr(chr))
+                    .unwrap_or(self.rem().len());
+                if len == 0 {
+                    return Err(self.error(ParseErrorKind::Expected("key")));
+                }
+                let part = self.rem()[..len].to_string();
+                self.pos += len;
+                part
+            };
+            parts.push((part, start));
+
+            self.skip_spaces();
+            if !self.eat_char('.') {
+                return Ok(parts);
+            }
+            self.skip_spaces();
+        }
+    }
+
+    /// Parses `key = value` and inserts it into `table`.
+    fn parse_key_value(&mut self, table: &mut Table, depth: usize) -> Result<(), ParseError> {
+        let key = self.parse_key()?;
+        if !self.eat_char('=') {
+            return Err(self.error(ParseErrorKind::Expected("`=`")));
+        }
+        self.skip_spaces();
+
+        let (last_key, parent_key) = key.split_last().unwrap();
+        let depth = depth + parent_key.len();
+        if depth >= MAX_DEPTH {
+            return Err(self.error_at(key[0].1, ParseErrorKind::TooDeep));
+        }
+
+        let mut table = table;
+        for (name, offset) in parent_key {
+            let node = table
+                .items
+                .entry(name.clone())
+                .or_insert_with(|| Node::Table(Table::new(TableKind::Dotted)));
+            table = match node {
+                Node::Table(sub_table) if sub_table.kind == TableKind::Dotted => sub_table,
+                _ => {
+                    return Err(self.error_at(*offset, ParseErrorKind::CannotExtend(name.clone())));
+                }
+            };
+        }
+
+        let (name, offset) = last_key;
+        if table.items.contains_key(name) {
+            return Err(self.error_at(*offset, ParseErrorKind::RepeatedKey(name.clone())));
+        }
+        let value = self.parse_value(depth + 1)?;
+        let node = match value {
+            ValueNode::Table(table) => Node::Table(table),
+            value => Node::Value(value),
+        };
+        table.items.insert(name.clone(), node);
+
+        Ok(())
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<ValueNode, ParseError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.error(ParseErrorKind::TooDeep));
+        }
+
+        match self.peek() {
+            Some('"') => {
+                if self.rem().starts_with("\"\"\"") {
+                    self.parse_multiline_basic_string().map(ValueNode::String)
+                } else {
+                    self.parse_basic_string().map(ValueNode::String)
+                }
+            }
+            Some('\'') => {
+                if self.rem().starts_with("'''") {
+                    self.parse_multiline_literal_string().map(ValueNode::String)
+                } else {
+                    self.parse_literal_string().map(ValueNode::String)
+                }
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_spaces_and_newlines()?;
+                    if self.eat_char(']') {
+                        break;
+                    }
+                    items.push(self.parse_value(depth + 1)?);
+                    self.skip_spaces_and_newlines()?;
+                    if self.eat_char(']') {
+                        break;
+                    } else if !self.eat_char(',') {
+                        return Err(self.error(ParseErrorKind::Expected("`]` or `,`")));
+                    }
+                }
+                Ok(ValueNode::Array(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut table = Table::new(TableKind::Inline);
+                self.skip_spaces();
+                if !self.eat_char('}') {
+                    loop {
+                        self.skip_spaces();
+                        self.parse_key_value(&mut table, depth)?;
+                        self.skip_spaces();
+                        if self.eat_char('}') {
+                            break;
+                        } else if !self.eat_char(',') {
+                            return Err(self.error(ParseErrorKind::Expected("`}` or `,`")));
+                        }
+                    }
+                }
+                mark_inline(&mut table);
+                Ok(ValueNode::Table(table))
+            }
+            Some('t') if self.eat_keyword("true") => Ok(ValueNode::Bool(true)),
+            Some('f') if self.eat_keyword("false") => Ok(ValueNode::Bool(false)),
+            Some('0'..='9' | '+' | '-' | 'i' | 'n') => {
+                if let Some(datetime) = self.parse_datetime()? {
+                    Ok(ValueNode::String(datetime))
+                } else {
+                    self.parse_number().map(ValueNode::Number)
+                }
+            }
+            _ => Err(self.error(ParseErrorKind::Expected("value"))),
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let rem = self.rem();
+        if rem.starts_with(keyword) && !rem[keyword.len()..].starts_with(is_bare_key_chr) {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<f64, ParseError> {
+        let start = self.pos;
+        let len = self
+            .rem()
+            .find(|chr: char| !matches!(chr, '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' | '+' | '-' | '.'))
+            .unwrap_or(self.rem().len());
+        let token = &self.input[start..(start + len)];
+        self.pos += len;
+
+        let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+        if unsigned == "inf" || unsigned == "nan" {
+            return Err(self.error_at(start, ParseErrorKind::NonFiniteNumber));
+        }
+
+        let radix_digits = [("0x", 16), ("0o", 8), ("0b", 2)]
+            .into_iter()
+            .find_map(|(prefix, radix)| Some((radix, token.strip_prefix(prefix)?)));
+        if let Some((radix, digits)) = radix_digits {
+            let digits = remove_underscores(digits, |chr| chr.is_digit(radix))
+                .ok_or_else(|| self.error_at(start, ParseErrorKind::InvalidNumber))?;
+            return self.check_int(i64::from_str_radix(&digits, radix).ok(), start);
+        }
+
+        let (int_part, rest) = match unsigned.find(['.', 'e', 'E']) {
+            Some(i) => unsigned.split_at(i),
+            None => (unsigned, ""),
+        };
+        let int_digits = remove_underscores(int_part, |chr| chr.is_ascii_digit())
+            .ok_or_else(|| self.error_at(start, ParseErrorKind::InvalidNumber))?;
+        if int_digits.len() > 1 && int_digits.starts_with('0') {
+            return Err(self.error_at(start, ParseErrorKind::InvalidNumber));
+        }
+
+        let mut clean = String::with_capacity(token.len());
+        if token.starts_with('-') {
+            clean.push('-');
+        }
+        clean.push_str(&int_digits);
+
+        if rest.is_empty() {
+            return self.check_int(clean.parse().ok(), start);
+        }
+
+        let (frac_part, exp_part) = match rest.find(['e', 'E']) {
+            Some(i) => (&rest[..i], Some(&rest[(i + 1)..])),
+            None => (rest, None),
+        };
+        if let Some(frac_part) = frac_part.strip_prefix('.') {
+            let frac_digits = remove_underscores(frac_part, |chr| chr.is_ascii_digit())
+                .ok_or_else(|| self.error_at(start, ParseErrorKind::InvalidNumber))?;
+            clean.push('.');
+            clean.push_str(&frac_digits);
+        } else if !frac_part.is_empty() {
+            return Err(self.error_at(start, ParseErrorKind::InvalidNumber));
+        }
+        if let Some(exp_part) = exp_part {
+            let (exp_sign, exp_digits) = match exp_part.strip_prefix(['+', '-']) {
+                Some(digits) => (&exp_part[..1], digits),
+                None => ("", exp_part),
+            };
+            let exp_digits = remove_underscores(exp_digits, |chr| chr.is_ascii_digit())
+                .ok_or_else(|| self.error_at(start, ParseErrorKind::InvalidNumber))?;
+            clean.push('e');
+            clean.push_str(exp_sign);
+            clean.push_str(&exp_digits);
+        }
+
+        let value: f64 = clean
+            .parse()
+            .map_err(|_| self.error_at(start, ParseErrorKind::InvalidNumber))?;
+        if !value.is_finite() {
+            return Err(self.error_at(start, ParseErrorKind::NumberOverflow));
+        }
+        Ok(value)
+    }
+
+    /// Converts an integer into a number, rejecting it if it cannot be
+    /// represented exactly.
+    fn check_int(&self, value: Option<i64>, start: usize) -> Result<f64, ParseError> {
+        const MAX_SAFE_INT: i64 = 1 << 53;
+        match value {
+            Some(value) if (-MAX_SAFE_INT..=MAX_SAFE_INT).contains(&value) => Ok(value as f64),
+            _ => Err(self.error_at(start, ParseErrorKind::IntegerOutOfRange)),
+        }
+    }
+
+    /// Parses an offset date-time, local date-time, local date or local time,
+    /// which are returned as strings since Jsonnet does not have a date type.
+    ///
+    /// Returns `None` when the input does not start like a date or time.
+    fn parse_datetime(&mut self) -> Result<Option<String>, ParseError> {
+        fn digits(s: &[u8], n: usize) -> bool {
+            s.len() >= n && s[..n].iter().all(u8::is_ascii_digit)
+        }
+
+        fn two_digits(s: &[u8]) -> u8 {
+            (s[0] - b'0') * 10 + (s[1] - b'0')
+        }
+
+        /// Returns the length of the time at the start of `s`, `None` if `s`
+        /// does not start with `HH:` and `Some(0)` if the time is invalid.
+        fn time_len(s: &[u8]) -> Option<usize> {
+            if !(digits(s, 2) && s.get(2) == Some(&b':')) {
+                return None;
+            }
+            if !(digits(&s[3..], 2) && s.get(5) == Some(&b':') && digits(&s[6..], 2)) {
+                return Some(0);
+            }
+            if two_digits(s) > 23 || two_digits(&s[3..]) > 59 || two_digits(&s[6..]) > 60 {
+                return Some(0);
+            }
+            let mut len = 8;
+            if s.get(len) == Some(&b'.') {
+                let frac_len = s[(len + 1)..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count();
+                if frac_len == 0 {
+                    return Some(0);
+                }
+                len += 1 + frac_len;
+            }
+            Some(len)
+        }
+
+        let start = self.pos;
+        let s = self.rem().as_bytes();
+        let is_date = digits(s, 4) && s.get(4) == Some(&b'-');
+        let is_time = digits(s, 2) && s.get(2) == Some(&b':');
+        if !is_date && !is_time {
+            return Ok(None);
+        }
+        let invalid = || self.error_at(start, ParseErrorKind::InvalidDatetime);
+
+        let mut len;
+        if is_date {
+            if !(digits(&s[5..], 2) && s.get(7) == Some(&b'-') && digits(&s[8..], 2)) {
+                return Err(invalid());
+            }
+            let year = u16::from(two_digits(s)) * 100 + u16::from(two_digits(&s[2..]));
+            let month = two_digits(&s[5..]);
+            let day = two_digits(&s[8..]);
+            let is_leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+            let month_days = match month {
+                1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+                4 | 6 | 9 | 11 => 30,
+                2 if is_leap => 29,
+                2 => 28,
+                _ => return Err(invalid()),
+            };
+            if !(1..=month_days).contains(&day) {
+                return Err(invalid());
+            }
+            len = 10;
+
+            if matches!(s.get(len), Some(b'T' | b't' | b' ')) {
+                match time_len(&s[(len + 1)..]) {
+                    Some(0) => return Err(invalid()),
+                    Some(t_len) => {
+                        len += 1 + t_len;
+                        match s.get(len) {
+                            Some(b'Z' | b'z') => len += 1,
+                            Some(b'+' | b'-') => {
+                                let offset = &s[(len + 1)..];
+                                if !(digits(offset, 2)
+                                    && offset.get(2) == Some(&b':')
+                                    && digits(&offset[3..], 2)
+                                    && two_digits(offset) <= 23
+                                    && two_digits(&offset[3..]) <= 59)
+                                {
+                                    return Err(invalid());
+                                }
+                                len += 6;
+                            }
+                            _ => {}
+                        }
+                    }
+                    // A space not followed by a time ends the date.
+                    None if s[len] == b' ' => {}
+                    None => return Err(invalid()),
+                }
+            }
+        } else {
+            len = match time_len(s) {
+                None | Some(0) => return Err(invalid()),
+                Some(len) => len,
+            };
+        }
+
+        if self.rem()[len..].starts_with(is_bare_key_chr) {
+            return Err(invalid());
+        }
+        let datetime = self.rem()[..len].to_string();
+        self.pos += len;
+        Ok(Some(datetime))
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some('\'') => {
+                    let s = self.input[(start + 1)..self.pos].to_string();
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(chr) if chr == '\t' || !is_invalid_chr(chr) => {
+                    self.pos += chr.len_utf8();
+                }
+                Some(_) => return Err(self.error(ParseErrorKind::InvalidChr)),
+                None => return Err(self.error_at(start, ParseErrorKind::Expected("`'`"))),
+            }
+        }
+    }
+
+    fn parse_multiline_literal_string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 3;
+        self.eat_newline();
+        let mut string = String::new();
+        loop {
+            if self.rem().starts_with("'''") {
+                let num_quotes = self.rem().bytes().take_while(|&b| b == b'\'').count();
+                if num_quotes > 5 {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                string.extend(std::iter::repeat_n('\'', num_quotes - 3));
+                self.pos += num_quotes;
+                return Ok(string);
+            }
+            if self.eat_str("\r\n") {
+                string.push_str("\r\n");
+                continue;
+            }
+            match self.peek() {
+                Some(chr) if chr == '\t' || chr == '\n' || !is_invalid_chr(chr) => {
+                    string.push(chr);
+                    self.pos += chr.len_utf8();
+                }
+                Some(_) => return Err(self.error(ParseErrorKind::InvalidChr)),
+                None => return Err(self.error_at(start, ParseErrorKind::Expected("`'''`"))),
+            }
+        }
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some('\\') => self.parse_escape(&mut string)?,
+                Some(chr) if chr == '\t' || !is_invalid_chr(chr) => {
+                    string.push(chr);
+                    self.pos += chr.len_utf8();
+                }
+                Some(_) => return Err(self.error(ParseErrorKind::InvalidChr)),
+                None => return Err(self.error_at(start, ParseErrorKind::Expected("`\"`"))),
+            }
+        }
+    }
+
+    fn parse_multiline_basic_string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 3;
+        self.eat_newline();
+        let mut string = String::new();
+        loop {
+            if self.rem().starts_with("\"\"\"") {
+                let num_quotes = self.rem().bytes().take_while(|&b| b == b'"').count();
+                if num_quotes > 5 {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                string.extend(std::iter::repeat_n('"', num_quotes - 3));
+                self.pos += num_quotes;
+                return Ok(string);
+            }
+            if self.eat_str("\r\n") {
+                string.push_str("\r\n");
+                continue;
+            }
+            match self.peek() {
+                Some('\\') => {
+                    // A backslash at the end of a line trims all the
+                    // following whitespace and newlines.
+                    let after = self.rem()[1..].trim_start_matches([' ', '\t']);
+                    if after.starts_with('\n') || after.starts_with("\r\n") {
+                        self.pos = self.input.len()
+                            - after.trim_start_matches([' ', '\t', '\n', '\r']).len();
+                    } else {
+                        self.parse_escape(&mut string)?;
+                    }
+                }
+                Some(chr) if chr == '\t' || chr == '\n' || !is_invalid_chr(chr) => {
+                    string.push(chr);
+                    self.pos += chr.len_utf8();
+                }
+                Some(_) => return Err(self.error(ParseErrorKind::InvalidChr)),
+                None => return Err(self.error_at(start, ParseErrorKind::Expected("`\"\"\"`"))),
+            }
+        }
+    }
+
+    fn parse_escape(&mut self, string: &mut String) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let chr = match self.peek() {
+            Some('b') => '\u{8}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{C}',
+            Some('r') => '\r',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(chr @ ('u' | 'U')) => {
+                let len = if chr == 'u' { 4 } else { 8 };
+                let hex = self.rem().get(1..(1 + len));
+                let chr = hex
+                    .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error_at(start, ParseErrorKind::InvalidStringEscape))?;
+                self.pos += len;
+                chr
+            }
+            _ => return Err(self.error_at(start, ParseErrorKind::InvalidStringEscape)),
+        };
+        self.pos += 1;
+        string.push(chr);
+        Ok(())
+    }
+}
+
+/// Marks `table` and its sub-tables (defined with dotted keys) as inline,
+/// so they cannot be extended.
+fn mark_inline(table: &mut Table) {
+    table.kind = TableKind::Inline;
+    for node in table.items.values_mut() {
+        if let Node::Table(sub_table) = node {
+            if sub_table.kind == TableKind::Dotted {
+                mark_inline(sub_table);
+            }
+        }
+    }
+}
+
+/// Removes underscores from `s`, checking that each one is surrounded by
+/// digits. Returns `None` if `s` is empty or contains invalid characters.
+fn remove_underscores(s: &str, is_digit: impl Fn(char) -> bool) -> Option<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut prev_is_digit = false;
+    for chr in s.chars() {
+        if chr == '_' {
+            if !prev_is_digit {
+                return None;
+            }
+            prev_is_digit = false;
+        } else if is_digit(chr) {
+            result.push(chr);
+            prev_is_digit = true;
+        } else {
+            return None;
+        }
+    }
+    if prev_is_digit { Some(result) } else { None }
+}
+
+#[inline]
+fn is_bare_key_chr(chr: char) -> bool {
+    chr.is_ascii_alphanumeric() || chr == '_' || chr == '-'
+}
+
+#[inline]
+fn is_invalid_chr(chr: char) -> bool {
+    matches!(chr, '\0'..='\x08' | '\x0A'..='\x1F' | '\x7F')
+}
@@ -0,0 +1,514 @@
+use super::super::data::{ObjectData, SimpleObjectBuilder, ThunkData, ValueData};
+use super::Program;
+use crate::ast;
+
+/// Maximum nesting of elements, which avoids overflowing the stack while
+/// parsing and building the value.
+const MAX_DEPTH: usize = 256;
+
+pub(super) struct ParseError {
+    line: usize,
+    column: usize,
+    kind: ParseErrorKind,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line + 1,
+            self.column + 1,
+            self.kind,
+        )
+    }
+}
+
+enum ParseErrorKind {
+    Expected(&'static str),
+    InvalidChr,
+    UnknownEntity(String),
+    InvalidCharRef,
+    MismatchedTag { expected: String, got: String },
+    RepeatedAttribute(String),
+    TooDeep,
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Expected(what) => write!(f, "expected {what}"),
+            Self::InvalidChr => write!(f, "invalid character"),
+            Self::UnknownEntity(name) => write!(f, "unknown entity `&{name};`"),
+            Self::InvalidCharRef => write!(f, "invalid character reference"),
+            Self::MismatchedTag { expected, got } => {
+                write!(f, "expected closing tag for {expected:?}, got {got:?}")
+            }
+            Self::RepeatedAttribute(name) => write!(f, "repeated attribute {name:?}"),
+            Self::TooDeep => write!(f, "too many nested elements"),
+        }
+    }
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+/// Options of `std.parseXml`.
+pub(super) struct ParseOptions {
+    /// Keep text nodes that only contain whitespace.
+    pub(super) keep_whitespace: bool,
+    /// Remove namespace prefixes from element and attribute names and drop
+    /// `xmlns` attributes.
+    pub(super) strip_namespaces: bool,
+}
+
+/// Parses an XML document into JsonML.
+///
+/// Each element becomes `[tag, {attrs}, children...]`, where the attribute
+/// object is always present. Comments, processing instructions and the
+/// document type declaration are ignored, and CDATA sections are merged
+/// with the surrounding text.
+pub(super) fn parse_xml<'p>(
+    program: &mut Program<'p>,
+    s: &str,
+    options: &ParseOptions,
+) -> Result<ValueData<'p>, ParseError> {
+    let mut parser = Parser {
+        input: s,
+        pos: s.strip_prefix('\u{FEFF}').map_or(0, |_| 3),
+        options,
+    };
+
+    parser.skip_misc(true)?;
+    if !parser.rem().starts_with('<') {
+        return Err(parser.error(ParseErrorKind::Expected("root element")));
+    }
+    let root = parser.parse_element(0)?;
+    parser.skip_misc(false)?;
+    if parser.pos != parser.input.len() {
+        return Err(parser.error(ParseErrorKind::Expected("end of document")));
+    }
+
+    Ok(element_to_value(program, root))
+}
+
+fn element_to_value<'p>(program: &mut Program<'p>, element: Element) -> ValueData<'p> {
+    let attrs = if element.attrs.is_empty() {
+        ObjectData::new_empty()
+    } else {
+        let mut obj_builder = SimpleObjectBuilder::new();
+        for (name, value) in element.attrs {
+            let inserted = obj_builder.try_insert_field(
+                program.intern_str(&name),
+                ast::Visibility::Default,
+                program.gc_alloc(ThunkData::new_done(ValueData::String(value.into()))),
+            );
+            assert!(inserted);
+        }
+        obj_builder.build()
+    };
+
+    let mut items = Vec::with_capacity(element.children.len() + 2);
+    items.push(program.gc_alloc(ThunkData::new_done(ValueData::String(element.name.into()))));
+    items.push(program.gc_alloc(ThunkData::new_done(ValueData::Object(
+        program.gc_alloc(attrs),
+    ))));
+    for child in element.children {
+        let value = match child {
+            Node::Element(child) => element_to_value(program, child),
+            Node::Text(text) => ValueData::String(text.into()),
+        };
+        items.push(program.gc_alloc(ThunkData::new_done(value)));
+    }
+    ValueData::Array(program.make_thunk_array(items))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    options: &'a ParseOptions,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: ParseErrorKind) -> ParseError {
+        let before = &self.input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            line: before.matches('\n').count(),
+            column: before[line_start..].chars().count(),
+            kind,
+        }
+    }
+
+    #[inline]
+    fn rem(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rem().chars().next()
+    }
+
+    #[inline]
+    fn eat_char(&mut self, chr: char) -> bool {
+        if self.peek() == Some(chr) {
+            self.pos += chr.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn eat_str(&mut self, s: &str) -> bool {
+        if self.rem().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_char(&mut self, chr: char, what: &'static str) -> Result<(), ParseError> {
+        if self.eat_char(chr) {
+            Ok(())
+        } else {
+            Err(self.error(ParseErrorKind::Expected(what)))
+        }
+    }
+
+    fn skip_spaces(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(is_space_chr) {
+            self.pos += 1;
+        }
+        self.pos != start
+    }
+
+    /// Skips `input` until `end`, checking that every character is valid.
+    fn skip_until(&mut self, end: &str, what: &'static str) -> Result<(), ParseError> {
+        loop {
+            if self.eat_str(end) {
+                return Ok(());
+            }
+            match self.peek() {
+                None => return Err(self.error(ParseErrorKind::Expected(what))),
+                Some(chr) if is_invalid_chr(chr) => {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                Some(chr) => self.pos += chr.len_utf8(),
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and processing instructions, and the
+    /// document type declaration if `allow_doctype` is true.
+    fn skip_misc(&mut self, mut allow_doctype: bool) -> Result<(), ParseError> {
+        loop {
+            self.skip_spaces();
+            if self.eat_str("<!--") {
+                self.skip_until("-->", "`-->`")?;
+            } else if self.eat_str("<?") {
+                self.skip_until("?>", "`?>`")?;
+            } else if allow_doctype && self.eat_str("<!DOCTYPE") {
+                self.skip_doctype()?;
+                allow_doctype = false;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_doctype(&mut self) -> Result<(), ParseError> {
+        let mut in_subset = false;
+        loop {
+            match self.peek() {
+                None => return Err(self.error(ParseErrorKind::Expected("`>`"))),
+                Some(quote @ ('"' | '\'')) => {
+                    self.pos += 1;
+                    self.skip_until(if quote == '"' { "\"" } else { "'" }, "end of string")?;
+                }
+                Some('[') if !in_subset => {
+                    self.pos += 1;
+                    in_subset = true;
+                }
+                Some(']') if in_subset => {
+                    self.pos += 1;
+                    in_subset = false;
+                }
+                Some('<') if in_subset && self.rem().starts_with("<!--") => {
+                    self.pos += 4;
+                    self.skip_until("-->", "`-->`")?;
+                }
+                Some('>') if !in_subset => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(chr) if is_invalid_chr(chr) => {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                Some(chr) => self.pos += chr.len_utf8(),
+            }
+        }
+    }
+
+    fn parse_name(&mut self, what: &'static str) -> Result<&'a str, ParseError> {
+        let start = self.pos;
+        let mut chars = self.rem().chars();
+        if !chars.next().is_some_and(is_name_start_chr) {
+            return Err(self.error(ParseErrorKind::Expected(what)));
+        }
+        let len = self.rem().len() - chars.as_str().len();
+        let len = len
+            + chars
+                .take_while(|&chr| is_name_chr(chr))
+                .map(char::len_utf8)
+                .sum::<usize>();
+        self.pos += len;
+        Ok(&self.input[start..(start + len)])
+    }
+
+    fn local_name<'s>(&self, name: &'s str) -> &'s str {
+        if self.options.strip_namespaces {
+            name.rsplit(':').next().unwrap()
+        } else {
+            name
+        }
+    }
+
+    fn parse_element(&mut self, depth: usize) -> Result<Element, ParseError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.error(ParseErrorKind::TooDeep));
+        }
+
+        let start = self.pos;
+        self.pos += 1;
+        let raw_name = self.parse_name("element name")?;
+        let mut element = Element {
+            name: self.local_name(raw_name).into(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        };
+
+        loop {
+            let had_spaces = self.skip_spaces();
+            if self.eat_str("/>") {
+                return Ok(element);
+            }
+            if self.eat_char('>') {
+                break;
+            }
+            if !had_spaces {
+                return Err(self.error(ParseErrorKind::Expected("whitespace, `>` or `/>`")));
+            }
+
+            let attr_start = self.pos;
+            let attr_name = self.parse_name("attribute name, `>` or `/>`")?;
+            let is_xmlns = attr_name == "xmlns" || attr_name.starts_with("xmlns:");
+            let attr_name = String::from(self.local_name(attr_name));
+            self.skip_spaces();
+            self.expect_char('=', "`=`")?;
+            self.skip_spaces();
+            let value = self.parse_attr_value()?;
+
+            if is_xmlns && self.options.strip_namespaces {
+                continue;
+            }
+            if element.attrs.iter().any(|(name, _)| *name == attr_name) {
+                return Err(self.error_at(attr_start, ParseErrorKind::RepeatedAttribute(attr_name)));
+            }
+            element.attrs.push((attr_name, value));
+        }
+
+        let mut text = String::new();
+        loop {
+            if self.eat_str("</") {
+                let close_start = self.pos;
+                let close_name = self.parse_name("element name")?;
+                if close_name != raw_name {
+                    return Err(self.error_at(
+                        close_start,
+                        ParseErrorKind::MismatchedTag {
+                            expected: raw_name.into(),
+                            got: close_name.into(),
+                        },
+                    ));
+                }
+                self.skip_spaces();
+                self.expect_char('>', "`>`")?;
+                break;
+            } else if self.eat_str("<!--") {
+                self.skip_until("-->", "`-->`")?;
+            } else if self.eat_str("<![CDATA[") {
+                let cdata_start = self.pos;
+                self.skip_until("]]>", "`]]>`")?;
+                push_normalized(&mut text, &self.input[cdata_start..(self.pos - 3)]);
+            } else if self.eat_str("<?") {
+                self.skip_until("?>", "`?>`")?;
+            } else if self.rem().starts_with('<') {
+                self.flush_text(&mut text, &mut element.children);
+                let child = self.parse_element(depth + 1)?;
+                element.children.push(Node::Element(child));
+            } else if self.eat_char('&') {
+                self.parse_reference(&mut text)?;
+            } else if self.rem().starts_with("]]>") {
+                return Err(self.error(ParseErrorKind::InvalidChr));
+            } else {
+                match self.peek() {
+                    None => {
+                        return Err(self.error_at(
+                            start,
+                            ParseErrorKind::Expected("closing tag for this element"),
+                        ));
+                    }
+                    Some(chr) if is_invalid_chr(chr) => {
+                        return Err(self.error(ParseErrorKind::InvalidChr));
+                    }
+                    Some('\r') => {
+                        self.pos += 1;
+                        self.eat_char('\n');
+                        text.push('\n');
+                    }
+                    Some(chr) => {
+                        self.pos += chr.len_utf8();
+                        text.push(chr);
+                    }
+                }
+            }
+        }
+        self.flush_text(&mut text, &mut element.children);
+
+        Ok(element)
+    }
+
+    fn flush_text(&self, text: &mut String, children: &mut Vec<Node>) {
+        if text.is_empty() {
+            return;
+        }
+        let text = std::mem::take(text);
+        if self.options.keep_whitespace || !text.chars().all(is_space_chr) {
+            children.push(Node::Text(text));
+        }
+    }
+
+    fn parse_attr_value(&mut self) -> Result<String, ParseError> {
+        let quote = match self.peek() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err(self.error(ParseErrorKind::Expected("quoted attribute value"))),
+        };
+        self.pos += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error(ParseErrorKind::Expected("end of attribute value"))),
+                Some(chr) if chr == quote => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some('&') => {
+                    self.pos += 1;
+                    self.parse_reference(&mut value)?;
+                }
+                Some(chr) if chr == '<' || is_invalid_chr(chr) => {
+                    return Err(self.error(ParseErrorKind::InvalidChr));
+                }
+                // Attribute value normalization, a line break counts as a
+                // single space.
+                Some('\r') => {
+                    self.pos += 1;
+                    self.eat_char('\n');
+                    value.push(' ');
+                }
+                Some('\t' | '\n') => {
+                    self.pos += 1;
+                    value.push(' ');
+                }
+                Some(chr) => {
+                    self.pos += chr.len_utf8();
+                    value.push(chr);
+                }
+            }
+        }
+    }
+
+    /// Parses an entity or character reference after `&`.
+    fn parse_reference(&mut self, out: &mut String) -> Result<(), ParseError> {
+        let start = self.pos - 1;
+        if self.eat_char('#') {
+            let radix = if self.eat_char('x') { 16 } else { 10 };
+            let digits_start = self.pos;
+            while self.peek().is_some_and(|chr| chr.is_digit(radix)) {
+                self.pos += 1;
+            }
+            let chr = u32::from_str_radix(&self.input[digits_start..self.pos], radix)
+                .ok()
+                .and_then(char::from_u32)
+                .filter(|&chr| !is_invalid_chr(chr));
+            match chr {
+                Some(chr) if self.eat_char(';') => {
+                    out.push(chr);
+                    Ok(())
+                }
+                _ => Err(self.error_at(start, ParseErrorKind::InvalidCharRef)),
+            }
+        } else {
+            let name = self.parse_name("entity name")?;
+            let chr = match name {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "apos" => '\'',
+                "quot" => '"',
+                _ => return Err(self.error_at(start, ParseErrorKind::UnknownEntity(name.into()))),
+            };
+            self.expect_char(';', "`;`")?;
+            out.push(chr);
+            Ok(())
+        }
+    }
+}
+
+/// Appends `s` to `out`, converting CRLF and CR line breaks to LF.
+fn push_normalized(out: &mut String, s: &str) {
+    let mut rem = s;
+    while let Some(i) = rem.find('\r') {
+        out.push_str(&rem[..i]);
+        out.push('\n');
+        rem = rem[(i + 1)..].strip_prefix('\n').unwrap_or(&rem[(i + 1)..]);
+    }
+    out.push_str(rem);
+}
+
+#[inline]
+fn is_space_chr(chr: char) -> bool {
+    matches!(chr, ' ' | '\t' | '\n' | '\r')
+}
+
+#[inline]
+fn is_name_start_chr(chr: char) -> bool {
+    chr.is_ascii_alphabetic() || matches!(chr, '_' | ':') || !chr.is_ascii()
+}
+
+#[inline]
+fn is_name_chr(chr: char) -> bool {
+    is_name_start_chr(chr) || chr.is_ascii_digit() || matches!(chr, '-' | '.')
+}
+
+#[inline]
+fn is_invalid_chr(chr: char) -> bool {
+    (chr < ' ' && !is_space_chr(chr)) || matches!(chr, '\u{FFFE}' | '\u{FFFF}')
+}
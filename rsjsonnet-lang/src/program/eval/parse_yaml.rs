@@ -15,6 +15,14 @@ pub(crate) enum ParseError<'p> {
     RepeatedFieldName(InternedStr<'p>),
 }
 
+impl From<ParseError<'_>> for super::super::ParseYamlError {
+    fn from(e: ParseError<'_>) -> Self {
+        Self {
+            message: e.to_string(),
+        }
+    }
+}
+
 impl From<saphyr_parser::ScanError> for ParseError<'_> {
     #[inline]
     fn from(e: saphyr_parser::ScanError) -> Self {
@@ -51,7 +59,7 @@ impl std::fmt::Display for ParseError<'_> {
     }
 }
 
-pub(super) fn parse_yaml<'p>(
+pub(crate) fn parse_yaml<'p>(
     program: &mut Program<'p>,
     s: &str,
 ) -> Result<ValueData<'p>, ParseError<'p>> {
@@ -109,7 +117,7 @@ pub(super) fn parse_yaml<'p>(
             if items.is_empty() {
                 Ok(ValueData::Array(Gc::from(&program.empty_array)))
             } else {
-                Ok(ValueData::Array(program.gc_alloc(items.into_boxed_slice())))
+                Ok(ValueData::Array(program.make_thunk_array(items)))
             }
         }
     }
@@ -265,7 +273,7 @@ fn parse_yaml_document<'p>(
                     } => {
                         items.push(program.gc_alloc(ThunkData::new_done(value)));
                         if matches!(event.0, saphyr_parser::Event::SequenceEnd) {
-                            let array = program.gc_alloc(items.into_boxed_slice());
+                            let array = program.make_thunk_array(items);
                             anchors.insert(anchor_id, AnchorValue::Array(array.clone()));
                             value = ValueData::Array(array);
 
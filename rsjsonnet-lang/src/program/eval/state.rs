@@ -1,9 +1,13 @@
 use std::cell::{Cell, OnceCell};
 use std::rc::Rc;
 
+use super::super::string_data::StringData;
 use super::super::{ArrayData, FuncData, ObjectData, ThunkData, ThunkEnv, ValueData, ir};
+use super::encoding::ByteEncoding;
 use super::format::FormatPart;
-use super::{EvalResult, Evaluator, ManifestJsonFormat, TraceItem};
+use super::hash::HashAlgo;
+use super::parse_csv::CsvFormat;
+use super::{EvalResult, Evaluator, ManifestJsonFormat, ManifestKind, TraceItem};
 use crate::ast;
 use crate::gc::{Gc, GcView};
 use crate::interner::InternedStr;
@@ -37,6 +41,7 @@ pub(super) enum State<'a, 'p> {
     AppendToString(String),
     ArrayToValue,
     ObjectToValue,
+    Manifest(ManifestKind),
     ManifestIniSection,
     ManifestIniSectionItem {
         name: InternedStr<'p>,
@@ -69,6 +74,19 @@ pub(super) enum State<'a, 'p> {
         depth: usize,
         single_line: bool,
     },
+    ManifestCsvFirstRow {
+        format: Rc<CsvFormat>,
+        columns: Option<Rc<[InternedStr<'p>]>>,
+        rows: GcView<ArrayData<'p>>,
+    },
+    ManifestCsvRow {
+        format: Rc<CsvFormat>,
+        columns: Option<Rc<[InternedStr<'p>]>>,
+    },
+    ManifestCsvField {
+        format: Rc<CsvFormat>,
+        only_field: bool,
+    },
     Expr {
         expr: &'p ir::Expr<'p>,
         env: GcView<ThunkEnv<'p>>,
@@ -104,23 +122,19 @@ pub(super) enum State<'a, 'p> {
         field_name: InternedStr<'p>,
     },
     InitCompSpec {
-        var_name: InternedStr<'p>,
         value: &'p ir::Expr<'p>,
         value_span: SpanId,
         env: GcView<ThunkEnv<'p>>,
     },
     GotInitCompSpec {
-        var_name: InternedStr<'p>,
         value_span: SpanId,
     },
     ForSpec {
-        var_name: InternedStr<'p>,
         value: &'p ir::Expr<'p>,
         value_span: SpanId,
         env: GcView<ThunkEnv<'p>>,
     },
     GotForSpec {
-        var_name: InternedStr<'p>,
         value_span: SpanId,
     },
     IfSpec {
@@ -202,11 +216,14 @@ pub(super) enum State<'a, 'p> {
         named_args: Box<[(InternedStr<'p>, GcView<ThunkData<'p>>)]>,
     },
     ExecTailstrictCall {
+        call_span: SpanId,
+        func_name: Option<InternedStr<'p>>,
         func: GcView<FuncData<'p>>,
         args: Box<[Gc<ThunkData<'p>>]>,
     },
     ExecNativeCall {
         name: InternedStr<'p>,
+        params: &'p [(InternedStr<'p>, Option<&'p ir::Expr<'p>>)],
         args: Box<[GcView<ThunkData<'p>>]>,
     },
     If {
@@ -215,6 +232,12 @@ pub(super) enum State<'a, 'p> {
         else_body: Option<&'p ir::Expr<'p>>,
         env: GcView<ThunkEnv<'p>>,
     },
+    /// Resumes running `code` at `pc`.
+    Bytecode {
+        code: &'p [ir::Op<'p>],
+        pc: usize,
+        env: GcView<ThunkEnv<'p>>,
+    },
     StdPruneValue,
     StdPruneArrayItem,
     StdPruneObjectField {
@@ -271,6 +294,7 @@ pub(super) enum State<'a, 'p> {
     StdManifestXmlJsonmlItem1,
     StdManifestXmlJsonmlItemN,
     StdManifestTomlEx,
+    StdManifestCsv,
     StdMember {
         value: GcView<ThunkData<'p>>,
     },
@@ -321,7 +345,7 @@ pub(super) enum State<'a, 'p> {
     },
     StdJoin,
     StdJoinStrItem {
-        sep: Rc<str>,
+        sep: StringData,
     },
     StdJoinStrFinish,
     StdJoinArrayItem {
@@ -513,13 +537,28 @@ pub(super) enum State<'a, 'p> {
         end: usize,
         mid: usize,
     },
-    StdBase64,
-    StdBase64Array {
+    StdEncodeBytes {
+        encoding: ByteEncoding,
+    },
+    StdEncodeBytesArray {
+        encoding: ByteEncoding,
         input: GcView<ArrayData<'p>>,
         bytes: Vec<u8>,
     },
-    StdBase64DecodeBytes,
-    StdBase64Decode,
+    StdDecodeBytes {
+        encoding: ByteEncoding,
+    },
+    StdDecodeString {
+        encoding: ByteEncoding,
+    },
+    StdCollectBytes {
+        func_name: &'static str,
+        arg_index: usize,
+    },
+    StdHash {
+        algo: HashAlgo,
+    },
+    StdHmac,
     StdMergePatchValue,
     StdMergePatchField {
         name: InternedStr<'p>,
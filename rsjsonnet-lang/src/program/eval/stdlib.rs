@@ -1,12 +1,16 @@
 use std::cell::{Cell, OnceCell};
-use std::fmt::Write as _;
+use std::collections::hash_map::Entry as HashMapEntry;
 use std::rc::Rc;
 
 use super::super::data::{
     ArrayData, FuncData, ObjectData, ObjectField, ObjectFieldData, SimpleObjectBuilder, ThunkData,
     ValueData,
 };
-use super::manifest::{escape_string_json, escape_string_python};
+use super::super::string_data::{StringBuilder, StringData};
+use super::encoding::ByteEncoding;
+use super::hash::HashAlgo;
+use super::manifest::{escape_string_json, escape_string_python, write_csv_record};
+use super::parse_csv::CsvFormat;
 use super::{
     EvalErrorKind, EvalErrorValueType, EvalResult, Evaluator, ManifestJsonFormat,
     ParseNumRadixError, State, TraceItem, float, parse_num_radix,
@@ -15,20 +19,46 @@ use crate::gc::{Gc, GcView};
 use crate::interner::InternedStr;
 use crate::{FHashSet, ast};
 
+/// Compiled regular expressions of a pattern, cached in the
+/// [`Program`](super::super::Program).
+pub(in super::super) struct CachedRegex {
+    partial: regex::Regex,
+    /// Anchored at both ends, only compiled when needed.
+    full: Option<regex::Regex>,
+}
+
 impl<'p> Evaluator<'_, 'p> {
     pub(super) fn do_std_ext_var(&mut self) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
         let name = self.expect_std_func_arg_string(arg, "extVar", 0)?;
-        let thunk = self
+        let name = self.program.intern_str(&name);
+        if self.program.requested_ext_vars_set.insert(name) {
+            self.program.requested_ext_vars.push(name);
+        }
+
+        let thunk = if let Some(thunk) = self
             .program
-            .str_interner
-            .get_interned(&name)
-            .and_then(|name| self.program.ext_vars.get(&name).cloned())
-            .ok_or_else(|| {
-                self.report_error(EvalErrorKind::UnknownExtVar {
-                    name: (*name).into(),
-                })
-            })?;
+            .ext_vars
+            .get(&name)
+            .or_else(|| self.program.resolved_ext_vars.get(&name))
+        {
+            thunk.clone()
+        } else if let Some(thunk) = self
+            .callbacks
+            .as_mut()
+            .and_then(|callbacks| callbacks.ext_var(self.program, name))
+        {
+            // Cache the value so the callback is not called again. It is
+            // not copied by `Program::fork`.
+            self.program
+                .resolved_ext_vars
+                .insert(name, thunk.data.clone());
+            thunk.data
+        } else {
+            return Err(self.report_error(EvalErrorKind::UnknownExtVar {
+                name: name.value().into(),
+            }));
+        };
 
         self.state_stack.push(State::DoThunk(thunk));
         Ok(())
@@ -978,6 +1008,187 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(())
     }
 
+    fn get_regex(&mut self, pattern: &str, full: bool) -> EvalResult<regex::Regex> {
+        if let Some(cached) = self.program.regex_cache.get(pattern) {
+            if !full {
+                return Ok(cached.partial.clone());
+            }
+            if let Some(ref full) = cached.full {
+                return Ok(full.clone());
+            }
+        }
+
+        let compile = |pattern: &str| {
+            regex::Regex::new(pattern).map_err(|e| {
+                // Syntax errors span several lines, with the pattern and a
+                // caret, followed by an "error: " line with the reason.
+                let e = e.to_string();
+                let reason = e.rsplit_once("error: ").map_or(&*e, |(_, reason)| reason);
+                EvalErrorKind::Other {
+                    span: None,
+                    message: format!("invalid regular expression {pattern:?}: {reason}"),
+                }
+            })
+        };
+        let cached = match self.program.regex_cache.entry(pattern.into()) {
+            HashMapEntry::Occupied(entry) => entry.into_mut(),
+            HashMapEntry::Vacant(entry) => match compile(pattern) {
+                Ok(partial) => entry.insert(CachedRegex {
+                    partial,
+                    full: None,
+                }),
+                Err(e) => return Err(self.report_error(e)),
+            },
+        };
+        if !full {
+            return Ok(cached.partial.clone());
+        }
+        // The original pattern is valid, so the anchored one is too.
+        let full = compile(&format!(r"\A(?:{pattern})\z")).unwrap();
+        cached.full = Some(full.clone());
+        Ok(full)
+    }
+
+    fn make_regex_match_value(
+        &mut self,
+        regex: &regex::Regex,
+        captures: &regex::Captures<'_>,
+    ) -> ValueData<'p> {
+        let capture_value = |m: Option<regex::Match<'_>>| {
+            m.map_or(ValueData::Null, |m| ValueData::String(m.as_str().into()))
+        };
+
+        let positional = self
+            .program
+            .make_value_array(captures.iter().skip(1).map(capture_value));
+
+        let mut named_builder = SimpleObjectBuilder::new();
+        for (i, name) in regex.capture_names().enumerate() {
+            if let Some(name) = name {
+                named_builder.insert_field(
+                    self.program.intern_str(name),
+                    ast::Visibility::Default,
+                    self.program
+                        .gc_alloc(ThunkData::new_done(capture_value(captures.get(i)))),
+                );
+            }
+        }
+        let named = self.program.gc_alloc(named_builder.build());
+
+        let mut obj_builder = SimpleObjectBuilder::new();
+        obj_builder.insert_field(
+            self.program.intern_str("string"),
+            ast::Visibility::Default,
+            self.program
+                .gc_alloc(ThunkData::new_done(capture_value(captures.get(0)))),
+        );
+        obj_builder.insert_field(
+            self.program.intern_str("captures"),
+            ast::Visibility::Default,
+            self.program
+                .gc_alloc(ThunkData::new_done(ValueData::Array(positional))),
+        );
+        obj_builder.insert_field(
+            self.program.intern_str("namedCaptures"),
+            ast::Visibility::Default,
+            self.program
+                .gc_alloc(ThunkData::new_done(ValueData::Object(named))),
+        );
+        ValueData::Object(self.program.gc_alloc(obj_builder.build()))
+    }
+
+    pub(super) fn do_std_regex_match(&mut self) -> EvalResult<()> {
+        let s = self.value_stack.pop().unwrap();
+        let pattern = self.value_stack.pop().unwrap();
+
+        let pattern = self.expect_std_func_arg_string(pattern, "regexMatch", 0)?;
+        let s = self.expect_std_func_arg_string(s, "regexMatch", 1)?;
+
+        let regex = self.get_regex(&pattern, false)?;
+        let result = match regex.captures(&s) {
+            Some(captures) => self.make_regex_match_value(&regex, &captures),
+            None => ValueData::Null,
+        };
+        self.value_stack.push(result);
+
+        Ok(())
+    }
+
+    pub(super) fn do_std_regex_full_match(&mut self) -> EvalResult<()> {
+        let s = self.value_stack.pop().unwrap();
+        let pattern = self.value_stack.pop().unwrap();
+
+        let pattern = self.expect_std_func_arg_string(pattern, "regexFullMatch", 0)?;
+        let s = self.expect_std_func_arg_string(s, "regexFullMatch", 1)?;
+
+        let regex = self.get_regex(&pattern, true)?;
+        let result = match regex.captures(&s) {
+            Some(captures) => self.make_regex_match_value(&regex, &captures),
+            None => ValueData::Null,
+        };
+        self.value_stack.push(result);
+
+        Ok(())
+    }
+
+    pub(super) fn do_std_regex_find_all(&mut self) -> EvalResult<()> {
+        let s = self.value_stack.pop().unwrap();
+        let pattern = self.value_stack.pop().unwrap();
+
+        let pattern = self.expect_std_func_arg_string(pattern, "regexFindAll", 0)?;
+        let s = self.expect_std_func_arg_string(s, "regexFindAll", 1)?;
+
+        let regex = self.get_regex(&pattern, false)?;
+        let matches: Vec<_> = regex
+            .captures_iter(&s)
+            .map(|captures| self.make_regex_match_value(&regex, &captures))
+            .collect();
+        let result_array = self.program.make_value_array(matches);
+        self.value_stack.push(ValueData::Array(result_array));
+
+        Ok(())
+    }
+
+    pub(super) fn do_std_regex_replace(&mut self) -> EvalResult<()> {
+        let to = self.value_stack.pop().unwrap();
+        let pattern = self.value_stack.pop().unwrap();
+        let s = self.value_stack.pop().unwrap();
+
+        let s = self.expect_std_func_arg_string(s, "regexReplace", 0)?;
+        let pattern = self.expect_std_func_arg_string(pattern, "regexReplace", 1)?;
+        let to = self.expect_std_func_arg_string(to, "regexReplace", 2)?;
+
+        let regex = self.get_regex(&pattern, false)?;
+        let result = regex.replace_all(&s, &*to);
+        self.value_stack.push(ValueData::String(result.into()));
+
+        Ok(())
+    }
+
+    pub(super) fn do_std_regex_split(&mut self) -> EvalResult<()> {
+        let pattern = self.value_stack.pop().unwrap();
+        let s = self.value_stack.pop().unwrap();
+
+        let s = self.expect_std_func_arg_string(s, "regexSplit", 0)?;
+        let pattern = self.expect_std_func_arg_string(pattern, "regexSplit", 1)?;
+
+        let regex = self.get_regex(&pattern, false)?;
+        let result_array = self
+            .program
+            .make_value_array(regex.split(&s).map(|s| ValueData::String(s.into())));
+        self.value_stack.push(ValueData::Array(result_array));
+
+        Ok(())
+    }
+
+    pub(super) fn do_std_regex_quote_meta(&mut self) -> EvalResult<()> {
+        let s = self.value_stack.pop().unwrap();
+        let s = self.expect_std_func_arg_string(s, "regexQuoteMeta", 0)?;
+        self.value_stack
+            .push(ValueData::String(regex::escape(&s).into()));
+        Ok(())
+    }
+
     pub(super) fn do_std_trim(&mut self) -> EvalResult<()> {
         let s = self.value_stack.pop().unwrap();
         let s = self.expect_std_func_arg_string(s, "trim", 0)?;
@@ -1187,6 +1398,81 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(())
     }
 
+    pub(super) fn do_std_parse_toml(&mut self) -> EvalResult<()> {
+        let arg = self.value_stack.pop().unwrap();
+        let s = self.expect_std_func_arg_string(arg, "parseToml", 0)?;
+        match super::parse_toml::parse_toml(self.program, &s) {
+            Ok(value) => {
+                self.value_stack.push(value);
+            }
+            Err(e) => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: format!("failed to parse TOML: {e}"),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn do_std_parse_csv(&mut self) -> EvalResult<()> {
+        let quote = self.value_stack.pop().unwrap();
+        let delimiter = self.value_stack.pop().unwrap();
+        let header = self.value_stack.pop().unwrap();
+        let s = self.value_stack.pop().unwrap();
+
+        let s = self.expect_std_func_arg_string(s, "parseCsv", 0)?;
+        let header = self.expect_std_func_arg_bool(header, "parseCsv", 1)?;
+        let delimiter = self.expect_std_func_arg_string(delimiter, "parseCsv", 2)?;
+        let quote = self.expect_std_func_arg_string(quote, "parseCsv", 3)?;
+
+        let format = match CsvFormat::from_args(&delimiter, &quote) {
+            Ok(format) => format,
+            Err(e) => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: e.into(),
+                }));
+            }
+        };
+        match super::parse_csv::parse_csv(self.program, &s, header, &format) {
+            Ok(value) => {
+                self.value_stack.push(value);
+            }
+            Err(e) => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: format!("failed to parse CSV: {e}"),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn do_std_parse_xml(&mut self) -> EvalResult<()> {
+        let strip_namespaces = self.value_stack.pop().unwrap();
+        let keep_whitespace = self.value_stack.pop().unwrap();
+        let s = self.value_stack.pop().unwrap();
+
+        let s = self.expect_std_func_arg_string(s, "parseXml", 0)?;
+        let options = super::parse_xml::ParseOptions {
+            keep_whitespace: self.expect_std_func_arg_bool(keep_whitespace, "parseXml", 1)?,
+            strip_namespaces: self.expect_std_func_arg_bool(strip_namespaces, "parseXml", 2)?,
+        };
+        match super::parse_xml::parse_xml(self.program, &s, &options) {
+            Ok(value) => {
+                self.value_stack.push(value);
+            }
+            Err(e) => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: format!("failed to parse XML: {e}"),
+                }));
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn do_std_encode_utf8(&mut self) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
         let s = self.expect_std_func_arg_string(arg, "encodeUTF8", 0)?;
@@ -1206,14 +1492,14 @@ impl<'p> Evaluator<'_, 'p> {
             .push(State::FnInfallible(Self::do_std_decode_utf8_finish));
         for item in array.iter().rev() {
             self.state_stack
-                .push(State::FnFallible(Self::do_std_decode_utf8_check_item));
+                .push(State::FnFallible(Self::do_std_check_byte_item));
             self.state_stack.push(State::DoThunk(item.view()));
         }
 
         Ok(())
     }
 
-    fn do_std_decode_utf8_check_item(&mut self) -> EvalResult<()> {
+    fn do_std_check_byte_item(&mut self) -> EvalResult<()> {
         let item = self.value_stack.pop().unwrap();
         let ValueData::Number(value) = item else {
             return Err(self.report_error(EvalErrorKind::Other {
@@ -1244,30 +1530,9 @@ impl<'p> Evaluator<'_, 'p> {
         let ini = self.value_stack.pop().unwrap();
         let ini = self.expect_std_func_arg_object(ini, "manifestIni", 0)?;
 
-        let main_name = self.program.intern_str("main");
-        let sections_name = self.program.intern_str("sections");
-
-        let Some(sections_thunk) = self.program.find_object_field_thunk(&ini, 0, sections_name)
-        else {
-            return Err(self.report_error(EvalErrorKind::Other {
-                span: None,
-                message: "missing \"sections\" field".into(),
-            }));
-        };
-
-        let main_thunk = self.program.find_object_field_thunk(&ini, 0, main_name);
-
         self.string_stack.push(String::new());
         self.state_stack.push(State::StringToValue);
-        self.state_stack.push(State::StdManifestIniSections);
-        self.state_stack.push(State::DoThunk(sections_thunk));
-        if let Some(main_thunk) = main_thunk {
-            self.state_stack.push(State::ManifestIniSection);
-            self.state_stack.push(State::DoThunk(main_thunk));
-        }
-        self.check_object_asserts(&ini);
-
-        Ok(())
+        self.prepare_manifest_ini(ini)
     }
 
     pub(super) fn do_std_manifest_ini_sections(&mut self) -> EvalResult<()> {
@@ -1385,30 +1650,14 @@ impl<'p> Evaluator<'_, 'p> {
             self.expect_std_func_arg_bool(c_document_end, "manifestYamlStream", 2)?;
         let quote_keys = self.expect_std_func_arg_bool(quote_keys, "manifestYamlStream", 3)?;
 
-        self.string_stack.push("---\n".into());
+        self.string_stack.push(String::new());
         self.state_stack.push(State::StringToValue);
-
-        if c_document_end {
-            self.state_stack
-                .push(State::AppendToString("\n...\n".into()));
-        } else {
-            self.state_stack.push(State::AppendToString('\n'.into()));
-        }
-
-        for (i, item) in array.iter().rev().enumerate() {
-            if i != 0 {
-                self.state_stack
-                    .push(State::AppendToString("\n---\n".into()));
-            }
-            self.state_stack.push(State::ManifestYamlDoc {
-                indent_array_in_object,
-                quote_keys,
-                depth: 0,
-                parent_is_array: false,
-                parent_is_object: false,
-            });
-            self.state_stack.push(State::DoThunk(item.view()));
-        }
+        self.prepare_manifest_yaml_stream(
+            array,
+            indent_array_in_object,
+            c_document_end,
+            quote_keys,
+        );
 
         Ok(())
     }
@@ -1560,6 +1809,81 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(())
     }
 
+    pub(super) fn do_std_manifest_csv(&mut self) -> EvalResult<()> {
+        let quote = self.value_stack.pop().unwrap();
+        let delimiter = self.value_stack.pop().unwrap();
+        let columns = self.value_stack.pop().unwrap();
+        let rows = self.value_stack.pop().unwrap();
+
+        let rows = self.expect_std_func_arg_array(rows, "manifestCsv", 0)?;
+        let columns = match columns {
+            ValueData::Null => None,
+            ValueData::Array(array) => {
+                let mut columns = Vec::with_capacity(array.view().len());
+                for item in array.view().iter() {
+                    let ValueData::String(name) =
+                        item.view().get_value(&self.program.fork_node).unwrap()
+                    else {
+                        return Err(self.report_error(EvalErrorKind::Other {
+                            span: None,
+                            message: "column names must be strings".into(),
+                        }));
+                    };
+                    columns.push(self.program.intern_str(&name));
+                }
+                Some(Rc::<[_]>::from(columns))
+            }
+            value => {
+                return Err(self.report_error(EvalErrorKind::InvalidStdFuncArgType {
+                    func_name: "manifestCsv".into(),
+                    arg_index: 1,
+                    expected_types: vec![EvalErrorValueType::Null, EvalErrorValueType::Array],
+                    got_type: EvalErrorValueType::from_value(&value),
+                }));
+            }
+        };
+        let delimiter = self.expect_std_func_arg_string(delimiter, "manifestCsv", 2)?;
+        let quote = self.expect_std_func_arg_string(quote, "manifestCsv", 3)?;
+
+        let format = match CsvFormat::from_args(&delimiter, &quote) {
+            Ok(format) => Rc::new(format),
+            Err(e) => {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: e.into(),
+                }));
+            }
+        };
+
+        let mut header = String::new();
+        if let Some(ref columns) = columns {
+            if let Err(e) = write_csv_record(&format, columns, |name| name.value(), &mut header) {
+                return Err(self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: e.into(),
+                }));
+            }
+        }
+
+        self.string_stack.push(header);
+        self.state_stack.push(State::StringToValue);
+
+        if !rows.is_empty() {
+            // The first row is evaluated before the others, so its fields
+            // can be used as column names when `columns` is null.
+            self.state_stack.push(State::ManifestCsvFirstRow {
+                format,
+                columns,
+                rows: rows.clone(),
+            });
+            self.push_trace_item(TraceItem::ManifestArrayItem { index: 0 });
+            self.state_stack.push(State::DoThunk(rows[0].view()));
+            self.delay_trace_item();
+        }
+
+        Ok(())
+    }
+
     pub(super) fn do_std_make_array(&mut self) -> EvalResult<()> {
         let func_value = self.value_stack.pop().unwrap();
         let sz_value = self.value_stack.pop().unwrap();
@@ -1597,9 +1921,8 @@ impl<'p> Evaluator<'_, 'p> {
             );
         }
 
-        self.value_stack.push(ValueData::Array(
-            self.program.gc_alloc(array.into_boxed_slice()),
-        ));
+        self.value_stack
+            .push(ValueData::Array(self.program.make_thunk_array(array)));
 
         Ok(())
     }
@@ -1608,7 +1931,8 @@ impl<'p> Evaluator<'_, 'p> {
         let arr_or_str = self.value_stack.pop().unwrap();
         match arr_or_str {
             ValueData::String(s) => {
-                self.state_stack.push(State::StdMemberString { string: s });
+                self.state_stack
+                    .push(State::StdMemberString { string: s.to_rc() });
                 self.state_stack.push(State::DoThunk(value));
                 Ok(())
             }
@@ -1764,7 +2088,7 @@ impl<'p> Evaluator<'_, 'p> {
         } else {
             let result = self.array_stack.pop().unwrap();
             *self.value_stack.last_mut().unwrap() =
-                ValueData::Array(self.program.gc_alloc(result.into_boxed_slice()));
+                ValueData::Array(self.program.make_thunk_array(result));
         }
     }
 
@@ -1788,9 +2112,8 @@ impl<'p> Evaluator<'_, 'p> {
                     );
                 }
 
-                self.value_stack.push(ValueData::Array(
-                    self.program.gc_alloc(array.into_boxed_slice()),
-                ));
+                self.value_stack
+                    .push(ValueData::Array(self.program.make_thunk_array(array)));
 
                 Ok(())
             }
@@ -1807,9 +2130,8 @@ impl<'p> Evaluator<'_, 'p> {
                     );
                 }
 
-                self.value_stack.push(ValueData::Array(
-                    self.program.gc_alloc(new_array.into_boxed_slice()),
-                ));
+                self.value_stack
+                    .push(ValueData::Array(self.program.make_thunk_array(new_array)));
 
                 Ok(())
             }
@@ -1845,9 +2167,8 @@ impl<'p> Evaluator<'_, 'p> {
                     );
                 }
 
-                self.value_stack.push(ValueData::Array(
-                    self.program.gc_alloc(array.into_boxed_slice()),
-                ));
+                self.value_stack
+                    .push(ValueData::Array(self.program.make_thunk_array(array)));
 
                 Ok(())
             }
@@ -1868,9 +2189,8 @@ impl<'p> Evaluator<'_, 'p> {
                     );
                 }
 
-                self.value_stack.push(ValueData::Array(
-                    self.program.gc_alloc(new_array.into_boxed_slice()),
-                ));
+                self.value_stack
+                    .push(ValueData::Array(self.program.make_thunk_array(new_array)));
 
                 Ok(())
             }
@@ -2231,7 +2551,7 @@ impl<'p> Evaluator<'_, 'p> {
             ValueData::String(sep) => {
                 self.bool_stack.push(true);
                 self.state_stack.push(State::StdJoinStrFinish);
-                self.string_stack.push(String::new());
+                self.string_builder_stack.push(StringBuilder::new());
                 for item in array.iter().rev() {
                     self.state_stack
                         .push(State::StdJoinStrItem { sep: sep.clone() });
@@ -2260,7 +2580,7 @@ impl<'p> Evaluator<'_, 'p> {
         }
     }
 
-    pub(super) fn do_std_join_str_item(&mut self, sep: Rc<str>) -> EvalResult<()> {
+    pub(super) fn do_std_join_str_item(&mut self, sep: StringData) -> EvalResult<()> {
         let item = self.value_stack.pop().unwrap();
         if !matches!(item, ValueData::Null) {
             let ValueData::String(item) = item else {
@@ -2272,22 +2592,22 @@ impl<'p> Evaluator<'_, 'p> {
                     ),
                 }));
             };
-            let s = self.string_stack.last_mut().unwrap();
+            let s = self.string_builder_stack.last_mut().unwrap();
             let first = self.bool_stack.last_mut().unwrap();
             if *first {
                 *first = false;
             } else {
-                s.push_str(&sep);
+                s.push_string(&sep);
             }
-            s.push_str(&item);
+            s.push_string(&item);
         }
         Ok(())
     }
 
     pub(super) fn do_std_join_str_finish(&mut self) {
-        let s = self.string_stack.pop().unwrap();
+        let s = self.string_builder_stack.pop().unwrap();
         self.bool_stack.pop().unwrap();
-        self.value_stack.push(ValueData::String(s.into()));
+        self.value_stack.push(ValueData::String(s.finish()));
     }
 
     pub(super) fn do_std_join_array_item(&mut self, sep: GcView<ArrayData<'p>>) -> EvalResult<()> {
@@ -2318,9 +2638,8 @@ impl<'p> Evaluator<'_, 'p> {
     pub(super) fn do_std_join_array_finish(&mut self) {
         let array = self.array_stack.pop().unwrap();
         self.bool_stack.pop().unwrap();
-        self.value_stack.push(ValueData::Array(
-            self.program.gc_alloc(array.into_boxed_slice()),
-        ));
+        self.value_stack
+            .push(ValueData::Array(self.program.make_thunk_array(array)));
     }
 
     pub(super) fn do_std_deep_join(&mut self) -> EvalResult<()> {
@@ -3707,18 +4026,28 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(())
     }
 
-    pub(super) fn do_std_base64(&mut self) -> EvalResult<()> {
+    pub(super) fn do_std_encode_bytes(&mut self, encoding: ByteEncoding) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
         match arg {
             ValueData::String(s) => {
-                let encoded = encode_base64(s.chars().map(|chr| {
-                    u8::try_from(chr).map_err(|_| {
-                        self.report_error(EvalErrorKind::Other {
-                            span: None,
-                            message: "only codepoints up to 255 can be base64 encoded".into(),
+                let bytes = if encoding == ByteEncoding::Url {
+                    s.as_bytes().to_vec()
+                } else {
+                    s.chars()
+                        .map(|chr| {
+                            u8::try_from(chr).map_err(|_| {
+                                self.report_error(EvalErrorKind::Other {
+                                    span: None,
+                                    message: format!(
+                                        "only codepoints up to 255 can be {} encoded",
+                                        encoding.name(),
+                                    ),
+                                })
+                            })
                         })
-                    })
-                }))?;
+                        .collect::<EvalResult<Vec<_>>>()?
+                };
+                let encoded = encoding.encode(&bytes);
                 self.value_stack.push(ValueData::String(encoded.into()));
                 Ok(())
             }
@@ -3727,18 +4056,20 @@ impl<'p> Evaluator<'_, 'p> {
                 if let Some(first_item) = arr.first() {
                     let first_item = first_item.view();
                     let arr_len = arr.len();
-                    self.state_stack.push(State::StdBase64Array {
+                    self.state_stack.push(State::StdEncodeBytesArray {
+                        encoding,
                         input: arr,
                         bytes: Vec::with_capacity(arr_len),
                     });
                     self.state_stack.push(State::DoThunk(first_item));
-                } else if arr.is_empty() {
-                    self.value_stack.push(ValueData::String("".into()));
+                } else {
+                    self.value_stack
+                        .push(ValueData::String(encoding.encode(&[]).into()));
                 }
                 Ok(())
             }
             _ => Err(self.report_error(EvalErrorKind::InvalidStdFuncArgType {
-                func_name: "base64".into(),
+                func_name: encoding.encode_func_name().into(),
                 arg_index: 0,
                 expected_types: vec![EvalErrorValueType::String, EvalErrorValueType::Array],
                 got_type: EvalErrorValueType::from_value(&arg),
@@ -3746,8 +4077,9 @@ impl<'p> Evaluator<'_, 'p> {
         }
     }
 
-    pub(super) fn do_std_base64_array(
+    pub(super) fn do_std_encode_bytes_array(
         &mut self,
+        encoding: ByteEncoding,
         input: GcView<ArrayData<'p>>,
         mut bytes: Vec<u8>,
     ) -> EvalResult<()> {
@@ -3765,31 +4097,34 @@ impl<'p> Evaluator<'_, 'p> {
             self.report_error(EvalErrorKind::Other {
                 span: None,
                 message: format!(
-                    "only numbers between 0 and 255 can be base64 encoded, got {item_value}",
+                    "only numbers between 0 and 255 can be {} encoded, got {item_value}",
+                    encoding.name(),
                 ),
             })
         })?;
 
         bytes.push(item_value_int);
         if bytes.len() == input.len() {
-            let encoded = encode_base64(bytes.iter().map(|&b| Ok(b)))?;
+            let encoded = encoding.encode(&bytes);
             self.value_stack.push(ValueData::String(encoded.into()));
         } else {
             let next_item = input[bytes.len()].view();
-            self.state_stack
-                .push(State::StdBase64Array { input, bytes });
+            self.state_stack.push(State::StdEncodeBytesArray {
+                encoding,
+                input,
+                bytes,
+            });
             self.state_stack.push(State::DoThunk(next_item));
         }
 
         Ok(())
     }
 
-    pub(super) fn do_std_base64_decode_bytes(&mut self) -> EvalResult<()> {
+    pub(super) fn do_std_decode_bytes(&mut self, encoding: ByteEncoding) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "base64DecodeBytes", 0)?;
+        let arg = self.expect_std_func_arg_string(arg, encoding.decode_bytes_func_name(), 0)?;
 
-        let encoded_chars: Vec<_> = arg.chars().collect();
-        let decoded = decode_base64(&encoded_chars).map_err(|e| {
+        let decoded = encoding.decode(&arg).map_err(|e| {
             self.report_error(EvalErrorKind::Other {
                 span: None,
                 message: e,
@@ -3804,84 +4139,84 @@ impl<'p> Evaluator<'_, 'p> {
         Ok(())
     }
 
-    pub(super) fn do_std_base64_decode(&mut self) -> EvalResult<()> {
+    pub(super) fn do_std_decode_string(&mut self, encoding: ByteEncoding) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "base64Decode", 0)?;
+        let arg = self.expect_std_func_arg_string(arg, encoding.decode_func_name(), 0)?;
 
-        let encoded_chars = arg.chars().collect::<Vec<_>>();
-        let decoded = decode_base64(&encoded_chars).map_err(|e| {
+        let decoded = encoding.decode(&arg).map_err(|e| {
             self.report_error(EvalErrorKind::Other {
                 span: None,
                 message: e,
             })
         })?;
-        let decoded: String = decoded.iter().map(|&c| char::from(c)).collect();
+        let decoded: String = if encoding == ByteEncoding::Url {
+            String::from_utf8(decoded).map_err(|_| {
+                self.report_error(EvalErrorKind::Other {
+                    span: None,
+                    message: "decoded URL string is not valid UTF-8".into(),
+                })
+            })?
+        } else {
+            decoded.iter().map(|&c| char::from(c)).collect()
+        };
 
         self.value_stack.push(ValueData::String(decoded.into()));
 
         Ok(())
     }
 
-    pub(super) fn do_std_md5(&mut self) -> EvalResult<()> {
-        let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "md5", 0)?;
-
-        use md5::Digest as _;
-        let hash = md5::Md5::digest(arg.as_bytes());
-
-        let hash_string = hash_to_hex_string(&hash);
-        self.value_stack.push(ValueData::String(hash_string.into()));
-
-        Ok(())
-    }
-
-    pub(super) fn do_std_sha1(&mut self) -> EvalResult<()> {
-        let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "sha1", 0)?;
-
-        use sha1::Digest as _;
-        let hash = sha1::Sha1::digest(arg.as_bytes());
-
-        let hash_string = hash_to_hex_string(&hash);
-        self.value_stack.push(ValueData::String(hash_string.into()));
-
-        Ok(())
-    }
-
-    pub(super) fn do_std_sha256(&mut self) -> EvalResult<()> {
+    /// Pops a string or byte array argument and pushes its bytes to
+    /// `byte_array_stack`. Strings are encoded as UTF-8.
+    pub(super) fn do_std_collect_bytes(
+        &mut self,
+        func_name: &'static str,
+        arg_index: usize,
+    ) -> EvalResult<()> {
         let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "sha256", 0)?;
-
-        use sha2::Digest as _;
-        let hash = sha2::Sha256::digest(arg.as_bytes());
-
-        let hash_string = hash_to_hex_string(&hash);
-        self.value_stack.push(ValueData::String(hash_string.into()));
-
+        match arg {
+            ValueData::String(s) => {
+                self.byte_array_stack.push(s.as_bytes().to_vec());
+            }
+            ValueData::Array(array) => {
+                let array = array.view();
+                self.byte_array_stack.push(Vec::with_capacity(array.len()));
+                for item in array.iter().rev() {
+                    self.state_stack
+                        .push(State::FnFallible(Self::do_std_check_byte_item));
+                    self.state_stack.push(State::DoThunk(item.view()));
+                }
+            }
+            _ => {
+                return Err(self.report_error(EvalErrorKind::InvalidStdFuncArgType {
+                    func_name: func_name.into(),
+                    arg_index,
+                    expected_types: vec![EvalErrorValueType::String, EvalErrorValueType::Array],
+                    got_type: EvalErrorValueType::from_value(&arg),
+                }));
+            }
+        }
         Ok(())
     }
 
-    pub(super) fn do_std_sha512(&mut self) -> EvalResult<()> {
-        let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "sha512", 0)?;
-
-        use sha2::Digest as _;
-        let hash = sha2::Sha512::digest(arg.as_bytes());
-
-        let hash_string = hash_to_hex_string(&hash);
+    pub(super) fn do_std_hash(&mut self, algo: HashAlgo) {
+        let data = self.byte_array_stack.pop().unwrap();
+        let hash_string = algo.hash(&data);
         self.value_stack.push(ValueData::String(hash_string.into()));
-
-        Ok(())
     }
 
-    pub(super) fn do_std_sha3(&mut self) -> EvalResult<()> {
-        let arg = self.value_stack.pop().unwrap();
-        let arg = self.expect_std_func_arg_string(arg, "sha3", 0)?;
-
-        use sha3::Digest as _;
-        let hash = sha3::Sha3_512::digest(arg.as_bytes());
+    pub(super) fn do_std_hmac(&mut self) -> EvalResult<()> {
+        let msg = self.byte_array_stack.pop().unwrap();
+        let key = self.byte_array_stack.pop().unwrap();
+        let algo = self.value_stack.pop().unwrap();
+        let algo = self.expect_std_func_arg_string(algo, "hmac", 0)?;
 
-        let hash_string = hash_to_hex_string(&hash);
+        let Some(hash_string) = HashAlgo::from_name(&algo).and_then(|algo| algo.hmac(&key, &msg))
+        else {
+            return Err(self.report_error(EvalErrorKind::Other {
+                span: None,
+                message: format!("unsupported HMAC algorithm {algo:?}"),
+            }));
+        };
         self.value_stack.push(ValueData::String(hash_string.into()));
 
         Ok(())
@@ -4032,106 +4367,3 @@ impl<'p> Evaluator<'_, 'p> {
         }
     }
 }
-
-fn hash_to_hex_string(hash: &[u8]) -> String {
-    let mut hash_string = String::with_capacity(hash.len() * 2);
-    for &byte in hash {
-        write!(hash_string, "{byte:02x}").unwrap();
-    }
-    hash_string
-}
-
-fn encode_base64<I>(input: I) -> EvalResult<String>
-where
-    I: IntoIterator<Item = EvalResult<u8>>,
-{
-    let encmap = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-
-    let mut input = input.into_iter();
-    let mut encoded = String::new();
-    loop {
-        let Some(b0) = input.next() else {
-            break;
-        };
-        let b0 = b0?;
-
-        encoded.push(char::from(encmap[usize::from(b0 >> 2)]));
-
-        let Some(b1) = input.next() else {
-            encoded.push(char::from(encmap[usize::from((b0 & 0b11) << 4)]));
-            encoded.push('=');
-            encoded.push('=');
-            break;
-        };
-        let b1 = b1?;
-
-        encoded.push(char::from(
-            encmap[usize::from(((b0 & 0b11) << 4) | (b1 >> 4))],
-        ));
-
-        let Some(b2) = input.next() else {
-            encoded.push(char::from(encmap[usize::from((b1 & 0b1111) << 2)]));
-            encoded.push('=');
-            break;
-        };
-        let b2 = b2?;
-
-        encoded.push(char::from(
-            encmap[usize::from(((b1 & 0b1111) << 2) | (b2 >> 6))],
-        ));
-        encoded.push(char::from(encmap[usize::from(b2 & 0b111111)]));
-    }
-
-    Ok(encoded)
-}
-
-fn decode_base64(encoded: &[char]) -> Result<Vec<u8>, String> {
-    let mut chunks = encoded.chunks_exact(4);
-    if !chunks.remainder().is_empty() {
-        return Err("length of base64 string is not a multiple of 4".into());
-    }
-
-    let mut decoded = Vec::new();
-    if let Some(last_chunk) = chunks.next_back() {
-        fn chr_to_index(chr: char) -> Result<u8, String> {
-            match chr {
-                'A'..='Z' => Ok(chr as u8 - b'A'),
-                'a'..='z' => Ok(chr as u8 - b'a' + 26),
-                '0'..='9' => Ok(chr as u8 - b'0' + 52),
-                '+' => Ok(62),
-                '/' => Ok(63),
-                _ => Err(format!("invalid base64 character: {chr:?}")),
-            }
-        }
-
-        for chunk in chunks {
-            let i0 = chr_to_index(chunk[0])?;
-            let i1 = chr_to_index(chunk[1])?;
-            let i2 = chr_to_index(chunk[2])?;
-            let i3 = chr_to_index(chunk[3])?;
-            decoded.push((i0 << 2) | (i1 >> 4));
-            decoded.push((i1 << 4) | (i2 >> 2));
-            decoded.push((i2 << 6) | i3);
-        }
-
-        let i0 = chr_to_index(last_chunk[0])?;
-        let i1 = chr_to_index(last_chunk[1])?;
-        decoded.push((i0 << 2) | (i1 >> 4));
-
-        match (last_chunk[2], last_chunk[3]) {
-            ('=', '=') => {}
-            (e2, '=') => {
-                let i2 = chr_to_index(e2)?;
-                decoded.push((i1 << 4) | (i2 >> 2));
-            }
-            (e2, e3) => {
-                let i2 = chr_to_index(e2)?;
-                let i3 = chr_to_index(e3)?;
-                decoded.push((i1 << 4) | (i2 >> 2));
-                decoded.push((i2 << 6) | i3);
-            }
-        }
-    }
-
-    Ok(decoded)
-}
@@ -0,0 +1,272 @@
+//! Copy-on-write copies of the program state, created with
+//! [`Program::fork`](super::Program::fork).
+//!
+//! A program and its copies share the same heap. Every object is tagged
+//! with the epoch of the heap in which it was allocated, and forking starts
+//! a new epoch, so objects allocated before forking are shared and objects
+//! allocated afterwards belong to a single program.
+//!
+//! Shared objects are never modified in place. When a program evaluates a
+//! shared thunk (or checks the asserts of a shared object), the result is
+//! stored in a per-program slot of the object ([`ForkStates`]), so other
+//! programs (which might have different external variables) do not see it.
+//! Slots are keyed by [`ForkNode`], which also lets a copy see the values
+//! that the original evaluated before forking.
+//! Objects created lazily on behalf of a shared object (such as the thunks
+//! of its fields) are allocated in the epoch of that object, so they are
+//! shared as well.
+
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::Program;
+use super::data::{ObjectData, ThunkData, ThunkState, ValueData};
+use crate::FHashMap;
+use crate::gc::{Gc, GcTrace, GcTraceCtx, GcView};
+
+/// Identifies a [`ForkNode`] among the programs that share a heap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct ProgramId(u64);
+
+impl ProgramId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The states that a program has stored in shared objects.
+///
+/// Forking freezes the node of the program, and both the program and its
+/// copy continue with a new node whose parent is the frozen one. A program
+/// sees the values stored by its own node and by its ancestors, so the
+/// values evaluated before forking are kept by both programs without
+/// copying them, while the values evaluated afterwards are not seen by the
+/// other program.
+pub(super) struct ForkNode<'p> {
+    pub(super) id: ProgramId,
+    parent: Option<Rc<Self>>,
+    /// Shared thunks and objects in which this node has a state. They are
+    /// kept alive until the node is dropped, which removes the states.
+    thunks: RefCell<Vec<Gc<ThunkData<'p>>>>,
+    objects: RefCell<Vec<Gc<ObjectData<'p>>>>,
+}
+
+impl<'p> ForkNode<'p> {
+    pub(super) fn new(parent: Option<Rc<Self>>) -> Rc<Self> {
+        Rc::new(Self {
+            id: ProgramId::new(),
+            parent,
+            thunks: RefCell::new(Vec::new()),
+            objects: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.thunks.borrow().is_empty() && self.objects.borrow().is_empty()
+    }
+
+    /// Returns the nodes of the programs this one was forked from, from the
+    /// most recent one.
+    pub(super) fn ancestors(&self) -> impl Iterator<Item = &Self> {
+        std::iter::successors(self.parent.as_deref(), |node| node.parent.as_deref())
+    }
+}
+
+impl Drop for ForkNode<'_> {
+    fn drop(&mut self) {
+        // Other programs might keep the shared objects alive. They are
+        // skipped if the whole heap has been dropped already.
+        for thunk in self.thunks.get_mut().iter() {
+            if let Some(thunk) = thunk.try_view() {
+                thunk.remove_fork_state(self.id);
+            }
+        }
+        for object in self.objects.get_mut().iter() {
+            if let Some(object) = object.try_view() {
+                object.fork_asserts_checked.remove(self.id);
+            }
+        }
+    }
+}
+
+/// Per-program state of a shared object.
+///
+/// Most objects are never shared, so the map is only allocated when
+/// needed.
+pub(super) struct ForkStates<T> {
+    states: OnceCell<Box<RefCell<FHashMap<ProgramId, T>>>>,
+}
+
+impl<T: GcTrace> GcTrace for ForkStates<T> {
+    fn trace<'a>(&self, ctx: &mut impl GcTraceCtx<'a>)
+    where
+        Self: 'a,
+    {
+        if let Some(states) = self.states.get() {
+            for state in states.borrow().values() {
+                state.trace(ctx);
+            }
+        }
+    }
+}
+
+impl<T> ForkStates<T> {
+    #[inline]
+    pub(super) fn new() -> Self {
+        Self {
+            states: OnceCell::new(),
+        }
+    }
+
+    #[inline]
+    pub(super) fn get<R>(&self, program: ProgramId, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let states = self.states.get()?.borrow();
+        states.get(&program).map(f)
+    }
+
+    /// Sets the state of `program`, returning whether it did not have one.
+    pub(super) fn set(&self, program: ProgramId, state: T) -> bool {
+        let mut states = self.states.get_or_init(Default::default).borrow_mut();
+        states.insert(program, state).is_none()
+    }
+
+    pub(super) fn remove(&self, program: ProgramId) {
+        if let Some(states) = self.states.get() {
+            states.borrow_mut().remove(&program);
+        }
+    }
+}
+
+impl<'p> Program<'p> {
+    /// Creates a copy of the program.
+    ///
+    /// The copy shares the heap, the string interner and the span manager
+    /// with the original, so forking does not depend on the amount of
+    /// loaded sources or evaluated values. Sources that have already been
+    /// loaded and values that have already been evaluated are not loaded or
+    /// evaluated again in the copy. This allows to load and evaluate shared
+    /// libraries once and then evaluate them with different external
+    /// variables or top-level arguments in each copy.
+    ///
+    /// Thunks created before forking can be used in both programs, and
+    /// evaluating them in one program does not affect the other. Thunks
+    /// and values created afterwards must only be used in the program that
+    /// created them.
+    ///
+    /// External variables added with [`Program::add_ext_var`], native
+    /// functions and changes to the standard library are copied, and the
+    /// copy can replace them. External variables provided by
+    /// [`Callbacks::ext_var`](super::Callbacks::ext_var) are not copied, so
+    /// they are requested again by the copy.
+    ///
+    /// Values evaluated before forking are not copied either: both programs
+    /// keep looking them up in the shared objects, so forking again without
+    /// evaluating anything in between is cheap.
+    pub fn fork(&mut self) -> Self {
+        let epoch = self.gc_ctx.new_epoch();
+        self.frozen_epoch = epoch;
+
+        // Freeze the values evaluated by this program in shared objects, so
+        // both programs keep them. If there are none since the last fork,
+        // the copy can share the frozen node of the last fork.
+        let frozen = if self.fork_node.is_empty() {
+            self.fork_node.parent.clone()
+        } else {
+            let frozen = self.fork_node.clone();
+            self.fork_node = ForkNode::new(Some(frozen.clone()));
+            Some(frozen)
+        };
+
+        Self {
+            arena: self.arena,
+            str_interner: self.str_interner.clone(),
+            span_mgr: self.span_mgr.fork(),
+            gc_ctx: self.gc_ctx.clone(),
+            fork_node: ForkNode::new(frozen),
+            frozen_epoch: epoch,
+            objs_after_last_gc: self.objs_after_last_gc,
+            max_stack: self.max_stack,
+            max_tail_calls: self.max_tail_calls,
+            optimize: self.optimize,
+            source_cache: self.source_cache.clone(),
+            shared_source_cache: self.shared_source_cache.clone(),
+            exprs: self.exprs,
+            stdlib_span_ctx: self.stdlib_span_ctx,
+            stdlib_src_id: self.stdlib_src_id,
+            stdlib_data: self.stdlib_data,
+            stdlib_base_obj: self.stdlib_base_obj.clone(),
+            stdlib_shared_obj: self.stdlib_shared_obj.clone(),
+            stdlib_extra: self.stdlib_extra.clone(),
+            stdlib_builtins: self.stdlib_builtins.clone(),
+            stdlib_removed: self.stdlib_removed.clone(),
+            empty_array: self.empty_array.clone(),
+            identity_func: self.identity_func.clone(),
+            unused_var: self.unused_var.clone(),
+            ext_vars: self.ext_vars.clone(),
+            resolved_ext_vars: FHashMap::default(),
+            requested_ext_vars: self.requested_ext_vars.clone(),
+            requested_ext_vars_set: self.requested_ext_vars_set.clone(),
+            native_funcs: self.native_funcs.clone(),
+            regex_cache: FHashMap::default(),
+        }
+    }
+
+    /// Returns `Some` with the fork node of the program if `obj` is shared
+    /// with other programs, so its state must be stored per program.
+    #[inline]
+    pub(super) fn fork_node_of<T: GcTrace>(&self, obj: &GcView<T>) -> Option<&ForkNode<'p>> {
+        (obj.epoch() < self.frozen_epoch).then_some(&*self.fork_node)
+    }
+
+    /// Like [`ThunkData::switch_state`], taking into account whether the
+    /// thunk is shared with other programs.
+    #[inline]
+    pub(super) fn switch_thunk_state(&mut self, thunk: &GcView<ThunkData<'p>>) -> ThunkState<'p> {
+        let fork_node = self.fork_node_of(thunk);
+        let state = thunk.switch_state(fork_node);
+        if let Some(fork_node) = fork_node {
+            if matches!(state, ThunkState::Pending(_)) {
+                fork_node.thunks.borrow_mut().push(Gc::from(thunk));
+            }
+        }
+        state
+    }
+
+    /// Like [`ThunkData::set_done`], taking into account whether the thunk
+    /// is shared with other programs.
+    #[inline]
+    pub(super) fn set_thunk_done(&self, thunk: &GcView<ThunkData<'p>>, value: ValueData<'p>) {
+        let fork_node = self.fork_node_of(thunk);
+        // The state is new if the program was forked while evaluating the
+        // thunk, since it was marked as in progress in the frozen node.
+        if thunk.set_done(fork_node.map(|node| node.id), value) {
+            let fork_node = fork_node.unwrap();
+            fork_node.thunks.borrow_mut().push(Gc::from(thunk));
+        }
+    }
+
+    /// Returns whether the asserts of `object` have been checked by this
+    /// program.
+    ///
+    /// Unlike values of thunks, this is not inherited from the programs
+    /// this one was forked from, since it is set before the asserts are
+    /// evaluated.
+    #[inline]
+    pub(super) fn object_asserts_checked(&self, object: &GcView<ObjectData<'p>>) -> bool {
+        object.asserts_checked.get()
+            || self
+                .fork_node_of(object)
+                .is_some_and(|node| object.fork_asserts_checked.get(node.id, |_| ()).is_some())
+    }
+
+    pub(super) fn set_object_asserts_checked(&self, object: &GcView<ObjectData<'p>>) {
+        if let Some(node) = self.fork_node_of(object) {
+            object.fork_asserts_checked.set(node.id, ());
+            node.objects.borrow_mut().push(Gc::from(object));
+        } else {
+            object.asserts_checked.set(true);
+        }
+    }
+}
@@ -13,6 +13,9 @@ pub(super) enum Expr<'p> {
         locals: &'p [(InternedStr<'p>, &'p Expr<'p>)],
         asserts: &'p [Assert<'p>],
         fields: &'p [ObjectField<'p>],
+        /// Names of `fields`, sorted, when all of them are fixed. Only
+        /// computed when optimizing.
+        fields_order: Option<&'p [(InternedStr<'p>, ast::Visibility)]>,
     },
     ObjectComp {
         is_top: bool,
@@ -45,6 +48,12 @@ pub(super) enum Expr<'p> {
         step: Option<&'p Expr<'p>>,
         expr_span: SpanId,
     },
+    /// A function of the standard library accessed as `std.<name>`, when
+    /// `std` is known to be the unmodified standard library object.
+    StdField {
+        field_name: InternedStr<'p>,
+        expr_span: SpanId,
+    },
     SuperField {
         super_span: SpanId,
         field_name: InternedStr<'p>,
@@ -62,11 +71,17 @@ pub(super) enum Expr<'p> {
         tailstrict: bool,
         span: SpanId,
     },
-    Var(InternedStr<'p>, SpanId),
+    Var {
+        name: InternedStr<'p>,
+        slot: VarSlot,
+        span: SpanId,
+    },
     SelfObj,
     TopObj,
     Local {
-        bindings: &'p [(InternedStr<'p>, &'p Expr<'p>)],
+        /// `None` for bindings that are never referenced, which are dropped
+        /// by the optimizer but still take a slot.
+        bindings: &'p [(InternedStr<'p>, Option<&'p Expr<'p>>)],
         inner: &'p Expr<'p>,
     },
     If {
@@ -118,6 +133,95 @@ pub(super) enum Expr<'p> {
     OtherError {
         msg: &'p str,
     },
+    /// `expr`, which is a `Binary`, `Unary`, `If`, `Field`, `Index` or
+    /// `Call`, compiled to bytecode.
+    /// See [`bytecode`](super::bytecode).
+    Compiled {
+        code: &'p [Op<'p>],
+        expr: &'p Expr<'p>,
+    },
+}
+
+/// Instruction of the bytecode of [`Expr::Compiled`].
+///
+/// Instructions push and pop values on the value stack of the evaluator.
+/// Jump targets are indices into the code, and jumping to its end finishes
+/// the evaluation.
+#[derive(Copy, Clone, Debug)]
+pub(super) enum Op<'p> {
+    Null,
+    Bool(bool),
+    Number(f64, SpanId),
+    String(&'p str),
+    Var {
+        name: InternedStr<'p>,
+        slot: VarSlot,
+        span: SpanId,
+    },
+    /// `std.<field_name>`.
+    StdField {
+        field_name: InternedStr<'p>,
+        span: SpanId,
+    },
+    /// `.<field_name>` of the object on top of the stack.
+    Field {
+        field_name: InternedStr<'p>,
+        span: SpanId,
+    },
+    /// Indexes the value below the top of the stack with the value on top
+    /// of it.
+    Index {
+        span: SpanId,
+    },
+    /// Calls the function on top of the stack with the arguments of
+    /// `call`, which is an [`Expr::Call`].
+    Call(&'p Expr<'p>),
+    /// Evaluates an expression that is not compiled.
+    Expr(&'p Expr<'p>),
+    /// Pushes the stack trace item of the expression at `SpanId`.
+    PushTrace(SpanId),
+    /// Pops the stack trace item pushed by the matching `PushTrace`.
+    PopTrace,
+    /// `<`, `<=`, `>` or `>=` of the two values on top of the stack.
+    Compare(ast::BinaryOp),
+    /// `==`, or `!=` when `negate` is `true`.
+    Equals {
+        negate: bool,
+    },
+    /// Any other binary operator.
+    Binary {
+        op: ast::BinaryOp,
+        span: SpanId,
+    },
+    Unary {
+        op: ast::UnaryOp,
+        span: SpanId,
+    },
+    /// Jumps to `target` if the value on top of the stack is the boolean
+    /// `value`, keeping it as the result of `&&` or `||`.
+    ShortCircuit {
+        value: bool,
+        target: u32,
+    },
+    /// Pops the condition of an `if` and jumps to `target` if it is
+    /// `false`.
+    JumpIfFalse {
+        cond_span: SpanId,
+        target: u32,
+    },
+    Jump(u32),
+}
+
+/// Location of a variable, resolved during analysis.
+///
+/// Each `local`, function call, object and comprehension creates an
+/// environment whose variables are stored in the order they are bound.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct VarSlot {
+    /// Number of environments to go up from the current one.
+    pub(super) depth: u32,
+    /// Index of the variable in that environment.
+    pub(super) index: u32,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -143,10 +247,25 @@ pub(super) enum FieldName<'p> {
     Dyn(&'p Expr<'p>),
 }
 
+/// Returns the names of `fields` in the order in which an object with only
+/// those fields would list them, or `None` if any name is not fixed.
+pub(super) fn fixed_fields_order<'p>(
+    fields: &[ObjectField<'p>],
+) -> Option<Vec<(InternedStr<'p>, ast::Visibility)>> {
+    let mut order = fields
+        .iter()
+        .map(|field| match field.name {
+            FieldName::Fix(name) => Some((name, field.visibility)),
+            FieldName::Dyn(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    order.sort_unstable_by(|(a, _), (b, _)| a.value().cmp(b.value()));
+    Some(order)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(super) enum CompSpecPart<'p> {
     For {
-        var: InternedStr<'p>,
         value: &'p Expr<'p>,
         value_span: SpanId,
     },
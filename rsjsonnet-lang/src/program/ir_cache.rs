@@ -0,0 +1,1145 @@
+//! Binary encoding of the IR of a source, used by
+//! [`Program::set_source_cache`](super::Program::set_source_cache).
+//!
+//! Nodes are written children first, and each node refers to its children
+//! by index, so both encoding and decoding are iterative. Strings are
+//! stored once in a table and spans are stored as offsets within the
+//! source.
+
+use std::fmt::Write as _;
+
+use sha2::Digest as _;
+
+use super::{Program, ir};
+use crate::interner::InternedStr;
+use crate::span::{SpanContextId, SpanId};
+use crate::{FHashMap, ast};
+
+/// Must be changed whenever the encoding or the meaning of the IR changes.
+const FORMAT_VERSION: u32 = 3;
+
+const MAGIC: &[u8; 8] = b"RSJSNTIR";
+
+impl<'p> Program<'p> {
+    /// Computes the key under which the IR of a source is cached.
+    ///
+    /// Besides the source itself, the IR depends on the top-level variables
+    /// and, when optimizing, on which fields of `std` are still built-in.
+    pub(super) fn source_cache_key(
+        &self,
+        input: &[u8],
+        env_names: &[InternedStr<'p>],
+        std_is_stdlib: bool,
+    ) -> String {
+        let mut hasher = sha2::Sha256::new();
+        let mut update_bytes = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        update_bytes(b"rsjsonnet-lang");
+        update_bytes(env!("CARGO_PKG_VERSION").as_bytes());
+        update_bytes(&FORMAT_VERSION.to_le_bytes());
+        update_bytes(&[u8::from(self.optimize), u8::from(std_is_stdlib)]);
+        for name in env_names.iter() {
+            update_bytes(name.value().as_bytes());
+        }
+        if self.optimize {
+            let mut builtins: Vec<_> = self.stdlib_builtins.keys().copied().collect();
+            builtins.sort_unstable_by_key(|name| name.value());
+            for name in builtins {
+                update_bytes(name.value().as_bytes());
+                update_bytes(&[u8::from(self.is_unmodified_std_builtin(name))]);
+            }
+        }
+        update_bytes(input);
+
+        let mut key = String::with_capacity(64);
+        for byte in hasher.finalize() {
+            write!(key, "{byte:02x}").unwrap();
+        }
+        key
+    }
+
+    /// Encodes the IR of a source. Returns `None` if it refers to spans
+    /// outside `span_ctx`, which cannot happen for a freshly analyzed source.
+    pub(super) fn encode_ir(
+        &self,
+        span_ctx: SpanContextId,
+        root: &'p ir::Expr<'p>,
+    ) -> Option<Vec<u8>> {
+        let mut encoder = Encoder {
+            program: self,
+            span_ctx,
+            strs: FHashMap::default(),
+            str_table: Vec::new(),
+            nodes: FHashMap::default(),
+            num_nodes: 0,
+            out: Vec::new(),
+        };
+
+        let mut stack = vec![(root, false)];
+        while let Some((expr, children_done)) = stack.pop() {
+            let key: *const ir::Expr<'p> = expr;
+            if encoder.nodes.contains_key(&key) {
+                continue;
+            }
+            if children_done {
+                encoder.encode_node(expr)?;
+                encoder.nodes.insert(key, encoder.num_nodes);
+                encoder.num_nodes += 1;
+            } else {
+                stack.push((expr, true));
+                for_each_child(expr, |child| stack.push((child, false)));
+            }
+        }
+
+        let mut data = Vec::new();
+        data.extend(MAGIC);
+        data.extend(FORMAT_VERSION.to_le_bytes());
+        let mut payload = Vec::new();
+        payload.extend((encoder.str_table.len() as u32).to_le_bytes());
+        for s in encoder.str_table.iter() {
+            payload.extend((s.len() as u32).to_le_bytes());
+            payload.extend(s.as_bytes());
+        }
+        payload.extend(encoder.num_nodes.to_le_bytes());
+        payload.extend(encoder.out);
+        data.extend(xxhash_rust::xxh3::xxh3_64(&payload).to_le_bytes());
+        data.extend(payload);
+        Some(data)
+    }
+
+    /// Decodes IR encoded with [`Program::encode_ir`]. Returns `None` if
+    /// the data is not valid.
+    pub(super) fn decode_ir(
+        &mut self,
+        span_ctx: SpanContextId,
+        source_len: usize,
+        data: &[u8],
+    ) -> Option<&'p ir::Expr<'p>> {
+        let payload = data.strip_prefix(MAGIC)?;
+        let payload = payload.strip_prefix(&FORMAT_VERSION.to_le_bytes())?;
+        let (checksum, payload) = payload.split_first_chunk::<8>()?;
+        if u64::from_le_bytes(*checksum) != xxhash_rust::xxh3::xxh3_64(payload) {
+            return None;
+        }
+
+        let mut decoder = Decoder {
+            program: self,
+            span_ctx,
+            source_len,
+            data: payload,
+            pos: 0,
+            str_table: Vec::new(),
+            interned: Vec::new(),
+            nodes: Vec::new(),
+        };
+
+        let num_strs = decoder.read_len()?;
+        for _ in 0..num_strs {
+            let len = decoder.read_len()?;
+            let bytes = decoder.read_bytes(len)?;
+            let s = std::str::from_utf8(bytes).ok()?;
+            decoder.str_table.push(decoder.program.arena.alloc_str(s));
+            decoder.interned.push(None);
+        }
+
+        let num_nodes = decoder.read_len()?;
+        decoder.nodes.reserve(num_nodes);
+        for _ in 0..num_nodes {
+            let expr = decoder.decode_node()?;
+            decoder.nodes.push(expr);
+        }
+        if decoder.pos != decoder.data.len() {
+            return None;
+        }
+        decoder.nodes.last().copied()
+    }
+}
+
+/// An in-memory cache of the analyzed form of sources, which can be shared
+/// by programs in different threads.
+///
+/// See [`Program::set_shared_source_cache`].
+#[derive(Clone, Default)]
+pub struct SharedSourceCache {
+    entries: std::sync::Arc<std::sync::Mutex<FHashMap<String, std::sync::Arc<[u8]>>>>,
+}
+
+impl SharedSourceCache {
+    /// Creates an empty cache.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn get(&self, key: &str) -> Option<std::sync::Arc<[u8]>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(key).cloned()
+    }
+
+    pub(super) fn insert(&self, key: String, data: std::sync::Arc<[u8]>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(key, data);
+    }
+}
+
+/// Writes a cache entry, ignoring errors. The data is written to a
+/// temporary file first so concurrent readers never see a partial entry.
+///
+/// The temporary file name includes the process ID and a per-process
+/// counter, and it is created exclusively, so threads and processes storing
+/// the same entry concurrently never write to the same temporary file.
+pub(super) fn store_cache_entry(path: &std::path::Path, data: &[u8]) {
+    use std::io::Write as _;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    if std::fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+    ));
+    let tmp_path = dir.join(tmp_name);
+    let Ok(mut tmp_file) = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+    else {
+        return;
+    };
+    let written = tmp_file.write_all(data).is_ok();
+    drop(tmp_file);
+    if !written || std::fs::rename(&tmp_path, path).is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+}
+
+fn for_each_child<'p>(expr: &'p ir::Expr<'p>, mut f: impl FnMut(&'p ir::Expr<'p>)) {
+    let f_assert = |assert: &ir::Assert<'p>, f: &mut dyn FnMut(&'p ir::Expr<'p>)| {
+        f(assert.cond);
+        if let Some(msg) = assert.msg {
+            f(msg);
+        }
+    };
+    let f_comp_spec = |comp_spec: &[ir::CompSpecPart<'p>], f: &mut dyn FnMut(&'p ir::Expr<'p>)| {
+        for part in comp_spec.iter() {
+            match *part {
+                ir::CompSpecPart::For { value, .. } => f(value),
+                ir::CompSpecPart::If { cond, .. } => f(cond),
+            }
+        }
+    };
+    match *expr {
+        ir::Expr::Null
+        | ir::Expr::Bool(_)
+        | ir::Expr::Number(..)
+        | ir::Expr::String(_)
+        | ir::Expr::StdField { .. }
+        | ir::Expr::SuperField { .. }
+        | ir::Expr::Var { .. }
+        | ir::Expr::SelfObj
+        | ir::Expr::TopObj
+        | ir::Expr::IdentityFunc
+        | ir::Expr::Import { .. }
+        | ir::Expr::ImportStr { .. }
+        | ir::Expr::ImportBin { .. }
+        | ir::Expr::OtherError { .. } => {}
+        ir::Expr::Object {
+            locals,
+            asserts,
+            fields,
+            ..
+        } => {
+            for &(_, value) in locals.iter() {
+                f(value);
+            }
+            for assert in asserts.iter() {
+                f_assert(assert, &mut f);
+            }
+            for field in fields.iter() {
+                if let ir::FieldName::Dyn(name) = field.name {
+                    f(name);
+                }
+                f(field.value);
+            }
+        }
+        ir::Expr::ObjectComp {
+            locals,
+            field_name,
+            field_value,
+            comp_spec,
+            ..
+        } => {
+            for &(_, value) in locals.iter() {
+                f(value);
+            }
+            f(field_name);
+            f(field_value);
+            f_comp_spec(comp_spec, &mut f);
+        }
+        ir::Expr::Array(items) => {
+            for &item in items.iter() {
+                f(item);
+            }
+        }
+        ir::Expr::ArrayComp { value, comp_spec } => {
+            f(value);
+            f_comp_spec(comp_spec, &mut f);
+        }
+        ir::Expr::Field { object, .. } => f(object),
+        ir::Expr::Index { object, index, .. } => {
+            f(object);
+            f(index);
+        }
+        ir::Expr::Slice {
+            array,
+            start_index,
+            end_index,
+            step,
+            ..
+        } => {
+            f(array);
+            for e in [start_index, end_index, step].into_iter().flatten() {
+                f(e);
+            }
+        }
+        ir::Expr::SuperIndex { index, .. } => f(index),
+        ir::Expr::Call {
+            callee,
+            positional_args,
+            named_args,
+            ..
+        } => {
+            f(callee);
+            for &arg in positional_args.iter() {
+                f(arg);
+            }
+            for &(_, _, arg) in named_args.iter() {
+                f(arg);
+            }
+        }
+        ir::Expr::Local { bindings, inner } => {
+            for &(_, value) in bindings.iter() {
+                if let Some(value) = value {
+                    f(value);
+                }
+            }
+            f(inner);
+        }
+        ir::Expr::If {
+            cond,
+            then_body,
+            else_body,
+            ..
+        } => {
+            f(cond);
+            f(then_body);
+            if let Some(else_body) = else_body {
+                f(else_body);
+            }
+        }
+        ir::Expr::Binary { lhs, rhs, .. } => {
+            f(lhs);
+            f(rhs);
+        }
+        ir::Expr::Unary { rhs, .. } => f(rhs),
+        ir::Expr::InSuper { lhs, .. } => f(lhs),
+        ir::Expr::Func { params, body } => {
+            for &(_, default) in params.iter() {
+                if let Some(default) = default {
+                    f(default);
+                }
+            }
+            f(body);
+        }
+        ir::Expr::Error { msg, .. } => f(msg),
+        ir::Expr::Assert { ref assert, inner } => {
+            f_assert(assert, &mut f);
+            f(inner);
+        }
+        ir::Expr::Compiled { expr, .. } => f(expr),
+    }
+}
+
+mod tag {
+    pub(super) const NULL: u8 = 0;
+    pub(super) const BOOL: u8 = 1;
+    pub(super) const NUMBER: u8 = 2;
+    pub(super) const STRING: u8 = 3;
+    pub(super) const OBJECT: u8 = 4;
+    pub(super) const OBJECT_COMP: u8 = 5;
+    pub(super) const ARRAY: u8 = 6;
+    pub(super) const ARRAY_COMP: u8 = 7;
+    pub(super) const FIELD: u8 = 8;
+    pub(super) const INDEX: u8 = 9;
+    pub(super) const SLICE: u8 = 10;
+    pub(super) const STD_FIELD: u8 = 11;
+    pub(super) const SUPER_FIELD: u8 = 12;
+    pub(super) const SUPER_INDEX: u8 = 13;
+    pub(super) const CALL: u8 = 14;
+    pub(super) const VAR: u8 = 15;
+    pub(super) const SELF_OBJ: u8 = 16;
+    pub(super) const TOP_OBJ: u8 = 17;
+    pub(super) const LOCAL: u8 = 18;
+    pub(super) const IF: u8 = 19;
+    pub(super) const BINARY: u8 = 20;
+    pub(super) const UNARY: u8 = 21;
+    pub(super) const IN_SUPER: u8 = 22;
+    pub(super) const IDENTITY_FUNC: u8 = 23;
+    pub(super) const FUNC: u8 = 24;
+    pub(super) const ERROR: u8 = 25;
+    pub(super) const ASSERT: u8 = 26;
+    pub(super) const IMPORT: u8 = 27;
+    pub(super) const IMPORT_STR: u8 = 28;
+    pub(super) const IMPORT_BIN: u8 = 29;
+    pub(super) const OTHER_ERROR: u8 = 30;
+    pub(super) const COMPILED: u8 = 31;
+}
+
+const BINARY_OPS: [ast::BinaryOp; 19] = [
+    ast::BinaryOp::Add,
+    ast::BinaryOp::Sub,
+    ast::BinaryOp::Mul,
+    ast::BinaryOp::Div,
+    ast::BinaryOp::Rem,
+    ast::BinaryOp::Shl,
+    ast::BinaryOp::Shr,
+    ast::BinaryOp::Lt,
+    ast::BinaryOp::Le,
+    ast::BinaryOp::Gt,
+    ast::BinaryOp::Ge,
+    ast::BinaryOp::Eq,
+    ast::BinaryOp::Ne,
+    ast::BinaryOp::In,
+    ast::BinaryOp::BitwiseAnd,
+    ast::BinaryOp::BitwiseOr,
+    ast::BinaryOp::BitwiseXor,
+    ast::BinaryOp::LogicAnd,
+    ast::BinaryOp::LogicOr,
+];
+
+const UNARY_OPS: [ast::UnaryOp; 4] = [
+    ast::UnaryOp::Minus,
+    ast::UnaryOp::Plus,
+    ast::UnaryOp::BitwiseNot,
+    ast::UnaryOp::LogicNot,
+];
+
+const VISIBILITIES: [ast::Visibility; 3] = [
+    ast::Visibility::Default,
+    ast::Visibility::Hidden,
+    ast::Visibility::ForceVisible,
+];
+
+struct Encoder<'a, 'p> {
+    program: &'a Program<'p>,
+    span_ctx: SpanContextId,
+    strs: FHashMap<&'p str, u32>,
+    str_table: Vec<&'p str>,
+    nodes: FHashMap<*const ir::Expr<'p>, u32>,
+    num_nodes: u32,
+    out: Vec<u8>,
+}
+
+impl<'p> Encoder<'_, 'p> {
+    fn encode_node(&mut self, expr: &'p ir::Expr<'p>) -> Option<()> {
+        match *expr {
+            ir::Expr::Null => self.u8(tag::NULL),
+            ir::Expr::Bool(value) => {
+                self.u8(tag::BOOL);
+                self.bool(value);
+            }
+            ir::Expr::Number(value, span) => {
+                self.u8(tag::NUMBER);
+                self.out.extend(value.to_bits().to_le_bytes());
+                self.span(span)?;
+            }
+            ir::Expr::String(s) => {
+                self.u8(tag::STRING);
+                self.str(s);
+            }
+            ir::Expr::Object {
+                is_top,
+                locals,
+                asserts,
+                fields,
+                // Computed again when reading.
+                fields_order: _,
+            } => {
+                self.u8(tag::OBJECT);
+                self.bool(is_top);
+                self.locals(locals);
+                self.len(asserts.len());
+                for assert in asserts.iter() {
+                    self.assert(assert)?;
+                }
+                self.len(fields.len());
+                for field in fields.iter() {
+                    match field.name {
+                        ir::FieldName::Fix(name) => {
+                            self.bool(false);
+                            self.str(name.value());
+                        }
+                        ir::FieldName::Dyn(name) => {
+                            self.bool(true);
+                            self.expr(name);
+                        }
+                    }
+                    self.span(field.name_span)?;
+                    self.bool(field.plus);
+                    self.visibility(field.visibility);
+                    self.expr(field.value);
+                }
+            }
+            ir::Expr::ObjectComp {
+                is_top,
+                locals,
+                field_name,
+                field_name_span,
+                field_plus,
+                field_value,
+                comp_spec,
+            } => {
+                self.u8(tag::OBJECT_COMP);
+                self.bool(is_top);
+                self.locals(locals);
+                self.expr(field_name);
+                self.span(field_name_span)?;
+                self.bool(field_plus);
+                self.expr(field_value);
+                self.comp_spec(comp_spec)?;
+            }
+            ir::Expr::Array(items) => {
+                self.u8(tag::ARRAY);
+                self.len(items.len());
+                for &item in items.iter() {
+                    self.expr(item);
+                }
+            }
+            ir::Expr::ArrayComp { value, comp_spec } => {
+                self.u8(tag::ARRAY_COMP);
+                self.expr(value);
+                self.comp_spec(comp_spec)?;
+            }
+            ir::Expr::Field {
+                object,
+                field_name,
+                expr_span,
+            } => {
+                self.u8(tag::FIELD);
+                self.expr(object);
+                self.str(field_name.value());
+                self.span(expr_span)?;
+            }
+            ir::Expr::Index {
+                object,
+                index,
+                expr_span,
+            } => {
+                self.u8(tag::INDEX);
+                self.expr(object);
+                self.expr(index);
+                self.span(expr_span)?;
+            }
+            ir::Expr::Slice {
+                array,
+                start_index,
+                end_index,
+                step,
+                expr_span,
+            } => {
+                self.u8(tag::SLICE);
+                self.expr(array);
+                self.opt_expr(start_index);
+                self.opt_expr(end_index);
+                self.opt_expr(step);
+                self.span(expr_span)?;
+            }
+            ir::Expr::StdField {
+                field_name,
+                expr_span,
+            } => {
+                self.u8(tag::STD_FIELD);
+                self.str(field_name.value());
+                self.span(expr_span)?;
+            }
+            ir::Expr::SuperField {
+                super_span,
+                field_name,
+                expr_span,
+            } => {
+                self.u8(tag::SUPER_FIELD);
+                self.span(super_span)?;
+                self.str(field_name.value());
+                self.span(expr_span)?;
+            }
+            ir::Expr::SuperIndex {
+                super_span,
+                index,
+                expr_span,
+            } => {
+                self.u8(tag::SUPER_INDEX);
+                self.span(super_span)?;
+                self.expr(index);
+                self.span(expr_span)?;
+            }
+            ir::Expr::Call {
+                callee,
+                positional_args,
+                named_args,
+                tailstrict,
+                span,
+            } => {
+                self.u8(tag::CALL);
+                self.expr(callee);
+                self.len(positional_args.len());
+                for &arg in positional_args.iter() {
+                    self.expr(arg);
+                }
+                self.len(named_args.len());
+                for &(name, name_span, arg) in named_args.iter() {
+                    self.str(name.value());
+                    self.span(name_span)?;
+                    self.expr(arg);
+                }
+                self.bool(tailstrict);
+                self.span(span)?;
+            }
+            ir::Expr::Var { name, slot, span } => {
+                self.u8(tag::VAR);
+                self.str(name.value());
+                self.u32(slot.depth);
+                self.u32(slot.index);
+                self.span(span)?;
+            }
+            ir::Expr::SelfObj => self.u8(tag::SELF_OBJ),
+            ir::Expr::TopObj => self.u8(tag::TOP_OBJ),
+            ir::Expr::Local { bindings, inner } => {
+                self.u8(tag::LOCAL);
+                self.len(bindings.len());
+                for &(name, value) in bindings.iter() {
+                    self.str(name.value());
+                    self.opt_expr(value);
+                }
+                self.expr(inner);
+            }
+            ir::Expr::If {
+                cond,
+                cond_span,
+                then_body,
+                else_body,
+            } => {
+                self.u8(tag::IF);
+                self.expr(cond);
+                self.span(cond_span)?;
+                self.expr(then_body);
+                self.opt_expr(else_body);
+            }
+            ir::Expr::Binary { op, lhs, rhs, span } => {
+                self.u8(tag::BINARY);
+                self.u8(BINARY_OPS.iter().position(|&o| o == op).unwrap() as u8);
+                self.expr(lhs);
+                self.expr(rhs);
+                self.span(span)?;
+            }
+            ir::Expr::Unary { op, rhs, span } => {
+                self.u8(tag::UNARY);
+                self.u8(UNARY_OPS.iter().position(|&o| o == op).unwrap() as u8);
+                self.expr(rhs);
+                self.span(span)?;
+            }
+            ir::Expr::InSuper { lhs, span } => {
+                self.u8(tag::IN_SUPER);
+                self.expr(lhs);
+                self.span(span)?;
+            }
+            ir::Expr::IdentityFunc => self.u8(tag::IDENTITY_FUNC),
+            ir::Expr::Func { params, body } => {
+                self.u8(tag::FUNC);
+                self.len(params.len());
+                for &(name, default) in params.iter() {
+                    self.str(name.value());
+                    self.opt_expr(default);
+                }
+                self.expr(body);
+            }
+            ir::Expr::Error { msg, span } => {
+                self.u8(tag::ERROR);
+                self.expr(msg);
+                self.span(span)?;
+            }
+            ir::Expr::Assert { ref assert, inner } => {
+                self.u8(tag::ASSERT);
+                self.assert(assert)?;
+                self.expr(inner);
+            }
+            ir::Expr::Import { path, span } => {
+                self.u8(tag::IMPORT);
+                self.str(path);
+                self.span(span)?;
+            }
+            ir::Expr::ImportStr { path, span } => {
+                self.u8(tag::IMPORT_STR);
+                self.str(path);
+                self.span(span)?;
+            }
+            ir::Expr::ImportBin { path, span } => {
+                self.u8(tag::IMPORT_BIN);
+                self.str(path);
+                self.span(span)?;
+            }
+            ir::Expr::OtherError { msg } => {
+                self.u8(tag::OTHER_ERROR);
+                self.str(msg);
+            }
+            // The code is compiled again when decoding.
+            ir::Expr::Compiled { code: _, expr } => {
+                self.u8(tag::COMPILED);
+                self.expr(expr);
+            }
+        }
+        Some(())
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.out.push(u8::from(value));
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.out.extend(value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn str(&mut self, s: &'p str) {
+        let i = *self.strs.entry(s).or_insert_with(|| {
+            self.str_table.push(s);
+            (self.str_table.len() - 1) as u32
+        });
+        self.u32(i);
+    }
+
+    fn span(&mut self, span: SpanId) -> Option<()> {
+        let (span_ctx, start, end) = self.program.span_mgr.get_span(span);
+        if span_ctx != self.span_ctx {
+            return None;
+        }
+        self.len(start);
+        self.len(end);
+        Some(())
+    }
+
+    fn visibility(&mut self, visibility: ast::Visibility) {
+        self.u8(VISIBILITIES.iter().position(|&v| v == visibility).unwrap() as u8);
+    }
+
+    fn expr(&mut self, expr: &'p ir::Expr<'p>) {
+        let key: *const ir::Expr<'p> = expr;
+        self.u32(self.nodes[&key]);
+    }
+
+    fn opt_expr(&mut self, expr: Option<&'p ir::Expr<'p>>) {
+        if let Some(expr) = expr {
+            self.bool(true);
+            self.expr(expr);
+        } else {
+            self.bool(false);
+        }
+    }
+
+    fn locals(&mut self, locals: &'p [(InternedStr<'p>, &'p ir::Expr<'p>)]) {
+        self.len(locals.len());
+        for &(name, value) in locals.iter() {
+            self.str(name.value());
+            self.expr(value);
+        }
+    }
+
+    fn assert(&mut self, assert: &ir::Assert<'p>) -> Option<()> {
+        self.span(assert.span)?;
+        self.expr(assert.cond);
+        self.span(assert.cond_span)?;
+        self.opt_expr(assert.msg);
+        Some(())
+    }
+
+    fn comp_spec(&mut self, comp_spec: &'p [ir::CompSpecPart<'p>]) -> Option<()> {
+        self.len(comp_spec.len());
+        for part in comp_spec.iter() {
+            match *part {
+                ir::CompSpecPart::For { value, value_span } => {
+                    self.bool(false);
+                    self.expr(value);
+                    self.span(value_span)?;
+                }
+                ir::CompSpecPart::If { cond, cond_span } => {
+                    self.bool(true);
+                    self.expr(cond);
+                    self.span(cond_span)?;
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+struct Decoder<'a, 'd, 'p> {
+    program: &'a mut Program<'p>,
+    span_ctx: SpanContextId,
+    source_len: usize,
+    data: &'d [u8],
+    pos: usize,
+    str_table: Vec<&'p str>,
+    interned: Vec<Option<InternedStr<'p>>>,
+    nodes: Vec<&'p ir::Expr<'p>>,
+}
+
+impl<'d, 'p> Decoder<'_, 'd, 'p> {
+    fn decode_node(&mut self) -> Option<&'p ir::Expr<'p>> {
+        let expr = match self.read_u8()? {
+            tag::NULL => return Some(self.program.exprs.null),
+            tag::BOOL => {
+                return Some(if self.read_bool()? {
+                    self.program.exprs.true_
+                } else {
+                    self.program.exprs.false_
+                });
+            }
+            tag::NUMBER => {
+                let bytes = self.read_bytes(8)?;
+                let value = f64::from_bits(u64::from_le_bytes(bytes.try_into().unwrap()));
+                ir::Expr::Number(value, self.read_span()?)
+            }
+            tag::STRING => ir::Expr::String(self.read_str()?),
+            tag::OBJECT => {
+                let is_top = self.read_bool()?;
+                let locals = self.read_locals()?;
+                let num_asserts = self.read_len()?;
+                let mut asserts = Vec::new();
+                for _ in 0..num_asserts {
+                    asserts.push(self.read_assert()?);
+                }
+                let num_fields = self.read_len()?;
+                let mut fields = Vec::new();
+                for _ in 0..num_fields {
+                    let name = if self.read_bool()? {
+                        ir::FieldName::Dyn(self.read_expr()?)
+                    } else {
+                        ir::FieldName::Fix(self.read_interned_str()?)
+                    };
+                    fields.push(ir::ObjectField {
+                        name,
+                        name_span: self.read_span()?,
+                        plus: self.read_bool()?,
+                        visibility: self.read_visibility()?,
+                        value: self.read_expr()?,
+                    });
+                }
+                let fields_order = if self.program.optimize {
+                    ir::fixed_fields_order(&fields)
+                        .map(|order| self.program.arena.alloc_slice(&order))
+                } else {
+                    None
+                };
+                ir::Expr::Object {
+                    is_top,
+                    locals,
+                    asserts: self.program.arena.alloc_slice(&asserts),
+                    fields: self.program.arena.alloc_slice(&fields),
+                    fields_order,
+                }
+            }
+            tag::OBJECT_COMP => ir::Expr::ObjectComp {
+                is_top: self.read_bool()?,
+                locals: self.read_locals()?,
+                field_name: self.read_expr()?,
+                field_name_span: self.read_span()?,
+                field_plus: self.read_bool()?,
+                field_value: self.read_expr()?,
+                comp_spec: self.read_comp_spec()?,
+            },
+            tag::ARRAY => {
+                let num_items = self.read_len()?;
+                let mut items = Vec::new();
+                for _ in 0..num_items {
+                    items.push(self.read_expr()?);
+                }
+                ir::Expr::Array(self.program.arena.alloc_slice(&items))
+            }
+            tag::ARRAY_COMP => ir::Expr::ArrayComp {
+                value: self.read_expr()?,
+                comp_spec: self.read_comp_spec()?,
+            },
+            tag::FIELD => ir::Expr::Field {
+                object: self.read_expr()?,
+                field_name: self.read_interned_str()?,
+                expr_span: self.read_span()?,
+            },
+            tag::INDEX => ir::Expr::Index {
+                object: self.read_expr()?,
+                index: self.read_expr()?,
+                expr_span: self.read_span()?,
+            },
+            tag::SLICE => ir::Expr::Slice {
+                array: self.read_expr()?,
+                start_index: self.read_opt_expr()?,
+                end_index: self.read_opt_expr()?,
+                step: self.read_opt_expr()?,
+                expr_span: self.read_span()?,
+            },
+            tag::STD_FIELD => {
+                let field_name = self.read_interned_str()?;
+                if !self.program.stdlib_builtins.contains_key(&field_name) {
+                    return None;
+                }
+                ir::Expr::StdField {
+                    field_name,
+                    expr_span: self.read_span()?,
+                }
+            }
+            tag::SUPER_FIELD => ir::Expr::SuperField {
+                super_span: self.read_span()?,
+                field_name: self.read_interned_str()?,
+                expr_span: self.read_span()?,
+            },
+            tag::SUPER_INDEX => ir::Expr::SuperIndex {
+                super_span: self.read_span()?,
+                index: self.read_expr()?,
+                expr_span: self.read_span()?,
+            },
+            tag::CALL => {
+                let callee = self.read_expr()?;
+                let num_positional_args = self.read_len()?;
+                let mut positional_args = Vec::new();
+                for _ in 0..num_positional_args {
+                    positional_args.push(self.read_expr()?);
+                }
+                let num_named_args = self.read_len()?;
+                let mut named_args = Vec::new();
+                for _ in 0..num_named_args {
+                    named_args.push((
+                        self.read_interned_str()?,
+                        self.read_span()?,
+                        self.read_expr()?,
+                    ));
+                }
+                ir::Expr::Call {
+                    callee,
+                    positional_args: self.program.arena.alloc_slice(&positional_args),
+                    named_args: self.program.arena.alloc_slice(&named_args),
+                    tailstrict: self.read_bool()?,
+                    span: self.read_span()?,
+                }
+            }
+            tag::VAR => ir::Expr::Var {
+                name: self.read_interned_str()?,
+                slot: ir::VarSlot {
+                    depth: self.read_u32()?,
+                    index: self.read_u32()?,
+                },
+                span: self.read_span()?,
+            },
+            tag::SELF_OBJ => return Some(self.program.exprs.self_obj),
+            tag::TOP_OBJ => return Some(self.program.exprs.top_obj),
+            tag::LOCAL => {
+                let num_bindings = self.read_len()?;
+                let mut bindings = Vec::new();
+                for _ in 0..num_bindings {
+                    bindings.push((self.read_interned_str()?, self.read_opt_expr()?));
+                }
+                ir::Expr::Local {
+                    bindings: self.program.arena.alloc_slice(&bindings),
+                    inner: self.read_expr()?,
+                }
+            }
+            tag::IF => ir::Expr::If {
+                cond: self.read_expr()?,
+                cond_span: self.read_span()?,
+                then_body: self.read_expr()?,
+                else_body: self.read_opt_expr()?,
+            },
+            tag::BINARY => ir::Expr::Binary {
+                op: *BINARY_OPS.get(usize::from(self.read_u8()?))?,
+                lhs: self.read_expr()?,
+                rhs: self.read_expr()?,
+                span: self.read_span()?,
+            },
+            tag::UNARY => ir::Expr::Unary {
+                op: *UNARY_OPS.get(usize::from(self.read_u8()?))?,
+                rhs: self.read_expr()?,
+                span: self.read_span()?,
+            },
+            tag::IN_SUPER => ir::Expr::InSuper {
+                lhs: self.read_expr()?,
+                span: self.read_span()?,
+            },
+            tag::IDENTITY_FUNC => ir::Expr::IdentityFunc,
+            tag::FUNC => {
+                let num_params = self.read_len()?;
+                let mut params = Vec::new();
+                for _ in 0..num_params {
+                    params.push((self.read_interned_str()?, self.read_opt_expr()?));
+                }
+                ir::Expr::Func {
+                    params: self.program.arena.alloc_slice(&params),
+                    body: self.read_expr()?,
+                }
+            }
+            tag::ERROR => ir::Expr::Error {
+                msg: self.read_expr()?,
+                span: self.read_span()?,
+            },
+            tag::ASSERT => ir::Expr::Assert {
+                assert: self.read_assert()?,
+                inner: self.read_expr()?,
+            },
+            tag::IMPORT => ir::Expr::Import {
+                path: self.read_str()?,
+                span: self.read_span()?,
+            },
+            tag::IMPORT_STR => ir::Expr::ImportStr {
+                path: self.read_str()?,
+                span: self.read_span()?,
+            },
+            tag::IMPORT_BIN => ir::Expr::ImportBin {
+                path: self.read_str()?,
+                span: self.read_span()?,
+            },
+            tag::OTHER_ERROR => ir::Expr::OtherError {
+                msg: self.read_str()?,
+            },
+            tag::COMPILED => {
+                let expr = self.read_expr()?;
+                return Some(self.program.compile_expr(expr));
+            }
+            _ => return None,
+        };
+        Some(self.program.arena.alloc(expr))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Option<&'d [u8]> {
+        let data = self.data;
+        let bytes = data.get(self.pos..)?.get(..len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read_bytes(1)?[0])
+    }
+
+    fn read_bool(&mut self) -> Option<bool> {
+        match self.read_u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_len(&mut self) -> Option<usize> {
+        let len = self.read_u32()? as usize;
+        // Every item takes at least one byte, so this rejects lengths
+        // that would make the decoder allocate too much memory.
+        (len <= self.data.len() - self.pos).then_some(len)
+    }
+
+    fn read_str(&mut self) -> Option<&'p str> {
+        let i = self.read_u32()? as usize;
+        self.str_table.get(i).copied()
+    }
+
+    fn read_interned_str(&mut self) -> Option<InternedStr<'p>> {
+        let i = self.read_u32()? as usize;
+        let interned = self.interned.get_mut(i)?;
+        Some(*interned.get_or_insert_with(|| {
+            self.program
+                .str_interner
+                .intern(self.program.arena, self.str_table[i])
+        }))
+    }
+
+    fn read_span(&mut self) -> Option<SpanId> {
+        let start = self.read_u32()? as usize;
+        let end = self.read_u32()? as usize;
+        if start > end || end > self.source_len {
+            return None;
+        }
+        Some(self.program.span_mgr.intern_span(self.span_ctx, start, end))
+    }
+
+    fn read_visibility(&mut self) -> Option<ast::Visibility> {
+        VISIBILITIES.get(usize::from(self.read_u8()?)).copied()
+    }
+
+    fn read_expr(&mut self) -> Option<&'p ir::Expr<'p>> {
+        let i = self.read_u32()? as usize;
+        self.nodes.get(i).copied()
+    }
+
+    fn read_opt_expr(&mut self) -> Option<Option<&'p ir::Expr<'p>>> {
+        if self.read_bool()? {
+            Some(Some(self.read_expr()?))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn read_locals(&mut self) -> Option<&'p [(InternedStr<'p>, &'p ir::Expr<'p>)]> {
+        let num_locals = self.read_len()?;
+        let mut locals = Vec::new();
+        for _ in 0..num_locals {
+            locals.push((self.read_interned_str()?, self.read_expr()?));
+        }
+        Some(self.program.arena.alloc_slice(&locals))
+    }
+
+    fn read_assert(&mut self) -> Option<ir::Assert<'p>> {
+        Some(ir::Assert {
+            span: self.read_span()?,
+            cond: self.read_expr()?,
+            cond_span: self.read_span()?,
+            msg: self.read_opt_expr()?,
+        })
+    }
+
+    fn read_comp_spec(&mut self) -> Option<&'p [ir::CompSpecPart<'p>]> {
+        let num_parts = self.read_len()?;
+        let mut parts = Vec::new();
+        for _ in 0..num_parts {
+            if self.read_bool()? {
+                parts.push(ir::CompSpecPart::If {
+                    cond: self.read_expr()?,
+                    cond_span: self.read_span()?,
+                });
+            } else {
+                parts.push(ir::CompSpecPart::For {
+                    value: self.read_expr()?,
+                    value_span: self.read_span()?,
+                });
+            }
+        }
+        if !matches!(parts.first(), Some(ir::CompSpecPart::For { .. })) {
+            return None;
+        }
+        Some(self.program.arena.alloc_slice(&parts))
+    }
+}
@@ -75,26 +75,49 @@
 //! assert_eq!(value.as_number(), Some(3.0));
 //! ```
 
+use std::rc::Rc;
+
 use crate::arena::Arena;
 use crate::gc::{Gc, GcContext, GcTrace, GcView};
 use crate::interner::{InternedStr, StrInterner};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 use crate::span::{SourceId, SpanContextId, SpanId, SpanManager};
-use crate::{FHashMap, ast};
+use crate::{FHashMap, FHashSet, ast};
 
 mod analyze;
+mod bytecode;
+mod convert;
 mod data;
+#[cfg(feature = "serde")]
+mod de;
 mod error;
 mod eval;
+mod fork;
 mod ir;
+mod ir_cache;
+mod optimize;
+#[cfg(feature = "serde")]
+mod ser;
 mod stdlib;
+mod string_data;
 
+pub use convert::{FromJsonnet, FromJsonnetError, IntoJsonnet};
 use data::{
     ArrayData, BuiltInFunc, FuncData, FuncKind, FuncParams, ObjectData, ObjectLayer,
-    SimpleObjectBuilder, ThunkData, ThunkEnv, ThunkEnvData, ThunkState, ValueData,
+    SimpleObjectBuilder, ThunkData, ThunkEnv, ThunkEnvData, ValueData,
+};
+#[cfg(feature = "serde")]
+pub use de::DeserializeError;
+pub use error::{
+    AnalyzeError, EvalError, EvalErrorKind, EvalErrorValueType, LoadError, ManifestError,
+    ParseJsonError, ParseTomlError, ParseYamlError,
 };
-pub use error::{AnalyzeError, EvalError, EvalErrorKind, EvalErrorValueType, LoadError};
+use fork::ForkNode;
+pub use ir_cache::SharedSourceCache;
+#[cfg(feature = "serde")]
+pub use ser::SerializeError;
+use string_data::StringData;
 
 /// Error type that can be returned by [`Callbacks::import`],
 /// [`Callbacks::import_str`] and [`Callbacks::import_bin`].
@@ -104,10 +127,24 @@ pub use error::{AnalyzeError, EvalError, EvalErrorKind, EvalErrorValueType, Load
 pub struct ImportError;
 
 /// Error type that can be returned by [`Callbacks::native_call`].
-///
-/// It does not carry any additional information.
 #[derive(Clone, Debug)]
-pub struct NativeError;
+pub enum NativeError {
+    /// The native function failed.
+    ///
+    /// It does not carry any additional information, the callback is
+    /// expected to report the cause by itself.
+    Failed,
+    /// An argument could not be converted to the type expected by the native
+    /// function.
+    ///
+    /// `arg_index` is the index of the parameter in the list passed to
+    /// [`Program::register_native_func`]. If it is out of range, the error
+    /// is reported like [`NativeError::Failed`].
+    InvalidArg {
+        arg_index: usize,
+        error: FromJsonnetError,
+    },
+}
 
 /// Trait to customize the behavior of operations during evaluation.
 ///
@@ -141,6 +178,20 @@ pub trait Callbacks<'p> {
     /// Called when a call to `std.trace` is evaluated.
     fn trace(&mut self, program: &mut Program<'p>, message: &str, stack: &[EvalStackTraceItem]);
 
+    /// Called when `std.extVar` is evaluated with the name of an external
+    /// variable that has not been added with [`Program::add_ext_var`].
+    ///
+    /// If it returns a thunk, it is cached as the value of the variable, so
+    /// this function is called at most once for each variable that exists.
+    /// If it returns `None`, the evaluation fails with
+    /// [`EvalErrorKind::UnknownExtVar`].
+    ///
+    /// The default implementation always returns `None`.
+    fn ext_var(&mut self, program: &mut Program<'p>, name: InternedStr<'p>) -> Option<Thunk<'p>> {
+        let _ = (program, name);
+        None
+    }
+
     /// Called when a function returned by `std.native` is called.
     ///
     /// Native functions must be registered with
@@ -161,6 +212,9 @@ pub enum EvalStackTraceItem {
     Call {
         span: Option<SpanId>,
         name: Option<String>,
+        /// Number of calls in tail position made after this call, which
+        /// are elided from the stack trace.
+        tail_calls: usize,
     },
     Variable {
         span: SpanId,
@@ -196,22 +250,49 @@ pub enum EvalStackTraceItem {
 /// See the [module-level documentation](self) for more information.
 pub struct Program<'p> {
     arena: &'p Arena,
-    str_interner: StrInterner<'p>,
+    str_interner: Rc<StrInterner<'p>>,
     span_mgr: SpanManager,
-    gc_ctx: GcContext<'p>,
+    gc_ctx: Rc<GcContext<'p>>,
+    /// The states of this program in objects shared with other programs.
+    /// See [`Program::fork`].
+    fork_node: Rc<ForkNode<'p>>,
+    /// Objects allocated in an earlier epoch are shared with other
+    /// programs.
+    frozen_epoch: u32,
     objs_after_last_gc: usize,
     max_stack: usize,
+    max_tail_calls: usize,
+    optimize: bool,
+    source_cache: Option<std::path::PathBuf>,
+    shared_source_cache: Option<SharedSourceCache>,
     exprs: Exprs<'p>,
+    stdlib_span_ctx: SpanContextId,
     stdlib_src_id: SourceId,
     stdlib_data: &'static [u8],
+    /// Loaded on first use.
     stdlib_base_obj: Option<GcView<ObjectData<'p>>>,
+    /// `std` without `thisFile`, shared by all loaded sources. Cleared when
+    /// the standard library is modified.
+    stdlib_shared_obj: Option<GcView<ObjectData<'p>>>,
     stdlib_extra: FHashMap<InternedStr<'p>, GcView<ThunkData<'p>>>,
+    /// Built-in fields of `std`, as they were before any
+    /// [`Program::add_stdlib_field`] or [`Program::remove_stdlib_field`].
+    stdlib_builtins: FHashMap<InternedStr<'p>, GcView<ThunkData<'p>>>,
+    stdlib_removed: FHashSet<InternedStr<'p>>,
     empty_array: GcView<ArrayData<'p>>,
     identity_func: GcView<FuncData<'p>>,
+    /// Placeholder for `local` bindings dropped by the optimizer.
+    unused_var: GcView<ThunkData<'p>>,
     ext_vars: FHashMap<InternedStr<'p>, GcView<ThunkData<'p>>>,
+    /// External variables provided by [`Callbacks::ext_var`].
+    resolved_ext_vars: FHashMap<InternedStr<'p>, GcView<ThunkData<'p>>>,
+    requested_ext_vars: Vec<InternedStr<'p>>,
+    requested_ext_vars_set: FHashSet<InternedStr<'p>>,
     native_funcs: FHashMap<InternedStr<'p>, GcView<FuncData<'p>>>,
+    regex_cache: FHashMap<Box<str>, eval::CachedRegex>,
 }
 
+#[derive(Clone, Copy)]
 struct Exprs<'p> {
     null: &'p ir::Expr<'p>,
     false_: &'p ir::Expr<'p>,
@@ -239,31 +320,47 @@ impl<'p> Program<'p> {
 
         let stdlib_extra = Self::build_stdlib_extra(arena, &str_interner, &gc_ctx, &exprs);
 
-        let empty_array: GcView<ArrayData<'p>> = gc_ctx.alloc_view(Box::new([]));
+        let empty_array: GcView<ArrayData<'p>> =
+            gc_ctx.alloc_view(ArrayData::new_flat(Box::new([])));
         let identity_func = gc_ctx.alloc_view(FuncData::new_identity_func(
             Some(str_interner.intern(arena, "id")),
             arena.alloc([(str_interner.intern(arena, "x"), None)]),
         ));
 
-        let mut this = Self {
+        let unused_var = gc_ctx.alloc_view(ThunkData::new_done(ValueData::Null));
+
+        Self {
             arena,
-            str_interner,
+            str_interner: Rc::new(str_interner),
             span_mgr,
-            gc_ctx,
+            gc_ctx: Rc::new(gc_ctx),
+            fork_node: ForkNode::new(None),
+            frozen_epoch: 0,
             objs_after_last_gc: 0,
             max_stack: 500,
+            max_tail_calls: 1_000_000,
+            optimize: false,
+            source_cache: None,
+            shared_source_cache: None,
             exprs,
+            stdlib_span_ctx,
             stdlib_src_id,
             stdlib_data,
             stdlib_base_obj: None,
+            stdlib_shared_obj: None,
+            stdlib_builtins: stdlib_extra.clone(),
             stdlib_extra,
+            stdlib_removed: FHashSet::default(),
             empty_array,
             identity_func,
+            unused_var,
             ext_vars: FHashMap::default(),
+            resolved_ext_vars: FHashMap::default(),
+            requested_ext_vars: Vec::new(),
+            requested_ext_vars_set: FHashSet::default(),
             native_funcs: FHashMap::default(),
-        };
-        this.load_stdlib(stdlib_span_ctx);
-        this
+            regex_cache: FHashMap::default(),
+        }
     }
 
     #[inline]
@@ -293,7 +390,13 @@ impl<'p> Program<'p> {
     }
 
     /// Runs garbage collection under certain conditions.
+    ///
+    /// Objects allocated since the last collection are collected frequently,
+    /// while the whole heap is only collected when it has doubled in size.
     pub fn maybe_gc(&mut self) {
+        if self.gc_ctx.num_young_objects() > 4096 {
+            self.gc_ctx.minor_gc();
+        }
         let num_objects = self.gc_ctx.num_objects();
         if num_objects > 1000 && (num_objects / 2) > self.objs_after_last_gc {
             self.gc();
@@ -307,25 +410,84 @@ impl<'p> Program<'p> {
         self.max_stack = max_stack;
     }
 
+    /// Sets the maximum number of consecutive `tailstrict` calls in tail
+    /// position that can reuse the same stack frame.
+    ///
+    /// The default is 1000000.
+    pub fn set_max_tail_calls(&mut self, max_tail_calls: usize) {
+        self.max_tail_calls = max_tail_calls;
+    }
+
+    /// Sets whether sources loaded from now on are optimized.
+    ///
+    /// When enabled, constant expressions (such as arithmetic on number
+    /// literals or string literal concatenation) are folded, unused `local`
+    /// bindings are dropped and functions of the standard library are
+    /// accessed without looking them up in the `std` object. The result
+    /// of the evaluation does not change, but stack traces can be shorter
+    /// since folded expressions are not evaluated anymore.
+    ///
+    /// The default is `false`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Sets a directory where the analyzed form of loaded sources is cached.
+    ///
+    /// When set, [`Program::load_source`] looks for a cache entry keyed by
+    /// a hash of the source, the rsjsonnet version and the options that
+    /// affect analysis, and skips lexing, parsing and analysis if one is
+    /// found. Otherwise, the analyzed source is stored in the directory
+    /// (which is created if needed) for later runs. Errors when reading or
+    /// writing the cache are ignored, and sources that fail to load are not
+    /// cached.
+    ///
+    /// The default is `None`, which disables the cache.
+    pub fn set_source_cache(&mut self, dir: Option<std::path::PathBuf>) {
+        self.source_cache = dir;
+    }
+
+    /// Sets an in-memory cache of the analyzed form of loaded sources.
+    ///
+    /// It works like [`Program::set_source_cache`], but entries are kept in
+    /// memory, so programs in different threads that load the same sources
+    /// can share a cache by cloning it, and only the first one to load a
+    /// source lexes, parses and analyzes it. If both caches are set, this
+    /// one is looked up first.
+    ///
+    /// The default is `None`, which disables the cache.
+    pub fn set_shared_source_cache(&mut self, cache: Option<SharedSourceCache>) {
+        self.shared_source_cache = cache;
+    }
+
     /// Returns the source of the part of the standard library that
     /// is implemented in Jsonnet.
     pub fn get_stdlib_source(&self) -> (SourceId, &[u8]) {
         (self.stdlib_src_id, self.stdlib_data)
     }
 
-    /// Adds an external variable.
+    /// Adds an external variable, replacing any variable with the same
+    /// name, including one provided by [`Callbacks::ext_var`].
     ///
     /// External variables can be accessed within a Jsonnet program
-    /// with the `std.extVar` function.
+    /// with the `std.extVar` function. They can also be provided on demand
+    /// with [`Callbacks::ext_var`]. To evaluate the same sources with
+    /// different values, add them to copies created with [`Program::fork`].
+    ///
+    /// Values that have already been computed from a replaced variable are
+    /// not updated.
     pub fn add_ext_var(&mut self, name: InternedStr<'p>, thunk: &Thunk<'p>) {
-        match self.ext_vars.entry(name) {
-            std::collections::hash_map::Entry::Occupied(entry) => {
-                panic!("external variable {:?} already set", entry.key());
-            }
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(thunk.data.clone());
-            }
-        }
+        self.resolved_ext_vars.remove(&name);
+        self.ext_vars.insert(name, thunk.data.clone());
+    }
+
+    /// Returns the names of the external variables that have been requested
+    /// with `std.extVar`, in the order they were first requested.
+    ///
+    /// It includes variables that were not found.
+    #[inline]
+    pub fn requested_ext_vars(&self) -> &[InternedStr<'p>] {
+        &self.requested_ext_vars
     }
 
     /// Registers a native function.
@@ -341,20 +503,101 @@ impl<'p> Program<'p> {
     /// Panics if a native function with the same name is already registered
     /// or if a parameter name is repeated.
     pub fn register_native_func(&mut self, name: InternedStr<'p>, params: &[InternedStr<'p>]) {
+        let params: Vec<_> = params.iter().map(|&name| (name, None)).collect();
+        self.register_native_func_with_params(name, &params, None);
+    }
+
+    /// Registers a native function whose parameters can have default
+    /// values and that can have a trailing rest parameter.
+    ///
+    /// Each item of `params` is a parameter name with its optional default
+    /// value. A default value can be created from a [`Value`] with
+    /// [`Program::value_to_thunk`] or from Jsonnet code with
+    /// [`Program::load_source`].
+    ///
+    /// If `rest_param` is provided, it is added as the last parameter and
+    /// it receives an array with all the positional arguments that do not
+    /// fit in `params` (or an empty array if there are none). The rest
+    /// parameter cannot be passed as a named argument.
+    ///
+    /// Arguments are checked the same way as with Jsonnet functions, so
+    /// [`Callbacks::native_call`] receives one argument for each parameter,
+    /// including the rest parameter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a native function with the same name is already registered
+    /// or if a parameter name is repeated.
+    pub fn register_native_func_with_params(
+        &mut self,
+        name: InternedStr<'p>,
+        params: &[(InternedStr<'p>, Option<Thunk<'p>>)],
+        rest_param: Option<InternedStr<'p>>,
+    ) {
         match self.native_funcs.entry(name) {
             std::collections::hash_map::Entry::Occupied(entry) => {
                 panic!("native function {:?} already registered", entry.key());
             }
             std::collections::hash_map::Entry::Vacant(entry) => {
-                let params_order: Vec<_> = params.iter().map(|&name| (name, None)).collect();
-                entry.insert(self.gc_ctx.alloc_view(FuncData::new(
+                let mut params_order: Vec<_> =
+                    params.iter().map(|&(name, _)| (name, None)).collect();
+                params_order.extend(rest_param.map(|name| (name, None)));
+                let default_thunks: Box<[_]> =
+                    if params.iter().any(|(_, default)| default.is_some()) {
+                        params
+                            .iter()
+                            .map(|(_, default)| default.as_ref().map(|thunk| Gc::from(&thunk.data)))
+                            .collect()
+                    } else {
+                        Box::new([])
+                    };
+                entry.insert(self.gc_ctx.alloc_view(FuncData::new_native_func(
+                    name,
                     self.arena.alloc_slice(&params_order),
-                    FuncKind::Native { name },
+                    default_thunks,
+                    rest_param.is_some(),
                 )));
             }
         }
     }
 
+    /// Returns a registered native function, like `std.native(name)`
+    /// would.
+    pub fn get_native_func(&self, name: InternedStr<'p>) -> Option<Value<'p>> {
+        self.native_funcs
+            .get(&name)
+            .map(|func| Value::from_value(ValueData::Function(Gc::from(func))))
+    }
+
+    /// Adds a field to the standard library object (`std`).
+    ///
+    /// If the standard library already has a field named `name`, it is
+    /// overridden. The field is hidden, like the rest of the standard
+    /// library fields. A Rust implementation can be provided with a native
+    /// function value from [`Program::get_native_func`], and a Jsonnet
+    /// implementation with a thunk from [`Program::load_source`].
+    ///
+    /// The change only affects sources that are loaded afterwards. Since the
+    /// part of the standard library that is written in Jsonnet accesses
+    /// other fields through `self`, overriding a field also affects those
+    /// accesses.
+    pub fn add_stdlib_field(&mut self, name: InternedStr<'p>, thunk: &Thunk<'p>) {
+        self.stdlib_removed.remove(&name);
+        self.stdlib_extra.insert(name, thunk.data.clone());
+        self.stdlib_shared_obj = None;
+    }
+
+    /// Removes a field from the standard library object (`std`).
+    ///
+    /// Does nothing if the field does not exist. As with
+    /// [`Program::add_stdlib_field`], the change only affects sources that
+    /// are loaded afterwards.
+    pub fn remove_stdlib_field(&mut self, name: InternedStr<'p>) {
+        self.stdlib_extra.remove(&name);
+        self.stdlib_removed.insert(name);
+        self.stdlib_shared_obj = None;
+    }
+
     #[must_use]
     #[inline]
     fn gc_alloc<T: GcTrace + 'p>(&self, data: T) -> Gc<T> {
@@ -371,6 +614,12 @@ impl<'p> Program<'p> {
         self.gc_alloc_view(ThunkData::new_done(value))
     }
 
+    /// Returns `value` tied to this program.
+    #[inline]
+    fn new_value(&self, value: ValueData<'p>) -> Value<'p> {
+        Value::new(value, Some(self.fork_node.clone()))
+    }
+
     /// Creates a thunk with an already evaluated value.
     pub fn value_to_thunk(&mut self, value: &Value<'p>) -> Thunk<'p> {
         Thunk::new(self.insert_thunk_with_value(value.inner.clone()))
@@ -379,7 +628,7 @@ impl<'p> Program<'p> {
     /// Creates an array value.
     pub fn make_array(&mut self, items: &[Value<'p>]) -> Value<'p> {
         let array = self.make_value_array(items.iter().map(|item| item.inner.clone()));
-        Value::from_value(ValueData::Array(array))
+        self.new_value(ValueData::Array(array))
     }
 
     /// Creates an object value.
@@ -394,7 +643,34 @@ impl<'p> Program<'p> {
         }
 
         let obj = self.gc_alloc(obj_builder.build());
-        Value::from_value(ValueData::Object(obj))
+        self.new_value(ValueData::Object(obj))
+    }
+
+    /// Parses a JSON document into a value.
+    ///
+    /// This is faster than loading the document as a Jsonnet source and
+    /// evaluating it, since the value is built directly.
+    pub fn parse_json(&mut self, s: &str) -> Result<Value<'p>, ParseJsonError> {
+        let value = eval::parse_json::parse_json(self, s)?;
+        Ok(self.new_value(value))
+    }
+
+    /// Parses a YAML document into a value, with the same rules as
+    /// `std.parseYaml`.
+    ///
+    /// A stream with more than one document is parsed into an array.
+    pub fn parse_yaml(&mut self, s: &str) -> Result<Value<'p>, ParseYamlError> {
+        let value = eval::parse_yaml::parse_yaml(self, s)?;
+        Ok(self.new_value(value))
+    }
+
+    /// Parses a TOML document into a value.
+    ///
+    /// Dates and times are represented as strings, since Jsonnet does not
+    /// have a type for them. Infinite and NaN floats are rejected.
+    pub fn parse_toml(&mut self, s: &str) -> Result<Value<'p>, ParseTomlError> {
+        let value = eval::parse_toml::parse_toml(self, s)?;
+        Ok(self.new_value(value))
     }
 
     /// Loads a Jsonnet source into a thunk.
@@ -408,6 +684,88 @@ impl<'p> Program<'p> {
         with_stdlib: bool,
         this_file: &str,
     ) -> Result<Thunk<'p>, LoadError> {
+        self.load_source_with_env(span_ctx, input, with_stdlib, this_file, &[])
+    }
+
+    /// Loads a Jsonnet source into a thunk, with additional variables in
+    /// its scope.
+    ///
+    /// Each item of `env` is a variable name and its value, which can be
+    /// accessed from the source as a top-level identifier. A variable in
+    /// `env` named `std` takes precedence over the standard library.
+    ///
+    /// See [`Program::load_source`] for the rest of the parameters.
+    pub fn load_source_with_env(
+        &mut self,
+        span_ctx: SpanContextId,
+        input: &[u8],
+        with_stdlib: bool,
+        this_file: &str,
+        env: &[(InternedStr<'p>, Thunk<'p>)],
+    ) -> Result<Thunk<'p>, LoadError> {
+        let std_name = self.intern_str("std");
+        let std_is_stdlib = with_stdlib && !env.iter().any(|&(name, _)| name == std_name);
+
+        let mut analyze_env = Vec::new();
+        if with_stdlib {
+            let stdlib_obj = self.make_custom_stdlib(this_file);
+            let stdlib_thunk =
+                self.gc_alloc_view(ThunkData::new_done(ValueData::Object(stdlib_obj)));
+
+            analyze_env.push((std_name, Thunk::new(stdlib_thunk)));
+        }
+        // Later variables shadow earlier ones with the same name.
+        analyze_env.extend(env.iter().cloned());
+
+        let cache_key =
+            (self.source_cache.is_some() || self.shared_source_cache.is_some()).then(|| {
+                let env_names: Vec<_> = analyze_env.iter().map(|&(name, _)| name).collect();
+                self.source_cache_key(input, &env_names, std_is_stdlib)
+            });
+        if let Some(ref cache_key) = cache_key {
+            let data = self
+                .shared_source_cache
+                .as_ref()
+                .and_then(|cache| cache.get(cache_key))
+                .or_else(|| {
+                    let dir = self.source_cache.as_ref()?;
+                    std::fs::read(dir.join(cache_key)).ok().map(Into::into)
+                });
+            if let Some(data) = data {
+                if let Some(ir_expr) = self.decode_ir(span_ctx, input.len(), &data) {
+                    if let Some(ref shared_cache) = self.shared_source_cache {
+                        shared_cache.insert(cache_key.clone(), data);
+                    }
+                    return Ok(self.make_root_thunk(ir_expr, &analyze_env));
+                }
+            }
+        }
+
+        let analyze_env_names: Vec<_> = analyze_env.iter().map(|&(name, _)| name).collect();
+        let ir_expr = self.analyze_source(span_ctx, input, &analyze_env_names, std_is_stdlib)?;
+
+        if let Some(cache_key) = cache_key {
+            if let Some(data) = self.encode_ir(span_ctx, ir_expr) {
+                if let Some(ref dir) = self.source_cache {
+                    ir_cache::store_cache_entry(&dir.join(&cache_key), &data);
+                }
+                if let Some(ref shared_cache) = self.shared_source_cache {
+                    shared_cache.insert(cache_key, data.into());
+                }
+            }
+        }
+
+        Ok(self.make_root_thunk(ir_expr, &analyze_env))
+    }
+
+    /// Lexes, parses and analyzes a source into IR.
+    fn analyze_source(
+        &mut self,
+        span_ctx: SpanContextId,
+        input: &[u8],
+        env_names: &[InternedStr<'p>],
+        std_is_stdlib: bool,
+    ) -> Result<&'p ir::Expr<'p>, LoadError> {
         let ast_arena = Arena::new();
         let lexer = Lexer::new(
             self.arena,
@@ -428,44 +786,24 @@ impl<'p> Program<'p> {
         );
         let root_expr = parser.parse_root_expr()?;
 
-        let env = if with_stdlib {
-            let stdlib_obj = self.make_custom_stdlib(this_file);
-            let stdlib_thunk =
-                self.gc_alloc_view(ThunkData::new_done(ValueData::Object(stdlib_obj)));
-
-            let mut env = FHashMap::default();
-            env.insert(self.intern_str("std"), Thunk::new(stdlib_thunk));
-
-            Some(env)
-        } else {
-            None
-        };
-        let thunk = self.analyze(&root_expr, env)?;
-        Ok(thunk)
+        let ir_expr = analyze::Analyzer::new(self).analyze(&root_expr, env_names, std_is_stdlib)?;
+        Ok(ir_expr)
     }
 
-    fn analyze(
+    fn make_root_thunk(
         &mut self,
-        ast: &ast::Expr<'p, '_>,
-        env: Option<FHashMap<InternedStr<'p>, Thunk<'p>>>,
-    ) -> Result<Thunk<'p>, AnalyzeError> {
-        let analyze_env = env
-            .as_ref()
-            .map(|env| env.keys().cloned().collect())
-            .unwrap_or_default();
-        let ir_expr = analyze::Analyzer::new(self).analyze(ast, analyze_env)?;
-
+        ir_expr: &'p ir::Expr<'p>,
+        env: &[(InternedStr<'p>, Thunk<'p>)],
+    ) -> Thunk<'p> {
         let mut thunk_env_data = ThunkEnvData::new(None);
-        if let Some(env) = env {
-            for (name, value) in env.iter() {
-                thunk_env_data.set_var(*name, Gc::from(&value.data));
-            }
+        for (_, value) in env.iter() {
+            thunk_env_data.push_var(Gc::from(&value.data));
         }
         let thunk_env = self.gc_alloc(ThunkEnv::from(thunk_env_data));
 
         let thunk = self.gc_alloc_view(ThunkData::new_pending_expr(ir_expr, thunk_env));
 
-        Ok(Thunk::new(thunk))
+        Thunk::new(thunk)
     }
 
     /// Evaluates a thunk into a value.
@@ -483,7 +821,7 @@ impl<'p> Program<'p> {
         let eval::EvalOutput::Value(value) = output else {
             unreachable!();
         };
-        Ok(Value::from_value(value))
+        Ok(self.new_value(value))
     }
 
     fn eval_value_internal(&mut self, thunk: &Thunk<'p>) -> Result<ValueData<'p>, EvalError> {
@@ -521,24 +859,263 @@ impl<'p> Program<'p> {
         let eval::EvalOutput::Value(value) = output else {
             unreachable!();
         };
-        Ok(Value::from_value(value))
+        Ok(self.new_value(value))
     }
 
-    /// Manifests a value as JSON.
-    pub fn manifest_json(
+    /// Evaluates a thunk into an object and returns the thunks of its
+    /// visible fields, in order, without evaluating them.
+    ///
+    /// The assertions of the object are checked. Returns `Ok(None)` if the
+    /// value is not an object.
+    ///
+    /// Unlike [`Program::eval_value`], this allows to evaluate the fields
+    /// one at a time, e.g., to manifest each one into a different file.
+    pub fn eval_object_fields(
+        &mut self,
+        thunk: &Thunk<'p>,
+        callbacks: &mut dyn Callbacks<'p>,
+    ) -> Result<Option<Vec<(InternedStr<'p>, Thunk<'p>)>>, EvalError> {
+        self.eval_object_fields_inner(thunk, None, callbacks)
+    }
+
+    /// Evaluates a function call into an object and returns the thunks of
+    /// its visible fields, in order, without evaluating them.
+    ///
+    /// See [`Program::eval_object_fields`].
+    pub fn eval_call_object_fields(
+        &mut self,
+        func: &Thunk<'p>,
+        pos_args: &[Thunk<'p>],
+        named_args: &[(InternedStr<'p>, Thunk<'p>)],
+        callbacks: &mut dyn Callbacks<'p>,
+    ) -> Result<Option<Vec<(InternedStr<'p>, Thunk<'p>)>>, EvalError> {
+        let args = eval::TopLevelArgs {
+            positional: pos_args.iter().map(|thunk| thunk.data.clone()).collect(),
+            named: named_args
+                .iter()
+                .map(|(name, thunk)| (*name, thunk.data.clone()))
+                .collect(),
+        };
+        self.eval_object_fields_inner(func, Some(args), callbacks)
+    }
+
+    fn eval_object_fields_inner(
+        &mut self,
+        thunk: &Thunk<'p>,
+        args: Option<eval::TopLevelArgs<'p>>,
+        callbacks: &mut dyn Callbacks<'p>,
+    ) -> Result<Option<Vec<(InternedStr<'p>, Thunk<'p>)>>, EvalError> {
+        let output = eval::Evaluator::eval(
+            self,
+            Some(callbacks),
+            eval::EvalInput::ObjectFields(thunk.data.clone(), args),
+        )
+        .map_err(|e| *e)?;
+        let eval::EvalOutput::ObjectFields(fields) = output else {
+            unreachable!();
+        };
+        Ok(fields.map(|fields| {
+            fields
+                .into_iter()
+                .map(|(name, thunk)| (name, Thunk::new(thunk)))
+                .collect()
+        }))
+    }
+
+    fn manifest(
         &mut self,
         value: &Value<'p>,
-        multiline: bool,
+        kind: eval::ManifestKind,
     ) -> Result<String, EvalError> {
         let thunk = self.insert_thunk_with_value(value.inner.clone());
-        let output =
-            eval::Evaluator::eval(self, None, eval::EvalInput::ManifestJson(thunk, multiline))
-                .map_err(|e| *e)?;
+        let output = eval::Evaluator::eval(self, None, eval::EvalInput::Manifest(thunk, kind))
+            .map_err(|e| *e)?;
         let eval::EvalOutput::String(s) = output else {
             unreachable!();
         };
         Ok(s)
     }
+
+    fn manifest_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        kind: eval::ManifestKind,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let thunk = self.insert_thunk_with_value(value.inner.clone());
+        eval::Evaluator::eval_to_writer(self, None, eval::EvalInput::Manifest(thunk, kind), writer)
+    }
+
+    /// Manifests a value as JSON.
+    pub fn manifest_json(
+        &mut self,
+        value: &Value<'p>,
+        multiline: bool,
+    ) -> Result<String, EvalError> {
+        self.manifest(value, eval::ManifestKind::json(multiline))
+    }
+
+    /// Manifests a value as JSON into `writer`.
+    ///
+    /// The output is written while it is being generated, so it is never
+    /// fully stored in memory. Like the rest of the `*_to_writer` functions,
+    /// `writer` is not flushed.
+    pub fn manifest_json_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        multiline: bool,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        self.manifest_to_writer(value, eval::ManifestKind::json(multiline), writer)
+    }
+
+    /// Manifests a value as JSON with a custom format.
+    ///
+    /// `indent` is repeated once per nesting level after each `newline`.
+    /// `key_val_sep` is placed between object keys and values, and
+    /// `item_sep` after each array item and object field except the last
+    /// one. For example, `std.manifestJsonEx(value, indent, newline,
+    /// key_val_sep)` is equivalent to using `","` as `item_sep`.
+    pub fn manifest_json_ex(
+        &mut self,
+        value: &Value<'p>,
+        indent: &str,
+        newline: &str,
+        key_val_sep: &str,
+        item_sep: &str,
+    ) -> Result<String, EvalError> {
+        let kind = eval::ManifestKind::json_ex(indent, newline, key_val_sep, item_sep);
+        self.manifest(value, kind)
+    }
+
+    /// Like [`Program::manifest_json_ex`], but writes into `writer`.
+    ///
+    /// See [`Program::manifest_json_to_writer`] for more details.
+    pub fn manifest_json_ex_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        indent: &str,
+        newline: &str,
+        key_val_sep: &str,
+        item_sep: &str,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let kind = eval::ManifestKind::json_ex(indent, newline, key_val_sep, item_sep);
+        self.manifest_to_writer(value, kind, writer)
+    }
+
+    /// Manifests a value as a YAML document, like `std.manifestYamlDoc`.
+    pub fn manifest_yaml(
+        &mut self,
+        value: &Value<'p>,
+        indent_array_in_object: bool,
+        quote_keys: bool,
+    ) -> Result<String, EvalError> {
+        let kind = eval::ManifestKind::YamlDoc {
+            indent_array_in_object,
+            quote_keys,
+        };
+        self.manifest(value, kind)
+    }
+
+    /// Like [`Program::manifest_yaml`], but writes into `writer`.
+    ///
+    /// See [`Program::manifest_json_to_writer`] for more details.
+    pub fn manifest_yaml_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        indent_array_in_object: bool,
+        quote_keys: bool,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let kind = eval::ManifestKind::YamlDoc {
+            indent_array_in_object,
+            quote_keys,
+        };
+        self.manifest_to_writer(value, kind, writer)
+    }
+
+    /// Manifests an array as a stream of YAML documents, like
+    /// `std.manifestYamlStream`.
+    ///
+    /// Fails if `value` is not an array.
+    pub fn manifest_yaml_stream(
+        &mut self,
+        value: &Value<'p>,
+        indent_array_in_object: bool,
+        c_document_end: bool,
+        quote_keys: bool,
+    ) -> Result<String, EvalError> {
+        let kind = eval::ManifestKind::YamlStream {
+            indent_array_in_object,
+            c_document_end,
+            quote_keys,
+        };
+        self.manifest(value, kind)
+    }
+
+    /// Like [`Program::manifest_yaml_stream`], but writes into `writer`.
+    ///
+    /// See [`Program::manifest_json_to_writer`] for more details.
+    pub fn manifest_yaml_stream_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        indent_array_in_object: bool,
+        c_document_end: bool,
+        quote_keys: bool,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let kind = eval::ManifestKind::YamlStream {
+            indent_array_in_object,
+            c_document_end,
+            quote_keys,
+        };
+        self.manifest_to_writer(value, kind, writer)
+    }
+
+    /// Manifests an object as TOML, like `std.manifestTomlEx`.
+    ///
+    /// Fails if `value` is not an object.
+    pub fn manifest_toml(&mut self, value: &Value<'p>, indent: &str) -> Result<String, EvalError> {
+        let kind = eval::ManifestKind::Toml {
+            indent: indent.into(),
+        };
+        self.manifest(value, kind)
+    }
+
+    /// Like [`Program::manifest_toml`], but writes into `writer`.
+    ///
+    /// See [`Program::manifest_json_to_writer`] for more details.
+    pub fn manifest_toml_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        indent: &str,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        let kind = eval::ManifestKind::Toml {
+            indent: indent.into(),
+        };
+        self.manifest_to_writer(value, kind, writer)
+    }
+
+    /// Manifests an object as INI, like `std.manifestIni`.
+    ///
+    /// Fails if `value` is not an object with the structure expected by
+    /// `std.manifestIni`.
+    pub fn manifest_ini(&mut self, value: &Value<'p>) -> Result<String, EvalError> {
+        self.manifest(value, eval::ManifestKind::Ini)
+    }
+
+    /// Like [`Program::manifest_ini`], but writes into `writer`.
+    ///
+    /// See [`Program::manifest_json_to_writer`] for more details.
+    pub fn manifest_ini_to_writer(
+        &mut self,
+        value: &Value<'p>,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ManifestError> {
+        self.manifest_to_writer(value, eval::ManifestKind::Ini, writer)
+    }
 }
 
 /// A value that might not be evaluated yet.
@@ -566,22 +1143,35 @@ impl<'p> Thunk<'p> {
 #[derive(Clone)]
 pub struct Value<'p> {
     inner: ValueData<'p>,
+    /// The fork node of the program that evaluated the value, to find the
+    /// values of thunks shared with other programs.
+    program: Option<Rc<ForkNode<'p>>>,
 }
 
 impl<'p> Value<'p> {
     #[inline]
     fn from_value(inner: ValueData<'p>) -> Self {
-        Self { inner }
+        Self::new(inner, None)
     }
 
     #[inline]
-    fn from_thunk(thunk: &ThunkData<'p>) -> Self {
-        Self {
-            inner: match *thunk.state() {
-                ThunkState::Done(ref value) => value.clone(),
-                _ => panic!("thunk not evaluated"),
-            },
-        }
+    fn new(inner: ValueData<'p>, program: Option<Rc<ForkNode<'p>>>) -> Self {
+        Self { inner, program }
+    }
+
+    #[inline]
+    fn from_thunk(thunk: &ThunkData<'p>, program: &Rc<ForkNode<'p>>) -> Self {
+        Self::new(
+            thunk.get_value(program).expect("thunk not evaluated"),
+            Some(program.clone()),
+        )
+    }
+
+    /// Returns the fork node of the program of an array or object value,
+    /// which is always evaluated by a program.
+    #[inline]
+    fn program(&self) -> &Rc<ForkNode<'p>> {
+        self.program.as_ref().expect("value not tied to a program")
     }
 
     /// Creates a null value.
@@ -627,10 +1217,8 @@ impl<'p> Value<'p> {
             ValueData::Bool(value) => ValueKind::Bool(value),
             ValueData::Number(value) => ValueKind::Number(value),
             ValueData::String(ref s) => ValueKind::String((**s).into()),
-            ValueData::Array(ref array) => ValueKind::Array(Self::extract_array(&array.view())),
-            ValueData::Object(ref object) => {
-                ValueKind::Object(Self::extract_object(&object.view()))
-            }
+            ValueData::Array(ref array) => ValueKind::Array(self.extract_array(&array.view())),
+            ValueData::Object(ref object) => ValueKind::Object(self.extract_object(&object.view())),
             ValueData::Function(_) => ValueKind::Function,
         }
     }
@@ -698,7 +1286,7 @@ impl<'p> Value<'p> {
     #[must_use]
     pub fn to_array(&self) -> Option<Vec<Self>> {
         if let ValueData::Array(ref array) = self.inner {
-            Some(Self::extract_array(&array.view()))
+            Some(self.extract_array(&array.view()))
         } else {
             None
         }
@@ -713,7 +1301,7 @@ impl<'p> Value<'p> {
     #[must_use]
     pub fn to_object(&self) -> Option<Vec<(InternedStr<'p>, Self)>> {
         if let ValueData::Object(ref object) = self.inner {
-            Some(Self::extract_object(&object.view()))
+            Some(self.extract_object(&object.view()))
         } else {
             None
         }
@@ -725,20 +1313,20 @@ impl<'p> Value<'p> {
         matches!(self.inner, ValueData::Function(_))
     }
 
-    fn extract_array(array: &ArrayData<'p>) -> Vec<Self> {
+    fn extract_array(&self, array: &ArrayData<'p>) -> Vec<Self> {
         array
             .iter()
-            .map(|item| Self::from_thunk(&item.view()))
+            .map(|item| Self::from_thunk(&item.view(), self.program()))
             .collect()
     }
 
-    fn extract_object(object: &ObjectData<'p>) -> Vec<(InternedStr<'p>, Self)> {
+    fn extract_object(&self, object: &ObjectData<'p>) -> Vec<(InternedStr<'p>, Self)> {
         let mut fields = Vec::new();
         for &(name, visibility) in object.get_fields_order().iter() {
             if visibility != ast::Visibility::Hidden {
                 let (_, field) = object.find_field(0, name).unwrap();
                 let thunk = field.thunk.get().unwrap().clone();
-                fields.push((name, Self::from_thunk(&thunk.view())));
+                fields.push((name, Self::from_thunk(&thunk.view(), self.program())));
             }
         }
         fields
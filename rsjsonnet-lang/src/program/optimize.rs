@@ -0,0 +1,194 @@
+//! Simplifications applied to the IR while it is built, enabled with
+//! [`Program::set_optimize`](super::Program::set_optimize).
+//!
+//! Every simplification preserves the result of the evaluation. Expressions
+//! that could fail are kept as they are, so errors are still reported at
+//! the same place.
+
+use super::data::{FuncKind, ValueData};
+use super::{Program, ir};
+use crate::ast;
+use crate::interner::InternedStr;
+use crate::span::SpanId;
+
+impl<'p> Program<'p> {
+    /// Returns whether `std.<name>` is known to be the built-in function
+    /// `name`, i.e., it has not been overridden or removed.
+    pub(super) fn is_unmodified_std_builtin(&self, name: InternedStr<'p>) -> bool {
+        if !self.stdlib_builtins.contains_key(&name) || self.stdlib_removed.contains(&name) {
+            return false;
+        }
+        let Some(thunk) = self.stdlib_extra.get(&name) else {
+            return false;
+        };
+        let Some(ValueData::Function(func)) = thunk.get_value(&self.fork_node) else {
+            return false;
+        };
+        matches!(
+            func.view().kind,
+            FuncKind::BuiltIn { name: func_name, .. } if func_name == name,
+        )
+    }
+
+    pub(super) fn fold_binary_op(
+        &self,
+        op: ast::BinaryOp,
+        lhs: &'p ir::Expr<'p>,
+        rhs: &'p ir::Expr<'p>,
+        span: SpanId,
+    ) -> Option<&'p ir::Expr<'p>> {
+        match (op, *lhs, *rhs) {
+            // `&&` and `||` do not evaluate the right-hand side when the
+            // left-hand side determines the result.
+            (ast::BinaryOp::LogicAnd, ir::Expr::Bool(false), _) => Some(self.exprs.false_),
+            (ast::BinaryOp::LogicOr, ir::Expr::Bool(true), _) => Some(self.exprs.true_),
+            (
+                ast::BinaryOp::LogicAnd | ast::BinaryOp::LogicOr,
+                ir::Expr::Bool(_),
+                ir::Expr::Bool(_),
+            ) => Some(rhs),
+            (_, ir::Expr::Number(lhs, _), ir::Expr::Number(rhs, _))
+                if lhs.is_finite() && rhs.is_finite() =>
+            {
+                let r = match op {
+                    ast::BinaryOp::Add => lhs + rhs,
+                    ast::BinaryOp::Sub => lhs - rhs,
+                    ast::BinaryOp::Mul => lhs * rhs,
+                    ast::BinaryOp::Div if rhs != 0.0 => lhs / rhs,
+                    ast::BinaryOp::Rem if rhs != 0.0 => lhs % rhs,
+                    _ => return self.fold_cmp_op(op, lhs.partial_cmp(&rhs)?),
+                };
+                if !r.is_finite() {
+                    return None;
+                }
+                Some(self.arena.alloc(ir::Expr::Number(r, span)))
+            }
+            (ast::BinaryOp::Add, ir::Expr::String(lhs), ir::Expr::String(rhs)) => {
+                let mut r = String::with_capacity(lhs.len() + rhs.len());
+                r.push_str(lhs);
+                r.push_str(rhs);
+                Some(self.arena.alloc(ir::Expr::String(self.arena.alloc_str(&r))))
+            }
+            (_, ir::Expr::String(lhs), ir::Expr::String(rhs)) => {
+                // Byte-wise comparison of UTF-8 strings matches the
+                // comparison by code points.
+                self.fold_cmp_op(op, lhs.cmp(rhs))
+            }
+            (ast::BinaryOp::Eq | ast::BinaryOp::Ne, ir::Expr::Bool(lhs), ir::Expr::Bool(rhs)) => {
+                self.fold_cmp_op(op, lhs.cmp(&rhs))
+            }
+            (ast::BinaryOp::Eq | ast::BinaryOp::Ne, ir::Expr::Null, ir::Expr::Null) => {
+                self.fold_cmp_op(op, std::cmp::Ordering::Equal)
+            }
+            _ => None,
+        }
+    }
+
+    fn fold_cmp_op(
+        &self,
+        op: ast::BinaryOp,
+        ordering: std::cmp::Ordering,
+    ) -> Option<&'p ir::Expr<'p>> {
+        let r = match op {
+            ast::BinaryOp::Lt => ordering.is_lt(),
+            ast::BinaryOp::Le => ordering.is_le(),
+            ast::BinaryOp::Gt => ordering.is_gt(),
+            ast::BinaryOp::Ge => ordering.is_ge(),
+            ast::BinaryOp::Eq => ordering.is_eq(),
+            ast::BinaryOp::Ne => ordering.is_ne(),
+            _ => return None,
+        };
+        Some(if r {
+            self.exprs.true_
+        } else {
+            self.exprs.false_
+        })
+    }
+
+    pub(super) fn fold_unary_op(
+        &self,
+        op: ast::UnaryOp,
+        rhs: &'p ir::Expr<'p>,
+        span: SpanId,
+    ) -> Option<&'p ir::Expr<'p>> {
+        match (op, *rhs) {
+            (ast::UnaryOp::Minus, ir::Expr::Number(value, _)) if value.is_finite() => {
+                Some(self.arena.alloc(ir::Expr::Number(-value, span)))
+            }
+            (ast::UnaryOp::Plus, ir::Expr::Number(value, _)) if value.is_finite() => Some(rhs),
+            (ast::UnaryOp::LogicNot, ir::Expr::Bool(value)) => Some(if value {
+                self.exprs.false_
+            } else {
+                self.exprs.true_
+            }),
+            _ => None,
+        }
+    }
+
+    pub(super) fn fold_if(
+        &self,
+        cond: &'p ir::Expr<'p>,
+        then_body: &'p ir::Expr<'p>,
+        else_body: Option<&'p ir::Expr<'p>>,
+    ) -> Option<&'p ir::Expr<'p>> {
+        match *cond {
+            ir::Expr::Bool(true) => Some(then_body),
+            ir::Expr::Bool(false) => Some(else_body.unwrap_or(self.exprs.null)),
+            _ => None,
+        }
+    }
+
+    /// Evaluates calls to some functions of the standard library whose
+    /// argument is a literal.
+    pub(super) fn fold_std_call(
+        &self,
+        func_name: InternedStr<'p>,
+        arg: &'p ir::Expr<'p>,
+        span: SpanId,
+    ) -> Option<&'p ir::Expr<'p>> {
+        match func_name.value() {
+            "length" => {
+                let length = match *arg {
+                    ir::Expr::String(s) => s.chars().count(),
+                    ir::Expr::Array(items) => items.len(),
+                    ir::Expr::Object { fields, .. } => {
+                        let mut length = 0;
+                        for field in fields.iter() {
+                            if !matches!(field.name, ir::FieldName::Fix(_)) {
+                                return None;
+                            }
+                            if field.visibility != ast::Visibility::Hidden {
+                                length += 1;
+                            }
+                        }
+                        length
+                    }
+                    ir::Expr::Func { params, .. } => params.len(),
+                    ir::Expr::IdentityFunc => 1,
+                    _ => return None,
+                };
+                Some(self.arena.alloc(ir::Expr::Number(length as f64, span)))
+            }
+            "type" => {
+                let type_str = match *arg {
+                    ir::Expr::Null => "null",
+                    ir::Expr::Bool(_) => "boolean",
+                    ir::Expr::Number(value, _) if value.is_finite() => "number",
+                    ir::Expr::String(_) => "string",
+                    ir::Expr::Array(_) => "array",
+                    ir::Expr::Object { fields, .. }
+                        if fields
+                            .iter()
+                            .all(|field| matches!(field.name, ir::FieldName::Fix(_))) =>
+                    {
+                        "object"
+                    }
+                    ir::Expr::Func { .. } | ir::Expr::IdentityFunc => "function",
+                    _ => return None,
+                };
+                Some(self.arena.alloc(ir::Expr::String(type_str)))
+            }
+            _ => None,
+        }
+    }
+}
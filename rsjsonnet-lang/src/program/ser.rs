@@ -0,0 +1,647 @@
+use std::fmt;
+
+use serde::ser;
+
+use super::{Program, Value};
+use crate::FHashSet;
+use crate::interner::InternedStr;
+
+/// Error returned by [`Program::serialize`].
+#[derive(Clone, Debug)]
+pub struct SerializeError {
+    message: String,
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            message: msg.to_string(),
+        }
+    }
+}
+
+impl<'p> Program<'p> {
+    /// Serializes a Rust value into a Jsonnet value.
+    ///
+    /// The returned value can be used, for example, as an external variable
+    /// (with [`Program::value_to_thunk`]) or as a top-level argument.
+    ///
+    /// Sequences are converted to arrays, and maps and structs to objects.
+    /// Enums use the externally tagged representation, so a unit variant
+    /// becomes a string and any other variant becomes an object with a
+    /// single field. Map keys must be strings, characters or integers.
+    pub fn serialize<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<Value<'p>, SerializeError> {
+        value.serialize(ValueSerializer { program: self })
+    }
+}
+
+struct ValueSerializer<'a, 'p> {
+    program: &'a mut Program<'p>,
+}
+
+impl<'a, 'p> ValueSerializer<'a, 'p> {
+    fn number(value: f64) -> Result<Value<'p>, SerializeError> {
+        if value.is_finite() {
+            Ok(Value::number(value))
+        } else {
+            Err(ser::Error::custom(format_args!(
+                "cannot serialize non-finite number {value}",
+            )))
+        }
+    }
+
+    /// Integers beyond ±2^53 cannot be represented exactly as an `f64`, so
+    /// they are rejected instead of being rounded.
+    fn integer<T: Copy + fmt::Display + TryInto<i64>>(
+        value: T,
+    ) -> Result<Value<'p>, SerializeError> {
+        const MAX_EXACT: i64 = 1 << 53;
+        match value.try_into() {
+            Ok(v) if (-MAX_EXACT..=MAX_EXACT).contains(&v) => Ok(Value::number(v as f64)),
+            _ => Err(ser::Error::custom(format_args!(
+                "cannot serialize integer {value} exactly as a number",
+            ))),
+        }
+    }
+
+    fn wrap_variant(
+        program: &mut Program<'p>,
+        variant: &str,
+        value: Value<'p>,
+    ) -> Result<Value<'p>, SerializeError> {
+        let name = program.intern_str(variant);
+        Ok(program.make_object(&[(name, value)]))
+    }
+
+    fn seq(self, variant: Option<&'static str>, len: Option<usize>) -> SeqSerializer<'a, 'p> {
+        SeqSerializer {
+            program: self.program,
+            variant,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        }
+    }
+
+    fn map(self, variant: Option<&'static str>) -> MapSerializer<'a, 'p> {
+        MapSerializer {
+            program: self.program,
+            variant,
+            fields: Vec::new(),
+            names: FHashSet::default(),
+            pending_key: None,
+        }
+    }
+}
+
+impl<'a, 'p> ser::Serializer for ValueSerializer<'a, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    type SerializeSeq = SeqSerializer<'a, 'p>;
+    type SerializeTuple = SeqSerializer<'a, 'p>;
+    type SerializeTupleStruct = SeqSerializer<'a, 'p>;
+    type SerializeTupleVariant = SeqSerializer<'a, 'p>;
+    type SerializeMap = MapSerializer<'a, 'p>;
+    type SerializeStruct = MapSerializer<'a, 'p>;
+    type SerializeStructVariant = MapSerializer<'a, 'p>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Self::integer(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        Self::integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        Self::integer(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        Self::integer(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        Self::number(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        Self::number(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::string(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let items: Vec<_> = v.iter().map(|&b| Value::number(b.into())).collect();
+        Ok(self.program.make_array(&items))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_some<T: ser::Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(Value::string(variant))
+    }
+
+    fn serialize_newtype_struct<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        let value = value.serialize(ValueSerializer {
+            program: self.program,
+        })?;
+        Self::wrap_variant(self.program, variant, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(self.seq(None, len))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(self.seq(None, Some(len)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(self.seq(None, Some(len)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(self.seq(Some(variant), Some(len)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(self.map(None))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(self.map(None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(self.map(Some(variant)))
+    }
+}
+
+struct SeqSerializer<'a, 'p> {
+    program: &'a mut Program<'p>,
+    variant: Option<&'static str>,
+    items: Vec<Value<'p>>,
+}
+
+impl<'p> SeqSerializer<'_, 'p> {
+    fn push<T: ser::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let item = value.serialize(ValueSerializer {
+            program: self.program,
+        })?;
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value<'p>, SerializeError> {
+        let array = self.program.make_array(&self.items);
+        if let Some(variant) = self.variant {
+            ValueSerializer::wrap_variant(self.program, variant, array)
+        } else {
+            Ok(array)
+        }
+    }
+}
+
+impl<'p> ser::SerializeSeq for SeqSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'p> ser::SerializeTuple for SeqSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'p> ser::SerializeTupleStruct for SeqSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'p> ser::SerializeTupleVariant for SeqSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+struct MapSerializer<'a, 'p> {
+    program: &'a mut Program<'p>,
+    variant: Option<&'static str>,
+    fields: Vec<(InternedStr<'p>, Value<'p>)>,
+    names: FHashSet<InternedStr<'p>>,
+    pending_key: Option<InternedStr<'p>>,
+}
+
+impl<'p> MapSerializer<'_, 'p> {
+    fn insert<T: ser::Serialize + ?Sized>(
+        &mut self,
+        name: InternedStr<'p>,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        if !self.names.insert(name) {
+            return Err(ser::Error::custom(format_args!(
+                "duplicate object field {:?}",
+                name.value(),
+            )));
+        }
+        let value = value.serialize(ValueSerializer {
+            program: self.program,
+        })?;
+        self.fields.push((name, value));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value<'p>, SerializeError> {
+        let object = self.program.make_object(&self.fields);
+        if let Some(variant) = self.variant {
+            ValueSerializer::wrap_variant(self.program, variant, object)
+        } else {
+            Ok(object)
+        }
+    }
+}
+
+impl<'p> ser::SerializeMap for MapSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_key<T: ser::Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        let key = key.serialize(KeySerializer)?;
+        self.pending_key = Some(self.program.intern_str(&key));
+        Ok(())
+    }
+
+    fn serialize_value<T: ser::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let name = self
+            .pending_key
+            .take()
+            .expect("`serialize_value` called before `serialize_key`");
+        self.insert(name, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'p> ser::SerializeStruct for MapSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let name = self.program.intern_str(key);
+        self.insert(name, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'p> ser::SerializeStructVariant for MapSerializer<'_, 'p> {
+    type Ok = Value<'p>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let name = self.program.intern_str(key);
+        self.insert(name, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+/// Serializes object keys, which must be strings.
+struct KeySerializer;
+
+impl KeySerializer {
+    fn invalid() -> SerializeError {
+        ser::Error::custom("object key must be a string")
+    }
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = SerializeError;
+
+    type SerializeSeq = ser::Impossible<String, SerializeError>;
+    type SerializeTuple = ser::Impossible<String, SerializeError>;
+    type SerializeTupleStruct = ser::Impossible<String, SerializeError>;
+    type SerializeTupleVariant = ser::Impossible<String, SerializeError>;
+    type SerializeMap = ser::Impossible<String, SerializeError>;
+    type SerializeStruct = ser::Impossible<String, SerializeError>;
+    type SerializeStructVariant = ser::Impossible<String, SerializeError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_some<T: ser::Serialize + ?Sized>(
+        self,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(variant.into())
+    }
+
+    fn serialize_newtype_struct<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ser::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(Self::invalid())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(Self::invalid())
+    }
+}
@@ -5,17 +5,25 @@ use super::{Program, ir};
 use crate::arena::Arena;
 use crate::gc::{Gc, GcContext, GcView};
 use crate::interner::{InternedStr, StrInterner};
-use crate::span::SpanContextId;
 use crate::{FHashMap, ast};
 
 pub(super) const STDLIB_DATA: &[u8] = include_bytes!("std.libsonnet");
 
+/// [`STDLIB_DATA`] already analyzed, encoded with
+/// [`Program::encode_ir`]. Regenerate it by running the tests with the
+/// `RSJSONNET_BLESS` environment variable set.
+const STDLIB_IR: &[u8] = include_bytes!("std.libsonnet.ir");
+
 impl<'p> Program<'p> {
-    pub(super) fn load_stdlib(&mut self, span_ctx: SpanContextId) {
-        let stdlib_data = self.stdlib_data;
-        let stdlib_thunk = self
-            .load_source(span_ctx, stdlib_data, false, "std.libsonnet")
-            .expect("failed to load stdlib");
+    /// Returns the part of the standard library that is implemented in
+    /// Jsonnet, loading it the first time it is needed.
+    fn get_stdlib_base_obj(&mut self) -> GcView<ObjectData<'p>> {
+        if let Some(ref stdlib_obj) = self.stdlib_base_obj {
+            return stdlib_obj.clone();
+        }
+
+        let stdlib_ir = self.analyze_stdlib();
+        let stdlib_thunk = self.make_root_thunk(stdlib_ir, &[]);
         let stdlib_value = self
             .eval_value_internal(&stdlib_thunk)
             .expect("failed to evaluate stdlib");
@@ -25,10 +33,29 @@ impl<'p> Program<'p> {
         };
         let stdlib_obj = stdlib_obj.view();
 
-        assert!(self.stdlib_base_obj.is_none());
-        self.stdlib_base_obj = Some(stdlib_obj);
+        self.stdlib_base_obj = Some(stdlib_obj.clone());
+        stdlib_obj
+    }
 
-        self.maybe_gc();
+    /// Returns the IR of the part of the standard library that is
+    /// implemented in Jsonnet.
+    ///
+    /// It is decoded from [`STDLIB_IR`], which is prepared ahead of time,
+    /// so the standard library is only lexed, parsed and analyzed if the
+    /// embedded data cannot be decoded.
+    fn analyze_stdlib(&mut self) -> &'p ir::Expr<'p> {
+        // The standard library is never optimized, so stack traces within
+        // it do not depend on `Program::set_optimize`.
+        let optimize = std::mem::replace(&mut self.optimize, false);
+        let stdlib_data = self.stdlib_data;
+        let ir_expr = self
+            .decode_ir(self.stdlib_span_ctx, stdlib_data.len(), STDLIB_IR)
+            .unwrap_or_else(|| {
+                self.analyze_source(self.stdlib_span_ctx, stdlib_data, &[], false)
+                    .expect("failed to load stdlib")
+            });
+        self.optimize = optimize;
+        ir_expr
     }
 
     pub(super) fn build_stdlib_extra(
@@ -155,6 +182,24 @@ impl<'p> Program<'p> {
             BuiltInFunc::StrReplace,
             &["str", "from", "to"],
         );
+        add_simple("regexMatch", BuiltInFunc::RegexMatch, &["pattern", "str"]);
+        add_simple(
+            "regexFullMatch",
+            BuiltInFunc::RegexFullMatch,
+            &["pattern", "str"],
+        );
+        add_simple(
+            "regexFindAll",
+            BuiltInFunc::RegexFindAll,
+            &["pattern", "str"],
+        );
+        add_simple(
+            "regexReplace",
+            BuiltInFunc::RegexReplace,
+            &["str", "pattern", "to"],
+        );
+        add_simple("regexSplit", BuiltInFunc::RegexSplit, &["str", "pattern"]);
+        add_simple("regexQuoteMeta", BuiltInFunc::RegexQuoteMeta, &["str"]);
         add_simple("trim", BuiltInFunc::Trim, &["str"]);
         add_simple(
             "equalsIgnoreCase",
@@ -183,6 +228,7 @@ impl<'p> Program<'p> {
         add_simple("parseHex", BuiltInFunc::ParseHex, &["str"]);
         add_simple("parseJson", BuiltInFunc::ParseJson, &["str"]);
         add_simple("parseYaml", BuiltInFunc::ParseYaml, &["str"]);
+        add_simple("parseToml", BuiltInFunc::ParseToml, &["str"]);
         add_simple("encodeUTF8", BuiltInFunc::EncodeUtf8, &["str"]);
         add_simple("decodeUTF8", BuiltInFunc::DecodeUtf8, &["arr"]);
         add_simple("manifestIni", BuiltInFunc::ManifestIni, &["ini"]);
@@ -247,11 +293,37 @@ impl<'p> Program<'p> {
             &["str"],
         );
         add_simple("base64Decode", BuiltInFunc::Base64Decode, &["str"]);
-        add_simple("md5", BuiltInFunc::Md5, &["str"]);
-        add_simple("sha1", BuiltInFunc::Sha1, &["str"]);
-        add_simple("sha256", BuiltInFunc::Sha256, &["str"]);
-        add_simple("sha512", BuiltInFunc::Sha512, &["str"]);
-        add_simple("sha3", BuiltInFunc::Sha3, &["str"]);
+        add_simple("base64UrlEncode", BuiltInFunc::Base64UrlEncode, &["input"]);
+        add_simple(
+            "base64UrlDecodeBytes",
+            BuiltInFunc::Base64UrlDecodeBytes,
+            &["str"],
+        );
+        add_simple("base64UrlDecode", BuiltInFunc::Base64UrlDecode, &["str"]);
+        add_simple("base32Encode", BuiltInFunc::Base32Encode, &["input"]);
+        add_simple(
+            "base32DecodeBytes",
+            BuiltInFunc::Base32DecodeBytes,
+            &["str"],
+        );
+        add_simple("base32Decode", BuiltInFunc::Base32Decode, &["str"]);
+        add_simple("hexEncode", BuiltInFunc::HexEncode, &["input"]);
+        add_simple("hexDecodeBytes", BuiltInFunc::HexDecodeBytes, &["str"]);
+        add_simple("hexDecode", BuiltInFunc::HexDecode, &["str"]);
+        add_simple("urlEncode", BuiltInFunc::UrlEncode, &["input"]);
+        add_simple("urlDecodeBytes", BuiltInFunc::UrlDecodeBytes, &["str"]);
+        add_simple("urlDecode", BuiltInFunc::UrlDecode, &["str"]);
+        add_simple("md5", BuiltInFunc::Md5, &["input"]);
+        add_simple("sha1", BuiltInFunc::Sha1, &["input"]);
+        add_simple("sha256", BuiltInFunc::Sha256, &["input"]);
+        add_simple("sha512", BuiltInFunc::Sha512, &["input"]);
+        add_simple("sha3", BuiltInFunc::Sha3, &["input"]);
+        add_simple("blake2b", BuiltInFunc::Blake2b, &["input"]);
+        add_simple("blake3", BuiltInFunc::Blake3, &["input"]);
+        add_simple("crc32", BuiltInFunc::Crc32, &["input"]);
+        add_simple("xxh64", BuiltInFunc::Xxh64, &["input"]);
+        add_simple("xxh3", BuiltInFunc::Xxh3, &["input"]);
+        add_simple("hmac", BuiltInFunc::Hmac, &["algo", "key", "msg"]);
         add_simple("mergePatch", BuiltInFunc::MergePatch, &["target", "patch"]);
         add_simple("mod", BuiltInFunc::Mod, &["a", "b"]);
         add_simple("native", BuiltInFunc::Native, &["name"]);
@@ -300,6 +372,35 @@ impl<'p> Program<'p> {
                 ("quote_keys", Some(exprs.true_)),
             ],
         );
+        add_with_defaults(
+            "parseCsv",
+            BuiltInFunc::ParseCsv,
+            &[
+                ("str", None),
+                ("header", Some(exprs.true_)),
+                ("delimiter", Some(arena.alloc(ir::Expr::String(",")))),
+                ("quote", Some(arena.alloc(ir::Expr::String("\"")))),
+            ],
+        );
+        add_with_defaults(
+            "parseXml",
+            BuiltInFunc::ParseXml,
+            &[
+                ("str", None),
+                ("keepWhitespace", Some(exprs.false_)),
+                ("stripNamespaces", Some(exprs.false_)),
+            ],
+        );
+        add_with_defaults(
+            "manifestCsv",
+            BuiltInFunc::ManifestCsv,
+            &[
+                ("rows", None),
+                ("columns", Some(exprs.null)),
+                ("delimiter", Some(arena.alloc(ir::Expr::String(",")))),
+                ("quote", Some(arena.alloc(ir::Expr::String("\"")))),
+            ],
+        );
         add_with_defaults(
             "sort",
             BuiltInFunc::Sort,
@@ -362,20 +463,100 @@ impl<'p> Program<'p> {
         extra_fields
     }
 
-    pub(super) fn make_custom_stdlib(&mut self, this_file: &str) -> Gc<ObjectData<'p>> {
-        let stdlib_base_obj = self.stdlib_base_obj.as_ref().unwrap().clone();
+    /// Returns the `std` object without `thisFile`, building it after the
+    /// standard library has been modified.
+    fn get_shared_stdlib(&mut self) -> GcView<ObjectData<'p>> {
+        if let Some(ref stdlib_obj) = self.stdlib_shared_obj {
+            return stdlib_obj.clone();
+        }
+
+        let stdlib_base_obj = self.get_stdlib_base_obj();
 
         let mut extra_obj_builder = SimpleObjectBuilder::new();
         for (name, thunk) in self.stdlib_extra.iter() {
             extra_obj_builder.insert_field(*name, ast::Visibility::Hidden, Gc::from(thunk));
         }
-        extra_obj_builder.insert_field(
-            self.intern_str("thisFile"),
-            ast::Visibility::Hidden,
-            self.gc_alloc(ThunkData::new_done(ValueData::String(this_file.into()))),
-        );
+        let removed_depth = stdlib_base_obj.super_layers.len() + 1;
+        for &name in self.stdlib_removed.iter() {
+            extra_obj_builder.insert_removed_field(name, removed_depth);
+        }
         let extra_obj = extra_obj_builder.build();
 
-        self.extend_object(&stdlib_base_obj, &extra_obj)
+        let stdlib_obj = self.extend_object(&stdlib_base_obj, &extra_obj).view();
+
+        // Create the thunks of all fields now, so the `std` object of each
+        // source shares them instead of evaluating them again.
+        for layer_i in 0..=stdlib_obj.super_layers.len() {
+            let names: Vec<_> = stdlib_obj
+                .get_layer(layer_i)
+                .fields
+                .keys()
+                .copied()
+                .collect();
+            for name in names {
+                self.find_object_field_thunk(&stdlib_obj, layer_i, name);
+            }
+        }
+
+        self.stdlib_shared_obj = Some(stdlib_obj.clone());
+        stdlib_obj
+    }
+
+    /// Builds the `std` object of a source.
+    ///
+    /// Only `thisFile` differs between sources, so it is put in a layer on
+    /// top of an object that is shared by all of them. Fields of the shared
+    /// object are evaluated with `self` bound to it, which is not
+    /// observable since they never access `thisFile`. Extending the
+    /// resulting object (e.g., `std + { ... }`) still binds `self` to the
+    /// extended object.
+    pub(super) fn make_custom_stdlib(&mut self, this_file: &str) -> Gc<ObjectData<'p>> {
+        let stdlib_obj = self.get_shared_stdlib();
+
+        let mut this_file_obj_builder = SimpleObjectBuilder::new();
+        let this_file_name = self.intern_str("thisFile");
+        if !self.stdlib_removed.contains(&this_file_name) {
+            this_file_obj_builder.insert_field(
+                this_file_name,
+                ast::Visibility::Hidden,
+                self.gc_alloc(ThunkData::new_done(ValueData::String(this_file.into()))),
+            );
+        }
+
+        self.extend_object_sharing_thunks(&stdlib_obj, this_file_obj_builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{STDLIB_DATA, STDLIB_IR};
+    use crate::arena::Arena;
+    use crate::program::Program;
+
+    /// Checks that [`STDLIB_IR`] is up to date, or updates it if the
+    /// `RSJSONNET_BLESS` environment variable is set.
+    #[test]
+    fn test_stdlib_ir() {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+        let ir_expr = program
+            .analyze_source(program.stdlib_span_ctx, STDLIB_DATA, &[], false)
+            .unwrap();
+        let data = program.encode_ir(program.stdlib_span_ctx, ir_expr).unwrap();
+
+        if std::env::var_os("RSJSONNET_BLESS").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/program/std.libsonnet.ir");
+            std::fs::write(path, &data).unwrap();
+        } else {
+            assert!(
+                data == STDLIB_IR,
+                "std.libsonnet.ir is outdated, run the tests with RSJSONNET_BLESS=1",
+            );
+            assert!(
+                program
+                    .decode_ir(program.stdlib_span_ctx, STDLIB_DATA.len(), STDLIB_IR)
+                    .is_some()
+            );
+        }
     }
 }
@@ -0,0 +1,236 @@
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+
+/// Concatenations that produce strings shorter than this are copied
+/// eagerly instead of building a rope node.
+const MIN_ROPE_LEN: usize = 256;
+
+/// Contents of a string value.
+///
+/// Concatenating long strings builds a rope, so repeatedly appending to an
+/// accumulator (for example, with `std.foldl`) takes linear time instead of
+/// copying the accumulator each time. The rope is flattened the first time
+/// its contents are needed (indexing, comparison, manifestation...) and the
+/// flat string is cached.
+#[derive(Clone)]
+pub(crate) struct StringData {
+    node: Rc<StringNode>,
+}
+
+struct StringNode {
+    /// Length in bytes.
+    len: usize,
+    flat: OnceCell<Rc<str>>,
+    /// Left and right sides of a concatenation that has not been flattened
+    /// yet.
+    parts: RefCell<Option<(StringData, StringData)>>,
+}
+
+impl Drop for StringNode {
+    fn drop(&mut self) {
+        // Ropes can be very deep, so avoid dropping them recursively.
+        let mut pending = Vec::new();
+        pending.extend(self.parts.get_mut().take().map(|(lhs, rhs)| [lhs, rhs]));
+        while let Some([lhs, rhs]) = pending.pop() {
+            for part in [lhs, rhs] {
+                if let Ok(mut node) = Rc::try_unwrap(part.node) {
+                    pending.extend(node.parts.get_mut().take().map(|(lhs, rhs)| [lhs, rhs]));
+                }
+            }
+        }
+    }
+}
+
+impl StringData {
+    fn new_flat(s: Rc<str>) -> Self {
+        Self {
+            node: Rc::new(StringNode {
+                len: s.len(),
+                flat: OnceCell::from(s),
+                parts: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Concatenates two strings, building a rope node if the result is long.
+    pub(crate) fn concat(lhs: &Self, rhs: &Self) -> Self {
+        if rhs.is_empty() {
+            lhs.clone()
+        } else if lhs.is_empty() {
+            rhs.clone()
+        } else {
+            let len = lhs.len() + rhs.len();
+            if len < MIN_ROPE_LEN {
+                let mut s = String::with_capacity(len);
+                s.push_str(lhs.as_str());
+                s.push_str(rhs.as_str());
+                s.into()
+            } else {
+                Self {
+                    node: Rc::new(StringNode {
+                        len,
+                        flat: OnceCell::new(),
+                        parts: RefCell::new(Some((lhs.clone(), rhs.clone()))),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Returns the length in bytes, without flattening.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.node.len
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.node.len == 0
+    }
+
+    #[inline]
+    pub(crate) fn as_str(&self) -> &str {
+        self.to_rc_ref()
+    }
+
+    /// Returns the flat string, flattening the rope if needed.
+    #[inline]
+    pub(crate) fn to_rc(&self) -> Rc<str> {
+        self.to_rc_ref().clone()
+    }
+
+    #[inline]
+    fn to_rc_ref(&self) -> &Rc<str> {
+        self.node.flat.get_or_init(|| self.flatten())
+    }
+
+    #[cold]
+    fn flatten(&self) -> Rc<str> {
+        let mut result = String::with_capacity(self.len());
+        // Traverse the rope iteratively, since it can be very deep.
+        let mut pending = vec![self.clone()];
+        while let Some(part) = pending.pop() {
+            if let Some(flat) = part.node.flat.get() {
+                result.push_str(flat);
+            } else {
+                let parts = part.node.parts.borrow();
+                let (lhs, rhs) = parts.as_ref().unwrap();
+                pending.push(rhs.clone());
+                pending.push(lhs.clone());
+            }
+        }
+        // The parts are not needed anymore.
+        self.node.parts.borrow_mut().take();
+        result.into()
+    }
+}
+
+/// Builds a string from pieces, keeping long pieces as rope nodes instead of
+/// copying them.
+///
+/// Used by functions that build a string from other strings (like
+/// `std.join` and `std.format`), so accumulating a string with them takes
+/// linear time like `+` does.
+#[derive(Default)]
+pub(crate) struct StringBuilder {
+    rope: Option<StringData>,
+    /// Short pieces appended after `rope`, copied eagerly.
+    tail: String,
+}
+
+impl StringBuilder {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub(crate) fn push_str(&mut self, s: &str) {
+        self.tail.push_str(s);
+    }
+
+    pub(crate) fn push_string(&mut self, s: &StringData) {
+        if s.len() < MIN_ROPE_LEN {
+            self.tail.push_str(s.as_str());
+        } else {
+            self.flush_tail();
+            self.rope = Some(match self.rope.take() {
+                Some(rope) => StringData::concat(&rope, s),
+                None => s.clone(),
+            });
+        }
+    }
+
+    fn flush_tail(&mut self) {
+        if !self.tail.is_empty() {
+            let tail = StringData::from(std::mem::take(&mut self.tail));
+            self.rope = Some(match self.rope.take() {
+                Some(rope) => StringData::concat(&rope, &tail),
+                None => tail,
+            });
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> StringData {
+        self.flush_tail();
+        self.rope.unwrap_or_else(|| StringData::from(""))
+    }
+}
+
+impl std::ops::Deref for StringData {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<Rc<str>> for StringData {
+    #[inline]
+    fn from(s: Rc<str>) -> Self {
+        Self::new_flat(s)
+    }
+}
+
+impl From<String> for StringData {
+    #[inline]
+    fn from(s: String) -> Self {
+        Self::new_flat(s.into())
+    }
+}
+
+impl From<&str> for StringData {
+    #[inline]
+    fn from(s: &str) -> Self {
+        Self::new_flat(s.into())
+    }
+}
+
+impl From<std::borrow::Cow<'_, str>> for StringData {
+    #[inline]
+    fn from(s: std::borrow::Cow<'_, str>) -> Self {
+        Self::new_flat(s.into())
+    }
+}
+
+impl PartialEq for StringData {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.node, &other.node)
+            || (self.len() == other.len() && self.as_str() == other.as_str())
+    }
+}
+
+impl Eq for StringData {}
+
+impl std::fmt::Debug for StringData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl std::fmt::Display for StringData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self.as_str(), f)
+    }
+}
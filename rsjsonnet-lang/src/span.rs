@@ -1,4 +1,5 @@
 use std::num::NonZeroU64;
+use std::rc::Rc;
 
 use crate::FHashMap;
 
@@ -52,6 +53,13 @@ pub enum SpanContext {
 }
 
 pub struct SpanManager {
+    /// Shared by the copies made with [`SpanManager::fork`] until one of
+    /// them is modified.
+    inner: Rc<SpanManagerInner>,
+}
+
+#[derive(Clone)]
+struct SpanManagerInner {
     contexts: Vec<(u64, SpanContext)>,
     sources: Vec<SpanContextId>,
     // span interner
@@ -66,23 +74,7 @@ impl Default for SpanManager {
     }
 }
 
-impl SpanManager {
-    pub fn new() -> Self {
-        Self {
-            contexts: Vec::new(),
-            sources: Vec::new(),
-            span_to_idx: FHashMap::default(),
-            idx_to_span: Vec::new(),
-        }
-    }
-
-    pub fn insert_source_context(&mut self, len: usize) -> (SpanContextId, SourceId) {
-        let source_id = SourceId(self.sources.len());
-        let context_id = self.insert_context(len, SpanContext::Source(source_id));
-        self.sources.push(context_id);
-        (context_id, source_id)
-    }
-
+impl SpanManagerInner {
     fn insert_context(&mut self, len: usize, context: SpanContext) -> SpanContextId {
         let len = u64::try_from(len).unwrap();
         let base_offset = if let Some(&(last_end, _)) = self.contexts.last() {
@@ -94,25 +86,59 @@ impl SpanManager {
         self.contexts.push((base_offset + len + 1, context));
         context_id
     }
+}
+
+impl SpanManager {
+    pub fn new() -> Self {
+        Self {
+            inner: Rc::new(SpanManagerInner {
+                contexts: Vec::new(),
+                sources: Vec::new(),
+                span_to_idx: FHashMap::default(),
+                idx_to_span: Vec::new(),
+            }),
+        }
+    }
+
+    /// Creates a copy of the span manager, which shares its data with the
+    /// original until one of them is modified.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn insert_source_context(&mut self, len: usize) -> (SpanContextId, SourceId) {
+        let inner = Rc::make_mut(&mut self.inner);
+        let source_id = SourceId(inner.sources.len());
+        let context_id = inner.insert_context(len, SpanContext::Source(source_id));
+        inner.sources.push(context_id);
+        (context_id, source_id)
+    }
 
     #[must_use]
     pub fn get_context(&self, context: SpanContextId) -> &SpanContext {
-        &self.contexts[context.0].1
+        &self.inner.contexts[context.0].1
     }
 
     #[must_use]
     fn get_context_offsets(&self, context: SpanContextId) -> (u64, u64) {
+        let contexts = &self.inner.contexts;
         let i = context.0;
         if i == 0 {
-            (0, self.contexts[0].0)
+            (0, contexts[0].0)
         } else {
-            (self.contexts[i - 1].0, self.contexts[i].0)
+            (contexts[i - 1].0, contexts[i].0)
         }
     }
 
     #[must_use]
     fn get_context_from_offset(&self, offset: u64) -> SpanContextId {
-        match self.contexts.binary_search_by_key(&offset, |entry| entry.0) {
+        match self
+            .inner
+            .contexts
+            .binary_search_by_key(&offset, |entry| entry.0)
+        {
             Ok(i) => SpanContextId(i + 1),
             Err(i) => SpanContextId(i),
         }
@@ -131,14 +157,14 @@ impl SpanManager {
         let len = end_u64 - start_u64;
         if len > SpanId::LEN_MAX || start_offset >= SpanId::OFFSET_MASK {
             let span = (context, start, end);
-            let i = match self.span_to_idx.entry(span) {
-                std::collections::hash_map::Entry::Occupied(entry) => *entry.get(),
-                std::collections::hash_map::Entry::Vacant(entry) => {
-                    let i = self.idx_to_span.len();
-                    self.idx_to_span.push(span);
-                    entry.insert(i);
-                    i
-                }
+            let i = if let Some(&i) = self.inner.span_to_idx.get(&span) {
+                i
+            } else {
+                let inner = Rc::make_mut(&mut self.inner);
+                let i = inner.idx_to_span.len();
+                inner.idx_to_span.push(span);
+                inner.span_to_idx.insert(span, i);
+                i
             };
             SpanId(NonZeroU64::new((i as u64) | (1 << 63)).unwrap())
         } else {
@@ -155,7 +181,7 @@ impl SpanManager {
                 let start = (start_offset - min_offset) as usize;
                 (context, start, start + len)
             }
-            ExpandedSpanId::Interned(i) => self.idx_to_span[i],
+            ExpandedSpanId::Interned(i) => self.inner.idx_to_span[i],
         }
     }
 
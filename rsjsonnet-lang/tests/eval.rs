@@ -10,15 +10,21 @@
 use rsjsonnet_lang::arena::Arena;
 use rsjsonnet_lang::interner::InternedStr;
 use rsjsonnet_lang::program::{
-    EvalErrorKind, EvalStackTraceItem, ImportError, NativeError, Program, Thunk, Value,
+    AnalyzeError, EvalErrorKind, EvalErrorValueType, EvalStackTraceItem, FromJsonnet,
+    FromJsonnetError, ImportError, IntoJsonnet, LoadError, ManifestError, NativeError,
+    ParseJsonError, ParseTomlError, Program, SharedSourceCache, Thunk, Value,
 };
 use rsjsonnet_lang::span::SpanId;
 
-pub(crate) struct TestCallbacks;
+pub(crate) struct TestCallbacks {
+    ext_var_calls: Vec<String>,
+}
 
 impl TestCallbacks {
     pub(crate) fn new() -> Self {
-        Self
+        Self {
+            ext_var_calls: Vec::new(),
+        }
     }
 
     pub(crate) fn init_native_funcs(&mut self, program: &mut Program<'_>) {
@@ -32,6 +38,28 @@ impl TestCallbacks {
             &[program.intern_str("array")],
         );
         program.register_native_func(program.intern_str("failure"), &[]);
+        program.register_native_func(program.intern_str("badArgIndex"), &[]);
+        program.register_native_func(
+            program.intern_str("repeat"),
+            &[program.intern_str("str"), program.intern_str("n")],
+        );
+
+        let sep_default = program.value_to_thunk(&Value::string(","));
+        let prefix_code = b"std.repeat(\"#\", 2)";
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(prefix_code.len());
+        let prefix_default = program
+            .load_source(span_ctx, prefix_code, true, "<prefix>")
+            .unwrap();
+        program.register_native_func_with_params(
+            program.intern_str("join"),
+            &[
+                (program.intern_str("prefix"), Some(prefix_default)),
+                (program.intern_str("sep"), Some(sep_default)),
+            ],
+            Some(program.intern_str("items")),
+        );
     }
 }
 
@@ -66,9 +94,15 @@ impl<'p> rsjsonnet_lang::program::Callbacks<'p> for TestCallbacks {
     fn trace(&mut self, _program: &mut Program<'p>, _message: &str, _stack: &[EvalStackTraceItem]) {
     }
 
+    fn ext_var(&mut self, program: &mut Program<'p>, name: InternedStr<'p>) -> Option<Thunk<'p>> {
+        self.ext_var_calls.push(name.value().into());
+        let value = name.value().strip_prefix("lazy_")?;
+        Some(program.value_to_thunk(&Value::string(value)))
+    }
+
     fn native_call(
         &mut self,
-        _program: &mut Program<'p>,
+        program: &mut Program<'p>,
         name: InternedStr<'p>,
         args: &[Value<'p>],
     ) -> Result<Value<'p>, NativeError> {
@@ -89,15 +123,49 @@ impl<'p> rsjsonnet_lang::program::Callbacks<'p> for TestCallbacks {
                 };
                 if let Some(root_items) = arg.to_array() {
                     if let Some(first_items) = root_items.first().and_then(Value::to_array) {
-                        first_items.last().ok_or(NativeError).cloned()
+                        first_items.last().ok_or(NativeError::Failed).cloned()
                     } else {
-                        Err(NativeError)
+                        Err(NativeError::Failed)
                     }
                 } else {
-                    Err(NativeError)
+                    Err(NativeError::Failed)
                 }
             }
-            "failure" => Err(NativeError),
+            "failure" => Err(NativeError::Failed),
+            "badArgIndex" => Err(NativeError::InvalidArg {
+                arg_index: 5,
+                error: FromJsonnetError::InvalidValue {
+                    message: "bad".into(),
+                },
+            }),
+            "repeat" => {
+                let [s, n] = args else {
+                    unreachable!();
+                };
+                let s = String::from_jsonnet(s).map_err(|error| NativeError::InvalidArg {
+                    arg_index: 0,
+                    error,
+                })?;
+                let n = u32::from_jsonnet(n).map_err(|error| NativeError::InvalidArg {
+                    arg_index: 1,
+                    error,
+                })?;
+                Ok(vec![s; n as usize].into_jsonnet(program))
+            }
+            "join" => {
+                let [prefix, sep, items] = args else {
+                    unreachable!();
+                };
+                let prefix = prefix.to_string().ok_or(NativeError::Failed)?;
+                let sep = sep.to_string().ok_or(NativeError::Failed)?;
+                let items = Vec::<String>::from_jsonnet(items).map_err(|error| {
+                    NativeError::InvalidArg {
+                        arg_index: 2,
+                        error,
+                    }
+                })?;
+                Ok(format!("{prefix}{}", items.join(&sep)).into_jsonnet(program))
+            }
             _ => unreachable!(),
         }
     }
@@ -244,6 +312,156 @@ fn test_manifest_multi_line() {
     test(b"{a:: error \"err\"}", r"{ }");
 }
 
+#[test]
+fn test_manifest_formats() {
+    fn eval<'p>(program: &mut Program<'p>, input: &[u8]) -> Value<'p> {
+        let mut callbacks = TestCallbacks::new();
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, "test.jsonnet")
+            .unwrap();
+        program.eval_value(&thunk, &mut callbacks).unwrap()
+    }
+
+    // Each output must be the same as the equivalent `std.manifest*`
+    // function, both as a string and when written into a writer.
+    #[track_caller]
+    fn test(
+        input: &str,
+        std_call: &str,
+        manifest: impl for<'p> Fn(&mut Program<'p>, &Value<'p>) -> String,
+        manifest_to_writer: impl for<'p> Fn(&mut Program<'p>, &Value<'p>, &mut Vec<u8>),
+    ) {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+
+        let value = eval(&mut program, input.as_bytes());
+        let expected = eval(
+            &mut program,
+            format!("local v = {input}; {std_call}").as_bytes(),
+        );
+        let expected = expected.to_string().unwrap();
+
+        assert_eq!(manifest(&mut program, &value), expected);
+
+        let mut output = Vec::new();
+        manifest_to_writer(&mut program, &value, &mut output);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    let inputs = [
+        "{ a: 1, b: [1, 2, { c: 'x' }], d: {}, e: { f: [] } }",
+        // Large enough to be written in several parts
+        "{ a: std.range(0, 20000), b: { c: [{ d: i } for i in std.range(0, 5000)] } }",
+    ];
+    for input in inputs {
+        test(
+            input,
+            "std.manifestJsonEx(v, '  ', '\\n', ': ')",
+            |program, value| {
+                program
+                    .manifest_json_ex(value, "  ", "\n", ": ", ",")
+                    .unwrap()
+            },
+            |program, value, writer| {
+                program
+                    .manifest_json_ex_to_writer(value, "  ", "\n", ": ", ",", writer)
+                    .unwrap()
+            },
+        );
+        test(
+            input,
+            "std.manifestYamlDoc(v, true, false)",
+            |program, value| program.manifest_yaml(value, true, false).unwrap(),
+            |program, value, writer| {
+                program
+                    .manifest_yaml_to_writer(value, true, false, writer)
+                    .unwrap()
+            },
+        );
+        test(
+            &format!("[{input}, 1, []]"),
+            "std.manifestYamlStream(v, false, true, true)",
+            |program, value| {
+                program
+                    .manifest_yaml_stream(value, false, true, true)
+                    .unwrap()
+            },
+            |program, value, writer| {
+                program
+                    .manifest_yaml_stream_to_writer(value, false, true, true, writer)
+                    .unwrap()
+            },
+        );
+        test(
+            input,
+            "std.manifestTomlEx(v, '\\t')",
+            |program, value| program.manifest_toml(value, "\t").unwrap(),
+            |program, value, writer| {
+                program
+                    .manifest_toml_to_writer(value, "\t", writer)
+                    .unwrap()
+            },
+        );
+    }
+    test(
+        "{ main: { a: 1 }, sections: { s1: { b: [2, 3] }, s2: {} } }",
+        "std.manifestIni(v)",
+        |program, value| program.manifest_ini(value).unwrap(),
+        |program, value, writer| program.manifest_ini_to_writer(value, writer).unwrap(),
+    );
+
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = eval(&mut program, b"[1, 2]");
+    assert_eq!(
+        program.manifest_json_ex(&value, "", "", ":", ";").unwrap(),
+        "[1;2]",
+    );
+
+    let error = program.manifest_toml(&value, "  ").err().unwrap();
+    assert_eq!(
+        error.kind,
+        EvalErrorKind::Other {
+            span: None,
+            message: "toml root must be an object".into(),
+        },
+    );
+
+    let value = eval(&mut program, b"[{ a: 1, f: function() 1 }]");
+    let mut output = Vec::new();
+    let error = program
+        .manifest_json_to_writer(&value, false, &mut output)
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        ManifestError::Eval(ref e) if e.kind == EvalErrorKind::ManifestFunction,
+    ));
+
+    struct FailingWriter;
+
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("failing writer"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let value = eval(&mut program, b"std.range(0, 20000)");
+    let error = program
+        .manifest_yaml_to_writer(&value, false, false, &mut FailingWriter)
+        .err()
+        .unwrap();
+    assert!(matches!(error, ManifestError::Io(ref e) if e.to_string() == "failing writer"));
+}
+
 #[test]
 fn test_native() {
     #[track_caller]
@@ -278,9 +496,24 @@ fn test_native() {
     test(b"std.isFunction(std.native(\"returnNum\"))", "true");
     test(b"std.length(std.native(\"returnNum\"))", "0");
     test(b"std.length(std.native(\"isString\"))", "1");
+    test(b"std.native(\"repeat\")(\"a\", 3)", "[\"a\", \"a\", \"a\"]");
+    test(b"std.length(std.native(\"join\"))", "3");
+    test(b"std.native(\"join\")()", "\"##\"");
+    test(b"std.native(\"join\")(\"$\")", "\"$\"");
+    test(b"std.native(\"join\")(\"$\", \"-\")", "\"$\"");
+    test(
+        b"std.native(\"join\")(\"$\", \"-\", \"a\", \"b\", \"c\")",
+        "\"$a-b-c\"",
+    );
+    test(b"std.native(\"join\")(sep=\"-\")", "\"##\"");
+    test(b"std.native(\"join\")(\"\", sep=\"-\")", "\"\"");
+    test(
+        b"std.native(\"join\")(\"\", \"-\", \"a\", \"b\")",
+        "\"a-b\"",
+    );
 
     #[track_caller]
-    fn test_fail(input: &[u8]) {
+    fn test_fail(input: &[u8], expected: EvalErrorKind) {
         let arena = Arena::new();
         let mut program = Program::new(&arena);
         let mut callbacks = TestCallbacks::new();
@@ -295,10 +528,961 @@ fn test_native() {
             .unwrap();
 
         let error = program.eval_value(&thunk, &mut callbacks).err().unwrap();
-        assert_eq!(error.kind, EvalErrorKind::NativeCallFailed);
+        assert_eq!(error.kind, expected);
     }
 
-    test_fail(b"std.native(\"failure\")()");
+    #[track_caller]
+    fn test_fail_call(input: &[u8], check: impl FnOnce(&EvalErrorKind) -> bool) {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+        let mut callbacks = TestCallbacks::new();
+        callbacks.init_native_funcs(&mut program);
+
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+
+        let thunk = program
+            .load_source(span_ctx, input, true, "test.jsonnet")
+            .unwrap();
+
+        let error = program.eval_value(&thunk, &mut callbacks).err().unwrap();
+        assert!(check(&error.kind), "unexpected error: {:?}", error.kind);
+    }
+
+    test_fail_call(
+        b"std.native(\"join\")(items=[])",
+        |kind| matches!(kind, EvalErrorKind::UnknownCallParam { param_name, .. } if param_name == "items"),
+    );
+    test_fail_call(
+        b"std.native(\"join\")(\"\", prefix=\"\")",
+        |kind| matches!(kind, EvalErrorKind::RepeatedCallParam { param_name, .. } if param_name == "prefix"),
+    );
+    test_fail_call(b"std.native(\"repeat\")(\"a\", 1, 2)", |kind| {
+        matches!(kind, EvalErrorKind::TooManyCallArgs { num_params: 2, .. })
+    });
+
+    test_fail(
+        b"std.native(\"failure\")()",
+        EvalErrorKind::NativeCallFailed,
+    );
+    test_fail(
+        b"std.native(\"badArgIndex\")()",
+        EvalErrorKind::NativeCallFailed,
+    );
+    test_fail(
+        b"std.native(\"repeat\")(1, 3)",
+        EvalErrorKind::InvalidNativeFuncArg {
+            func_name: "repeat".into(),
+            param_name: "str".into(),
+            error: FromJsonnetError::InvalidType {
+                expected_types: vec![EvalErrorValueType::String],
+                got_type: EvalErrorValueType::Number,
+            },
+        },
+    );
+    test_fail(
+        b"std.native(\"join\")(\"\", \"\", 1)",
+        EvalErrorKind::InvalidNativeFuncArg {
+            func_name: "join".into(),
+            param_name: "items".into(),
+            error: FromJsonnetError::InvalidValue {
+                message: "array item 0: expected string, got number".into(),
+            },
+        },
+    );
+    test_fail(
+        b"std.native(\"repeat\")(\"a\", n=-1)",
+        EvalErrorKind::InvalidNativeFuncArg {
+            func_name: "repeat".into(),
+            param_name: "n".into(),
+            error: FromJsonnetError::InvalidValue {
+                message: "expected an integer between 0 and 4294967295, got -1".into(),
+            },
+        },
+    );
+}
+
+#[test]
+fn test_load_source_with_env() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    let config = program.value_to_thunk(&Value::number(2.0));
+    let cluster = program.value_to_thunk(&Value::string("prod"));
+    let env = [
+        (program.intern_str("config"), config),
+        (program.intern_str("cluster"), cluster),
+    ];
+
+    let input = b"[cluster, config * 3, std.length(cluster), local config = 1; config]";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source_with_env(span_ctx, input, true, "test.jsonnet", &env)
+        .unwrap();
+    let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, "[\"prod\", 6, 4, 1]");
+
+    // Without the standard library
+    let input = b"config";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source_with_env(span_ctx, input, false, "test.jsonnet", &env)
+        .unwrap();
+    let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+    assert_eq!(value.as_number(), Some(2.0));
+
+    // Variables are not available to other sources
+    let input = b"config";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let error = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        LoadError::Analyze(AnalyzeError::UnknownVariable { ref name, .. }) if name == "config",
+    ));
+}
+
+#[test]
+fn test_lazy_ext_vars() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    let thunk = program.value_to_thunk(&Value::number(1.0));
+    program.add_ext_var(program.intern_str("eager"), &thunk);
+
+    let input = b"[std.extVar(\"lazy_a\"), std.extVar(\"eager\"), std.extVar(\"lazy_b\"), std.extVar(\"lazy_a\")]";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+
+    let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, "[\"a\", 1, \"b\", \"a\"]");
+    assert_eq!(callbacks.ext_var_calls, ["lazy_a", "lazy_b"]);
+
+    let input = b"std.extVar(\"unknown\")";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+
+    let error = program.eval_value(&thunk, &mut callbacks).err().unwrap();
+    assert_eq!(
+        error.kind,
+        EvalErrorKind::UnknownExtVar {
+            name: "unknown".into(),
+        },
+    );
+    assert_eq!(callbacks.ext_var_calls, ["lazy_a", "lazy_b", "unknown"]);
+
+    let requested: Vec<_> = program
+        .requested_ext_vars()
+        .iter()
+        .map(|name| name.value())
+        .collect();
+    assert_eq!(requested, ["lazy_a", "eager", "lazy_b", "unknown"]);
+
+    // Both added and resolved variables can be replaced.
+    let thunk = program.value_to_thunk(&Value::number(2.0));
+    program.add_ext_var(program.intern_str("eager"), &thunk);
+    let thunk = program.value_to_thunk(&Value::number(3.0));
+    program.add_ext_var(program.intern_str("lazy_a"), &thunk);
+
+    let input = b"[std.extVar(\"lazy_a\"), std.extVar(\"eager\")]";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+
+    let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, "[3, 2]");
+    assert_eq!(callbacks.ext_var_calls, ["lazy_a", "lazy_b", "unknown"]);
+}
+
+#[test]
+fn test_lazy_arrays_gc() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    let input = b"local a = std.range(1, 100);
+local r = [a + a, a[10:60:2], (a + [0] + a)[95:105]];
+assert std.length(r[0] + r[1] + r[2]) == 235;
+r";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+    let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+    drop(thunk);
+
+    // The concatenated and sliced arrays have not been flattened yet, so
+    // they must keep their operands alive.
+    program.gc();
+
+    let items = Vec::<Vec<f64>>::from_jsonnet(&value).unwrap();
+    let [concat, slice, concat_slice] = items.as_slice() else {
+        panic!("unexpected number of items");
+    };
+    let range: Vec<f64> = (1..=100).map(f64::from).collect();
+    assert_eq!(*concat, [range.as_slice(), range.as_slice()].concat());
+    assert_eq!(
+        *slice,
+        range[10..60].iter().step_by(2).copied().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        *concat_slice,
+        [96.0, 97.0, 98.0, 99.0, 100.0, 0.0, 1.0, 2.0, 3.0, 4.0]
+    );
+}
+
+#[test]
+fn test_stdlib_fields() {
+    #[track_caller]
+    fn test(customize: impl Fn(&mut Program<'_>), input: &[u8], expected: &str) {
+        // Optimized sources must not assume that `std` fields are built-in.
+        for optimize in [false, true] {
+            let arena = Arena::new();
+            let mut program = Program::new(&arena);
+            program.set_optimize(optimize);
+            let mut callbacks = TestCallbacks::new();
+            callbacks.init_native_funcs(&mut program);
+            customize(&mut program);
+
+            let (span_ctx, _) = program
+                .span_manager_mut()
+                .insert_source_context(input.len());
+
+            let root_thunk = program
+                .load_source(span_ctx, input, true, "test.jsonnet")
+                .unwrap();
+
+            let value = program.eval_value(&root_thunk, &mut callbacks).unwrap();
+            let value_str = program.manifest_json(&value, false).unwrap();
+            assert_eq!(value_str, expected);
+        }
+    }
+
+    fn add_code_field(program: &mut Program<'_>, name: &str, code: &[u8]) {
+        let (span_ctx, _) = program.span_manager_mut().insert_source_context(code.len());
+        let thunk = program.load_source(span_ctx, code, true, name).unwrap();
+        program.add_stdlib_field(program.intern_str(name), &thunk);
+    }
+
+    test(
+        |program| add_code_field(program, "myorg", b"{ twice(x):: [x, x] }"),
+        b"std.myorg.twice(1)",
+        "[1, 1]",
+    );
+    test(
+        |program| {
+            let func = program
+                .get_native_func(program.intern_str("repeat"))
+                .unwrap();
+            let thunk = program.value_to_thunk(&func);
+            program.add_stdlib_field(program.intern_str("repeat"), &thunk);
+        },
+        b"std.repeat(\"a\", 2)",
+        "[\"a\", \"a\"]",
+    );
+    test(
+        |program| {
+            let thunk = program.value_to_thunk(&Value::number(2.0));
+            program.add_stdlib_field(program.intern_str("pi"), &thunk);
+        },
+        b"[std.pi, std.objectHas(std, \"pi\"), std.objectHasAll(std, \"pi\")]",
+        "[2, false, true]",
+    );
+    // Overriding a field also affects the Jsonnet part of the stdlib.
+    test(
+        |program| add_code_field(program, "isNumber", b"function(x) false"),
+        b"std.isNumber(1)",
+        "false",
+    );
+    test(
+        |program| {
+            program.remove_stdlib_field(program.intern_str("extVar"));
+            program.remove_stdlib_field(program.intern_str("thisFile"));
+            program.remove_stdlib_field(program.intern_str("abs"));
+            program.remove_stdlib_field(program.intern_str("unknown"));
+        },
+        b"[std.objectHasAll(std, x) for x in [\"extVar\", \"thisFile\", \"abs\", \"unknown\", \"sign\"]]",
+        "[false, false, false, false, true]",
+    );
+    test(
+        |program| {
+            program.remove_stdlib_field(program.intern_str("extVar"));
+            let thunk = program.value_to_thunk(&Value::null());
+            program.add_stdlib_field(program.intern_str("extVar"), &thunk);
+        },
+        b"std.extVar",
+        "null",
+    );
+    test(
+        |program| add_code_field(program, "length", b"function(x) 42"),
+        b"[std.length(\"abc\"), std.type(1)]",
+        "[42, \"number\"]",
+    );
+    test(
+        |program| program.remove_stdlib_field(program.intern_str("length")),
+        b"std.objectHasAll(std, \"length\")",
+        "false",
+    );
+    test(
+        |program| program.remove_stdlib_field(program.intern_str("abs")),
+        b"[std.member(std.objectFieldsAll(std), \"abs\"), std.objectHasAll(std + { abs: 1 }, \"abs\")]",
+        "[false, true]",
+    );
+}
+
+#[test]
+fn test_source_cache() {
+    let cache_dir = std::env::temp_dir().join(format!(
+        "rsjsonnet-test-source-cache-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let input: &[u8] = br#"
+        local f(x, y=2) = x * y;
+        local obj = { a: 1, b:: self.a + 1, [std.toString(3)]: f(3), assert self.a > 0 };
+        local arr = [x + 1 for x in std.range(1, 5) if x % 2 == 1];
+        {
+            obj: obj + { a+: 10, c: super.b, d: "b" in super },
+            arr: arr[1:] + arr[::2],
+            comp: { [k]: obj[k] for k in std.objectFields(obj) },
+            s: "%s-%d" % ["a", 1],
+            neg: -f(1) + ~1,
+            cond: if !false then std.length(arr) else error "unreachable",
+            len: std.length("abc"),
+            this: std.thisFile,
+        }
+    "#;
+
+    let run = |optimize: bool, this_file: &str| {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+        program.set_optimize(optimize);
+        program.set_source_cache(Some(cache_dir.clone()));
+        let mut callbacks = TestCallbacks::new();
+
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, this_file)
+            .unwrap();
+        let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+        program.manifest_json(&value, false).unwrap()
+    };
+
+    let expected = r#"{"arr": [4, 6, 2, 6], "comp": {"3": 6, "a": 1}, "cond": 3, "len": 3, "neg": -4, "obj": {"3": 6, "a": 11, "c": 12, "d": true}, "s": "a-1", "this": "a.jsonnet"}"#;
+    for optimize in [false, true] {
+        // The first run fills the cache and the second one reads it.
+        assert_eq!(run(optimize, "a.jsonnet"), expected);
+        assert_eq!(run(optimize, "a.jsonnet"), expected);
+        // `std.thisFile` is not part of the cached data.
+        assert!(run(optimize, "b.jsonnet").contains(r#""this": "b.jsonnet""#));
+    }
+
+    let entries: Vec<_> = std::fs::read_dir(&cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    // One entry with and one without optimization.
+    assert_eq!(entries.len(), 2);
+
+    // Corrupted entries are ignored and replaced.
+    for path in entries.iter() {
+        let mut data = std::fs::read(path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(path, data).unwrap();
+    }
+    assert_eq!(run(false, "a.jsonnet"), expected);
+    assert_eq!(run(false, "a.jsonnet"), expected);
+
+    // Errors are reported with the same spans when loaded from the cache.
+    let input: &[u8] = b"local x = 1;\n{ a: x + error 'e' }.a";
+    let eval_error = || {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+        program.set_source_cache(Some(cache_dir.clone()));
+        let mut callbacks = TestCallbacks::new();
+
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, "error.jsonnet")
+            .unwrap();
+        let Err(error) = program.eval_value(&thunk, &mut callbacks) else {
+            panic!("expected error");
+        };
+        error
+            .stack_trace
+            .iter()
+            .map(|item| {
+                let span = match *item {
+                    EvalStackTraceItem::Expr { span } => Some(span),
+                    EvalStackTraceItem::Call { span, .. } => span,
+                    EvalStackTraceItem::ObjectField { span, .. } => span,
+                    _ => None,
+                };
+                span.map(|span| program.span_manager().get_span(span))
+                    .map(|(_, start, end)| (start, end))
+            })
+            .collect::<Vec<_>>()
+    };
+    let uncached = eval_error();
+    assert!(!uncached.is_empty());
+    assert_eq!(eval_error(), uncached);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_shared_source_cache() {
+    let cache = SharedSourceCache::new();
+    let input: &[u8] = b"local f(x) = x * 2; { a: f(1), b: [f(x) for x in std.range(1, 3)] }";
+
+    let run = |cache: SharedSourceCache, optimize: bool| {
+        let arena = Arena::new();
+        let mut program = Program::new(&arena);
+        program.set_optimize(optimize);
+        program.set_shared_source_cache(Some(cache));
+        let mut callbacks = TestCallbacks::new();
+
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, "a.jsonnet")
+            .unwrap();
+        let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+        program.manifest_json(&value, false).unwrap()
+    };
+
+    let expected = r#"{"a": 2, "b": [2, 4, 6]}"#;
+    for optimize in [false, true] {
+        // The first run fills the cache and the other threads read it.
+        assert_eq!(run(cache.clone(), optimize), expected);
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| run(cache.clone(), optimize)))
+                .collect();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), expected);
+            }
+        });
+    }
+}
+
+#[test]
+fn test_stdlib_shared() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    let mut eval = |program: &mut Program<'_>, input: &[u8], this_file: &str| {
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, this_file)
+            .unwrap();
+        let value = program.eval_value(&thunk, &mut callbacks).unwrap();
+        program.manifest_json(&value, false).unwrap()
+    };
+
+    let input = b"[std.thisFile, std.round(1.4), (std + { floor(x): 42 }).round(1)]";
+    assert_eq!(
+        eval(&mut program, input, "a.jsonnet"),
+        r#"["a.jsonnet", 1, 42]"#
+    );
+    assert_eq!(
+        eval(&mut program, input, "b.jsonnet"),
+        r#"["b.jsonnet", 1, 42]"#
+    );
+
+    // Sources loaded after modifying the standard library see the change.
+    let code = b"function(x) 10";
+    let (span_ctx, _) = program.span_manager_mut().insert_source_context(code.len());
+    let thunk = program.load_source(span_ctx, code, false, "").unwrap();
+    program.add_stdlib_field(program.intern_str("floor"), &thunk);
+    assert_eq!(
+        eval(&mut program, input, "c.jsonnet"),
+        r#"["c.jsonnet", 10, 42]"#
+    );
+
+    program.remove_stdlib_field(program.intern_str("thisFile"));
+    assert_eq!(
+        eval(
+            &mut program,
+            b"std.objectHasAll(std, 'thisFile')",
+            "d.jsonnet"
+        ),
+        "false",
+    );
+}
+
+#[test]
+fn test_fork() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    fn eval<'p>(
+        program: &mut Program<'p>,
+        callbacks: &mut TestCallbacks,
+        lib: &Thunk<'p>,
+        input: &[u8],
+    ) -> String {
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let env = [(program.intern_str("lib"), lib.clone())];
+        let thunk = program
+            .load_source_with_env(span_ctx, input, true, "main.jsonnet", &env)
+            .unwrap();
+        let value = program.eval_value(&thunk, callbacks).unwrap();
+        program.manifest_json(&value, false).unwrap()
+    }
+
+    let lib_code = br#"
+        {
+            local lib = self,
+            base: 10,
+            scaled(n): lib.base * n,
+            ext: std.extVar("a"),
+            ext2: std.extVar("a") + "!",
+            arr: std.range(1, 40) + std.range(1, 40),
+            sum: std.foldl(function(acc, x) acc + x, self.arr, 0),
+        }
+    "#;
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(lib_code.len());
+    let lib = program
+        .load_source(span_ctx, lib_code, true, "lib.jsonnet")
+        .unwrap();
+    assert_eq!(eval(&mut program, &mut callbacks, &lib, b"lib.sum"), "1640");
+
+    let input = b"[lib.ext, lib.scaled(2), lib.sum, (lib + { base: 1 }).scaled(2), lib.arr[79]]";
+    let mut forks = Vec::new();
+    for ext_value in ["one", "two"] {
+        let mut fork = program.fork();
+        let ext_thunk = fork.value_to_thunk(&Value::string(ext_value));
+        fork.add_ext_var(fork.intern_str("a"), &ext_thunk);
+        fork.gc();
+        forks.push(fork);
+    }
+    for (fork, ext_value) in forks.iter_mut().zip(["one", "two"]) {
+        assert_eq!(
+            eval(fork, &mut callbacks, &lib, input),
+            format!(r#"["{ext_value}", 20, 1640, 2, 40]"#),
+        );
+    }
+
+    // A copy of a copy keeps the values evaluated by the latter.
+    let mut fork = forks[0].fork();
+    assert_eq!(
+        eval(&mut fork, &mut callbacks, &lib, b"lib.ext"),
+        r#""one""#
+    );
+    drop(fork);
+    drop(forks);
+
+    // The original program is not affected by its copies.
+    let ext_thunk = program.value_to_thunk(&Value::string("zero"));
+    program.add_ext_var(program.intern_str("a"), &ext_thunk);
+    assert_eq!(
+        eval(&mut program, &mut callbacks, &lib, input),
+        r#"["zero", 20, 1640, 2, 40]"#,
+    );
+
+    // A copy can replace external variables inherited from the original,
+    // and it does not see values evaluated by the original after forking.
+    let input = b"[std.extVar(\"a\"), lib.ext2]";
+    let mut fork = program.fork();
+    assert_eq!(
+        eval(&mut program, &mut callbacks, &lib, input),
+        r#"["zero", "zero!"]"#,
+    );
+    let ext_thunk = fork.value_to_thunk(&Value::string("three"));
+    fork.add_ext_var(fork.intern_str("a"), &ext_thunk);
+    assert_eq!(
+        eval(&mut fork, &mut callbacks, &lib, input),
+        r#"["three", "three!"]"#,
+    );
+    drop(fork);
+    assert_eq!(
+        eval(&mut program, &mut callbacks, &lib, input),
+        r#"["zero", "zero!"]"#,
+    );
+
+    // External variables provided by callbacks are not copied.
+    let input = b"std.extVar(\"lazy_x\")";
+    callbacks.ext_var_calls.clear();
+    assert_eq!(eval(&mut program, &mut callbacks, &lib, input), r#""x""#);
+    let mut fork = program.fork();
+    assert_eq!(eval(&mut fork, &mut callbacks, &lib, input), r#""x""#);
+    assert_eq!(callbacks.ext_var_calls, ["lazy_x", "lazy_x"]);
+}
+
+#[test]
+fn test_eval_object_fields() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+    let mut callbacks = TestCallbacks::new();
+
+    let input = br#"
+        local obj = { a: 1, b:: 2, c: error "c", d: self.a + 1 };
+        {
+            obj: obj,
+            func: function(x) obj { e: x },
+            arr: [1, 2],
+            bad: { a: 1, assert false },
+        }
+    "#;
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+
+    let root_fields = program
+        .eval_object_fields(&thunk, &mut callbacks)
+        .unwrap()
+        .unwrap();
+    let names: Vec<_> = root_fields.iter().map(|(name, _)| name.value()).collect();
+    assert_eq!(names, ["arr", "bad", "func", "obj"]);
+    let [ref arr, ref bad, ref func, ref obj] = root_fields[..] else {
+        unreachable!();
+    };
+
+    // Hidden fields are skipped and visible fields are not evaluated.
+    let fields = program
+        .eval_object_fields(&obj.1, &mut callbacks)
+        .unwrap()
+        .unwrap();
+    let names: Vec<_> = fields.iter().map(|(name, _)| name.value()).collect();
+    assert_eq!(names, ["a", "c", "d"]);
+    let d_value = program.eval_value(&fields[2].1, &mut callbacks).unwrap();
+    assert_eq!(d_value.as_number(), Some(2.0));
+    assert!(program.eval_value(&fields[1].1, &mut callbacks).is_err());
+
+    let arg = program.value_to_thunk(&Value::number(3.0));
+    let fields = program
+        .eval_call_object_fields(&func.1, &[arg], &[], &mut callbacks)
+        .unwrap()
+        .unwrap();
+    let names: Vec<_> = fields.iter().map(|(name, _)| name.value()).collect();
+    assert_eq!(names, ["a", "c", "d", "e"]);
+
+    assert!(
+        program
+            .eval_object_fields(&arr.1, &mut callbacks)
+            .unwrap()
+            .is_none()
+    );
+    assert!(program.eval_object_fields(&bad.1, &mut callbacks).is_err());
+}
+
+#[test]
+fn test_parse_json() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = program
+        .parse_json(r#"{"a": [1, 2.5, true, null], "b": {"c": "\u00e9"}}"#)
+        .unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, r#"{"a": [1, 2.5, true, null], "b": {"c": "é"}}"#);
+
+    let value = program.parse_json(" \"x\" ").unwrap();
+    assert_eq!(value.to_string().as_deref(), Some("x"));
+
+    let Err(error) = program.parse_json("[1,\n  2,\n  ]") else {
+        panic!("expected error");
+    };
+    assert_eq!(
+        error,
+        ParseJsonError {
+            line: 3,
+            column: 3,
+            offset: 11,
+            message: "expected value".into(),
+        },
+    );
+    assert_eq!(error.to_string(), "line 3, column 3: expected value");
+
+    let Err(error) = program.parse_json(r#"{"a": 1, "a": 2}"#) else {
+        panic!("expected error");
+    };
+    assert_eq!((error.line, error.column, error.offset), (1, 16, 15));
+    assert_eq!(error.message, "repeated field name \"a\"");
+
+    let Err(error) = program.parse_json("1 2") else {
+        panic!("expected error");
+    };
+    assert_eq!((error.line, error.column, error.offset), (1, 3, 2));
+    assert_eq!(error.message, "expected end-of-file");
+}
+
+#[test]
+fn test_parse_yaml() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = program.parse_yaml("a: [1, x]\nb: {c: null}\n").unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, r#"{"a": [1, "x"], "b": {"c": null}}"#);
+
+    let value = program.parse_yaml("--- 1\n--- 2\n").unwrap();
+    let value_str = program.manifest_json(&value, false).unwrap();
+    assert_eq!(value_str, "[1, 2]");
+
+    let Err(error) = program.parse_yaml("a: !tag 1") else {
+        panic!("expected error");
+    };
+    assert_eq!(error.message, "tags are not allowed");
+}
+
+#[test]
+fn test_parse_toml() {
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let mut test_ok = |input: &str, expected: &str| {
+        let value = match program.parse_toml(input) {
+            Ok(value) => value,
+            Err(e) => panic!("failed to parse {input:?}: {e}"),
+        };
+        let value_str = program.manifest_json(&value, false).unwrap();
+        assert_eq!(value_str, expected, "input: {input:?}");
+    };
+
+    test_ok("", "{ }");
+    test_ok("\u{FEFF}a = 2000-02-29", r#"{"a": "2000-02-29"}"#);
+    test_ok("# comment\n\n  \n", "{ }");
+    test_ok("a = 1\r\nb = -2 # comment\n", r#"{"a": 1, "b": -2}"#);
+    test_ok(
+        "a = 1_000\nb = 0xDEAD_beef\nc = 0o17\nd = 0b101\ne = +5",
+        r#"{"a": 1000, "b": 3735928559, "c": 15, "d": 5, "e": 5}"#,
+    );
+    test_ok(
+        "a = 1.5\nb = -1e3\nc = 2E-2\nd = 6.25e-2\ne = 1_0.0_1",
+        r#"{"a": 1.5, "b": -1000, "c": 0.02, "d": 0.0625, "e": 10.01}"#,
+    );
+    test_ok(
+        "a = 9007199254740992\nb = -9007199254740992",
+        r#"{"a": 9007199254740992, "b": -9007199254740992}"#,
+    );
+    test_ok("a = true\nb = false", r#"{"a": true, "b": false}"#);
+    test_ok(
+        r#"a = "x\ty\u00E9\U0001F600\"\\""#,
+        r#"{"a": "x\tyé😀\"\\"}"#,
+    );
+    test_ok(r"a = 'C:\path\x'", r#"{"a": "C:\\path\\x"}"#);
+    test_ok(
+        "a = \"\"\"\nline 1\nline 2\\\n    continued\"\"\"",
+        r#"{"a": "line 1\nline 2continued"}"#,
+    );
+    test_ok("a = \"\"\"x\"\"\"\"\"", r#"{"a": "x\"\""}"#);
+    test_ok("a = '''\nraw \\n\n'''", r#"{"a": "raw \\n\n"}"#);
+    test_ok(
+        "a = 1979-05-27T07:32:00Z\nb = 1979-05-27 07:32:00.999-07:00\nc = 1979-05-27\nd = 07:32:00",
+        r#"{"a": "1979-05-27T07:32:00Z", "b": "1979-05-27 07:32:00.999-07:00", "c": "1979-05-27", "d": "07:32:00"}"#,
+    );
+    test_ok(
+        "a = [\n  1,\n  # comment\n  [2, 'x'],\n  { b = 3 },\n]\nc = []",
+        r#"{"a": [1, [2, "x"], {"b": 3}], "c": [ ]}"#,
+    );
+    test_ok(
+        "a.b.c = 1\na.b.d = 2\n\"x.y\" = 3\n'' = 4",
+        r#"{"": 4, "a": {"b": {"c": 1, "d": 2}}, "x.y": 3}"#,
+    );
+    test_ok(
+        "a = { b.c = 1, d = {} }",
+        r#"{"a": {"b": {"c": 1}, "d": { }}}"#,
+    );
+    test_ok(
+        "[a.b]\nc = 1\n[a]\nd = 2\n[a.e]",
+        r#"{"a": {"b": {"c": 1}, "d": 2, "e": { }}}"#,
+    );
+    test_ok(
+        "[[a]]\nb = 1\n[[a.c]]\nd = 2\n[[a]]\n[[a.c]]\n[[a.c]]\nd = 3",
+        r#"{"a": [{"b": 1, "c": [{"d": 2}]}, {"c": [{ }, {"d": 3}]}]}"#,
+    );
+    test_ok(
+        "[fruit]\napple.color = 'red'\n[fruit.apple.texture]\nsmooth = true",
+        r#"{"fruit": {"apple": {"color": "red", "texture": {"smooth": true}}}}"#,
+    );
+
+    let mut test_err = |input: &str, expected: ParseTomlError| {
+        let Err(error) = program.parse_toml(input) else {
+            panic!("expected error for {input:?}");
+        };
+        assert_eq!(error, expected, "input: {input:?}");
+    };
+    let err = |line: usize, column: usize, offset: usize, message: &str| ParseTomlError {
+        line,
+        column,
+        offset,
+        message: message.into(),
+    };
+
+    test_err("a = ", err(1, 5, 4, "expected value"));
+    test_err("a = 1 2", err(1, 7, 6, "expected end of line"));
+    test_err("a = 1\na = 2", err(2, 1, 6, "repeated key \"a\""));
+    test_err("a = 1\n[a]", err(2, 2, 7, "repeated key \"a\""));
+    test_err("[a]\n[a]", err(2, 2, 5, "repeated key \"a\""));
+    test_err("a = {}\n[a.b]", err(2, 2, 8, "cannot extend \"a\""));
+    test_err("a = {}\na.b = 1", err(2, 1, 7, "cannot extend \"a\""));
+    test_err("a = []\n[[a]]", err(2, 3, 9, "cannot extend \"a\""));
+    test_err("[a.b]\n[a]\nb.c = 1", err(3, 1, 10, "cannot extend \"b\""));
+    test_err("a = 01", err(1, 5, 4, "invalid number"));
+    test_err("a = 1__0", err(1, 5, 4, "invalid number"));
+    test_err("a = 0x_ff", err(1, 5, 4, "invalid number"));
+    test_err("a = 1.", err(1, 5, 4, "invalid number"));
+    test_err(
+        "a = 99999999999999999999",
+        err(
+            1,
+            5,
+            4,
+            "integer out of range (must be between -2^53 and 2^53)",
+        ),
+    );
+    test_err(
+        "a = 9007199254740993",
+        err(
+            1,
+            5,
+            4,
+            "integer out of range (must be between -2^53 and 2^53)",
+        ),
+    );
+    test_err("a = -0x20000000000001", err(1, 5, 4, "invalid number"));
+    test_err(
+        "a = 0x20000000000001",
+        err(
+            1,
+            5,
+            4,
+            "integer out of range (must be between -2^53 and 2^53)",
+        ),
+    );
+    test_err("a = 1e400", err(1, 5, 4, "number overflow"));
+    test_err(
+        "a = inf",
+        err(1, 5, 4, "infinite and NaN numbers are not supported"),
+    );
+    test_err("a = 1979-13-01", err(1, 5, 4, "invalid date or time"));
+    test_err("a = 1979-02-29", err(1, 5, 4, "invalid date or time"));
+    test_err(
+        "a = 1979-05-27T24:00:00",
+        err(1, 5, 4, "invalid date or time"),
+    );
+    test_err("a = 1979-05-27T07:32", err(1, 5, 4, "invalid date or time"));
+    test_err("a = 1979-05-27Tx", err(1, 5, 4, "invalid date or time"));
+    test_err("a = 07:32:00+01:00", err(1, 13, 12, "expected end of line"));
+    test_err("a = \"\\x\"", err(1, 6, 5, "invalid string escape"));
+    test_err("a = \"x", err(1, 5, 4, "expected `\"`"));
+    test_err("a = \"x\ny\"", err(1, 7, 6, "invalid character"));
+    test_err("a = [1 2]", err(1, 8, 7, "expected `]` or `,`"));
+    test_err("a = { b = 1, }", err(1, 14, 13, "expected key"));
+    test_err("[a", err(1, 3, 2, "expected `]`"));
+    test_err("ä = 1", err(1, 1, 0, "expected key"));
+    test_err("x = 'ä' 1", err(1, 9, 9, "expected end of line"));
+
+    let deep = "a = ".to_string() + &"[".repeat(1000) + &"]".repeat(1000);
+    let Err(error) = program.parse_toml(&deep) else {
+        panic!("expected error");
+    };
+    assert_eq!(error.message, "too many nested tables or arrays");
+}
+
+#[test]
+fn test_convert() {
+    use std::collections::HashMap;
+
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = vec![Some(1u8), None].into_jsonnet(&mut program);
+    assert_eq!(program.manifest_json(&value, false).unwrap(), "[1, null]");
+    assert_eq!(
+        Vec::<Option<u8>>::from_jsonnet(&value),
+        Ok(vec![Some(1), None]),
+    );
+    assert_eq!(
+        Vec::<u8>::from_jsonnet(&value),
+        Err(FromJsonnetError::InvalidValue {
+            message: "array item 1: expected number, got null".into(),
+        }),
+    );
+    assert_eq!(
+        Option::<String>::from_jsonnet(&value),
+        Err(FromJsonnetError::InvalidType {
+            expected_types: vec![EvalErrorValueType::String, EvalErrorValueType::Null],
+            got_type: EvalErrorValueType::Array,
+        }),
+    );
+
+    let value = HashMap::from([("a".to_string(), 1.5)]).into_jsonnet(&mut program);
+    assert_eq!(
+        program.manifest_json(&value, false).unwrap(),
+        "{\"a\": 1.5}"
+    );
+    assert_eq!(
+        HashMap::<String, f64>::from_jsonnet(&value),
+        Ok(HashMap::from([("a".to_string(), 1.5)])),
+    );
+    assert_eq!(
+        HashMap::<String, i64>::from_jsonnet(&value),
+        Err(FromJsonnetError::InvalidValue {
+            message: "field \"a\": expected an integer between -9223372036854775808 and \
+                      9223372036854775807, got 1.5"
+                .into(),
+        }),
+    );
+
+    assert_eq!(
+        i64::from_jsonnet(&Value::number(-(2f64.powi(63)))),
+        Ok(i64::MIN)
+    );
+    assert!(i64::from_jsonnet(&Value::number(2f64.powi(63))).is_err());
+    assert_eq!(
+        u64::from_jsonnet(&Value::number(2f64.powi(63))),
+        Ok(1 << 63)
+    );
+    assert!(u64::from_jsonnet(&Value::number(2f64.powi(64))).is_err());
+    assert_eq!(bool::from_jsonnet(&Value::bool(true)), Ok(true));
 }
 
 #[test]
@@ -321,3 +1505,170 @@ fn test_native_panic_repeated_param() {
     let param_name = program.intern_str("param");
     program.register_native_func(func_name, &[param_name, param_name]);
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_deserialize() {
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Config {
+        name: String,
+        replicas: u32,
+        ratio: f64,
+        enabled: bool,
+        tags: Vec<String>,
+        limits: BTreeMap<String, i64>,
+        owner: Option<String>,
+        mode: Mode,
+        ports: Vec<Port>,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    enum Mode {
+        Fast,
+        Slow { delay: u8 },
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Port(u16, String);
+
+    #[track_caller]
+    fn eval<'p>(program: &mut Program<'p>, input: &[u8]) -> Value<'p> {
+        let mut callbacks = TestCallbacks::new();
+        let (span_ctx, _) = program
+            .span_manager_mut()
+            .insert_source_context(input.len());
+        let thunk = program
+            .load_source(span_ctx, input, true, "test.jsonnet")
+            .unwrap();
+        program.eval_value(&thunk, &mut callbacks).unwrap()
+    }
+
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = eval(
+        &mut program,
+        br#"{
+            name: "app",
+            replicas: 1 + 2,
+            ratio: 0.5,
+            enabled: true,
+            tags: ["a", "b"],
+            limits: { cpu: 2, mem: -1 },
+            owner: null,
+            mode: "Fast",
+            ports: [[80, "http"]],
+            hidden:: error "not evaluated",
+        }"#,
+    );
+    let config: Config = program.deserialize(&value).unwrap();
+    assert_eq!(
+        config,
+        Config {
+            name: "app".into(),
+            replicas: 3,
+            ratio: 0.5,
+            enabled: true,
+            tags: vec!["a".into(), "b".into()],
+            limits: BTreeMap::from([("cpu".into(), 2), ("mem".into(), -1)]),
+            owner: None,
+            mode: Mode::Fast,
+            ports: vec![Port(80, "http".into())],
+        },
+    );
+
+    let value = eval(&mut program, br#"{ Slow: { delay: 7 } }"#);
+    let mode: Mode = program.deserialize(&value).unwrap();
+    assert_eq!(mode, Mode::Slow { delay: 7 });
+
+    let value = eval(&mut program, br#"{ a: [{ b: 1 }, { b: 1.5 }] }"#);
+    let err = program
+        .deserialize::<BTreeMap<String, Vec<BTreeMap<String, u32>>>>(&value)
+        .unwrap_err();
+    assert_eq!(err.path(), ".a[1].b");
+    assert_eq!(
+        err.to_string(),
+        "invalid type: floating point `1.5`, expected u32 at `.a[1].b`",
+    );
+
+    let value = eval(&mut program, b"-1");
+    let err = program.deserialize::<u8>(&value).unwrap_err();
+    assert_eq!(err.path(), "");
+    assert_eq!(err.message(), "invalid value: integer `-1`, expected u8",);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serialize() {
+    use std::collections::BTreeMap;
+
+    #[derive(serde::Serialize)]
+    struct Config {
+        name: String,
+        replicas: u32,
+        tags: Vec<&'static str>,
+        labels: BTreeMap<u32, bool>,
+        owner: Option<String>,
+        mode: Mode,
+        unit: (),
+    }
+
+    #[derive(serde::Serialize)]
+    enum Mode {
+        Slow { delay: u8 },
+    }
+
+    let arena = Arena::new();
+    let mut program = Program::new(&arena);
+
+    let value = program
+        .serialize(&Config {
+            name: "app".into(),
+            replicas: 3,
+            tags: vec!["a", "b"],
+            labels: BTreeMap::from([(1, true)]),
+            owner: None,
+            mode: Mode::Slow { delay: 7 },
+            unit: (),
+        })
+        .unwrap();
+    assert_eq!(
+        program.manifest_json(&value, false).unwrap(),
+        r#"{"labels": {"1": true}, "mode": {"Slow": {"delay": 7}}, "name": "app", "owner": null, "replicas": 3, "tags": ["a", "b"], "unit": null}"#,
+    );
+
+    // Serialized values can be used as external variables.
+    let thunk = program.value_to_thunk(&value);
+    program.add_ext_var(program.intern_str("cfg"), &thunk);
+    let input = b"std.extVar('cfg').mode.Slow.delay * 2";
+    let (span_ctx, _) = program
+        .span_manager_mut()
+        .insert_source_context(input.len());
+    let thunk = program
+        .load_source(span_ctx, input, true, "test.jsonnet")
+        .unwrap();
+    let value = program
+        .eval_value(&thunk, &mut TestCallbacks::new())
+        .unwrap();
+    assert_eq!(value.as_number(), Some(14.0));
+
+    let err = program.serialize(&f64::NAN).err().unwrap();
+    assert_eq!(err.to_string(), "cannot serialize non-finite number NaN");
+
+    let value = program.serialize(&-(1i64 << 53)).unwrap();
+    assert_eq!(value.as_number(), Some(-(2f64.powi(53))));
+    let err = program.serialize(&((1u64 << 53) + 1)).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "cannot serialize integer 9007199254740993 exactly as a number",
+    );
+    assert!(program.serialize(&i128::MIN).is_err());
+
+    let err = program
+        .serialize(&BTreeMap::from([((1, 2), 3)]))
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), "object key must be a string");
+}
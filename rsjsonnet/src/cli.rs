@@ -1,4 +1,5 @@
 use std::ffi::OsString;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 #[derive(Debug, clap::Parser)]
@@ -14,6 +15,15 @@ pub(crate) struct Cli {
         help = "Specify an additional library search dir (right-most wins)"
     )]
     pub(crate) jpath: Vec<PathBuf>,
+    #[clap(long = "import-json", help = "Import .json files as strict JSON data")]
+    pub(crate) import_json: bool,
+    #[clap(
+        long = "import-yaml",
+        help = "Import .yaml and .yml files as YAML data"
+    )]
+    pub(crate) import_yaml: bool,
+    #[clap(long = "import-toml", help = "Import .toml files as TOML data")]
+    pub(crate) import_toml: bool,
     #[clap(
         long = "output-file",
         short = 'o',
@@ -28,6 +38,13 @@ pub(crate) struct Cli {
         help = "Write multiple files to the directory, list files on stdout"
     )]
     pub(crate) multi: Option<PathBuf>,
+    #[clap(
+        long = "jobs",
+        short = 'j',
+        value_name = "n",
+        help = "Number of threads for multi mode, each re-evaluating the top-level object (std.trace may repeat)"
+    )]
+    pub(crate) jobs: Option<NonZeroUsize>,
     #[clap(
         long = "yaml-stream",
         short = 'y',
@@ -52,6 +69,12 @@ pub(crate) struct Cli {
         value_name = "n"
     )]
     pub(crate) max_stack: Option<usize>,
+    #[clap(
+        long = "max-tail-calls",
+        help = "Number of allowed consecutive tailstrict calls reusing a stack frame",
+        value_name = "n"
+    )]
+    pub(crate) max_tail_calls: Option<usize>,
     #[clap(
         long = "max-trace",
         short = 't',
@@ -59,6 +82,18 @@ pub(crate) struct Cli {
         value_name = "n"
     )]
     pub(crate) max_trace: Option<usize>,
+    #[clap(
+        long = "optimize",
+        short = 'O',
+        help = "Fold constant expressions and drop unused locals before evaluating"
+    )]
+    pub(crate) optimize: bool,
+    #[clap(
+        long = "cache-dir",
+        value_name = "dir",
+        help = "Cache analyzed sources in the directory across runs"
+    )]
+    pub(crate) cache_dir: Option<PathBuf>,
     #[clap(
         long = "ext-str",
         short = 'V',
@@ -11,11 +11,14 @@ use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fmt::Write as _;
 use std::io::{Read as _, Write as _};
+use std::num::NonZeroUsize;
 use std::path::Path;
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use rsjsonnet_front::Session;
-use rsjsonnet_lang::program::{Program, Thunk, Value};
+use rsjsonnet_front::{DataFormat, Session};
+use rsjsonnet_lang::interner::InternedStr;
+use rsjsonnet_lang::program::{Program, SharedSourceCache, Thunk, Value};
 
 mod cli;
 
@@ -49,9 +52,9 @@ fn main_inner() -> Result<(), RunError> {
         return Err(RunError::Usage);
     }
 
-    enum Input<'a> {
-        File(&'a Path),
-        Virt(&'a str, Vec<u8>),
+    if args.jobs.is_some() && args.multi.is_none() {
+        eprintln!("error: cannot use '-j' / '--jobs' without '-m' / '--multi'");
+        return Err(RunError::Usage);
     }
 
     let input;
@@ -76,13 +79,155 @@ fn main_inner() -> Result<(), RunError> {
         input = Input::File(Path::new(&args.input));
     }
 
+    // With several jobs, the analyzed sources are shared with the other
+    // threads, so they do not lex, parse and analyze them again.
+    let shared_cache = args
+        .jobs
+        .is_some_and(|jobs| jobs.get() > 1)
+        .then(SharedSourceCache::new);
+
     let arena = rsjsonnet_lang::arena::Arena::new();
-    let mut session = Session::new(&arena);
+    let (mut session, root_thunk, tla) = load_root(&args, &input, shared_cache.as_ref(), &arena)?;
+
+    let output = if let Some(ref dir_path) = args.multi {
+        let dir_path = Path::new(dir_path);
+        let fields = eval_root_fields(&mut session, &root_thunk, &tla)?;
+        let jobs = args
+            .jobs
+            .map_or(1, NonZeroUsize::get)
+            .min(fields.len().max(1));
+
+        // Each field is manifested by the thread with index `i % jobs`.
+        // Other threads load the input again in their own session, since a
+        // `Program` cannot be shared between threads, but they take the
+        // analyzed sources from the shared cache. Each field is evaluated
+        // once, but the top-level object and values shared by fields of
+        // different threads are evaluated by each thread, so their side
+        // effects (such as `std.trace` output) are repeated.
+        let first_failure = AtomicUsize::new(usize::MAX);
+        let load_failed = AtomicBool::new(false);
+        let mut reprs: Vec<Option<String>> = vec![None; fields.len()];
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (1..jobs)
+                .map(|job| {
+                    let (args, input, shared_cache) = (&args, &input, shared_cache.as_ref());
+                    let (first_failure, load_failed) = (&first_failure, &load_failed);
+                    scope.spawn(move || {
+                        let arena = rsjsonnet_lang::arena::Arena::new();
+                        let fields = load_root(args, input, shared_cache, &arena).and_then(
+                            |(mut session, root_thunk, tla)| {
+                                let fields = eval_root_fields(&mut session, &root_thunk, &tla)?;
+                                Ok((session, fields))
+                            },
+                        );
+                        let Ok((mut session, fields)) = fields else {
+                            load_failed.store(true, Ordering::Relaxed);
+                            return Vec::new();
+                        };
+                        manifest_multi_fields(args, &mut session, &fields, job, jobs, first_failure)
+                    })
+                })
+                .collect();
+
+            for (i, repr) in
+                manifest_multi_fields(&args, &mut session, &fields, 0, jobs, &first_failure)
+            {
+                reprs[i] = Some(repr);
+            }
+            for worker in workers {
+                let worker_reprs = worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                for (i, repr) in worker_reprs {
+                    reprs[i] = Some(repr);
+                }
+            }
+        });
+        if first_failure.into_inner() != usize::MAX || load_failed.into_inner() {
+            return Err(RunError::Generic);
+        }
+
+        let mut path_list = String::new();
+        for ((field_name, _), repr) in fields.iter().zip(reprs) {
+            let path = dir_path.join(field_name.value());
+            match std::fs::write(&path, repr.unwrap().as_bytes()) {
+                Ok(()) => {}
+                Err(e) => {
+                    eprintln!("error: failed to write {path:?}: {e}");
+                    return Err(RunError::Generic);
+                }
+            }
+            writeln!(path_list, "{}", path.display()).unwrap();
+        }
+        path_list
+    } else {
+        let root_value = eval_root(&mut session, &root_thunk, &tla)?;
+        session.push_custom_stack_trace_item("during manifestation".into());
+        let s = value_to_repr(&args, &mut session, &root_value)?;
+        session.pop_custom_stack_trace_item();
+        s
+    };
+
+    if let Some(output_path) = args.output {
+        match std::fs::write(&output_path, output.as_bytes()) {
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("failed to write {output_path:?}: {e}");
+                return Err(RunError::Generic);
+            }
+        }
+    } else {
+        match std::io::stdout().write_all(output.as_bytes()) {
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("failed to write to stdout: {e}");
+                return Err(RunError::Generic);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Input<'a> {
+    File(&'a Path),
+    Virt(&'a str, Vec<u8>),
+}
+
+type NamedThunks<'p> = Vec<(InternedStr<'p>, Thunk<'p>)>;
+
+/// Creates a session, adds the external variables and loads the input,
+/// along with the top-level arguments.
+fn load_root<'p>(
+    args: &cli::Cli,
+    input: &Input<'_>,
+    shared_cache: Option<&SharedSourceCache>,
+    arena: &'p rsjsonnet_lang::arena::Arena,
+) -> Result<(Session<'p>, Thunk<'p>, NamedThunks<'p>), RunError> {
+    let mut session = Session::new(arena);
 
     if let Some(max_stack) = args.max_stack {
         session.program_mut().set_max_stack(max_stack);
     }
 
+    if let Some(max_tail_calls) = args.max_tail_calls {
+        session.program_mut().set_max_tail_calls(max_tail_calls);
+    }
+
+    if args.optimize {
+        session.program_mut().set_optimize(true);
+    }
+
+    if let Some(ref cache_dir) = args.cache_dir {
+        session
+            .program_mut()
+            .set_source_cache(Some(cache_dir.clone()));
+    }
+
+    session
+        .program_mut()
+        .set_shared_source_cache(shared_cache.cloned());
+
     if let Some(max_trace) = args.max_trace {
         session.set_max_trace(max_trace);
     }
@@ -93,10 +238,21 @@ fn main_inner() -> Result<(), RunError> {
         session.add_search_path(path.clone());
     }
 
-    let root_thunk = match input {
+    if args.import_json {
+        session.set_import_format("json", Some(DataFormat::Json));
+    }
+    if args.import_yaml {
+        session.set_import_format("yaml", Some(DataFormat::Yaml));
+        session.set_import_format("yml", Some(DataFormat::Yaml));
+    }
+    if args.import_toml {
+        session.set_import_format("toml", Some(DataFormat::Toml));
+    }
+
+    let root_thunk = match *input {
         Input::File(input_path) => session.load_real_file(input_path),
-        Input::Virt(input_repr_path, input_data) => {
-            session.load_virt_file(input_repr_path, input_data)
+        Input::Virt(input_repr_path, ref input_data) => {
+            session.load_virt_file(input_repr_path, input_data.clone())
         }
     };
     let Some(root_thunk) = root_thunk else {
@@ -204,8 +360,18 @@ fn main_inner() -> Result<(), RunError> {
         tla.push((name, thunk));
     }
 
+    Ok((session, root_thunk, tla))
+}
+
+/// Evaluates the top-level value, calling it with the top-level arguments
+/// if it is a function.
+fn eval_root<'p>(
+    session: &mut Session<'p>,
+    root_thunk: &Thunk<'p>,
+    tla: &[(InternedStr<'p>, Thunk<'p>)],
+) -> Result<Value<'p>, RunError> {
     session.push_custom_stack_trace_item("during top-level value evaluation".into());
-    let Some(mut root_value) = session.eval_value(&root_thunk) else {
+    let Some(root_value) = session.eval_value(root_thunk) else {
         return Err(RunError::Generic);
     };
     session.pop_custom_stack_trace_item();
@@ -213,67 +379,104 @@ fn main_inner() -> Result<(), RunError> {
     if root_value.is_function() {
         let func_thunk = session.program_mut().value_to_thunk(&root_value);
         session.push_custom_stack_trace_item("during top-level function call evaluation".into());
-        let Some(call_value) = session.eval_call(&func_thunk, &[], &tla) else {
+        let Some(call_value) = session.eval_call(&func_thunk, &[], tla) else {
             return Err(RunError::Generic);
         };
         session.pop_custom_stack_trace_item();
-        root_value = call_value;
+        Ok(call_value)
     } else if !tla.is_empty() {
         eprintln!("error: top-level arguments provided, but root value is not a function");
-        return Err(RunError::Generic);
+        Err(RunError::Generic)
+    } else {
+        Ok(root_value)
     }
+}
 
-    let output = if let Some(ref dir_path) = args.multi {
-        let dir_path = Path::new(dir_path);
-        let Some(fields) = root_value.to_object() else {
-            eprintln!("error: in multi mode, the top-level value must be an object");
+/// Like [`eval_root`], but the top-level value must be an object, and its
+/// fields are not evaluated.
+fn eval_root_fields<'p>(
+    session: &mut Session<'p>,
+    root_thunk: &Thunk<'p>,
+    tla: &[(InternedStr<'p>, Thunk<'p>)],
+) -> Result<NamedThunks<'p>, RunError> {
+    session.push_custom_stack_trace_item("during top-level value evaluation".into());
+    let Some(root_fields) = session.eval_object_fields(root_thunk) else {
+        return Err(RunError::Generic);
+    };
+    session.pop_custom_stack_trace_item();
+
+    let fields = if let Some(root_fields) = root_fields {
+        if !tla.is_empty() {
+            eprintln!("error: top-level arguments provided, but root value is not a function");
             return Err(RunError::Generic);
-        };
-        let mut path_list = String::new();
-        for (field_name, field_value) in fields.iter() {
-            session.push_custom_stack_trace_item(format!(
-                "during manifestation of object field {}",
-                field_name.value(),
-            ));
-            let repr = value_to_repr(&args, &mut session, field_value)?;
-            session.pop_custom_stack_trace_item();
-            let path = dir_path.join(field_name.value());
-            match std::fs::write(&path, repr.as_bytes()) {
-                Ok(()) => {}
-                Err(e) => {
-                    eprintln!("error: failed to write {path:?}: {e}");
-                    return Err(RunError::Generic);
-                }
-            }
-            writeln!(path_list, "{}", path.display()).unwrap();
         }
-        path_list
+        Some(root_fields)
     } else {
-        session.push_custom_stack_trace_item("during manifestation".into());
-        let s = value_to_repr(&args, &mut session, &root_value)?;
+        // The value has already been evaluated, so this does not evaluate
+        // anything other than the items of a non-object value.
+        session.push_custom_stack_trace_item("during top-level value evaluation".into());
+        let Some(root_value) = session.eval_value(root_thunk) else {
+            return Err(RunError::Generic);
+        };
         session.pop_custom_stack_trace_item();
-        s
-    };
-
-    if let Some(output_path) = args.output {
-        match std::fs::write(&output_path, output.as_bytes()) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("failed to write {output_path:?}: {e}");
+        if root_value.is_function() {
+            let func_thunk = session.program_mut().value_to_thunk(&root_value);
+            session
+                .push_custom_stack_trace_item("during top-level function call evaluation".into());
+            let Some(call_fields) = session.eval_call_object_fields(&func_thunk, &[], tla) else {
                 return Err(RunError::Generic);
-            }
+            };
+            session.pop_custom_stack_trace_item();
+            call_fields
+        } else if !tla.is_empty() {
+            eprintln!("error: top-level arguments provided, but root value is not a function");
+            return Err(RunError::Generic);
+        } else {
+            None
         }
-    } else {
-        match std::io::stdout().write_all(output.as_bytes()) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("failed to write to stdout: {e}");
-                return Err(RunError::Generic);
-            }
+    };
+
+    fields.ok_or_else(|| {
+        eprintln!("error: in multi mode, the top-level value must be an object");
+        RunError::Generic
+    })
+}
+
+/// Evaluates and manifests the fields of the top-level object whose index
+/// modulo `jobs` is `job`.
+///
+/// Stops at the first field that fails or once a field with a lower index
+/// has failed in another thread, so usually only the first error is
+/// reported.
+fn manifest_multi_fields<'p>(
+    args: &cli::Cli,
+    session: &mut Session<'p>,
+    fields: &[(InternedStr<'p>, Thunk<'p>)],
+    job: usize,
+    jobs: usize,
+    first_failure: &AtomicUsize,
+) -> Vec<(usize, String)> {
+    let mut reprs = Vec::new();
+    for (i, (field_name, field_thunk)) in fields.iter().enumerate().skip(job).step_by(jobs) {
+        if i > first_failure.load(Ordering::Relaxed) {
+            break;
         }
+        session.push_custom_stack_trace_item(format!(
+            "during manifestation of object field {}",
+            field_name.value(),
+        ));
+        let repr = session
+            .eval_value(field_thunk)
+            .ok_or(RunError::Generic)
+            .and_then(|field_value| value_to_repr(args, session, &field_value));
+        let Ok(repr) = repr else {
+            first_failure.fetch_min(i, Ordering::Relaxed);
+            break;
+        };
+        session.pop_custom_stack_trace_item();
+        reprs.push((i, repr));
     }
-
-    Ok(())
+    reprs
 }
 
 fn ext_str_to_thunk<'p>(